@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use seal_isa::cpu::decode_instr;
+
+// Every 32-bit word has to either decode or be rejected with an error
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(4) {
+        let _ = decode_instr(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use seal_isa::{
+    machine::{Machine, MachineConfig},
+    mmu::VAddr,
+};
+
+/// Address the fuzzed code is loaded at, outside of the default layout's fixed regions
+const CODE_ADDR: u32 = 0x40000;
+
+/// Longest instruction sequence that is run, in bytes
+const MAX_CODE: usize = 64 * 4;
+
+// The first byte selects the execution model, the rest is run as machine code for a bounded
+// number of cycles. Guest faults stop the simulation, but must never panic the simulator
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, code)) = data.split_first() else {
+        return;
+    };
+    let config = MachineConfig {
+        seed:         Some(0),
+        pipelining:   mode & 1 != 0,
+        forwarding:   mode & 2 != 0,
+        out_of_order: mode & 4 != 0,
+        golden:       mode & 8 != 0,
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::new(config).unwrap();
+    if machine.load_binary(VAddr(CODE_ADDR), &code[..code.len().min(MAX_CODE)]).is_ok() {
+        machine.run(2_000);
+    }
+});
@@ -0,0 +1,227 @@
+use crate::{
+    simulator::{Simulator, SimErr},
+    headless::{self, RunEnd},
+    logger::Logger,
+    machine::{Machine, MachineConfig},
+    energy::{Energy, format_energy},
+    Stats,
+};
+
+/// Cycle limit applied to every program of a batch unless configured otherwise
+pub const DEFAULT_BATCH_CYCLES: u32 = 10_000_000;
+
+/// Result of running a single program of a batch
+#[derive(Debug, Clone)]
+pub struct BatchEntry {
+    /// Name the program is listed under in the table, usually its path
+    pub name: String,
+
+    /// How the run ended, or the error that prevented the program from being loaded
+    pub end: Result<RunEnd, SimErr>,
+
+    /// Clock-counter at the end of the run
+    pub clock: u32,
+
+    /// Statistics collected during the run
+    pub stats: Stats,
+
+    /// Energy spent during the run
+    pub energy: Energy,
+}
+
+impl BatchEntry {
+    /// Short description of how the run ended
+    pub fn status(&self) -> String {
+        match &self.end {
+            Ok(RunEnd::Offline)    => String::from("done"),
+            Ok(RunEnd::CycleLimit) => String::from("limit"),
+            Err(err)               => format!("{:?}", err),
+        }
+    }
+}
+
+/// Run every `(name, source)` program to completion or until `max_cycles` elapsed. Each program
+/// runs on its own copy of `template`, so configuration sweeps only need to set up the template
+pub fn run_batch(template: &Simulator, programs: &[(String, String)], max_cycles: u32,
+                 log: &dyn Logger) -> Vec<BatchEntry> {
+    programs.iter().map(|(name, source)| {
+        let mut sim = template.clone();
+        let end = headless::run(&mut sim, source, Some(max_cycles), log).map(|(end, _)| end);
+        let energy = sim.energy();
+        BatchEntry { name: name.clone(), end, clock: sim.clock, stats: sim.stats, energy }
+    }).collect()
+}
+
+/// Table of the per-program results, one row per program with its cycles, CPI, hit-rates and
+/// instruction mix (percentages of all executed instructions)
+pub fn format_table(entries: &[BatchEntry]) -> String {
+    let ratio = |part: u64, total: u64| {
+        if total == 0 { 0.0 } else { part as f64 / total as f64 * 100.0 }
+    };
+    let width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0).max("Program".len());
+
+    let mut lines = vec![format!("{:<width$}  {:<15}  {:>10}  {:>10}  {:>6}  {:>7}  {:>7}  \
+                                 {:>6}  {:>6}  {:>6}  {:>6}", "Program", "Status", "Cycles",
+                                 "Instrs", "CPI", "L1 Hit", "TLB Hit", "Arith", "Load", "Store",
+                                 "Ctrl", width = width)];
+    for entry in entries {
+        let stats = &entry.stats;
+        let total = stats.total_instrs;
+        let cpi = if total == 0 { String::from("-") }
+                  else { format!("{:.2}", entry.clock as f64 / total as f64) };
+        lines.push(format!("{:<width$}  {:<15}  {:>10}  {:>10}  {:>6}  {:>6.2}%  {:>6.2}%  \
+                            {:>5.1}%  {:>5.1}%  {:>5.1}%  {:>5.1}%",
+                           entry.name, entry.status(), entry.clock, total, cpi,
+                           ratio(stats.cache_hits, stats.cache_hits + stats.cache_misses),
+                           ratio(stats.tlb_hits, stats.tlb_hits + stats.tlb_misses),
+                           ratio(stats.arithmetic_instrs, total), ratio(stats.load_instrs, total),
+                           ratio(stats.store_instrs, total), ratio(stats.control_instrs, total),
+                           width = width));
+    }
+    lines.join("\n")
+}
+
+/// Machine configuration a program is run under in a configuration sweep
+#[derive(Debug, Clone)]
+pub struct SweepPoint {
+    /// Name the configuration is listed under in the table
+    pub name: String,
+
+    pub config: MachineConfig,
+}
+
+/// The configurations most commonly compared: `base` itself, without caches, with a 4x smaller
+/// and 4x larger L1, and with pipelining toggled. All of them share one seed, so only the
+/// configuration differs between the runs
+pub fn default_sweep(base: &MachineConfig) -> Vec<SweepPoint> {
+    let base  = MachineConfig { seed: Some(base.seed.unwrap_or_else(rand::random)), ..*base };
+    let point = |name: String, config| SweepPoint { name, config };
+    let small = (base.l1_sets / 4).max(1);
+    let large = base.l1_sets * 4;
+    let pipelining = !(base.pipelining || base.out_of_order);
+    vec![
+        point(String::from("baseline"), base),
+        point(String::from("no caches"), MachineConfig { caches: false, ..base }),
+        point(format!("L1 {} sets", small), MachineConfig { caches: true, l1_sets: small, ..base }),
+        point(format!("L1 {} sets", large), MachineConfig { caches: true, l1_sets: large, ..base }),
+        point(format!("pipeline {}", if pipelining { "on" } else { "off" }),
+              MachineConfig { pipelining, out_of_order: false, ..base }),
+    ]
+}
+
+/// Run `source` once under every configuration in `points`, each until it exits or `max_cycles`
+/// elapsed
+pub fn run_sweep(source: &str, points: &[SweepPoint], max_cycles: u32) -> Vec<BatchEntry> {
+    points.iter().map(|point| {
+        let run = Machine::new(point.config).and_then(|mut machine| {
+            machine.load_program(source)?;
+            Ok((machine.run(max_cycles), machine))
+        });
+        match run {
+            Ok((end, mut machine)) => BatchEntry { name: point.name.clone(), end: Ok(end),
+                                                   clock: machine.clock(),
+                                                   stats: machine.stats().clone(),
+                                                   energy: machine.simulator().energy() },
+            Err(err) => BatchEntry { name: point.name.clone(), end: Err(err), clock: 0,
+                                     stats: Stats::default(), energy: Energy::default() },
+        }
+    }).collect()
+}
+
+/// Table comparing the configurations of a sweep, one row per configuration with its cycles, CPI,
+/// hit-rates, energy and energy-delay product, and speedup over the first configuration
+pub fn format_sweep(entries: &[BatchEntry]) -> String {
+    let ratio = |part: u64, total: u64| {
+        if total == 0 { 0.0 } else { part as f64 / total as f64 * 100.0 }
+    };
+    let width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0).max("Config".len());
+    let base  = entries.first().map_or(0, |e| e.clock);
+
+    let mut lines = vec![format!("{:<width$}  {:<15}  {:>10}  {:>6}  {:>7}  {:>7}  {:>9}  \
+                                 {:>9}  {:>7}", "Config", "Status", "Cycles", "CPI", "L1 Hit",
+                                 "L2 Hit", "Energy", "EDP (Js)", "Speedup", width = width)];
+    for entry in entries {
+        let stats = &entry.stats;
+        let cpi = if stats.total_instrs == 0 { String::from("-") }
+                  else { format!("{:.2}", entry.clock as f64 / stats.total_instrs as f64) };
+        let speedup = if entry.clock == 0 { String::from("-") }
+                      else { format!("{:.2}x", base as f64 / entry.clock as f64) };
+        lines.push(format!("{:<width$}  {:<15}  {:>10}  {:>6}  {:>6.2}%  {:>6.2}%  {:>9}  \
+                            {:>9.2e}  {:>7}",
+                           entry.name, entry.status(), entry.clock, cpi,
+                           ratio(stats.cache_hits, stats.cache_hits + stats.cache_misses),
+                           ratio(stats.l2_cache_hits, stats.l2_cache_hits + stats.l2_cache_misses),
+                           format_energy(entry.energy.total()), entry.energy.edp(), speedup,
+                           width = width));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::MsgLog, simulator::DEFAULT_STACK_BASE, mmu::FrameAllocator};
+
+    #[test]
+    fn runs_every_program_on_a_fresh_copy() {
+        let finishes = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let spins = ".entry _start\n.load 0x10000\n._start\n.loop\njmpr .loop\n.end_section";
+        let programs = [("finishes", finishes), ("spins", spins), ("broken", "garbage")]
+            .map(|(name, source)| (name.to_string(), source.to_string()));
+
+        let mut template = Simulator::new();
+        template.mmu.frame_allocator = FrameAllocator::sequential();
+        template.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+
+        let entries = run_batch(&template, &programs, 5_000, &MsgLog::default());
+        assert_eq!(entries.iter().map(BatchEntry::status).collect::<Vec<_>>(),
+                   ["done", "limit", "LoadErr"]);
+        assert!(entries[0].clock < 5_000 && entries[0].stats.store_instrs >= 1);
+        assert_eq!(entries[1].clock, 5_000);
+        assert_eq!(template.clock, 0);
+
+        let table = format_table(&entries);
+        assert_eq!(table.lines().count(), 4);
+        assert!(table.lines().nth(2).unwrap().starts_with("spins     limit"));
+    }
+
+    #[test]
+    fn sweeps_compare_configurations() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r6 0x20
+            .loop
+                ld r3 r15 0x0
+                subi r6 r6 0x1
+                bne r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let base   = MachineConfig { seed: Some(1), ..MachineConfig::default() };
+        let points = default_sweep(&base);
+        assert_eq!(points.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+                   ["baseline", "no caches", "L1 8 sets", "L1 128 sets", "pipeline off"]);
+
+        let entries = run_sweep(program, &points, 1_000_000);
+        assert!(entries.iter().all(|entry| entry.status() == "done"));
+        assert!(entries[1].clock > entries[0].clock && entries[1].stats.cache_hits == 0);
+        assert!(entries[4].clock > entries[0].clock);
+        assert!(entries[1].energy.dram > entries[0].energy.dram);
+
+        let table = format_sweep(&entries);
+        assert_eq!(table.lines().count(), 6);
+        assert!(table.lines().nth(1).unwrap().ends_with("1.00x"));
+    }
+}
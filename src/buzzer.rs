@@ -0,0 +1,311 @@
+use crate::mmio::{MmioDevice, MmioAction, read_regs, write_regs};
+
+use std::cell::RefCell;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::Once;
+
+/// Address of the buzzer's registers, next to the dma engine
+pub const BUZZER_BASE: u32 = 0x2080;
+
+/// Size of the buzzer's register block
+pub const BUZZER_SIZE: u32 = 0x10;
+
+/// Interrupt-vector raised when a tone completes, its handler is stored at address 0x18
+pub const BUZZER_IRQ: u32 = 6;
+
+/// Clock-cycles per millisecond of a tone, the simulated clock runs at 100 kHz
+pub const BUZZER_CYCLES_PER_MS: u32 = 100;
+
+/// Control bit that starts playing a tone, ignored while one is playing
+pub const BUZZER_PLAY: u32 = 1 << 0;
+
+/// Status bit that is set while a tone is playing
+pub const BUZZER_PLAYING: u32 = 1 << 0;
+
+/// Samples per second of the audio played on the host
+const SAMPLE_RATE: u32 = 8000;
+
+/// Host programs tried in order to play a tone
+const PLAYERS: [&str; 3] = ["paplay", "aplay", "afplay"];
+
+/// Tone the guest played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+    /// Pitch in Hz, 0 is a rest
+    pub frequency: u32,
+
+    /// Length in milliseconds
+    pub duration: u32,
+
+    /// Clock-cycle the tone started in
+    pub clock: u32,
+}
+
+/// State shared between all clones of a `Buzzer`
+#[derive(Debug, Default)]
+struct BuzzerState {
+    /// Tones the guest played, oldest first
+    tones: Vec<Tone>,
+
+    frequency: u32,
+    duration:  u32,
+    status:    u32,
+
+    /// Cycles until the tone that is playing completes
+    remaining: Option<u32>,
+
+    /// Play tones on the host's speakers
+    audio: bool,
+}
+
+/// Tone generator at `BUZZER_BASE`. Its 32-bit registers are:
+///  - 0x0 Frequency: Pitch of the next tone in Hz, 0 plays silence
+///  - 0x4 Duration: Length of the next tone in milliseconds
+///  - 0x8 Control: Writing `BUZZER_PLAY` starts the tone
+///  - 0xc Status: `BUZZER_PLAYING`
+///
+/// A tone lasts `BUZZER_CYCLES_PER_MS` cycles per millisecond, then `BUZZER_IRQ` is raised. Writes
+/// are ignored while a tone is playing. Clones share the same state
+#[derive(Clone, Debug, Default)]
+pub struct Buzzer {
+    state: Rc<RefCell<BuzzerState>>,
+}
+
+impl Buzzer {
+    /// Buzzer that only records the tones it plays
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buzzer that also plays its tones on the host, through the first of `PLAYERS` that works
+    pub fn speaker() -> Self {
+        let buzzer = Self::new();
+        buzzer.state.borrow_mut().audio = true;
+        buzzer
+    }
+
+    /// Every tone the guest played so far
+    pub fn tones(&self) -> Vec<Tone> {
+        self.state.borrow().tones.clone()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        let state = self.state.borrow();
+        [state.frequency, state.duration, 0, state.status]
+    }
+}
+
+impl MmioDevice for Buzzer {
+    fn name(&self) -> &str {
+        "buzzer"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value. The
+    /// status can't be written
+    fn write(&mut self, offset: u32, data: &[u8], clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        let mut state = self.state.borrow_mut();
+        if state.status & BUZZER_PLAYING != 0 {
+            return None;
+        }
+        state.frequency = regs[0];
+        state.duration  = regs[1];
+        if (offset..offset + data.len()).contains(&8) && regs[2] & BUZZER_PLAY != 0 {
+            let tone = Tone { frequency: state.frequency, duration: state.duration, clock };
+            if state.audio {
+                play(tone);
+            }
+            state.tones.push(tone);
+            state.status    = BUZZER_PLAYING;
+            state.remaining = Some(tone.duration.saturating_mul(BUZZER_CYCLES_PER_MS));
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        match state.remaining {
+            None => Vec::new(),
+            Some(0) => {
+                state.remaining = None;
+                state.status   &= !BUZZER_PLAYING;
+                vec![MmioAction::RaiseIrq]
+            },
+            Some(remaining) => {
+                state.remaining = Some(remaining - 1);
+                Vec::new()
+            },
+        }
+    }
+
+    /// The tone that is playing is dropped from the simulation, the host finishes playing it
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let audio = state.audio;
+        *state = BuzzerState { audio, ..BuzzerState::default() };
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+/// Square wave of `tone` as an 8-bit mono wav file
+fn wav(tone: Tone) -> Vec<u8> {
+    let samples = (SAMPLE_RATE as u64 * tone.duration as u64 / 1000) as u32;
+    let half_period = match tone.frequency {
+        0 => None,
+        frequency => Some((SAMPLE_RATE / 2 / frequency).max(1)),
+    };
+
+    let mut data = Vec::with_capacity(44 + samples as usize);
+    data.extend(b"RIFF");
+    data.extend((36 + samples).to_le_bytes());
+    data.extend(b"WAVEfmt ");
+    data.extend(16u32.to_le_bytes());
+    data.extend(1u16.to_le_bytes());
+    data.extend(1u16.to_le_bytes());
+    data.extend(SAMPLE_RATE.to_le_bytes());
+    data.extend(SAMPLE_RATE.to_le_bytes());
+    data.extend(1u16.to_le_bytes());
+    data.extend(8u16.to_le_bytes());
+    data.extend(b"data");
+    data.extend(samples.to_le_bytes());
+    data.extend((0..samples).map(|sample| match half_period {
+        Some(half_period) if (sample / half_period) % 2 == 0 => 0xa0,
+        Some(_) => 0x60,
+        None => 0x80,
+    }));
+    data
+}
+
+/// Play `tone` on the host in the background
+fn play(tone: Tone) {
+    static NO_PLAYER: Once = Once::new();
+
+    let path = std::env::temp_dir()
+        .join(format!("seal_tone_{}_{}.wav", std::process::id(), tone.clock));
+    if let Err(err) = std::fs::write(&path, wav(tone)) {
+        eprintln!("Failed to write tone: {}", err);
+        return;
+    }
+    std::thread::spawn(move || {
+        let played = PLAYERS.iter().any(|player| {
+            Command::new(player).arg(&path).stdout(Stdio::null()).stderr(Stdio::null()).status()
+                .is_ok_and(|status| status.success())
+        });
+        let _ = std::fs::remove_file(&path);
+        if !played {
+            NO_PLAYER.call_once(|| {
+                eprintln!("No audio player found, tried {}", PLAYERS.join(", "));
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::{Simulator, DEFAULT_STACK_BASE},
+        difftest::MsgLog,
+        mmu::VAddr,
+        cpu::Register,
+    };
+
+    #[test]
+    fn tones_complete_after_their_duration() {
+        let mut buzzer = Buzzer::new();
+        buzzer.write(0x0, &440u32.to_le_bytes(), 0);
+        buzzer.write(0x4, &[2], 0);
+        buzzer.write(0x8, &[BUZZER_PLAY as u8], 5);
+        assert_eq!(buzzer.regs()[3], BUZZER_PLAYING);
+        assert_eq!(buzzer.tones(), [Tone { frequency: 440, duration: 2, clock: 5 }]);
+
+        // Tones can't be changed while they play
+        buzzer.write(0x0, &[0], 6);
+        assert_eq!(buzzer.regs()[0], 440);
+
+        let cycles = 2 * BUZZER_CYCLES_PER_MS;
+        assert!((0..cycles).all(|clock| buzzer.tick(clock).is_empty()));
+        assert_eq!(buzzer.tick(cycles), [MmioAction::RaiseIrq]);
+        assert_eq!(buzzer.regs()[3], 0);
+        assert!(buzzer.tick(cycles + 1).is_empty());
+
+        let wav = wav(buzzer.tones()[0]);
+        assert_eq!(wav.len(), 44 + 2 * SAMPLE_RATE as usize / 1000);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[44..].iter().filter(|&&sample| sample == 0xa0).count(), 9);
+    }
+
+    /// Play a short melody, starting every note from the interrupt of the previous one
+    #[test]
+    fn plays_notes_from_the_interrupt_handler() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                addi r6 r6 0x1
+                addi r1 r1 0x64
+                st r1 r2 0x0
+                movi r4 0x1
+                st r4 r2 0x8
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x18
+                movi r2 0x2080
+                movi r1 0xc8
+                st r1 r2 0x0
+                movi r1 0x10
+                st r1 r2 0x4
+                movi r1 0x1
+                st r1 r2 0x8
+                movi r1 0xc8
+            .wait
+                subi r3 r6 0x3
+                bne r3 r0 .wait
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            let buzzer = Buzzer::new();
+            sim.register_device(VAddr(BUZZER_BASE), BUZZER_SIZE, Some(BUZZER_IRQ),
+                                Box::new(buzzer.clone())).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert_eq!(sim.read_reg(Register::R6), 3, "{}", mode);
+
+            // The last note started from the handler is still playing when the program exits
+            let tones = buzzer.tones();
+            let frequencies = tones.iter().map(|tone| tone.frequency).collect::<Vec<_>>();
+            assert_eq!(frequencies, [200, 300, 400, 500], "{}", mode);
+            assert!(tones.windows(2).all(|pair| pair[1].clock - pair[0].clock
+                                          > 0x10 * BUZZER_CYCLES_PER_MS), "{}", mode);
+        }
+    }
+}
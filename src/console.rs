@@ -0,0 +1,294 @@
+use crate::{
+    simulator::Simulator,
+    mmu::VAddr,
+    cpu::{Register, NUM_REGS},
+    debugger::{DebugStop, DEBUG_CYCLE_LIMIT},
+    headless::stats_report,
+    logger::Logger,
+};
+
+/// Commands understood by `execute`, printed by `help`
+pub const HELP: &str = "\
+x/NF ADDR      Print N units of memory at ADDR, F is b, h or w (eg. x/16w 0x10000)
+reg [REG]      Print a register (r0-r15 or pc), or all of them
+set REG VALUE  Write VALUE to a general-purpose register
+bp [ADDR]      Set a breakpoint at ADDR, or list all breakpoints
+del ADDR       Remove the breakpoint at ADDR
+step [N]       Step N clock-cycles, 1 by default
+until ADDR     Run until the pc reaches ADDR
+over           Step a single instruction, running called functions to completion
+out            Run until the current function returns
+continue       Run until a breakpoint or exit
+bt             Print the backtrace
+stats          Print the run statistics
+help           Print this list
+Addresses and values are in hex, addresses can also be labels of the loaded program";
+
+/// Maximum number of units a single `x` command prints
+const MAX_EXAMINE_UNITS: usize = 1024;
+
+/// Execute a single console command such as `x/16w 0x10000` or `until .loop`, and return its
+/// output. Errors reported while the simulator runs go to `err_log`, invalid commands are returned
+/// as `Err`
+pub fn execute(sim: &mut Simulator, line: &str, err_log: &dyn Logger) -> Result<String, String> {
+    let mut args = line.split_whitespace();
+    let Some(cmd) = args.next() else {
+        return Ok(String::new());
+    };
+    let args = args.collect::<Vec<_>>();
+    let (cmd, format) = cmd.split_once('/').map_or((cmd, None), |(cmd, f)| (cmd, Some(f)));
+    if format.is_some() && cmd != "x" {
+        return Err(format!("`{}` does not take a format", cmd));
+    }
+
+    match (cmd, args.as_slice()) {
+        ("x", [addr]) => examine(sim, format.unwrap_or(""), parse_addr(sim, addr)?),
+        ("reg", []) => {
+            let mut lines = (0..NUM_REGS).collect::<Vec<_>>().chunks(4).map(|regs| {
+                regs.iter().map(|&reg| {
+                    let reg = Register::from(reg as u32);
+                    format!("{:<4}{:#010x}", reg.to_string(), sim.read_reg(reg))
+                }).collect::<Vec<_>>().join("  ")
+            }).collect::<Vec<_>>();
+            lines.push(format!("pc  {:#010x}", sim.pc.0));
+            Ok(lines.join("\n"))
+        },
+        ("reg", ["pc"]) => Ok(format!("pc  {:#010x}", sim.pc.0)),
+        ("reg", [reg]) => {
+            let reg = parse_reg(reg)?;
+            Ok(format!("{:<4}{:#010x}", reg.to_string(), sim.read_reg(reg)))
+        },
+        ("set", [reg, value]) => {
+            let (reg, value) = (parse_reg(reg)?, parse_hex(value)?);
+            if reg == Register::R0 {
+                return Err("r0 is always zero".to_string());
+            }
+            sim.write_reg(reg, value);
+            Ok(format!("{:<4}{:#010x}", reg.to_string(), value))
+        },
+        ("bp", []) => {
+            let mut addrs = sim.breakpoints.keys().copied().collect::<Vec<_>>();
+            addrs.sort_unstable();
+            Ok(addrs.iter().map(|&addr| format!("{:#010x} in {}", addr,
+                                                sim.symbolize(VAddr(addr))))
+                .collect::<Vec<_>>().join("\n"))
+        },
+        ("bp", [addr]) => {
+            let addr = parse_addr(sim, addr)?;
+            sim.breakpoints.insert(addr, 0);
+            Ok(format!("Breakpoint at {:#010x} in {}", addr, sim.symbolize(VAddr(addr))))
+        },
+        ("del", [addr]) => {
+            let addr = parse_addr(sim, addr)?;
+            match sim.breakpoints.remove(&addr) {
+                Some(_) => Ok(format!("Removed breakpoint at {:#010x}", addr)),
+                None => Err(format!("No breakpoint at {:#010x}", addr)),
+            }
+        },
+        ("step", []) | ("step", [_]) => {
+            let count = match args.first() {
+                Some(count) => count.parse::<u64>()
+                    .map_err(|_| format!("Invalid count `{}`", count))?,
+                None => 1,
+            };
+            for _ in 0..count {
+                if !sim.online {
+                    break;
+                }
+                sim.step(err_log);
+            }
+            Ok(describe_stop(sim, DebugStop::Reached))
+        },
+        ("until", [addr]) => {
+            let addr = parse_addr(sim, addr)?;
+            let stop = sim.run_until(VAddr(addr), DEBUG_CYCLE_LIMIT, err_log);
+            check_stop(sim, stop, DEBUG_CYCLE_LIMIT)
+        },
+        ("over", []) => {
+            let stop = sim.step_over(DEBUG_CYCLE_LIMIT, err_log);
+            check_stop(sim, stop, DEBUG_CYCLE_LIMIT)
+        },
+        ("out", []) => {
+            let stop = sim.step_out(DEBUG_CYCLE_LIMIT, err_log);
+            check_stop(sim, stop, DEBUG_CYCLE_LIMIT)
+        },
+        ("continue" | "c", []) => {
+            let limit = sim.run_limits.max_cycles.unwrap_or(u32::MAX);
+            let stop  = sim.resume(limit, err_log);
+            check_stop(sim, stop, limit)
+        },
+        ("bt", []) => Ok(sim.backtrace().join("\n")),
+        ("stats", []) => Ok(stats_report(sim)),
+        ("help", []) => Ok(HELP.to_string()),
+        ("x" | "reg" | "set" | "bp" | "del" | "step" | "until" | "over" | "out" | "continue"
+         | "c" | "bt" | "stats" | "help", _) => {
+            Err(format!("Invalid arguments for `{}`, see `help`", cmd))
+        },
+        _ => Err(format!("Unknown command `{}`, see `help`", cmd)),
+    }
+}
+
+/// Print `count` units of memory at `addr`, 16 bytes per line. `format` is the count followed by
+/// the unit size, eg. `16w`, both are optional
+fn examine(sim: &mut Simulator, format: &str, addr: u32) -> Result<String, String> {
+    let unit  = format.trim_start_matches(|c: char| c.is_ascii_digit());
+    let count = &format[..format.len() - unit.len()];
+    let count = if count.is_empty() { 1 } else {
+        count.parse::<usize>().map_err(|_| format!("Invalid count `{}`", count))?
+    };
+    let size = match unit {
+        "b"      => 1,
+        "h"      => 2,
+        "w" | "" => 4,
+        _ => return Err(format!("Invalid unit `{}`, use b, h or w", unit)),
+    };
+    if count == 0 || count > MAX_EXAMINE_UNITS {
+        return Err(format!("Count has to be between 1 and {}", MAX_EXAMINE_UNITS));
+    }
+
+    let mut bytes = vec![0u8; count * size];
+    sim.gui_mem_read(VAddr(addr), &mut bytes)
+        .map_err(|err| format!("Cannot read {:#x} bytes at {:#010x}: {:?}", bytes.len(), addr,
+                               err))?;
+    Ok(bytes.chunks(16).enumerate().map(|(line, chunk)| {
+        let units = chunk.chunks(size).map(|unit| {
+            let value = unit.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32);
+            format!("{:#0width$x}", value, width = size * 2 + 2)
+        }).collect::<Vec<_>>().join(" ");
+        format!("{:#010x}:  {}", addr.wrapping_add(line as u32 * 16), units)
+    }).collect::<Vec<_>>().join("\n"))
+}
+
+/// Location the simulator stopped at, and the reason it stopped if it didn't complete the command
+fn describe_stop(sim: &Simulator, stop: DebugStop) -> String {
+    let location = format!("{:#010x} in {}, cycle {}", sim.pc.0, sim.symbolize(sim.pc), sim.clock);
+    match stop {
+        _ if !sim.online      => format!("Simulator stopped at {}", location),
+        DebugStop::Breakpoint => format!("Breakpoint at {}", location),
+        _                     => format!("Stopped at {}", location),
+    }
+}
+
+/// Output of a run command that stopped with `stop`, running out of `limit` cycles is an error
+fn check_stop(sim: &Simulator, stop: DebugStop, limit: u32) -> Result<String, String> {
+    match stop {
+        DebugStop::CycleLimit => Err(format!("Gave up after {} cycles at {:#010x}", limit,
+                                             sim.pc.0)),
+        _ => Ok(describe_stop(sim, stop)),
+    }
+}
+
+/// Parse a general-purpose register such as `r3`
+fn parse_reg(arg: &str) -> Result<Register, String> {
+    match arg.strip_prefix('r').and_then(|idx| idx.parse::<u32>().ok()) {
+        Some(idx) if (idx as usize) < NUM_REGS => Ok(Register::from(idx)),
+        _ => Err(format!("Invalid register `{}`", arg)),
+    }
+}
+
+/// Parse a hex-value, with or without its `0x` prefix
+fn parse_hex(arg: &str) -> Result<u32, String> {
+    u32::from_str_radix(arg.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("Invalid hex value `{}`", arg))
+}
+
+/// Parse an address, either in hex or as a label of the loaded program. Values with a `0x` prefix
+/// are always addresses, labels take precedence otherwise
+fn parse_addr(sim: &Simulator, arg: &str) -> Result<u32, String> {
+    if !arg.starts_with("0x") {
+        let name = arg.trim_start_matches('.');
+        if let Some((&addr, _)) = sim.symbols.iter().find(|(_, symbol)| *symbol == name) {
+            return Ok(addr);
+        }
+    }
+    parse_hex(arg).map_err(|_| format!("Invalid address or unknown label `{}`", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::MsgLog, simulator::DEFAULT_STACK_BASE};
+
+    const PROGRAM: &str = "
+        .entry _start
+        .load 0x20000
+        .count
+            addi r3 r3 0x1
+            ret
+        .end_section
+        .load 0x10000
+        ._start
+            movi r6 0x3
+        .loop
+            call 0x20000
+            subi r6 r6 0x1
+            bne r6 r0 .loop
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+    ";
+
+    fn setup(log: &MsgLog) -> Simulator {
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(PROGRAM, log).unwrap();
+        sim
+    }
+
+    #[test]
+    fn examines_memory_and_registers() {
+        let log = MsgLog::default();
+        let mut sim = setup(&log);
+        sim.mem_write(VAddr(0x0), &mut (0u8..20).collect()).unwrap();
+
+        assert_eq!(execute(&mut sim, "x/5w 0x0", &log).unwrap(),
+                   "0x00000000:  0x03020100 0x07060504 0x0b0a0908 0x0f0e0d0c\n\
+                    0x00000010:  0x13121110");
+        assert_eq!(execute(&mut sim, "x/2h 0x2", &log).unwrap(), "0x00000002:  0x0302 0x0504");
+        assert_eq!(execute(&mut sim, "x/3b 0x1", &log).unwrap(), "0x00000001:  0x01 0x02 0x03");
+        assert_eq!(execute(&mut sim, "x 0x4", &log).unwrap(), "0x00000004:  0x07060504");
+        assert!(execute(&mut sim, "x/4q 0x0", &log).is_err());
+        assert!(execute(&mut sim, "x/4w 0x90000000", &log).is_err());
+
+        assert_eq!(execute(&mut sim, "set r4 0xbeef", &log).unwrap(), "r4  0x0000beef");
+        assert_eq!(execute(&mut sim, "reg r4", &log).unwrap(), "r4  0x0000beef");
+        assert_eq!(execute(&mut sim, "reg pc", &log).unwrap(), "pc  0x00010000");
+        let regs = execute(&mut sim, "reg", &log).unwrap();
+        assert_eq!(regs.lines().count(), 5);
+        assert!(regs.starts_with("r0  0x00000000  r1  0x00000000  r2  0x00000000  r3"));
+        assert!(execute(&mut sim, "reg r16", &log).is_err());
+        assert!(execute(&mut sim, "set r0 0x1", &log).is_err());
+        assert!(execute(&mut sim, "frobnicate", &log).unwrap_err().starts_with("Unknown"));
+        assert!(execute(&mut sim, "step/4", &log).is_err());
+        assert_eq!(execute(&mut sim, "  ", &log).unwrap(), "");
+    }
+
+    #[test]
+    fn runs_to_labels_and_breakpoints() {
+        let log = MsgLog::default();
+        let mut sim = setup(&log);
+
+        assert_eq!(execute(&mut sim, "until loop", &log).unwrap(),
+                   format!("Stopped at 0x00010004 in loop, cycle {}", sim.clock));
+        assert_eq!(execute(&mut sim, "bp count", &log).unwrap(),
+                   "Breakpoint at 0x00020000 in count");
+        assert_eq!(execute(&mut sim, "bp", &log).unwrap(), "0x00020000 in count");
+        assert!(execute(&mut sim, "continue", &log).unwrap().starts_with("Breakpoint at \
+                                                                          0x00020000 in count"));
+        assert!(execute(&mut sim, "bt", &log).unwrap().ends_with("in loop+0x4"));
+        execute(&mut sim, "out", &log).unwrap();
+        assert_eq!(sim.read_reg(Register::R3), 1);
+
+        let clock = sim.clock;
+        execute(&mut sim, "step 10", &log).unwrap();
+        assert_eq!(sim.clock, clock + 10);
+
+        execute(&mut sim, "del 0x20000", &log).unwrap();
+        assert!(execute(&mut sim, "del 0x20000", &log).is_err());
+        assert!(execute(&mut sim, "c", &log).unwrap().starts_with("Simulator stopped at"));
+        assert_eq!(sim.read_reg(Register::R3), 3);
+        assert!(!sim.online);
+        assert!(log.errors.borrow().is_empty(), "{:?}", log);
+    }
+}
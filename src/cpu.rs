@@ -4,6 +4,7 @@ pub const NUM_REGS: usize = 16;
 
 use num_traits::Signed;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Serialize, Deserialize};
 
 use std::fmt::{LowerHex, Formatter};
 use std::convert::TryFrom;
@@ -30,7 +31,8 @@ pub enum PipelineStage {
 }
 
 /// Registers supported by this architecture
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize,
+         Deserialize)]
 #[repr(usize)]
 pub enum Register {
     R0,
@@ -93,7 +95,7 @@ impl fmt::Display for Register {
 }
 
 /// Instructions supported by this architecture
-#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Instr {
     #[default]
     None,
@@ -123,6 +125,7 @@ pub enum Instr {
     Stb  { rs3: Register, rs1: Register, imm: i32 },
     Sth  { rs3: Register, rs1: Register, imm: i32 },
     St   { rs3: Register, rs1: Register, imm: i32 },
+    Stnt { rs3: Register, rs1: Register, imm: i32 },
 
     Bne  { rs3: Register, rs1: Register, imm: i32 },
     Beq  { rs3: Register, rs1: Register, imm: i32 },
@@ -183,6 +186,8 @@ pub enum InstrCode {
     Mul = 30,
     Div = 31,
 
+    Stnt = 32,
+
     Int0 = 40,
 }
 
@@ -223,6 +228,8 @@ impl fmt::Display for Instr {
                                                     ReallySigned(*imm)),
             Instr::St   { rs3, rs1, imm } => write!(f, "st {} {} {:#0x}", rs3, rs1, 
                                                     ReallySigned(*imm)),
+            Instr::Stnt { rs3, rs1, imm } => write!(f, "stnt {} {} {:#0x}", rs3, rs1, 
+                                                    ReallySigned(*imm)),
             Instr::Bne  { rs3, rs1, imm } => write!(f, "bne {} {} {:#0x}", rs3, rs1, 
                                                     ReallySigned(*imm)),
             Instr::Beq  { rs3, rs1, imm } => write!(f, "beq {} {} {:#0x}", rs3, rs1, 
@@ -265,6 +272,7 @@ impl Instr {
             Instr::Stb  { rs3, .. }   | // Store instructions can write to `rs3` for mmio operations
             Instr::Sth  { rs3, .. }   |
             Instr::St   { rs3, .. }   |
+            Instr::Stnt { rs3, .. }   |
             Instr::Ld   { rs3, .. }   => {
                 vec![*rs3]
             },
@@ -315,6 +323,7 @@ impl Instr {
             Instr::Bne  { rs3, rs1, .. } |
             Instr::Stb  { rs3, rs1, .. } |
             Instr::Sth  { rs3, rs1, .. } |
+            Instr::Stnt { rs3, rs1, .. } |
             Instr::St { rs3, rs1, .. }   => {
                 vec![*rs3, *rs1]
             },
@@ -343,7 +352,7 @@ pub fn decode_instr(instr: u32) -> Result<Instr, SimErr> {
     let imm    = extract_imm(instr);
 
     if let Ok(instr_code) = InstrCode::try_from(extract_opcode(instr)) {
-        match instr_code {
+        let instr = match instr_code {
             InstrCode::Add  => Ok(Instr::Add  { rs3, rs1, rs2 }),
             InstrCode::Sub  => Ok(Instr::Sub  { rs3, rs1, rs2 }),
             InstrCode::Xor  => Ok(Instr::Xor  { rs3, rs1, rs2 }),
@@ -364,6 +373,7 @@ pub fn decode_instr(instr: u32) -> Result<Instr, SimErr> {
             InstrCode::Stb  => Ok(Instr::Stb  { rs3, rs1, imm }),
             InstrCode::Sth  => Ok(Instr::Sth  { rs3, rs1, imm }),
             InstrCode::St   => Ok(Instr::St   { rs3, rs1, imm }),
+            InstrCode::Stnt => Ok(Instr::Stnt { rs3, rs1, imm }),
             InstrCode::Bne  => Ok(Instr::Bne  { rs3, rs1, imm }),
             InstrCode::Beq  => Ok(Instr::Beq  { rs3, rs1, imm }),
             InstrCode::Blt  => Ok(Instr::Blt  { rs3, rs1, imm }),
@@ -374,7 +384,13 @@ pub fn decode_instr(instr: u32) -> Result<Instr, SimErr> {
             InstrCode::Int0 => Ok(Instr::Int0 { }),
             InstrCode::Ret  => Ok(Instr::Ret  { }),
             InstrCode::Nop  => Ok(Instr::Nop  { }),
-        } 
+        }?;
+
+        // Register fields are 5 bits wide, but only 16 registers exist
+        if instr.uses_regs().into_iter().chain(instr.writes_to_rs3()).any(|r| r == Register::None) {
+            return Err(SimErr::InstrDecode);
+        }
+        Ok(instr)
     } else {
         //println!("+====================================+");
         //println!("Failed to decode");
@@ -385,6 +401,51 @@ pub fn decode_instr(instr: u32) -> Result<Instr, SimErr> {
     }
 }
 
+/// Number of entries in a `DecodeCache`
+pub const DECODE_CACHE_SIZE: usize = 4096;
+
+/// Direct-mapped cache of decoded instructions, indexed by the address they were fetched from.
+/// Entries keep the raw word they were decoded from and are only hit if it matches, so code that
+/// gets overwritten is decoded again without entries having to be invalidated on writes
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    /// `(addr, raw, instr)` of the most recent decode per set
+    entries: Vec<Option<(u32, u32, Instr)>>,
+
+    pub hits: u64,
+
+    pub misses: u64,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            entries: vec![None; DECODE_CACHE_SIZE],
+            hits:    0,
+            misses:  0,
+        }
+    }
+}
+
+impl DecodeCache {
+    /// Decode `raw`, which was fetched from `addr`
+    pub fn decode(&mut self, addr: u32, raw: u32) -> Result<Instr, SimErr> {
+        let entry = &mut self.entries[(addr as usize >> 2) % DECODE_CACHE_SIZE];
+        match entry {
+            Some((hit_addr, hit_raw, instr)) if *hit_addr == addr && *hit_raw == raw => {
+                self.hits += 1;
+                Ok(*instr)
+            },
+            _ => {
+                self.misses += 1;
+                let instr = decode_instr(raw)?;
+                *entry = Some((addr, raw, instr));
+                Ok(instr)
+            },
+        }
+    }
+}
+
 /// Extract the bits representing the instr `opcode` from the provided value
 fn extract_opcode(val: u32) -> u32 {
     val >> 26
@@ -417,3 +478,40 @@ fn extract_offset(val: u32) -> i32 {
     (((val & 0x1fffff) as i32) << 11) >> 11
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_cache_checks_the_raw_word() {
+        let add  = (InstrCode::Add as u32) << 26 | 0x1234;
+        let addi = (InstrCode::Addi as u32) << 26 | 0x1234;
+        let mut cache = DecodeCache::default();
+
+        assert_eq!(cache.decode(0x1000, add), decode_instr(add));
+        assert_eq!(cache.decode(0x1000, add), decode_instr(add));
+        assert_eq!((cache.hits, cache.misses), (1, 1));
+
+        // Overwritten code and addresses that map to the same set are decoded again
+        assert_eq!(cache.decode(0x1000, addi), decode_instr(addi));
+        assert_eq!(cache.decode(0x1000 + 4 * DECODE_CACHE_SIZE as u32, add), decode_instr(add));
+        assert_eq!((cache.hits, cache.misses), (1, 3));
+        assert_eq!(cache.decode(0x2000, u32::MAX), Err(SimErr::InstrDecode));
+    }
+
+    #[test]
+    fn rejects_register_fields_past_the_register_file() {
+        let add = |rs3: u32, rs1: u32, rs2: u32|
+            (InstrCode::Add as u32) << 26 | rs3 << 21 | rs1 << 16 | rs2 << 11;
+        assert_eq!(decode_instr(add(3, 1, 15)),
+                   Ok(Instr::Add { rs3: Register::R3, rs1: Register::R1, rs2: Register::R15 }));
+        assert_eq!(decode_instr(add(16, 1, 2)), Err(SimErr::InstrDecode));
+        assert_eq!(decode_instr(add(3, 31, 2)), Err(SimErr::InstrDecode));
+        assert_eq!(decode_instr(add(3, 1, 16)), Err(SimErr::InstrDecode));
+
+        // Fields that overlap an instruction's immediate are not registers
+        let addi = (InstrCode::Addi as u32) << 26 | 3 << 21 | 1 << 16 | 0xffff;
+        assert!(decode_instr(addi).is_ok());
+    }
+}
@@ -0,0 +1,470 @@
+use crate::{
+    simulator::Simulator,
+    mmu::{VAddr, PageDirEntry, FrameAllocator, MemBus},
+    mmio::MmioMap,
+    pipeline::Pipeline,
+    ooo::OooEngine,
+    cpu::Instr,
+    logger::Logger,
+    Stats,
+};
+
+use rustc_hash::FxHashMap;
+use serde::{Serialize, Deserialize};
+
+use std::collections::VecDeque;
+use std::io;
+
+/// Maximum number of frames kept on the shadow call stack, the oldest frames are dropped once
+/// deeper recursion exceeds it
+pub const MAX_CALL_FRAMES: usize = 1024;
+
+/// Cycles a run may take by default before it is considered stuck
+pub const DEFAULT_RUN_CYCLE_LIMIT: u32 = 100_000_000;
+
+/// Maximum number of cycles the run-until, step-over and step-out commands run for
+pub const DEBUG_CYCLE_LIMIT: u32 = 10_000_000;
+
+/// Limits that stop a run that is presumably stuck, eg. in an infinite loop. Both count from the
+/// start of the run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunLimits {
+    pub max_cycles: Option<u32>,
+
+    pub max_instrs: Option<u64>,
+}
+
+impl Default for RunLimits {
+    fn default() -> Self {
+        Self {
+            max_cycles: Some(DEFAULT_RUN_CYCLE_LIMIT),
+            max_instrs: None,
+        }
+    }
+}
+
+/// Progress of the simulator when a run started, the limits are checked against it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunStart {
+    clock: u32,
+
+    instrs: u64,
+}
+
+impl RunStart {
+    /// Whether `sim` didn't advance since the run started
+    pub fn at_start(&self, sim: &Simulator) -> bool {
+        sim.clock == self.clock
+    }
+}
+
+impl RunLimits {
+    /// Remember where a run starts
+    pub fn start(sim: &Simulator) -> RunStart {
+        RunStart { clock: sim.clock, instrs: sim.stats.total_instrs }
+    }
+
+    /// Message describing the limit the run that began at `start` exceeded, if any
+    pub fn exceeded(&self, sim: &Simulator, start: &RunStart) -> Option<String> {
+        let cycles = sim.clock - start.clock;
+        let instrs = sim.stats.total_instrs - start.instrs;
+        match (self.max_cycles, self.max_instrs) {
+            (Some(max), _) if cycles >= max => {
+                Some(format!("Run stopped after reaching the limit of {} cycles", max))
+            },
+            (_, Some(max)) if instrs >= max => {
+                Some(format!("Run stopped after reaching the limit of {} instructions", max))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Function call on the shadow call stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallFrame {
+    /// Address of the `call` instruction
+    pub call_site: VAddr,
+
+    /// Address of the called function
+    pub target: VAddr,
+}
+
+impl CallFrame {
+    /// Address execution continues at once the called function returns
+    pub fn return_addr(&self) -> VAddr {
+        VAddr(self.call_site.0 + 4)
+    }
+}
+
+/// Reason a debugger command stopped running the simulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugStop {
+    /// The command completed
+    Reached,
+
+    /// Execution reached a breakpoint first
+    Breakpoint,
+
+    /// The simulator went offline first
+    Offline,
+
+    /// The cycle limit elapsed first
+    CycleLimit,
+}
+
+/// State captured when execution stopped at a breakpoint, see `restart_from_stop`. Like an
+/// `UndoRecord`, memory is not copied here. Instead, pages are backed up in `Mmu::page_backups`
+/// the first time they are modified after the stop
+#[derive(Debug, Clone)]
+pub struct StopCheckpoint {
+    pub clock: u32,
+
+    pub pc: VAddr,
+
+    pub gen_regs: [u32; 16],
+
+    pub online: bool,
+
+    pub sleep_until: Option<u32>,
+
+    pub pipeline: Pipeline,
+
+    pub ooo: OooEngine,
+
+    pub stats: Stats,
+
+    pub cpi_samples: VecDeque<(u32, u64)>,
+
+    pub call_depth: i32,
+
+    pub call_stack: Vec<CallFrame>,
+
+    /// Mappings at the stop, so pages mapped or unmapped since can be undone along with memory
+    pub page_table: Vec<Option<PageDirEntry>>,
+
+    pub frame_allocator: FrameAllocator,
+
+    /// Ram requests in flight, they are timed by the clock
+    pub bus: MemBus,
+
+    pub devices: MmioMap,
+}
+
+impl Simulator {
+    /// Push a frame onto the shadow call stack for the call at `call_site` to `target`
+    pub(crate) fn enter_call(&mut self, call_site: VAddr, target: VAddr) {
+        self.call_depth += 1;
+        if self.call_stack.len() == MAX_CALL_FRAMES {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(CallFrame { call_site, target });
+    }
+
+    /// Pop the innermost frame off the shadow call stack after a return
+    pub(crate) fn leave_call(&mut self) {
+        self.call_depth -= 1;
+        self.call_stack.pop();
+    }
+
+    /// Name of the closest symbol at or below `addr`, with the offset from it if there is one
+    pub fn symbolize(&self, addr: VAddr) -> String {
+        match self.symbols.range(..=addr.0).next_back() {
+            Some((start, name)) if *start == addr.0 => name.clone(),
+            Some((start, name)) => format!("{}+{:#x}", name, addr.0 - start),
+            None => format!("{:#x}", addr.0),
+        }
+    }
+
+    /// Current backtrace, innermost function first. Lists the pc for the innermost function, and
+    /// the return address for each of its callers
+    pub fn backtrace(&self) -> Vec<String> {
+        let mut pc = self.pc;
+        let mut lines = Vec::new();
+        for (depth, frame) in self.call_stack.iter().rev().enumerate() {
+            lines.push(format!("#{:<3} {:#010x} in {}", depth, pc.0, self.symbolize(pc)));
+            pc = frame.return_addr();
+        }
+        lines.push(format!("#{:<3} {:#010x} in {}", self.call_stack.len(), pc.0,
+                           self.symbolize(pc)));
+        lines
+    }
+    /// Capture the current state as the checkpoint `restart_from_stop` returns to. Memory written
+    /// from here on is backed up a page at a time
+    pub(crate) fn checkpoint_stop(&mut self) {
+        self.last_stop = Some(Box::new(StopCheckpoint {
+            clock:           self.clock,
+            pc:              self.pc,
+            gen_regs:        self.gen_regs,
+            online:          self.online,
+            sleep_until:     self.sleep_until,
+            pipeline:        self.pipeline.clone(),
+            ooo:             self.ooo.clone(),
+            stats:           self.stats.clone(),
+            cpi_samples:     self.cpi_samples.clone(),
+            call_depth:      self.call_depth,
+            call_stack:      self.call_stack.clone(),
+            page_table:      self.mmu.page_table.clone(),
+            frame_allocator: self.mmu.frame_allocator.clone(),
+            bus:             self.mmu.bus.clone(),
+            devices:         self.devices.clone(),
+        }));
+        self.mmu.page_backups = Some(FxHashMap::default());
+    }
+
+    /// Return to the state captured when execution last stopped at a breakpoint, so the same
+    /// region can be run again, eg. after modifying memory or registers. Registers, the pipeline,
+    /// stats, devices and memory are restored, caches start out cold. Breakpoints, hooks and run
+    /// limits are kept, the undo history is cleared and traces are stopped since the clock jumps.
+    /// The checkpoint is kept, so this can be repeated. Returns `false` if there is no checkpoint
+    pub fn restart_from_stop(&mut self) -> io::Result<bool> {
+        let Some(checkpoint) = self.last_stop.as_deref() else {
+            return Ok(false);
+        };
+        let checkpoint = checkpoint.clone();
+        self.stop_pipeline_trace()?;
+        self.stop_exec_trace()?;
+
+        self.mmu.restore_pages();
+        self.mmu.page_table      = checkpoint.page_table;
+        self.mmu.frame_allocator = checkpoint.frame_allocator;
+        self.mmu.bus             = checkpoint.bus;
+        self.devices             = checkpoint.devices;
+
+        self.clock       = checkpoint.clock;
+        self.pc          = checkpoint.pc;
+        self.gen_regs    = checkpoint.gen_regs;
+        self.online      = checkpoint.online;
+        self.sleep_until = checkpoint.sleep_until;
+        self.pipeline    = checkpoint.pipeline;
+        self.ooo         = checkpoint.ooo;
+        self.stats       = checkpoint.stats;
+        self.cpi_samples = checkpoint.cpi_samples;
+        self.call_depth  = checkpoint.call_depth;
+        self.call_stack  = checkpoint.call_stack;
+
+        self.timeline.rewind(self.clock);
+        if let Some(history) = self.pipeline_history.as_mut() {
+            history.rewind(self.clock);
+        }
+        if let Some(history) = self.reg_history.as_mut() {
+            history.rewind(self.clock);
+        }
+        if let Some(log) = self.retire_log.as_mut() {
+            log.rewind(self.clock);
+        }
+        if let Some(log) = self.undo_log.as_mut() {
+            log.clear();
+        }
+        // The golden model can't be rewound
+        self.golden = None;
+        Ok(true)
+    }
+
+    /// Step until `done` holds, for at most `max_cycles` cycles. Breakpoints stop the run once the
+    /// pc left the address it started at
+    fn run_while_not(&mut self, max_cycles: u32, err_log: &dyn Logger,
+                     done: impl Fn(&Simulator) -> bool) -> DebugStop {
+        let start = self.pc;
+        let mut moved = false;
+        for _ in 0..max_cycles {
+            if !self.online {
+                return DebugStop::Offline;
+            }
+            self.step(err_log);
+            if done(self) {
+                return DebugStop::Reached;
+            }
+            moved |= self.pc != start;
+            if moved && self.at_breakpoint() {
+                return DebugStop::Breakpoint;
+            }
+        }
+        DebugStop::CycleLimit
+    }
+
+    /// Run until execution reaches a breakpoint or the simulator goes offline
+    pub fn resume(&mut self, max_cycles: u32, err_log: &dyn Logger) -> DebugStop {
+        self.run_while_not(max_cycles, err_log, |_| false)
+    }
+
+    /// Run until the pc reaches `addr`
+    pub fn run_until(&mut self, addr: VAddr, max_cycles: u32, err_log: &dyn Logger)
+            -> DebugStop {
+        self.run_while_not(max_cycles, err_log, |sim| sim.pc == addr)
+    }
+
+    /// Execute the next instruction, until the pc moves past it. If it is a call, run until the
+    /// called function returned
+    pub fn step_over(&mut self, max_cycles: u32, err_log: &dyn Logger) -> DebugStop {
+        let depth = self.call_depth;
+        if let Ok(Instr::Call { .. }) = self.gui_decode_instr(self.pc) {
+            // Wait for the call to execute, then for the matching return
+            let called = std::cell::Cell::new(false);
+            return self.run_while_not(max_cycles, err_log, |sim| {
+                called.set(called.get() || sim.call_depth > depth);
+                called.get() && sim.call_depth <= depth
+            });
+        }
+
+        let pc = self.pc;
+        self.run_while_not(max_cycles, err_log, |sim| sim.pc != pc)
+    }
+
+    /// Run until the current function returned to its caller
+    pub fn step_out(&mut self, max_cycles: u32, err_log: &dyn Logger) -> DebugStop {
+        let depth = self.call_depth;
+        self.run_while_not(max_cycles, err_log, |sim| sim.call_depth < depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::MsgLog, simulator::DEFAULT_STACK_BASE, cpu::Register};
+
+    /// `_start` calls `.outer`, which calls `.inner` twice
+    const PROGRAM: &str = "
+        .entry _start
+        .load 0x20000
+        .inner
+            addi r1 r1 0x1
+            ret
+        .end_section
+        .load 0x30000
+        .outer
+            call 0x20000
+            call 0x20000
+            movi r2 0x7
+            ret
+        .end_section
+        .load 0x10000
+        ._start
+            movi r1 0x0
+            call 0x30000
+            movi r3 0x9
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+    ";
+
+    fn setup(ooo: bool) -> Simulator {
+        let mut sim = Simulator::new();
+        sim.set_out_of_order(ooo);
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(PROGRAM, &MsgLog::default()).unwrap();
+        sim
+    }
+
+    #[test]
+    fn steps_over_and_out_of_calls() {
+        for ooo in [false, true] {
+            let log = MsgLog::default();
+            let mut sim = setup(ooo);
+            assert_eq!(sim.run_until(VAddr(0x30000), 1_000, &log), DebugStop::Reached);
+            assert_eq!(sim.read_reg(Register::R1), 0);
+
+            // Stepping over the first nested call runs it completely
+            assert_eq!(sim.step_over(1_000, &log), DebugStop::Reached);
+            assert_eq!((sim.call_depth, sim.read_reg(Register::R1)), (1, 1));
+
+            // Stepping out of `.outer` skips the second call
+            assert_eq!(sim.step_out(1_000, &log), DebugStop::Reached);
+            assert_eq!(sim.call_depth, 0);
+            assert_eq!((sim.read_reg(Register::R1), sim.read_reg(Register::R2)), (2, 7));
+            assert_eq!(sim.read_reg(Register::R3), 0, "ooo: {}", ooo);
+
+            assert_eq!(sim.run_until(VAddr(0x40000), 1_000, &log), DebugStop::Offline);
+        }
+    }
+
+    #[test]
+    fn backtrace_follows_calls_and_returns() {
+        for ooo in [false, true] {
+            let log = MsgLog::default();
+            let mut sim = setup(ooo);
+            assert_eq!(sim.symbolize(VAddr(0x10004)), "_start+0x4");
+
+            assert_eq!(sim.run_until(VAddr(0x20000), 1_000, &log), DebugStop::Reached);
+            assert_eq!(sim.call_stack, [
+                CallFrame { call_site: VAddr(0x10004), target: VAddr(0x30000) },
+                CallFrame { call_site: VAddr(0x30000), target: VAddr(0x20000) },
+            ]);
+            assert_eq!(sim.backtrace(), [
+                "#0   0x00020000 in inner",
+                "#1   0x00030004 in outer+0x4",
+                "#2   0x00010008 in _start+0x8",
+            ]);
+
+            sim.step_out(1_000, &log);
+            sim.step_out(1_000, &log);
+            assert!(sim.call_stack.is_empty(), "ooo: {}", ooo);
+            assert_eq!(sim.backtrace(), ["#0   0x00010008 in _start+0x8"]);
+        }
+    }
+
+    #[test]
+    fn run_limits_count_from_the_start_of_the_run() {
+        let log = MsgLog::default();
+        let mut sim = setup(false);
+        sim.step(&log);
+
+        let limits = RunLimits { max_cycles: Some(100), max_instrs: Some(5) };
+        let start = RunLimits::start(&sim);
+        for _ in 0..99 {
+            sim.step(&log);
+        }
+        assert_eq!(limits.exceeded(&sim, &start), None);
+        sim.step(&log);
+        assert!(limits.exceeded(&sim, &start).unwrap().contains("100 cycles"));
+
+        let limits = RunLimits { max_cycles: None, ..limits };
+        let start = RunLimits::start(&sim);
+        while limits.exceeded(&sim, &start).is_none() {
+            sim.step(&log);
+        }
+        assert!(limits.exceeded(&sim, &start).unwrap().contains("5 instructions"));
+        assert_eq!(sim.stats.total_instrs - start.instrs, 5);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_cycle_limit() {
+        let log = MsgLog::default();
+        let mut sim = setup(false);
+        sim.breakpoints.insert(0x20000, 0);
+        assert_eq!(sim.step_out(1_000, &log), DebugStop::Breakpoint);
+        assert_eq!(sim.pc, VAddr(0x20000));
+        assert_eq!(sim.run_until(VAddr(0x50000), 3, &log), DebugStop::CycleLimit);
+    }
+
+    #[test]
+    fn restarts_from_the_last_breakpoint_stop() {
+        let log = MsgLog::default();
+        let mut sim = setup(false);
+        assert!(!sim.restart_from_stop().unwrap());
+
+        sim.breakpoints.insert(0x30000, 0);
+        assert_eq!(sim.run_until(VAddr(0x50000), 1_000, &log), DebugStop::Breakpoint);
+        let (clock, regs) = (sim.clock, sim.gen_regs);
+
+        // The calls of `.outer` push return addresses below the stack pointer
+        let pushed = VAddr(sim.read_reg(Register::R15) - 4);
+        let mut word = [0u8; 4];
+
+        // Tweak a register and run the region to the end, then go back and run it again
+        for start in [5, 9] {
+            assert!(sim.restart_from_stop().unwrap());
+            assert_eq!((sim.pc, sim.clock, sim.gen_regs), (VAddr(0x30000), clock, regs));
+            assert!(sim.breakpoints.contains_key(&0x30000));
+            sim.gui_mem_read(pushed, &mut word).unwrap();
+            assert_eq!(word, [0; 4]);
+
+            sim.write_reg(Register::R1, start);
+            sim.run_until(VAddr(0x10008), 1_000, &log);
+            assert_eq!(sim.read_reg(Register::R1), start + 2);
+            sim.gui_mem_read(pushed, &mut word).unwrap();
+            assert_ne!(word, [0; 4]);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    simulator::{Simulator, DEFAULT_STACK_BASE},
+    mmu::{VAddr, FrameAllocator, MemChange},
+    cpu::{Instr, Register, NUM_REGS},
+    logger::Logger,
+};
+
+use std::cell::RefCell;
+use std::fmt;
+
+/// Collects the messages reported while a program runs outside of the gui
+#[derive(Debug, Default)]
+pub struct MsgLog {
+    /// Reported errors, oldest first
+    pub errors: RefCell<Vec<String>>,
+
+    /// Reported log messages, oldest first
+    pub logs: RefCell<Vec<String>>,
+}
+
+impl Logger for MsgLog {
+    fn err(&self, msg: &str) {
+        self.errors.borrow_mut().push(msg.to_string());
+    }
+
+    fn log(&self, msg: &str) {
+        self.logs.borrow_mut().push(msg.to_string());
+    }
+}
+
+/// Instruction that completed writeback, along with the register values it wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retired {
+    /// Address of the instruction
+    pub pc: VAddr,
+
+    /// The instruction itself
+    pub instr: Instr,
+
+    /// Values of the registers the instruction writes, right after it completed writeback
+    pub writes: Vec<(Register, u32)>,
+}
+
+/// Result of running a program until it stopped or hit the cycle limit
+#[derive(Debug)]
+pub struct Run {
+    /// Simulator state at the end of the run
+    pub sim: Simulator,
+
+    /// Instructions in the order they completed writeback
+    pub retired: Vec<Retired>,
+
+    /// Messages reported during the run
+    pub log: MsgLog,
+}
+
+/// Load `program` into a fresh simulator with the default memory layout, and step it until it
+/// goes offline or `max_cycles` elapsed. `setup` can configure the simulator before the program is
+/// loaded. Physical frames are allocated sequentially so runs are reproducible
+pub fn run_program(program: &str, pipelined: bool, max_cycles: u32, setup: &dyn Fn(&mut Simulator))
+        -> Run {
+    let log = MsgLog::default();
+    let mut sim = Simulator::new();
+    sim.mmu.frame_allocator = FrameAllocator::sequential();
+    sim.pipelining_enabled  = pipelined;
+    setup(&mut sim);
+    sim.map_default_layout(DEFAULT_STACK_BASE).expect("Failed to map default layout");
+    if sim.load_input(program, &log).is_err() {
+        return Run { sim, retired: Vec::new(), log };
+    }
+
+    let mut retired = Vec::new();
+    while sim.online && sim.clock < max_cycles {
+        let wb = &sim.pipeline.slots[4];
+        let pending = wb.valid.then_some((wb.seq, wb.pc, wb.instr));
+
+        sim.step(&log);
+
+        // The instruction in writeback completed if it did not stay there due to a stall
+        if let Some((seq, pc, instr)) = pending {
+            let wb = &sim.pipeline.slots[4];
+            if !wb.valid || wb.seq != seq {
+                let writes = instr.writes_to_rs3().into_iter()
+                    .map(|reg| (reg, sim.read_reg(reg)))
+                    .collect();
+                retired.push(Retired { pc, instr, writes });
+            }
+        }
+    }
+    Run { sim, retired, log }
+}
+
+/// First difference between a pipelined and a non-pipelined run of the same program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The `idx`th retired instruction differs. Either side is `None` if that run retired fewer
+    /// instructions
+    Retired { idx: usize, pipelined: Option<Retired>, sequential: Option<Retired> },
+
+    /// Both runs retired the same instructions, but ended with different register values
+    Registers { regs: Vec<(Register, u32, u32)> },
+
+    /// Both runs retired the same instructions, but ended with different memory contents. `old`
+    /// holds the non-pipelined value, `new` the pipelined one
+    Memory { changes: Vec<MemChange> },
+}
+
+impl Divergence {
+    /// Pc of the first instruction the runs disagree on, if the divergence can be tied to one
+    pub fn pc(&self) -> Option<VAddr> {
+        match self {
+            Divergence::Retired { pipelined, sequential, .. } => {
+                pipelined.as_ref().or(sequential.as_ref()).map(|r| r.pc)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::Retired { idx, pipelined, sequential } => {
+                let show = |r: &Option<Retired>| match r {
+                    Some(r) => format!("{:#x}: {} {:x?}", r.pc.0, r.instr, r.writes),
+                    None    => String::from("<nothing>"),
+                };
+                write!(f, "Retired instruction #{} differs\n  pipelined:     {}\n  \
+                       non-pipelined: {}", idx, show(pipelined), show(sequential))
+            },
+            Divergence::Registers { regs } => {
+                write!(f, "Final registers differ (reg, pipelined, non-pipelined): {:x?}", regs)
+            },
+            Divergence::Memory { changes } => {
+                write!(f, "Final memory differs at {} words, first at {:#x}", changes.len(),
+                       changes[0].addr.0)
+            },
+        }
+    }
+}
+
+/// Run `program` with the pipeline enabled and disabled and compare the instructions they
+/// retire and their final register and memory state. Both runs are limited to `max_cycles`, and
+/// configured by `setup` as in `run_program`. Returns `None` if the runs agree
+pub fn diff_pipeline_modes(program: &str, max_cycles: u32, setup: &dyn Fn(&mut Simulator))
+        -> Option<Divergence> {
+    let pipelined  = run_program(program, true, max_cycles, setup);
+    let sequential = run_program(program, false, max_cycles, setup);
+
+    let len = pipelined.retired.len().max(sequential.retired.len());
+    for idx in 0..len {
+        let (a, b) = (pipelined.retired.get(idx), sequential.retired.get(idx));
+        if a != b {
+            return Some(Divergence::Retired { idx, pipelined: a.cloned(),
+                                              sequential: b.cloned() });
+        }
+    }
+
+    let regs = (0..NUM_REGS as u32).map(Register::from)
+        .map(|reg| (reg, pipelined.sim.read_reg(reg), sequential.sim.read_reg(reg)))
+        .filter(|(_, a, b)| a != b)
+        .collect::<Vec<_>>();
+    if !regs.is_empty() {
+        return Some(Divergence::Registers { regs });
+    }
+
+    let changes = pipelined.sim.mmu.diff(&sequential.sim.mmu.snapshot());
+    if !changes.is_empty() {
+        return Some(Divergence::Memory { changes });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predictor::{BranchPredictor, PredictorKind};
+
+    /// Registers an exit handler, then loops over a stack buffer with calls, loads, stores and
+    /// dependent arithmetic, followed by a jump and a not-taken branch
+    const LOOP_PROGRAM: &str = "
+        .entry _start
+
+        .load 0x40000
+        .exit_handler
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+
+        .load 0x20000
+        .square
+            mul r1 r1 r1
+            ret
+        .end_section
+
+        .load 0x10000
+        ._start
+            lui r1 0x40
+            st r1 r0 0x0
+            subi r15 r15 0x100
+            mov r12 r15
+            movi r4 0x0
+            movi r5 0x40
+            movi r7 0x0
+        .loop
+            mov r1 r4
+            call 0x20000
+            add r6 r12 r4
+            st r1 r6 0x0
+            ld r2 r6 0x0
+            add r7 r7 r2
+            addi r4 r4 0x4
+            blt r4 r5 .loop
+            jmpr .skip
+            movi r7 0x0
+        .skip
+            beq r7 r0 .done
+            movi r8 0x1
+        .done
+            addi r15 r15 0x100
+            int0
+        .end_section
+    ";
+
+    #[test]
+    fn pipeline_modes_agree() {
+        let run = run_program(LOOP_PROGRAM, true, 200_000, &|_| {});
+        assert!(!run.sim.online, "program did not finish: {:?}", run.log);
+        assert_eq!(run.sim.read_reg(Register::R8), 1);
+
+        if let Some(divergence) = diff_pipeline_modes(LOOP_PROGRAM, 200_000, &|_| {}) {
+            panic!("{}", divergence);
+        }
+    }
+
+    #[test]
+    fn pipeline_modes_agree_with_prediction_and_nonblocking_loads() {
+        for kind in [PredictorKind::NotTaken, PredictorKind::BackwardTaken, PredictorKind::TwoBit] {
+            let setup = |sim: &mut Simulator| {
+                sim.pipeline.predictor = BranchPredictor::new(kind);
+                sim.nonblocking_cache  = true;
+            };
+            if let Some(divergence) = diff_pipeline_modes(LOOP_PROGRAM, 200_000, &setup) {
+                panic!("{:?}: {}", kind, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn pipeline_modes_agree_with_forwarding() {
+        for nonblocking in [false, true] {
+            let setup = |sim: &mut Simulator| {
+                sim.pipeline.predictor = BranchPredictor::new(PredictorKind::TwoBit);
+                sim.forwarding_enabled = true;
+                sim.nonblocking_cache  = nonblocking;
+            };
+            if let Some(divergence) = diff_pipeline_modes(LOOP_PROGRAM, 200_000, &setup) {
+                panic!("nonblocking {}: {}", nonblocking, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_first_diverging_pc() {
+        // The clock-counter the command-port writes to r1 differs between the modes
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x42
+                movi r2 0x2000
+                st r1 r2 0x0
+                add r3 r1 r0
+                add r4 r0 r0
+                movi r1 0x41
+                st r1 r2 0x0
+            .end_section
+        ";
+        let divergence = diff_pipeline_modes(program, 1_000, &|_| {}).unwrap();
+        assert_eq!(divergence.pc(), Some(VAddr(0x10008)), "{}", divergence);
+        assert!(matches!(divergence, Divergence::Retired { idx: 2, .. }));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    mmio::{MmioDevice, MmioAction, read_regs, write_regs},
+    mmu::VAddr,
+};
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+/// Address of the disk's registers, next to the uart
+pub const DISK_BASE: u32 = 0x2030;
+
+/// Size of the disk's register block
+pub const DISK_SIZE: u32 = 0x10;
+
+/// Interrupt-vector raised when a command completes, its handler is stored at address 0x10
+pub const DISK_IRQ: u32 = 4;
+
+/// Number of bytes transferred by a command
+pub const SECTOR_SIZE: usize = 512;
+
+/// Clock-cycles a command takes before its transfer happens
+pub const DISK_LATENCY: u32 = 200;
+
+/// Command that copies a sector into guest memory
+pub const DISK_CMD_READ: u32 = 1;
+
+/// Command that copies guest memory into a sector
+pub const DISK_CMD_WRITE: u32 = 2;
+
+/// Status bit that is set while a command is in progress
+pub const DISK_BUSY: u32 = 1 << 0;
+
+/// Status bit that is set if the last command was invalid or addressed a sector past the end of
+/// the disk
+pub const DISK_ERROR: u32 = 1 << 1;
+
+/// State shared between all clones of a `Disk`
+#[derive(Debug, Default)]
+struct DiskState {
+    /// Contents of the disk, a whole number of sectors
+    image: Vec<u8>,
+
+    /// Host file that written sectors are stored to
+    file: Option<File>,
+
+    sector:  u32,
+    buffer:  u32,
+    command: u32,
+    status:  u32,
+
+    /// Cycles until the command in progress transfers its sector. Once it did, the busy bit is
+    /// cleared on the next tick, after the transfer took effect
+    remaining: Option<u32>,
+}
+
+/// Block storage at `DISK_BASE`, backed by a host file. Its 32-bit registers are:
+///  - 0x0 Sector: Sector the next command transfers
+///  - 0x4 Buffer: Guest address the sector is copied to or from
+///  - 0x8 Command: Writing `DISK_CMD_READ` or `DISK_CMD_WRITE` starts a transfer
+///  - 0xc Status: `DISK_BUSY` and `DISK_ERROR`
+///
+/// After `DISK_LATENCY` cycles the sector is copied by dma and `DISK_IRQ` is raised. Writes are
+/// ignored while the disk is busy. Clones share the same state
+#[derive(Clone, Debug, Default)]
+pub struct Disk {
+    state: Rc<RefCell<DiskState>>,
+}
+
+impl Disk {
+    /// Disk with the contents of `image`, padded to a whole number of sectors. Writes are only
+    /// kept in memory
+    pub fn new(mut image: Vec<u8>) -> Self {
+        image.resize(image.len().next_multiple_of(SECTOR_SIZE), 0);
+        let state = DiskState { image, ..DiskState::default() };
+        Self { state: Rc::new(RefCell::new(state)) }
+    }
+
+    /// Disk backed by the image file at `path`, sectors the guest writes are stored to the file
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let disk = Self::new(std::fs::read(path)?);
+        disk.state.borrow_mut().file = Some(File::options().write(true).open(path)?);
+        Ok(disk)
+    }
+
+    /// Current contents of the disk
+    pub fn image(&self) -> Vec<u8> {
+        self.state.borrow().image.clone()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        let state = self.state.borrow();
+        [state.sector, state.buffer, state.command, state.status]
+    }
+}
+
+impl MmioDevice for Disk {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value. The
+    /// status can't be written
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        let mut state = self.state.borrow_mut();
+        if state.status & DISK_BUSY != 0 {
+            return None;
+        }
+        state.sector = regs[0];
+        state.buffer = regs[1];
+        if (offset..offset + data.len()).contains(&8) {
+            state.command   = regs[2];
+            state.status    = DISK_BUSY;
+            state.remaining = Some(DISK_LATENCY);
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        match state.remaining {
+            None => {
+                state.status &= !DISK_BUSY;
+                return Vec::new();
+            },
+            Some(0) => state.remaining = None,
+            Some(remaining) => {
+                state.remaining = Some(remaining - 1);
+                return Vec::new();
+            },
+        }
+
+        let start = state.sector as usize * SECTOR_SIZE;
+        let addr  = VAddr(state.buffer);
+        match state.command {
+            _ if start + SECTOR_SIZE > state.image.len() => state.status |= DISK_ERROR,
+            DISK_CMD_READ => {
+                let data = state.image[start..start + SECTOR_SIZE].to_vec();
+                return vec![MmioAction::Dma { addr, data }, MmioAction::RaiseIrq];
+            },
+            DISK_CMD_WRITE => {
+                let len = SECTOR_SIZE as u32;
+                return vec![MmioAction::DmaRead { addr, len }, MmioAction::RaiseIrq];
+            },
+            _ => state.status |= DISK_ERROR,
+        }
+        vec![MmioAction::RaiseIrq]
+    }
+
+    fn dma_read(&mut self, _addr: VAddr, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let start = state.sector as usize * SECTOR_SIZE;
+        state.image[start..start + data.len()].copy_from_slice(data);
+        if let Some(file) = state.file.as_mut() {
+            let stored = file.seek(SeekFrom::Start(start as u64))
+                .and_then(|_| file.write_all(data));
+            if let Err(err) = stored {
+                eprintln!("Failed to store disk sector {}: {}", start / SECTOR_SIZE, err);
+            }
+        }
+    }
+
+    /// Pending commands are dropped, the contents of the disk are kept
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let (image, file) = (std::mem::take(&mut state.image), state.file.take());
+        *state = DiskState { image, file, ..DiskState::default() };
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::{Simulator, DEFAULT_STACK_BASE},
+        difftest::MsgLog,
+        mmio::read_reg,
+        mmu::Perms,
+        cpu::Register,
+    };
+
+    #[test]
+    fn commands_finish_after_the_latency() {
+        let mut disk = Disk::new(vec![7; 10]);
+        assert_eq!(disk.image().len(), SECTOR_SIZE);
+
+        disk.write(0x4, &0x3000u32.to_le_bytes(), 0);
+        disk.write(0x8, &[DISK_CMD_READ as u8], 0);
+        assert_eq!(read_reg(&mut disk, 0xc), DISK_BUSY);
+
+        // Registers can't be changed while the disk is busy
+        disk.write(0x0, &[1], 0);
+        assert_eq!(disk.regs()[0], 0);
+
+        assert!((0..DISK_LATENCY).all(|clock| disk.tick(clock).is_empty()));
+        let mut data = vec![7; 10];
+        data.resize(SECTOR_SIZE, 0);
+        assert_eq!(disk.tick(DISK_LATENCY), [MmioAction::Dma { addr: VAddr(0x3000), data },
+                                             MmioAction::RaiseIrq]);
+        assert_eq!(disk.regs()[3], DISK_BUSY);
+        assert!(disk.tick(DISK_LATENCY + 1).is_empty());
+        assert_eq!(disk.regs()[3], 0);
+
+        // Sectors past the end of the disk fail
+        disk.write(0x0, &[1], 0);
+        disk.write(0x8, &[DISK_CMD_READ as u8], 0);
+        let actions = (0..=DISK_LATENCY).flat_map(|clock| disk.tick(clock)).collect::<Vec<_>>();
+        assert_eq!(actions, [MmioAction::RaiseIrq]);
+        assert_eq!(disk.regs()[3], DISK_BUSY | DISK_ERROR);
+    }
+
+    /// Copy sector 0 to sector 1 through guest memory, waiting for the read by polling and for the
+    /// write by its interrupt. The read's interrupt was taken before the disk stopped being busy
+    #[test]
+    fn copies_sectors_through_guest_memory() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r6 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x10
+                movi r2 0x2030
+                movi r1 0x3000
+                st r1 r2 0x4
+                movi r1 0x1
+                st r1 r2 0x8
+            .poll
+                ld r3 r2 0xc
+                bne r3 r0 .poll
+                movi r6 0x0
+                movi r4 0x3000
+                ld r5 r4 0x0
+                addi r5 r5 0x1
+                st r5 r4 0x0
+                movi r1 0x1
+                st r1 r2 0x0
+                movi r1 0x2
+                st r1 r2 0x8
+            .wait
+                beq r6 r0 .wait
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let mut image = vec![0u8; SECTOR_SIZE * 2];
+        image[..4].copy_from_slice(&0x1234u32.to_le_bytes());
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            sim.map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+            let disk = Disk::new(image.clone());
+            sim.register_device(VAddr(DISK_BASE), DISK_SIZE, Some(DISK_IRQ),
+                                Box::new(disk.clone())).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert_eq!(sim.read_reg(Register::R5), 0x1235, "{}", mode);
+            assert_eq!(&disk.image()[SECTOR_SIZE..SECTOR_SIZE + 4], &0x1235u32.to_le_bytes(),
+                       "{}", mode);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    mmio::{MmioDevice, MmioAction, read_regs, write_regs},
+    mmu::{VAddr, CACHE_LINE_SIZE, RAM_STALL},
+};
+
+/// Address of the dma engine's registers, next to the interrupt controller
+pub const DMA_BASE: u32 = 0x2060;
+
+/// Size of the dma engine's register block
+pub const DMA_SIZE: u32 = 0x18;
+
+/// Interrupt-vector raised when a copy completes, its handler is stored at address 0x14
+pub const DMA_IRQ: u32 = 5;
+
+/// Number of bytes copied per step, a cache-line so every step is one ram request per direction
+pub const DMA_CHUNK: u32 = CACHE_LINE_SIZE as u32;
+
+/// Clock-cycles between two steps, the time the memory bus needs to read and write a chunk
+pub const DMA_CHUNK_CYCLES: u32 = 2 * RAM_STALL as u32;
+
+/// Control bit that starts a copy, ignored while one is in progress
+pub const DMA_START: u32 = 1 << 0;
+
+/// Status bit that is set while a copy is in progress
+pub const DMA_BUSY: u32 = 1 << 0;
+
+/// Status bit that is set when a copy completes, writing a 1 to it clears it
+pub const DMA_DONE: u32 = 1 << 1;
+
+/// Dma engine at `DMA_BASE` that copies blocks of memory in the background. Its 32-bit registers
+/// are:
+///  - 0x00 Source: Address the block is copied from
+///  - 0x04 Destination: Address the block is copied to
+///  - 0x08 Length: Size of the block in bytes
+///  - 0x0c Control: `DMA_START`
+///  - 0x10 Status: `DMA_BUSY` and `DMA_DONE`
+///  - 0x14 Copied: Number of bytes read from the source so far
+///
+/// Every `DMA_CHUNK_CYCLES` the engine writes the chunk it read in the previous step and reads the
+/// next one, so the copy runs alongside the program and competes with it for the memory bus. Once
+/// the last chunk is written `DMA_IRQ` is raised. Addresses are translated like the cpu's
+#[derive(Debug, Clone, Default)]
+pub struct DmaEngine {
+    source:      u32,
+    destination: u32,
+    length:      u32,
+    status:      u32,
+    copied:      u32,
+
+    /// Chunk read in the last step, written in the next one
+    buffer: Vec<u8>,
+
+    /// Cycles until the next step of the copy in progress. Once the last chunk was written, the
+    /// busy bit is cleared on the next tick, after the write took effect
+    countdown: Option<u32>,
+}
+
+impl DmaEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 6] {
+        [self.source, self.destination, self.length, 0, self.status, self.copied]
+    }
+}
+
+impl MmioDevice for DmaEngine {
+    fn name(&self) -> &str {
+        "dma"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value.
+    /// Writes are ignored while a copy is in progress, other than clearing `DMA_DONE`
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        let touched = |reg: usize| (offset..offset + data.len()).any(|o| o / 4 == reg);
+        if touched(4) {
+            self.status &= !(regs[4] & DMA_DONE);
+        }
+        if self.status & DMA_BUSY != 0 {
+            return None;
+        }
+        self.source      = regs[0];
+        self.destination = regs[1];
+        self.length      = regs[2];
+        if touched(3) && regs[3] & DMA_START != 0 {
+            self.status    = DMA_BUSY;
+            self.copied    = 0;
+            self.countdown = Some(0);
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        match self.countdown {
+            None => {
+                self.status &= !DMA_BUSY;
+                return Vec::new();
+            },
+            Some(0) => {},
+            Some(countdown) => {
+                self.countdown = Some(countdown - 1);
+                return Vec::new();
+            },
+        }
+
+        let mut actions = Vec::new();
+        if !self.buffer.is_empty() {
+            let data = std::mem::take(&mut self.buffer);
+            let addr = VAddr(self.destination.wrapping_add(self.copied - data.len() as u32));
+            actions.push(MmioAction::Dma { addr, data });
+        }
+        if self.copied < self.length {
+            let len = DMA_CHUNK.min(self.length - self.copied);
+            let addr = VAddr(self.source.wrapping_add(self.copied));
+            actions.push(MmioAction::DmaRead { addr, len });
+            self.copied   += len;
+            self.countdown = Some(DMA_CHUNK_CYCLES);
+        } else {
+            self.status   |= DMA_DONE;
+            self.countdown = None;
+            actions.push(MmioAction::RaiseIrq);
+        }
+        actions
+    }
+
+    fn dma_read(&mut self, _addr: VAddr, data: &[u8]) {
+        self.buffer = data.to_vec();
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmu::Perms, cpu::Register};
+
+    #[test]
+    fn copies_a_chunk_per_step() {
+        let mut dma = DmaEngine::new();
+        dma.write(0x0, &0x3000u32.to_le_bytes(), 0);
+        dma.write(0x4, &0x4000u32.to_le_bytes(), 0);
+        dma.write(0x8, &(DMA_CHUNK + 4).to_le_bytes(), 0);
+        dma.write(0xc, &[DMA_START as u8], 0);
+        assert_eq!(dma.regs()[4], DMA_BUSY);
+
+        let read = |addr, len| MmioAction::DmaRead { addr: VAddr(addr), len };
+        assert_eq!(dma.tick(0), [read(0x3000, DMA_CHUNK)]);
+        dma.dma_read(VAddr(0x3000), &[1; DMA_CHUNK as usize]);
+        assert!((1..=DMA_CHUNK_CYCLES).all(|clock| dma.tick(clock).is_empty()));
+
+        let data = vec![1; DMA_CHUNK as usize];
+        assert_eq!(dma.tick(DMA_CHUNK_CYCLES + 1), [MmioAction::Dma { addr: VAddr(0x4000), data },
+                                                    read(0x3000 + DMA_CHUNK, 4)]);
+        dma.dma_read(VAddr(0x3000 + DMA_CHUNK), &[2; 4]);
+
+        // The last chunk is written with the interrupt, the engine stays busy until it landed
+        let actions = (0..=DMA_CHUNK_CYCLES).flat_map(|clock| dma.tick(clock)).collect::<Vec<_>>();
+        let addr = VAddr(0x4000 + DMA_CHUNK);
+        assert_eq!(actions, [MmioAction::Dma { addr, data: vec![2; 4] }, MmioAction::RaiseIrq]);
+        assert_eq!(dma.regs()[4], DMA_BUSY | DMA_DONE);
+        dma.tick(0);
+        dma.write(0x10, &[DMA_DONE as u8], 0);
+        assert_eq!(dma.regs()[4], 0);
+    }
+
+    /// Copy a block in the background while the program counts, and wait for the interrupt
+    #[test]
+    fn copies_alongside_the_program() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r6 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x14
+                movi r3 0x3000
+                movi r4 0x40
+            .fill
+                subi r4 r4 0x4
+                add r5 r3 r4
+                st r4 r5 0x0
+                st r4 r5 0x40
+                st r4 r5 0x80
+                st r4 r5 0xc0
+                bne r4 r0 .fill
+                movi r2 0x2060
+                st r3 r2 0x0
+                movi r1 0x4000
+                st r1 r2 0x4
+                movi r1 0x100
+                st r1 r2 0x8
+                movi r1 0x1
+                st r1 r2 0xc
+            .count
+                addi r7 r7 0x1
+                beq r6 r0 .count
+                movi r4 0x4000
+                ld r8 r4 0xfc
+                ld r9 r2 0x10
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+                sim.map_page(VAddr(0x4000), Perms::READ | Perms::WRITE).unwrap();
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            let mut sim = run.sim;
+            let mut copied = [0u8; 0x100];
+            sim.gui_mem_read(VAddr(0x4000), &mut copied).unwrap();
+            let expected = (0..0x100u32).step_by(4).flat_map(|offset| (offset % 0x40).to_le_bytes())
+                .collect::<Vec<_>>();
+            assert_eq!(copied.to_vec(), expected, "{}", mode);
+            assert_eq!(sim.read_reg(Register::R8), 0x3c, "{}", mode);
+            assert_eq!(sim.read_reg(Register::R9) & DMA_DONE, DMA_DONE, "{}", mode);
+
+            // The program kept running during the copy, which took the memory bus for a ram
+            // request per chunk and direction
+            assert!(sim.read_reg(Register::R7) > 10, "{}", mode);
+            assert!(sim.stats.bus_busy_clock >= 8 * RAM_STALL as u64, "{}", mode);
+        }
+    }
+}
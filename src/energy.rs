@@ -0,0 +1,163 @@
+use crate::Stats;
+
+/// Energy charged per event, in nanojoules. The defaults are rough order-of-magnitude figures,
+/// meant for comparing configurations rather than predicting real hardware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnergyModel {
+    /// Per arithmetic instruction
+    pub alu_op: f64,
+
+    /// Per access serviced by the L1 cache
+    pub l1_hit: f64,
+
+    /// Per access serviced by the L2 cache
+    pub l2_hit: f64,
+
+    /// Per access that went to ram, including all accesses while the caches are disabled
+    pub dram_access: f64,
+
+    /// Leakage per clock-cycle, charged whether or not anything executes
+    pub static_cycle: f64,
+
+    /// Length of a clock-cycle in nanoseconds, used to turn cycles into the delay of the EDP
+    pub cycle_ns: f64,
+}
+
+impl Default for EnergyModel {
+    fn default() -> Self {
+        Self {
+            alu_op:       0.5,
+            l1_hit:       1.0,
+            l2_hit:       5.0,
+            dram_access:  50.0,
+            static_cycle: 0.2,
+            cycle_ns:     1.0,
+        }
+    }
+}
+
+/// Energy spent by a run, in nanojoules, split by where it was spent
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Energy {
+    pub alu: f64,
+
+    /// L1 and L2 hits
+    pub caches: f64,
+
+    pub dram: f64,
+
+    /// Leakage over all cycles of the run
+    pub leakage: f64,
+
+    /// Duration of the run in nanoseconds
+    pub delay_ns: f64,
+}
+
+impl Energy {
+    /// Total energy in nanojoules
+    pub fn total(&self) -> f64 {
+        self.alu + self.caches + self.dram + self.leakage
+    }
+
+    /// Energy-delay product in joule-seconds, lower is better
+    pub fn edp(&self) -> f64 {
+        self.total() * 1e-9 * self.delay_ns * 1e-9
+    }
+}
+
+impl EnergyModel {
+    /// Parse a comma-separated list of `key=nJ` pairs, eg. `alu=0.5,dram=80`. Keys are `alu`,
+    /// `l1`, `l2`, `dram`, `static` and `cycle` (in ns), unlisted ones keep their default
+    pub fn parse(config: &str) -> Option<Self> {
+        let mut model = Self::default();
+        for pair in config.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair.split_once('=')?;
+            let value = value.trim().parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0)?;
+            match key.trim() {
+                "alu"    => model.alu_op       = value,
+                "l1"     => model.l1_hit       = value,
+                "l2"     => model.l2_hit       = value,
+                "dram"   => model.dram_access  = value,
+                "static" => model.static_cycle = value,
+                "cycle"  => model.cycle_ns     = value,
+                _ => return None,
+            }
+        }
+        Some(model)
+    }
+
+    /// Energy of a run that took `cycles` and counted `stats`. Accesses that went to ram are the
+    /// L1 misses that didn't hit in the L2
+    pub fn estimate(&self, stats: &Stats, cycles: u64) -> Energy {
+        let dram_accesses = stats.cache_misses.saturating_sub(stats.l2_cache_hits);
+        Energy {
+            alu:      stats.arithmetic_instrs as f64 * self.alu_op,
+            caches:   stats.cache_hits as f64 * self.l1_hit
+                      + stats.l2_cache_hits as f64 * self.l2_hit,
+            dram:     dram_accesses as f64 * self.dram_access,
+            leakage:  cycles as f64 * self.static_cycle,
+            delay_ns: cycles as f64 * self.cycle_ns,
+        }
+    }
+}
+
+/// `nj` nanojoules with a unit that keeps the number short, eg. `12.35uJ`
+pub fn format_energy(nj: f64) -> String {
+    match nj {
+        nj if nj >= 1e9 => format!("{:.2}J", nj / 1e9),
+        nj if nj >= 1e6 => format!("{:.2}mJ", nj / 1e6),
+        nj if nj >= 1e3 => format!("{:.2}uJ", nj / 1e3),
+        nj              => format!("{:.2}nJ", nj),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Machine, MachineConfig};
+
+    #[test]
+    fn parses_partial_configurations() {
+        let model = EnergyModel::parse("dram=80, alu=0.25").unwrap();
+        assert_eq!(model, EnergyModel { dram_access: 80.0, alu_op: 0.25,
+                                        ..EnergyModel::default() });
+        assert_eq!(EnergyModel::parse(""), Some(EnergyModel::default()));
+        assert_eq!(EnergyModel::parse("dram"), None);
+        assert_eq!(EnergyModel::parse("fpu=1"), None);
+        assert_eq!(EnergyModel::parse("l1=-1"), None);
+        assert_eq!(format_energy(12_345.0), "12.35uJ");
+    }
+
+    #[test]
+    fn caches_trade_dram_energy_for_hits() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r6 0x40
+            .loop
+                ld r3 r15 0x0
+                subi r6 r6 0x1
+                bne r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let model = EnergyModel::default();
+        let run = |caches| {
+            let mut machine = Machine::new(MachineConfig { seed: Some(1), caches,
+                                                           ..MachineConfig::default() }).unwrap();
+            machine.load_program(program).unwrap();
+            machine.run(1_000_000);
+            model.estimate(machine.stats(), machine.clock() as u64)
+        };
+        let (cached, uncached) = (run(true), run(false));
+        assert_eq!(cached.alu, uncached.alu);
+        assert!(cached.alu >= 0x40 as f64 * model.alu_op);
+        assert!(cached.dram < uncached.dram && cached.caches > uncached.caches);
+        assert_eq!(uncached.caches, 0.0);
+        assert!(cached.total() < uncached.total() && cached.edp() < uncached.edp());
+        assert_eq!(cached.total(), cached.alu + cached.caches + cached.dram + cached.leakage);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::{
+    simulator::{Simulator, SimErr, EXEC_FAULT_VECTOR},
+    mmio::{MmioMap, MmioAction},
+    mmu::{Mmu, VAddr, Perms, PAGE_SIZE, split_aligned},
+    cpu::{self, Instr, Register, NUM_REGS},
+};
+
+use std::ops::Range;
+
+/// Functional model that executes one instruction at a time, without pipeline or caches. Run in
+/// lockstep with the simulator, it checks every retired instruction against the architecturally
+/// expected result
+#[derive(Debug, Clone)]
+pub struct GoldenModel {
+    pub regs: [u32; NUM_REGS],
+
+    /// Address of the next instruction
+    pub pc: VAddr,
+
+    /// Private copy of the simulator's memory
+    mmu: Mmu,
+
+    /// Misaligned accesses are split up like in the simulator
+    unaligned_emulation: bool,
+
+    /// Address ranges of the memory-mapped devices. Devices are not modeled, the results of
+    /// instructions that access them are taken over from the simulator
+    mmio: Vec<Range<u32>>,
+
+    /// Set if the most recently executed instruction accessed a device
+    mmio_access: bool,
+
+    /// Devices that accesses are forwarded to while the model drives the simulation in turbo mode.
+    /// Otherwise device accesses only touch memory
+    devices: Option<MmioMap>,
+
+    /// Clock-counter handed to devices, advanced by one per instruction in turbo mode
+    pub(crate) clock: u32,
+
+    /// Actions devices requested in turbo mode that only the simulator can perform
+    pub(crate) pending: Vec<MmioAction>,
+
+    /// First difference found while checking retired instructions
+    pub divergence: Option<String>,
+}
+
+impl GoldenModel {
+    /// Start from the architectural state of `sim`. Its pipeline has to be empty
+    pub fn new(sim: &Simulator) -> Self {
+        let mut mmu = sim.mmu.clone();
+        mmu.cache_enabled = false;
+        mmu.write_journal = None;
+        mmu.page_backups  = None;
+        Self {
+            regs:                sim.gen_regs,
+            pc:                  sim.pc,
+            mmu,
+            unaligned_emulation: sim.unaligned_emulation,
+            mmio:                sim.devices.regions.iter()
+                .map(|region| region.base.0..region.base.0.saturating_add(region.size))
+                .collect(),
+            mmio_access:         false,
+            devices:             None,
+            clock:               sim.clock,
+            pending:             Vec::new(),
+            divergence:          None,
+        }
+    }
+
+    /// Take over the memory and devices of `sim`, to execute its program in turbo mode. Its
+    /// pipeline has to be empty. `hand_back` returns the state to the simulator
+    pub(crate) fn take_over(sim: &mut Simulator) -> Self {
+        let mmu = std::mem::take(&mut sim.mmu);
+        let mut model = Self::new(sim);
+        model.mmu     = mmu;
+        model.devices = Some(std::mem::take(&mut sim.devices));
+        model
+    }
+
+    /// Devices of the simulator the model took over
+    pub(crate) fn devices_mut(&mut self) -> &mut MmioMap {
+        self.devices.get_or_insert_with(MmioMap::default)
+    }
+
+    /// Return the architectural state, memory and devices to `sim`
+    pub(crate) fn hand_back(self, sim: &mut Simulator) {
+        sim.gen_regs    = self.regs;
+        sim.pc          = self.pc;
+        sim.pipeline.pc = self.pc;
+        sim.clock       = self.clock;
+        sim.mmu         = self.mmu;
+        sim.devices     = self.devices.unwrap_or_default();
+    }
+
+    fn read_reg(&self, reg: Register) -> u32 {
+        self.regs[reg as usize]
+    }
+
+    fn write_reg(&mut self, reg: Register, val: u32) {
+        if reg != Register::R0 {
+            self.regs[reg as usize] = val;
+        }
+    }
+
+    /// Accesses the memory system performs for a `len` byte access at `addr`
+    fn chunks(&self, addr: VAddr, len: usize) -> Vec<(VAddr, usize)> {
+        if self.unaligned_emulation {
+            split_aligned(addr, len)
+        } else {
+            vec![(addr, len)]
+        }
+    }
+
+    /// Remember if a `len` byte access at `addr` touches a device, returns whether it does
+    fn note_mmio(&mut self, addr: VAddr, len: usize) -> bool {
+        let end = addr.0 as u64 + len as u64;
+        let hit = self.mmio.iter()
+            .any(|range| (addr.0 as u64) < range.end as u64 && end > range.start as u64);
+        self.mmio_access |= hit;
+        hit
+    }
+
+    /// Forward a store of `data` to `addr` to the devices in turbo mode
+    fn device_write(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let Some(devices) = self.devices.as_mut() else {
+            return Ok(());
+        };
+        for action in devices.write(addr, data, self.clock) {
+            match action {
+                MmioAction::Shutdown => return Err(SimErr::Shutdown),
+                MmioAction::WriteReg(reg, val) => self.write_reg(reg, val),
+                action => self.pending.push(action),
+            }
+        }
+        Ok(())
+    }
+
+    /// Load `len` bytes from `addr`, zero-extended
+    fn load(&mut self, addr: VAddr, len: usize) -> Result<u32, SimErr> {
+        let mmio = self.note_mmio(addr, len);
+        let mut bytes = [0u8; 4];
+        for (chunk, chunk_len) in self.chunks(addr, len) {
+            let offset = (chunk.0 - addr.0) as usize;
+            self.mmu.populate_page(chunk)?;
+            self.mmu.mem_read(chunk, &mut bytes[offset..offset + chunk_len])?;
+        }
+        if let Some(devices) = self.devices.as_mut().filter(|_| mmio) {
+            devices.read(addr, &mut bytes[..len]);
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Store the low `len` bytes of `val` to `addr`
+    fn store(&mut self, addr: VAddr, val: u32, len: usize) -> Result<(), SimErr> {
+        let mmio = self.note_mmio(addr, len);
+        let bytes = val.to_le_bytes();
+        for (chunk, chunk_len) in self.chunks(addr, len) {
+            let offset = (chunk.0 - addr.0) as usize;
+            self.mmu.populate_page(chunk)?;
+            self.mmu.mem_write(chunk, &bytes[offset..offset + chunk_len])?;
+        }
+        if mmio {
+            self.device_write(addr, &bytes[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Write `data` to `addr` like a device's dma transfer does in the simulator
+    pub fn dma_write(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + data.len()).step_by(PAGE_SIZE) {
+            self.mmu.populate_page(VAddr(page as u32))?;
+        }
+        self.mmu.mem_write_bulk(addr, data)
+    }
+
+    /// Write `data` to `addr` like the debugger patches memory in the simulator
+    pub fn patch(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + data.len()).step_by(PAGE_SIZE) {
+            self.mmu.populate_page(VAddr(page as u32))?;
+        }
+        self.mmu.patch_bulk(addr, data)
+    }
+
+    /// Fetch and decode the instruction at `pc`. Fetches from non-executable pages continue at
+    /// the handler registered at `EXEC_FAULT_VECTOR`, like they do in the simulator
+    pub(crate) fn fetch(&mut self) -> Result<Instr, SimErr> {
+        self.mmu.populate_page(self.pc)?;
+        if let Err(err) = self.mmu.translate_addr(self.pc, Perms::READ | Perms::EXEC) {
+            if err != SimErr::Permission {
+                return Err(err);
+            }
+            match self.load(VAddr(EXEC_FAULT_VECTOR), 4) {
+                Ok(handler) if handler != 0 => self.pc = VAddr(handler),
+                _ => return Err(SimErr::ExecFault),
+            }
+            self.mmu.populate_page(self.pc)?;
+            self.mmu.translate_addr(self.pc, Perms::READ | Perms::EXEC)
+                .map_err(|_| SimErr::ExecFault)?;
+        }
+        let raw = self.load(self.pc, 4)?;
+        cpu::decode_instr(raw).map_err(|_| SimErr::InstrDecode)
+    }
+
+    /// Execute `instr`, which was fetched from `pc`
+    pub(crate) fn execute(&mut self, instr: Instr) -> Result<(), SimErr> {
+        let pc   = self.pc;
+        let next = VAddr(pc.0.wrapping_add(4));
+        let addr = |base: u32, imm: i32| VAddr((base as i64 + imm as i64) as u32);
+        let branch = |taken: bool, imm: i32| if taken { addr(pc.0, imm) } else { next };
+
+        self.pc = match instr {
+            Instr::Add  { rs3, rs1, rs2 } |
+            Instr::Sub  { rs3, rs1, rs2 } |
+            Instr::Xor  { rs3, rs1, rs2 } |
+            Instr::Or   { rs3, rs1, rs2 } |
+            Instr::And  { rs3, rs1, rs2 } |
+            Instr::Shr  { rs3, rs1, rs2 } |
+            Instr::Shl  { rs3, rs1, rs2 } |
+            Instr::Mul  { rs3, rs1, rs2 } |
+            Instr::Div  { rs3, rs1, rs2 } => {
+                let (a, b) = (self.read_reg(rs1), self.read_reg(rs2));
+                let val = match instr {
+                    Instr::Add { .. } => a.wrapping_add(b),
+                    Instr::Sub { .. } => a.wrapping_sub(b),
+                    Instr::Xor { .. } => a ^ b,
+                    Instr::Or  { .. } => a | b,
+                    Instr::And { .. } => a & b,
+                    Instr::Shr { .. } => a.wrapping_shr(b),
+                    Instr::Shl { .. } => a.wrapping_shl(b),
+                    Instr::Mul { .. } => a.wrapping_mul(b),
+                    _ => a.checked_div(b).ok_or(SimErr::DivByZero)?,
+                };
+                self.write_reg(rs3, val);
+                next
+            },
+            Instr::Addi { rs3, rs1, imm } |
+            Instr::Subi { rs3, rs1, imm } |
+            Instr::Xori { rs3, rs1, imm } |
+            Instr::Ori  { rs3, rs1, imm } |
+            Instr::Andi { rs3, rs1, imm } => {
+                let a = self.read_reg(rs1) as i32;
+                let val = match instr {
+                    Instr::Addi { .. } => a.wrapping_add(imm),
+                    Instr::Subi { .. } => a.wrapping_sub(imm),
+                    Instr::Xori { .. } => a ^ imm,
+                    Instr::Ori  { .. } => a | imm,
+                    _                  => a & imm,
+                };
+                self.write_reg(rs3, val as u32);
+                next
+            },
+            Instr::Lui { rs3, imm } => {
+                self.write_reg(rs3, (imm << 12) as u32);
+                next
+            },
+            Instr::Ldb { rs3, rs1, imm } |
+            Instr::Ldh { rs3, rs1, imm } |
+            Instr::Ld  { rs3, rs1, imm } => {
+                let len = match instr {
+                    Instr::Ldb { .. } => 1,
+                    Instr::Ldh { .. } => 2,
+                    _ => 4,
+                };
+                let val = self.load(addr(self.read_reg(rs1), imm), len)?;
+                self.write_reg(rs3, val);
+                next
+            },
+            Instr::Stb  { rs3, rs1, imm } |
+            Instr::Sth  { rs3, rs1, imm } |
+            Instr::St   { rs3, rs1, imm } => {
+                let len = match instr {
+                    Instr::Stb { .. } => 1,
+                    Instr::Sth { .. } => 2,
+                    _ => 4,
+                };
+                self.store(addr(self.read_reg(rs1), imm), self.read_reg(rs3), len)?;
+                next
+            },
+            Instr::Stnt { rs3, rs1, imm } => {
+                // Non-temporal stores are never split up
+                let target = addr(self.read_reg(rs1), imm);
+                let bytes  = self.read_reg(rs3).to_le_bytes();
+                let mmio   = self.note_mmio(target, 4);
+                self.mmu.populate_page(target)?;
+                self.mmu.mem_write(target, &bytes)?;
+                if mmio {
+                    self.device_write(target, &bytes)?;
+                }
+                next
+            },
+            Instr::Bne { rs3, rs1, imm } => branch(self.read_reg(rs3) != self.read_reg(rs1), imm),
+            Instr::Beq { rs3, rs1, imm } => branch(self.read_reg(rs3) == self.read_reg(rs1), imm),
+            Instr::Blt { rs3, rs1, imm } => branch(self.read_reg(rs3) <  self.read_reg(rs1), imm),
+            Instr::Bgt { rs3, rs1, imm } => branch(self.read_reg(rs3) >  self.read_reg(rs1), imm),
+            Instr::Jmpr { offset, .. } => addr(pc.0, offset),
+            Instr::Call { offset, .. } => {
+                // Push the link register and update it to the return address
+                let sp = self.read_reg(Register::R15).wrapping_sub(4);
+                self.write_reg(Register::R15, sp);
+                self.store(VAddr(sp), self.read_reg(Register::R14), 4)?;
+                self.write_reg(Register::R14, next.0);
+                VAddr(offset as u32)
+            },
+            Instr::Ret { } => {
+                // Return to the link register and pop the previous one from the stack
+                let target = VAddr(self.read_reg(Register::R14));
+                let sp = self.read_reg(Register::R15);
+                let link = self.load(VAddr(sp), 4)?;
+                self.write_reg(Register::R14, link);
+                self.write_reg(Register::R15, sp.wrapping_add(4));
+                target
+            },
+            Instr::Int0 { } => VAddr(self.load(VAddr(0x0), 4)?),
+            Instr::Nop => next,
+            Instr::None | Instr::Invalid => return Err(SimErr::InstrDecode),
+        };
+        Ok(())
+    }
+
+    /// Execute the next instruction, and compare it against `instr` at `pc` that the simulator
+    /// just retired, leaving its registers at `regs`. Returns a description of the first
+    /// difference in the retired instruction, its pc or the registers it wrote
+    pub fn check(&mut self, pc: VAddr, instr: Instr, regs: &[u32; NUM_REGS]) -> Option<String> {
+        self.mmio_access = false;
+        let expected = match self.fetch() {
+            Ok(expected) => expected,
+            Err(err) => return Some(format!("golden model faulted with {:?} fetching {:#x}",
+                                            err, self.pc.0)),
+        };
+        if pc != self.pc || instr != expected {
+            return Some(format!("retired `{}` at {:#x}, expected `{}` at {:#x}", instr, pc.0,
+                                expected, self.pc.0));
+        }
+        if let Err(err) = self.execute(instr) {
+            return Some(format!("golden model faulted with {:?} executing `{}` at {:#x}", err,
+                                instr, pc.0));
+        }
+
+        // Device side effects can't be checked. Call and return update the stack pointer before
+        // older instructions retire, so it is not taken over
+        if self.mmio_access {
+            for reg in (0..Register::R14 as usize).filter(|&reg| reg != Register::R0 as usize) {
+                self.regs[reg] = regs[reg];
+            }
+        }
+
+        instr.writes_to_rs3().into_iter()
+            .find(|&reg| regs[reg as usize] != self.read_reg(reg))
+            .map(|reg| format!("`{}` at {:#x} set {} to {:#x}, expected {:#x}", instr, pc.0, reg,
+                               regs[reg as usize], self.read_reg(reg)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::{MsgLog, run_program}, simulator::DEFAULT_STACK_BASE};
+
+    /// Byte and halfword loads and stores, a call, arithmetic that overflows and a loop
+    const PROGRAM: &str = "
+        .entry _start
+        .load 0x20000
+        .sum
+            add r3 r3 r1
+            ret
+        .end_section
+        .load 0x10000
+        ._start
+            movi r1 0x1234
+            sth r1 r15 0x0
+            ldb r7 r15 0x1
+            ldh r4 r15 0x0
+            lui r5 0xffff
+            mul r5 r5 r5
+            movi r6 0x5
+        .loop
+            call 0x20000
+            subi r6 r6 0x1
+            bne r6 r0 .loop
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+    ";
+
+    #[test]
+    fn lockstep_run_matches_in_every_mode() {
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(PROGRAM, pipelined, 10_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.golden = Some(GoldenModel::new(sim));
+            });
+            assert!(run.log.errors.borrow().is_empty(), "ooo: {}, {:?}", ooo, run.log);
+            assert_eq!(run.sim.read_reg(Register::R7), 0x12);
+            assert_eq!(run.sim.read_reg(Register::R3), 5 * 0x1234);
+
+            let golden = run.sim.golden.as_ref().unwrap();
+            assert_eq!(golden.regs[Register::R4 as usize], 0x1234);
+            assert_eq!(golden.regs[Register::R15 as usize], run.sim.read_reg(Register::R15));
+        }
+    }
+
+    #[test]
+    fn example_programs_match_in_every_mode() {
+        for program in [include_str!("../code/matrix_mul"), include_str!("../code/nx_demo")] {
+            for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+                let run = run_program(program, pipelined, 2_000_000, &|sim| {
+                    sim.set_out_of_order(ooo);
+                    sim.golden = Some(GoldenModel::new(sim));
+                });
+                assert!(run.sim.golden.is_some(), "ooo: {}, {:?}", ooo, run.log);
+                assert!(!run.sim.online);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_the_first_divergence() {
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.start_golden();
+        sim.load_input(PROGRAM, &log).unwrap();
+
+        // Corrupt a register of the golden model, the first add that reads it diverges
+        sim.golden.as_mut().unwrap().regs[Register::R3 as usize] = 0x99;
+        while sim.online {
+            sim.step(&log);
+        }
+        let errors = log.errors.borrow();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0], "Error: Diverged from the golden model: `add r3 r3 r1` at 0x20000 \
+                               set r3 to 0x1234, expected 0x12cd");
+        assert!(sim.golden.is_none());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::mmio::{MmioDevice, MmioAction, read_regs, write_regs};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Address of the gpio's registers, next to the buzzer
+pub const GPIO_BASE: u32 = 0x2090;
+
+/// Size of the gpio's register block
+pub const GPIO_SIZE: u32 = 0x10;
+
+/// Number of leds and of switches
+pub const GPIO_PINS: u32 = 8;
+
+/// Bits of the output and input registers that are connected to a pin
+const GPIO_MASK: u32 = (1 << GPIO_PINS) - 1;
+
+/// State shared between all clones of a `Gpio`
+#[derive(Debug, Default)]
+struct GpioState {
+    leds:     u32,
+    switches: u32,
+}
+
+/// General purpose io at `GPIO_BASE`, a row of leds and a row of switches like on fpga boards. Its
+/// 32-bit registers are:
+///  - 0x0 Output: Bit `n` lights led `n`
+///  - 0x4 Input: Bit `n` is set while switch `n` is on, writes are ignored
+///
+/// Only the low `GPIO_PINS` bits are connected, the others read as 0. Clones share the same state,
+/// so the host can flip switches and watch the leds after registering the device
+#[derive(Clone, Debug, Default)]
+pub struct Gpio {
+    state: Rc<RefCell<GpioState>>,
+}
+
+impl Gpio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leds that are lit, bit `n` is led `n`
+    pub fn leds(&self) -> u32 {
+        self.state.borrow().leds
+    }
+
+    /// Switches that are on, bit `n` is switch `n`
+    pub fn switches(&self) -> u32 {
+        self.state.borrow().switches
+    }
+
+    /// Turn the switches whose bits are set in `switches` on and the others off
+    pub fn set_switches(&self, switches: u32) {
+        self.state.borrow_mut().switches = switches & GPIO_MASK;
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        let state = self.state.borrow();
+        [state.leds, state.switches, 0, 0]
+    }
+}
+
+impl MmioDevice for Gpio {
+    fn name(&self) -> &str {
+        "gpio"
+    }
+
+    /// The output can be written a byte at a time, bytes that aren't written keep their value
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs = write_regs(self.regs(), offset, data);
+        self.state.borrow_mut().leds = regs[0] & GPIO_MASK;
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    /// Leds go dark, the switches keep their position
+    fn reset(&mut self) {
+        self.state.borrow_mut().leds = 0;
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+/// Leds as a row of `*` for lit and `.` for dark ones, led 0 on the right like on the boards
+pub fn led_row(leds: u32) -> String {
+    (0..GPIO_PINS).rev().map(|pin| if leds & (1 << pin) != 0 { '*' } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmio::read_reg, mmu::VAddr};
+
+    #[test]
+    fn leds_follow_the_output_register() {
+        let mut gpio = Gpio::new();
+        gpio.write(0x0, &0x1ffu32.to_le_bytes(), 0);
+        assert_eq!(gpio.leds(), 0xff);
+        gpio.write(0x0, &[0x05], 0);
+        assert_eq!(led_row(gpio.leds()), ".....*.*");
+
+        // The switches can only be flipped by the host
+        gpio.write(0x4, &[0xff], 0);
+        assert_eq!(gpio.switches(), 0);
+        gpio.set_switches(0x1a5);
+        assert_eq!(read_reg(&mut gpio, 0x4), 0xa5);
+
+        gpio.reset();
+        assert_eq!((gpio.leds(), gpio.switches()), (0, 0xa5));
+    }
+
+    /// Show the switches on the leds, shifted left by one
+    #[test]
+    fn program_mirrors_the_switches() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x2090
+                ld r1 r2 0x4
+                add r1 r1 r1
+                st r1 r2 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let gpio = Gpio::new();
+            gpio.set_switches(0x43);
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.devices.register(VAddr(GPIO_BASE), GPIO_SIZE, Box::new(gpio.clone())).unwrap();
+                sim.start_golden();
+            });
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "pipelined: {}: {:?}", pipelined, run.log);
+            assert_eq!(gpio.leds(), 0x86, "pipelined: {}", pipelined);
+        }
+    }
+}
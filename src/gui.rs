@@ -1,80 +1,469 @@
 use crate::{
-    simulator::Simulator,
-    mmu::VAddr,
-    cpu::{Instr, NUM_REGS},
-    VgaDriver,
+    simulator::{Simulator, MemFollow, DEFAULT_STATE_PATH},
+    mmu::{VAddr, Perms},
+    predictor::BranchPredictor,
+    pipeline::{PipelineHistory, DiagramCell, DEFAULT_HISTORY_LEN, FETCH_QUEUE_SIZE},
+    cpu::{Instr, Register, NUM_REGS},
+    logger::{Logger, gui_err_print, gui_log_print},
+    mmio::{MmioDevice, MmioAction},
+    profiler::{self, Profile, DEFAULT_PROFILE_PATH},
+    retire_log::{RetireLog, DEFAULT_RETIRE_LOG_DEPTH},
+    timeline::ChartMetric,
+    theme::{Theme, Palette, Rgb},
+    console,
+    uart::{Uart, UART_BASE, UART_SIZE, UART_IRQ},
+    gpio::{Gpio, GPIO_BASE, GPIO_SIZE, GPIO_PINS},
+    segment::{self, SevenSegment, SEGMENT_BASE, SEGMENT_SIZE, SEGMENT_DIGITS},
+    putc::{PutcPort, PUTC_BASE, PUTC_SIZE},
+    mouse::{Mouse, MOUSE_BASE, MOUSE_SIZE, MOUSE_IRQ, MOUSE_LEFT, MOUSE_RIGHT, MOUSE_MIDDLE},
+    pic,
+    highlight,
+    isa,
+    hexedit,
+    energy::format_energy,
+    recent::RecentFiles,
+    debugger::{DebugStop, RunLimits, RunStart, DEBUG_CYCLE_LIMIT},
+    batch::{self, DEFAULT_BATCH_CYCLES},
+    machine::MachineConfig,
+    vga::{TextMode, VgaSize, VGA_PALETTE, attr_colors},
+    VGA_BASE, vga_char,
     as_u32_le, as_u16_le,
 };
 
 use fltk::{
     app,
+    draw,
     frame::Frame, 
     prelude::*,
-    button::Button,
+    button::{Button, LightButton},
+    browser::HoldBrowser,
+    dialog,
     window::Window,
-    enums::{Color, Align, LabelType, Font},
-    input::{Input, MultilineInput},
+    group::{Group, Scroll, Tile},
+    enums::{Color, Align, LabelType, Font, CallbackTrigger, FrameType, Event, Shortcut},
+    input::Input,
+    menu::{MenuButton, MenuFlag},
+    misc::{Chart, ChartType},
+    text::{TextBuffer, TextDisplay, TextEditor, StyleTableEntryExt, TextAttr},
+    valuator::HorNiceSlider,
 };
 use num_format::{Locale, ToFormattedString};
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 const RUNS_PER_GUI_UPDATE: usize = 500_000;
 
+/// How fast `Run` advances the simulator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunSpeed {
+    /// Slow motion, a number of cycles per second
+    PerSecond(u32),
+
+    /// A number of cycles every time the gui gets to update
+    PerUpdate(usize),
+}
+
+/// Speeds the speed slider selects from, slowest first
+const RUN_SPEEDS: [RunSpeed; 8] = [
+    RunSpeed::PerSecond(1), RunSpeed::PerSecond(10), RunSpeed::PerSecond(100),
+    RunSpeed::PerUpdate(100), RunSpeed::PerUpdate(1_000), RunSpeed::PerUpdate(10_000),
+    RunSpeed::PerUpdate(100_000), RunSpeed::PerUpdate(RUNS_PER_GUI_UPDATE),
+];
+
+impl std::fmt::Display for RunSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RunSpeed::PerSecond(rate) => write!(f, "{} cycles/s", rate),
+            RunSpeed::PerUpdate(steps) => {
+                write!(f, "{} / update", steps.to_formatted_string(&Locale::en))
+            },
+        }
+    }
+}
+
+/// Steps run between checks whether the gui should get control back
+const RUN_CHUNK_STEPS: usize = 10_000;
+
+/// Time a run keeps the gui from handling events, so `Pause` and the other buttons stay responsive
+const RUN_CHUNK_TIME: Duration = Duration::from_millis(50);
+
+/// Maximum number of changed words listed in the snapshot-diff window
+const MAX_DIFF_LINES: usize = 1000;
+
+/// Maximum number of distinct attributes the screen shows at once, limited by fltk's style table.
+/// Characters with other attributes are shown in the default colors
+const MAX_VGA_STYLES: usize = 60;
+
+/// Position and size of the screen's slot in the main window, larger screens get their own window
+const VGA_SLOT: (i32, i32, i32, i32) = (730, 540, 300, 200);
+
+/// Pixels taken up by a character of the screen, and around its text
+const VGA_CHAR_W: i32 = 10;
+const VGA_CHAR_H: i32 = 20;
+const VGA_MARGIN: i32 = 10;
+
+/// Number of sets the cache grid shows at once, caches with more sets are shown a block at a time
+const CACHE_GRID_SETS: usize = 32;
+
+/// Number of ways the cache grid has room for
+const CACHE_GRID_WAYS: usize = 8;
+
+/// Height of the cache grid, shared by the ways of the displayed cache
+const CACHE_GRID_HEIGHT: i32 = 48;
+
+/// Number of lines the hex editor shows
+const HEX_EDIT_LINES: u32 = 24;
+
+/// Number of instructions the disassembly lists, it can be scrolled through
+const DISASS_ROWS: u32 = 256;
+
+/// Width of the disassembly's breakpoint gutter, clicking into it toggles a breakpoint
+const DISASS_GUTTER: i32 = 20;
+
+/// Range the disassembly lists and what it showed when it was last rebuilt
+#[derive(Debug, Default)]
+struct DisassState {
+    /// Address of the first listed instruction
+    base: u32,
+
+    /// Pc, clock-counter and breakpoints in the range the list was built for, it is only rebuilt
+    /// when they change
+    shown: Option<(u32, u32, Vec<u32>)>,
+
+    /// Address to scroll to once the list is rebuilt
+    scroll_to: Option<u32>,
+}
+
+/// Size the main window is laid out for, smaller windows scroll the panels that don't fit
+const LAYOUT_W: i32 = 1260;
+const LAYOUT_H: i32 = 960;
+
+/// Smallest size the main window can be resized to
+const MIN_WINDOW_W: i32 = 640;
+const MIN_WINDOW_H: i32 = 480;
+
+/// Height of the toolbar at the top of the main window, the panels are below it
+const TOOLBAR_HEIGHT: i32 = 100;
+
+/// Left edges of the panels: disassembly, pipeline and cache, then memory, code and devices, then
+/// registers and stats
+const PANEL_EDGES: [i32; 3] = [0, 420, 1030];
+
+/// Closest the edges between panels can be dragged to the sides of the window
+const MIN_PANEL_W: i32 = 60;
+
+/// Toolbar and panels of the main window. The panels are split by edges that can be dragged to
+/// resize them, and scroll when they are smaller than their contents. The side panels can be
+/// collapsed to make room for the middle one
+struct Panels {
+    toolbar: Scroll,
+    tile:    Tile,
+    panels:  [Scroll; 3],
+
+    /// Width of each collapsed panel before it was collapsed
+    collapsed: [Option<i32>; 3],
+}
+
+impl Panels {
+    /// Move the widgets of `window` into the toolbar and panels by their position, they keep their
+    /// positions within the window
+    fn new(window: &mut Window) -> Self {
+        let widgets = (0..window.children()).filter_map(|idx| window.child(idx))
+            .collect::<Vec<_>>();
+        let height  = LAYOUT_H - TOOLBAR_HEIGHT;
+
+        let mut toolbar = Scroll::new(0, 0, LAYOUT_W, TOOLBAR_HEIGHT, "");
+        toolbar.end();
+        let tile       = Tile::new(0, TOOLBAR_HEIGHT, LAYOUT_W, height, "");
+        let mut panels = [0, 1, 2].map(|idx| {
+            let right = PANEL_EDGES.get(idx + 1).copied().unwrap_or(LAYOUT_W);
+            let width = right - PANEL_EDGES[idx];
+            let panel = Scroll::new(PANEL_EDGES[idx], TOOLBAR_HEIGHT, width, height, "");
+            // Labels can extend past their widgets, this keeps the whole panel scrollable
+            Frame::new(PANEL_EDGES[idx], TOOLBAR_HEIGHT, width, height, "");
+            panel.end();
+            panel
+        });
+        let limits = Frame::new(MIN_PANEL_W, TOOLBAR_HEIGHT, LAYOUT_W - 2 * MIN_PANEL_W, height,
+                                "");
+        tile.resizable(&limits);
+        tile.end();
+
+        for widget in widgets {
+            if widget.y() < TOOLBAR_HEIGHT {
+                toolbar.add(&widget);
+            } else {
+                let idx = PANEL_EDGES.iter().rposition(|&edge| widget.x() >= edge).unwrap_or(0);
+                panels[idx].add(&widget);
+            }
+        }
+        window.resizable(&tile);
+
+        Self { toolbar, tile, panels, collapsed: [None; 3] }
+    }
+
+    /// Background of the toolbar and panels
+    fn set_color(&mut self, color: Color) {
+        self.toolbar.set_color(color);
+        for panel in &mut self.panels {
+            panel.set_color(color);
+        }
+    }
+
+    /// Collapse the side panel `idx`, or restore the width it had if it is collapsed. The middle
+    /// panel takes up the difference
+    fn toggle(&mut self, idx: usize) {
+        let width = match self.collapsed[idx].take() {
+            Some(width) => width,
+            None => {
+                self.collapsed[idx] = Some(self.panels[idx].w());
+                0
+            },
+        };
+        let middle = &self.panels[1];
+        let (left, right) = if idx == 0 {
+            (self.tile.x() + width, middle.x() + middle.w())
+        } else {
+            (middle.x(), self.tile.x() + self.tile.w() - width)
+        };
+        let (x, y, h) = (self.panels[idx].x(), self.tile.y(), self.tile.h());
+        self.panels[idx].resize(if idx == 0 { x } else { right }, y, width, h);
+        self.panels[1].resize(left, y, right - left, h);
+        if width == 0 {
+            self.panels[idx].hide();
+        } else {
+            self.panels[idx].show();
+        }
+        self.tile.init_sizes();
+        self.tile.redraw();
+    }
+}
+
+/// Metrics charted by the `Charts` window, top to bottom
+const CHART_METRICS: [ChartMetric; 3] = [ChartMetric::HitRate, ChartMetric::Cpi,
+                                         ChartMetric::MemStall];
+
+/// Number of points of a chart that are labeled with their clock-cycle
+const CHART_LABELS: usize = 5;
+
+/// Window of an open device panel and the frames it updates, eg. the gpio's leds
+type PanelView = Rc<RefCell<Option<(Window, Vec<Frame>)>>>;
+
+/// Window of the stats charts and its charts, in the order of `CHART_METRICS`
+type ChartsView = Rc<RefCell<Option<(Window, Vec<Chart>)>>>;
+
+/// Position and size of the segments `a` to `g` and the decimal point within a digit of the
+/// seven-segment display
+const SEGMENT_RECTS: [(i32, i32, i32, i32); 8] = [
+    (8, 4, 24, 5), (32, 8, 5, 26), (32, 38, 5, 26), (8, 63, 24, 5),
+    (3, 38, 5, 26), (3, 8, 5, 26), (8, 33, 24, 5), (40, 63, 5, 5),
+];
+
+/// Provides an interface to write to the simulator's output screen
+#[derive(Clone, Debug)]
+pub struct VgaDriver {
+    display: TextDisplay,
+    text:    TextBuffer,
+    styles:  TextBuffer,
+    screen:  Rc<RefCell<TextMode>>,
+
+    /// Attributes in the display's style table, characters shown with style `A + n` have
+    /// attribute `palette[n]`
+    palette: Rc<RefCell<Vec<u8>>>,
+}
+
+impl VgaDriver {
+    /// Screen of `size` displayed at `x`, `y` in the current group
+    pub fn new(size: VgaSize, x: i32, y: i32) -> Self {
+        let text   = TextBuffer::default();
+        let styles = TextBuffer::default();
+        let (w, h) = Self::display_size(size);
+        let mut display = TextDisplay::new(x, y, w, h, "");
+        display.set_buffer(text.clone());
+        display.set_color(Color::Black);
+        display.set_text_font(Font::Courier);
+
+        let mut driver = Self {
+            display,
+            text,
+            styles,
+            screen:  Rc::new(RefCell::new(TextMode::new(size))),
+            palette: Rc::new(RefCell::new(Vec::new())),
+        };
+        driver.clear();
+        driver
+    }
+
+    /// Size of the display showing a screen of `size`, at least that of its slot in the main
+    /// window
+    fn display_size(size: VgaSize) -> (i32, i32) {
+        (std::cmp::max(VGA_SLOT.2, size.columns as i32 * VGA_CHAR_W + VGA_MARGIN),
+         std::cmp::max(VGA_SLOT.3, size.lines as i32 * VGA_CHAR_H + VGA_MARGIN))
+    }
+
+    /// Initialize empty screen
+    fn clear(&mut self) {
+        let size = self.screen.borrow().size();
+        *self.screen.borrow_mut() = TextMode::new(size);
+        self.palette.borrow_mut().clear();
+        self.render();
+    }
+
+    /// Show the current contents of the screen in their colors
+    fn render(&mut self) {
+        let screen = self.screen.borrow();
+        let mut palette = self.palette.borrow_mut();
+        let palette_len = palette.len();
+
+        let mut styles = String::new();
+        for (&byte, &attr) in screen.chars.iter().zip(&screen.attrs) {
+            let style = match palette.iter().position(|&known| known == attr) {
+                Some(style) => style,
+                None if palette.len() < MAX_VGA_STYLES => {
+                    palette.push(attr);
+                    palette.len() - 1
+                },
+                None => 0,
+            };
+            // The style buffer holds a style for every byte of the utf-8 text
+            let style = (b'A' + style as u8) as char;
+            styles.extend(std::iter::repeat_n(style, vga_char(byte).len_utf8()));
+        }
+
+        if palette.len() != palette_len || palette_len == 0 {
+            let entries = palette.iter().map(|&attr| {
+                let (fg, bg) = attr_colors(attr);
+                let color = |idx: usize| {
+                    let (r, g, b) = VGA_PALETTE[idx];
+                    Color::from_rgb(r, g, b)
+                };
+                StyleTableEntryExt {
+                    color:   color(fg),
+                    font:    Font::Courier,
+                    size:    self.display.text_size(),
+                    attr:    TextAttr::BgColor,
+                    bgcolor: color(bg),
+                }
+            }).collect::<Vec<_>>();
+            self.display.set_highlight_data_ext(self.styles.clone(), entries);
+        }
+        self.text.set_text(&screen.text());
+        self.styles.set_text(&styles);
+    }
+}
+
+impl MmioDevice for VgaDriver {
+    fn name(&self) -> &str {
+        "vga"
+    }
+
+    /// Update the screen with a write of `output` to `offset` into its address range
+    fn write(&mut self, offset: u32, output: &[u8], _clock: u32) -> Option<MmioAction> {
+        let action = self.screen.borrow_mut().write(offset, output);
+        self.render();
+        action
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+/// Color of `rgb` on the gui
+fn color((r, g, b): Rgb) -> Color {
+    Color::from_rgb(r, g, b)
+}
+
+/// Switch the colors that widgets use by default to those of `palette`
+fn apply_theme(palette: &Palette) {
+    let (r, g, b) = palette.background;
+    app::background(r, g, b);
+    let (r, g, b) = palette.background2;
+    app::background2(r, g, b);
+    let (r, g, b) = palette.foreground;
+    app::foreground(r, g, b);
+    let (r, g, b) = palette.selection;
+    app::set_selection_color(r, g, b);
+    let (r, g, b) = palette.inactive;
+    app::set_inactive_color(r, g, b);
+}
+
+/// Label color of row `row` of the register and memory views, every other row is highlighted
+fn row_color(palette: &Palette, row: usize) -> Color {
+    if row.is_multiple_of(2) { Color::Foreground } else { color(palette.alt_text) }
+}
+
+/// Style table of the code editor in the colors of `palette`, in the order of the `highlight`
+/// styles
+fn code_style_table(palette: &Palette) -> Vec<StyleTableEntryExt> {
+    let style = |color: Color, font: Font, bgcolor: Option<Color>| StyleTableEntryExt {
+        color,
+        font,
+        size:    app::font_size(),
+        attr:    if bgcolor.is_some() { TextAttr::BgColor } else { TextAttr::None },
+        bgcolor: bgcolor.unwrap_or(Color::Background2),
+    };
+    vec![
+        style(Color::Foreground, Font::Courier, None),
+        style(color(palette.mnemonic), Font::CourierBold, None),
+        style(color(palette.register), Font::Courier, None),
+        style(color(palette.label), Font::Courier, None),
+        style(color(palette.number), Font::Courier, None),
+        style(color(palette.comment), Font::CourierItalic, None),
+        style(Color::White, Font::CourierBold, Some(Color::Red)),
+    ]
+}
+
 /// Gui-helper for register-display
-pub fn get_reg_frames() -> Vec<Frame> {
+pub fn get_reg_frames(palette: &Palette) -> Vec<Frame> {
     let mut reg_display = Vec::new();
 
     for i in 0..NUM_REGS {
         let mut f = Frame::new(1040, (140 + (i * 23)) as i32, 40, 40, "").with_align(Align::Right);
         f.set_label_font(Font::CourierBold);
         f.set_label_size(14);
-        if i % 2 == 0 {
-            f.set_label_color(Color::Gray0);
-        } else {
-            f.set_label_color(Color::Blue);
-        }
+        f.set_label_color(row_color(palette, i));
         reg_display.push(f);
     }
 
     reg_display
 }
 
-/// Gui-helper for instruction-display
-pub fn get_instr_frames() -> Vec<Frame> {
-    let mut instr_display = Vec::new();
-    for i in 0..11 {
-        let mut f = Frame::new(0, 120 + (i * 26), 40, 40, "").with_align(Align::Right);
-        f.set_label_font(Font::CourierBold);
-        f.set_label_size(14);
-        if i % 2 == 0 {
-            f.set_label_color(Color::Gray0);
-        } else {
-            f.set_label_color(Color::Blue);
-        }
-        instr_display.push(f);
-    }
-    instr_display
-}
-
 /// Gui-helper for memory-display
-pub fn get_mem_frames() -> Vec<Frame> {
+pub fn get_mem_frames(palette: &Palette) -> Vec<Frame> {
     let mut mem_display = Vec::new();
     for i in 0..11 {
-        let mut f = Frame::new(360, 140 + (i * 28), 40, 40, "").with_align(Align::Right);
+        let mut f = Frame::new(420, 140 + (i * 28), 0, 40, "").with_align(Align::Right);
         f.set_label_font(Font::CourierBold);
         f.set_label_size(14);
-        if i % 2 == 0 {
-            f.set_label_color(Color::Gray0);
-        } else {
-            f.set_label_color(Color::Blue);
-        }
+        f.set_label_color(row_color(palette, i as usize));
         mem_display.push(f);
     }
     mem_display
 }
 
+/// Gui-helper for the cache grid, a column of `CACHE_GRID_WAYS` entries per set. Entries beyond
+/// the ways of the displayed cache are hidden
+pub fn get_cache_grid_frames() -> Vec<Frame> {
+    let mut cells = Vec::new();
+    for set in 0..CACHE_GRID_SETS as i32 {
+        for way in 0..CACHE_GRID_WAYS as i32 {
+            let height = CACHE_GRID_HEIGHT / CACHE_GRID_WAYS as i32;
+            let mut f  = Frame::new(20 + set * 11, 612 + way * height, 10, height - 1, "");
+            f.set_frame(FrameType::FlatBox);
+            cells.push(f);
+        }
+    }
+    cells
+}
+
 /// Gui-helper for pipeline gui-display
 pub fn get_pipeline_frames() -> Vec<Frame> {
     let mut pipeline_stages = Vec::new();
@@ -87,18 +476,251 @@ pub fn get_pipeline_frames() -> Vec<Frame> {
     pipeline_stages
 }
 
+/// Text of the pipeline diagram for `history` and its style buffer: `B` for stages, `C` for
+/// stalls and `D` for flushed instructions. Every 10th cycle is numbered in the first line
+fn diagram_text(history: &PipelineHistory) -> (String, String) {
+    let prefix = " ".repeat(38);
+    let mut text   = prefix.clone();
+    let mut styles = "A".repeat(prefix.len());
+    for (col, record) in history.iter().enumerate().step_by(10) {
+        let clock = format!("{:<20}", record.clock);
+        let width = 2 * std::cmp::min(10, history.len() - col);
+        text.push_str(&clock[..std::cmp::min(clock.len(), width)]);
+    }
+    styles.push_str(&"A".repeat(text.len() - styles.len()));
+
+    for row in history.diagram() {
+        let instr = row.instr.map_or("?".to_string(), |instr| instr.to_string());
+        let line  = format!("\n{:#010x}  {:<24}  ", row.pc.0, instr);
+        styles.push_str(&"A".repeat(line.len()));
+        text.push_str(&line);
+        for cell in row.cells {
+            let (label, style) = match cell {
+                Some(cell @ DiagramCell::Stage(_)) => (cell.label(), 'B'),
+                Some(cell @ DiagramCell::Stall(_)) => (cell.label(), 'C'),
+                Some(cell @ DiagramCell::Flushed)  => (cell.label(), 'D'),
+                None => (" ".to_string(), 'A'),
+            };
+            text.push_str(&format!("{} ", label));
+            styles.push(style);
+            styles.push('A');
+        }
+    }
+    (text, styles)
+}
+
+/// Show the path of the program in the window's title
+fn set_program_title(window: &mut Window, path: Option<&Path>) {
+    match path {
+        Some(path) => window.set_label(&format!("Simulator - {}", path.display())),
+        None => window.set_label("Simulator"),
+    }
+}
+
+/// Ask for the file to open a program from, or to save it to. `None` if the dialog was cancelled
+fn choose_program_file(save: bool, current: Option<&Path>) -> Option<PathBuf> {
+    let mut chooser = dialog::NativeFileChooser::new(if save {
+        dialog::NativeFileChooserType::BrowseSaveFile
+    } else {
+        dialog::NativeFileChooserType::BrowseFile
+    });
+    chooser.set_title(if save { "Save program as" } else { "Open program" });
+    if save {
+        chooser.set_option(dialog::NativeFileChooserOptions::SaveAsConfirm);
+    }
+    if let Some(current) = current {
+        if let Some(dir) = current.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = chooser.set_directory(&dir);
+        }
+        if let Some(name) = current.file_name() {
+            chooser.set_preset_file(&name.to_string_lossy());
+        }
+    }
+    chooser.show();
+    let path = chooser.filename();
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// `text` as the label of a menu item, so slashes don't start submenus
+fn menu_label(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('/', "\\/").replace('&', "&&").replace('_', "\\_")
+}
+
+/// Program in the code box, the file it was opened from or last saved to, and the programs opened
+/// before it
+#[derive(Clone)]
+struct ProgramFile {
+    path:     Rc<RefCell<Option<PathBuf>>>,
+    recent:   Rc<RefCell<RecentFiles>>,
+    window:   Window,
+    code_buf: TextBuffer,
+
+    /// Assembles and loads the code box
+    load_btn: Button,
+}
+
+impl ProgramFile {
+    fn new(window: Window, code_buf: TextBuffer, load_btn: Button) -> Self {
+        let recent = RecentFiles::default_path()
+            .and_then(|path| RecentFiles::load(&path).ok())
+            .unwrap_or_default();
+        Self {
+            path:   Rc::new(RefCell::new(None)),
+            recent: Rc::new(RefCell::new(recent)),
+            window,
+            code_buf,
+            load_btn,
+        }
+    }
+
+    /// File the program was opened from or last saved to
+    fn path(&self) -> Option<PathBuf> {
+        self.path.borrow().clone()
+    }
+
+    /// Make `path` the program's file, show it in the title and list it as the most recent program
+    fn set_path(&mut self, path: PathBuf, err_log: &dyn Logger) {
+        set_program_title(&mut self.window, Some(&path));
+        self.recent.borrow_mut().push(&path);
+        *self.path.borrow_mut() = Some(path);
+        if let Some(recent_path) = RecentFiles::default_path() {
+            if let Err(err) = self.recent.borrow().save(&recent_path) {
+                gui_err_print(&format!("Error: Saving the recent programs to {} failed: {}",
+                                       recent_path.display(), err), err_log);
+            }
+        }
+    }
+
+    /// Put the program at `path` into the code box, and assemble and load it
+    fn open(&mut self, path: PathBuf, err_log: &dyn Logger) {
+        match std::fs::read_to_string(&path) {
+            Ok(code) => {
+                self.code_buf.set_text(&code);
+                self.set_path(path, err_log);
+                self.load_btn.do_callback();
+            },
+            Err(err) => gui_err_print(&format!("Error: Opening {} failed: {}", path.display(),
+                                               err), err_log),
+        }
+    }
+
+    /// Write the code box to `path`, or to a file chosen through a dialog if unset
+    fn save(&mut self, path: Option<PathBuf>, err_log: &dyn Logger) {
+        let Some(path) = path.or_else(|| choose_program_file(true, self.path().as_deref())) else {
+            return;
+        };
+        match std::fs::write(&path, self.code_buf.text()) {
+            Ok(()) => {
+                gui_log_print(&format!("Saved program to {}", path.display()), err_log);
+                self.set_path(path, err_log);
+            },
+            Err(err) => gui_err_print(&format!("Error: Saving {} failed: {}", path.display(),
+                                               err), err_log),
+        }
+    }
+}
+
+/// Reference of the instruction on a line of assembly or disassembly, see `InstrSpec::reference`.
+/// Its mnemonic is the line's first word
+fn instr_reference(line: &str) -> Option<String> {
+    let mnemonic = line.split_whitespace().next()?;
+    isa::lookup(&mnemonic.to_ascii_lowercase()).map(|spec| spec.reference())
+}
+
+/// Show the reference of the instruction on `line` in the reference pane, if it has one
+fn show_instr_reference(pane: &mut TextBuffer, line: &str) {
+    if let Some(reference) = instr_reference(line) {
+        if pane.text() != reference {
+            pane.set_text(&reference);
+        }
+    }
+}
+
+/// Report debugger commands that stopped before they completed
+fn report_debug_stop(stop: DebugStop, err_log: &dyn Logger) {
+    match stop {
+        DebugStop::Reached => {},
+        DebugStop::Breakpoint => gui_log_print("Stopped at breakpoint", err_log),
+        DebugStop::Offline => gui_err_print("Error: Simulator stopped before reaching the target",
+                                            err_log),
+        DebugStop::CycleLimit => gui_err_print(&format!("Error: Target not reached within {} \
+                                                        cycles", DEBUG_CYCLE_LIMIT), err_log),
+    }
+}
+
 /// Setup gui-windows, setup basic execution loop, and register callbacks for the different
 /// input-fields/buttons
-pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) -> app::App {
+pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>, theme: Theme)
+        -> app::App {
     let app        = app::App::default();
-    let mut window = Window::new(0, 100, 1260, 800, "Simulator");
+    let theme      = Rc::new(RefCell::new(theme));
+    apply_theme(theme.borrow().palette());
+    let mut window = Window::new(0, 100, LAYOUT_W, LAYOUT_H, "Simulator");
 
     let mut cl_warning = Button::new(1020, 10, 110, 40, "Clear Warning");
-    //let mut reset_btn  = Button::new(1140, 10, 60, 40, "Reset");
-    let mut quit_btn   = Button::new(1210, 10, 40, 40, "Quit");
-    let mut bp_btn     = Button::new(220, 10, 40, 40, "BP");
-    let mut step_btn   = Button::new(270, 10, 40, 40, "Step");
-    let mut run_btn    = Button::new(320, 10, 40, 40, "Run");
+    let mut reset_btn  = Button::new(1210, 30, 40, 20, "Reset");
+    let mut quit_btn   = Button::new(1210, 10, 40, 20, "Quit");
+    let mut bp_btn     = Button::new(220, 10, 40, 20, "BP");
+    let mut step_btn   = Button::new(270, 10, 40, 20, "Step");
+    let mut run_btn    = Button::new(320, 10, 40, 20, "Run");
+    let mut until_btn  = Button::new(220, 30, 40, 20, "Until");
+    let mut over_btn   = Button::new(270, 30, 40, 20, "Over");
+    let mut out_btn    = Button::new(320, 30, 40, 20, "Out");
+    let mut back_btn   = Button::new(10, 10, 90, 40, "Step Back");
+    let mut rerun_btn  = Button::new(10, 55, 90, 25, "Restart");
+    let mut snap_btn   = Button::new(860, 10, 75, 40, "Snapshot");
+    let mut diff_btn   = Button::new(940, 10, 75, 40, "Changes");
+    let mut maps_btn   = Button::new(860, 55, 75, 25, "Mappings");
+    let mut sweep_btn  = Button::new(790, 55, 60, 25, "Sweep");
+    let mut rob_btn    = Button::new(940, 55, 75, 25, "ROB/RS");
+    let mut trace_btn  = Button::new(1020, 55, 110, 25, "Record Trace");
+    let mut hist_btn   = Button::new(1140, 55, 110, 25, "History");
+    let mut exec_btn   = Button::new(460, 55, 110, 25, "Exec Trace");
+    let mut save_btn   = Button::new(320, 55, 60, 25, "Save");
+    let mut load_btn   = Button::new(390, 55, 60, 25, "Load");
+    let mut bt_btn     = Button::new(1140, 10, 65, 20, "Backtrace");
+    let mut prof_btn   = Button::new(1140, 30, 65, 20, "Profile");
+    let mut retire_btn = Button::new(890, 105, 75, 25, "Retired");
+    let mut console_btn = Button::new(970, 105, 65, 25, "Console");
+    let mut serial_btn  = Button::new(730, 745, 70, 25, "Serial");
+    let mut pic_btn     = Button::new(805, 745, 50, 25, "PIC");
+    let mut gpio_btn    = Button::new(860, 745, 55, 25, "GPIO");
+    let mut segment_btn = Button::new(920, 745, 60, 25, "7-Seg");
+    let mut putc_btn    = Button::new(985, 745, 55, 25, "Putc");
+    let mut charts_btn  = Button::new(730, 810, 70, 25, "Charts");
+    let mut theme_btn   = Button::new(805, 810, 90, 25, "");
+    let mut left_btn    = Button::new(575, 58, 35, 22, "@|<");
+    let mut right_btn   = Button::new(610, 58, 35, 22, "@>|");
+    left_btn.set_tooltip("Collapse or restore the disassembly, pipeline and cache panel");
+    right_btn.set_tooltip("Collapse or restore the register and stats panel");
+    theme_btn.set_label(&format!("Theme: {}", theme.borrow().name()));
+
+    // Speed of `Run`, from slow motion to full speed
+    let mut speed_slider = HorNiceSlider::new(780, 780, 160, 20, "Speed");
+    let mut speed_label  = Frame::new(945, 780, 0, 20, "").with_align(Align::Right);
+    let run_speed        = Rc::new(RefCell::new(RUN_SPEEDS[RUN_SPEEDS.len() - 1]));
+    speed_slider.set_align(Align::Left);
+    speed_slider.set_range(0.0, (RUN_SPEEDS.len() - 1) as f64);
+    speed_slider.set_step(1.0, 1);
+    speed_slider.set_value((RUN_SPEEDS.len() - 1) as f64);
+    speed_label.set_label(&run_speed.borrow().to_string());
+    speed_slider.set_callback({
+        let run_speed = run_speed.clone();
+        move |slider| {
+            let speed = RUN_SPEEDS[slider.value() as usize];
+            *run_speed.borrow_mut() = speed;
+            speed_label.set_label(&speed.to_string());
+        }
+    });
+    let rob_view: Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let bt_view:  Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let retire_view: Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let serial_view: Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let pic_view:    Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let putc_view:   Rc<RefCell<Option<(Window, TextBuffer)>>> = Rc::new(RefCell::new(None));
+    let charts_view: ChartsView = Rc::new(RefCell::new(None));
+    let gpio_view:    PanelView = Rc::new(RefCell::new(None));
+    let segment_view: PanelView = Rc::new(RefCell::new(None));
 
     let mut pc_display = Frame::new(360, 10, 100, 40, "").with_align(Align::Right);
     pc_display.set_label_type(LabelType::Engraved);
@@ -109,6 +731,9 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     clock_display.set_label_size(14);
 
     let bp_input   = Input::new(110, 10, 100, 40, "");
+    let mut seed_input = Input::new(110, 55, 100, 25, "");
+    let mut seed_btn   = Button::new(220, 55, 90, 25, "Set Seed");
+    seed_input.set_value(&simulator.borrow().seed.to_string());
 
     let mut reg_header = Frame::new(1040, 100, 40, 40, "Registers").with_align(Align::Right);
     reg_header.set_label_type(LabelType::Engraved);
@@ -118,7 +743,16 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     disass_header.set_label_type(LabelType::Engraved);
     disass_header.set_label_size(14);
 
-    let mut mem_header = Frame::new(300, 100, 100, 40, "Memory at ").with_align(Align::Right);
+    // Instructions around the pc, the gutter shows breakpoints and the pc's row is selected
+    let mut disass = HoldBrowser::new(5, 135, 350, 275, "");
+    disass.set_text_size(13);
+    disass.set_column_widths(&[DISASS_GUTTER, 100, 80]);
+    disass.set_selection_color(color(theme.borrow().palette().current));
+    disass.set_trigger(CallbackTrigger::ReleaseAlways);
+    let disass_input   = Input::new(140, 108, 100, 24, "");
+    let mut disass_btn = Button::new(245, 108, 40, 24, "Go");
+
+    let mut mem_header = Frame::new(420, 100, 0, 40, "Memory at ").with_align(Align::Right);
     mem_header.set_label_type(LabelType::Engraved);
     mem_header.set_label_size(14);
 
@@ -126,36 +760,131 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     f.set_label_size(14);
     let mut f = Frame::new(580, 30, 100, 40, "Pipeline").with_align(Align::Right);
     f.set_label_size(14);
+    let mut f = Frame::new(650, 10, 100, 40, "L2").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(650, 30, 100, 40, "Write-Alloc").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(650, 50, 100, 40, "Unaligned").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(580, 50, 100, 40, "Non-Block").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(580, 70, 100, 40, "Predictor").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(650, 70, 100, 40, "Out-of-Order").with_align(Align::Right);
+    f.set_label_size(14);
+    let mut f = Frame::new(720, 10, 100, 40, "Fwd").with_align(Align::Right);
+    f.set_label_size(14);
 
     let mut caches_enabled   = Button::new(650, 20, 30, 20, "On");
     let mut pipeline_enabled = Button::new(650, 40, 30, 20, "On");
-
-    let err_log = Rc::new(RefCell::new(Frame::new(200, 490, 200, 40, "")
+    let mut l2_enabled       = Button::new(720, 20, 30, 20, "On");
+    let mut write_allocate   = Button::new(720, 40, 30, 20, "Off");
+    let mut unaligned_emu    = Button::new(720, 60, 30, 20, "Off");
+    let mut nonblocking      = Button::new(650, 60, 30, 20, 
+        if simulator.borrow().nonblocking_cache { "On" } else { "Off" });
+    let mut predictor_kind   = Button::new(650, 80, 30, 20, 
+        simulator.borrow().pipeline.predictor.kind.short_name());
+    let mut ooo_enabled      = Button::new(720, 80, 30, 20, "Off");
+    let mut forwarding       = Button::new(790, 20, 30, 20,
+        if simulator.borrow().forwarding_enabled { "On" } else { "Off" });
+
+    let err_log = Rc::new(RefCell::new(Frame::new(420, 490, 0, 40, "")
                                            .with_align(Align::Right)));
 
     err_log.borrow_mut().set_label_type(LabelType::Engraved);
     err_log.borrow_mut().set_label_size(14);
     err_log.borrow_mut().set_label_color(Color::Red);
 
-    let reg_displays = Rc::new(RefCell::new(get_reg_frames()));
-    let disass_view  = Rc::new(RefCell::new(get_instr_frames()));
-    let mem_view     = Rc::new(RefCell::new(get_mem_frames()));
+    let reg_displays = Rc::new(RefCell::new(get_reg_frames(theme.borrow().palette())));
+    let disass_state = Rc::new(RefCell::new(DisassState::default()));
+    let mem_view     = Rc::new(RefCell::new(get_mem_frames(theme.borrow().palette())));
     let pipeline     = Rc::new(RefCell::new(get_pipeline_frames()));
 
     let stage_names = ["Fetch ", "Decode", "Exec  ", "Mem   ", "WriteB"];
 
     let mem_disp_input   = Input::new(500, 100, 100, 30, "");
-    let mut mem_disp_btn = Button::new(610, 100, 200, 30, "Set Memory (in hex)");
-
-    let mut code_box     = MultilineInput::new(420, 540, 300, 200, "");
+    let mut mem_disp_btn = Button::new(610, 100, 140, 30, "Set Memory (in hex)");
+    let mut hex_btn      = Button::new(755, 100, 60, 30, "Hex Edit");
+    let mut follow_btn   = Button::new(420, 460, 100, 22, "");
+    follow_btn.set_label(&format!("Follow: {}", simulator.borrow().mem_follow.name()));
+    follow_btn.set_tooltip("Follow the pc or r15 with the memory view, other registers can be \
+                            entered as its address");
+
+    let mut code_box     = TextEditor::new(420, 540, 300, 200, "");
+    let mut code_buf     = TextBuffer::default();
+    let mut code_styles  = TextBuffer::default();
     let mut code_box_btn = Button::new(570, 740, 150, 30, "Assemble and Load");
+    let mut file_menu    = MenuButton::new(420, 740, 70, 30, "File");
+    let mut reload_btn   = Button::new(495, 740, 70, 30, "Reload");
+    let mut program      = ProgramFile::new(window.clone(), code_buf.clone(), code_box_btn.clone());
+    reload_btn.set_shortcut(Shortcut::Ctrl | 'r');
+    reload_btn.set_tooltip("Reload the program from its file and assemble it again (Ctrl+R)");
+
+    // Set while the simulator is running
+    let run_state: Rc<RefCell<Option<RunStart>>> = Rc::new(RefCell::new(None));
+
+    // Highlight mnemonics, registers, labels and comments, and the line that failed to assemble
+    // until the code is edited again
+    code_box.set_buffer(code_buf.clone());
+    code_box.set_text_font(Font::Courier);
+    code_box.set_linenumber_width(30);
+    code_box.set_linenumber_font(Font::Courier);
+    code_box.set_highlight_data_ext(code_styles.clone(),
+                                    code_style_table(theme.borrow().palette()));
+    code_buf.add_modify_callback({
+        let code_buf        = code_buf.clone();
+        let mut code_styles = code_styles.clone();
+        move |_, _, _, _, _| {
+            code_styles.set_text(&highlight::highlight(&code_buf.text(), None));
+        }
+    });
 
-    let run_state = Rc::new(RefCell::new(false));
+    // Describe the instruction under the mouse, or on the cursor's line while typing
+    let mut isa_pane = TextDisplay::new(420, 780, 300, 170, "");
+    let mut isa_buf  = TextBuffer::default();
+    isa_pane.set_buffer(isa_buf.clone());
+    isa_pane.set_text_font(Font::Courier);
+    isa_pane.set_text_size(12);
+    isa_buf.set_text("Hover over an instruction in the\ndisassembly or the code box to\n\
+                      see what it does and how it is\nencoded");
+    code_box.handle({
+        let code_buf    = code_buf.clone();
+        let mut isa_buf = isa_buf.clone();
+        move |editor, event| {
+            match event {
+                Event::Move => {
+                    draw::set_font(editor.text_font(), editor.text_size());
+                    let (height, y) = (draw::height(), app::event_y());
+                    let mut start = 0;
+                    loop {
+                        let (_, top) = editor.position_to_xy(start);
+                        if (top..top + height).contains(&y) {
+                            show_instr_reference(&mut isa_buf, &code_buf.line_text(start));
+                            break;
+                        }
+                        let end = editor.line_end(start, true);
+                        if end >= code_buf.length() {
+                            break;
+                        }
+                        start = end + 1;
+                    }
+                },
+                Event::KeyUp => {
+                    show_instr_reference(&mut isa_buf,
+                                         &code_buf.line_text(editor.insert_position()));
+                },
+                _ => {},
+            }
+            false
+        }
+    });
 
-    code_box.set_value("# Load code at this address (in hex)\n.load 0x10000\n._start\n");
-    code_box.append("\n# Insert instructions below\n\n").unwrap();
+    code_buf.set_text("# Begin execution at this label\n.entry _start\n\n\
+                       # Load code at this address (in hex)\n.load 0x10000\n._start\n\
+                       \n# Insert instructions below\n\n.end_section");
 
-    code_box.append(".end_section").unwrap();
+    // Pipeline title, also shows the fill-level of the fetch queue
+    let mut pipeline_title = Frame::new(10, 422, 0, 40, "").with_align(Align::Right);
 
     // Print pipeline titles/borders to gui
     {
@@ -163,9 +892,7 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
             .with_align(Align::Right);
         f.set_label_font(Font::CourierBold);
 
-        let mut f = Frame::new(10, 422, 0, 40, "|               Pipeline                |")
-            .with_align(Align::Right);
-        f.set_label_font(Font::CourierBold);
+        pipeline_title.set_label_font(Font::CourierBold);
 
         let mut f = Frame::new(10, 430, 0, 40, "+---------------------------------------+")
             .with_align(Align::Right);
@@ -219,47 +946,77 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
             .with_align(Align::Right);
         f.set_label_font(Font::CourierBold);
 
-        for i in 0..10 {
+        for i in 0..25 {
             let mut f = Frame::new(1030, 537+(i*16), 0, 40, 
                                    "|                          |")
                 .with_align(Align::Right);
             f.set_label_font(Font::CourierBold);
         }
 
-        let mut f = Frame::new(1030, 697, 0, 40, "+--------------------------+")
+        let mut f = Frame::new(1030, 937, 0, 40, "+--------------------------+")
             .with_align(Align::Right);
         f.set_label_font(Font::CourierBold);
     }
 
-    let mut hit_rate = Frame::new(1040, 560, 0, 40, "").with_align(Align::Right);
-    let mut cpu_time = Frame::new(1040, 560+16, 0, 40, "").with_align(Align::Right);
-    let mut mem_time = Frame::new(1040, 560+32, 0, 40, "").with_align(Align::Right);
-    let mut control_rate = Frame::new(1040, 560+48, 0, 40, "").with_align(Align::Right);
-    let mut load_rate = Frame::new(1040, 560+64, 0, 40, "").with_align(Align::Right);
-    let mut store_rate = Frame::new(1040, 560+80, 0, 40, "").with_align(Align::Right);
-    let mut arithmetic_rate = Frame::new(1040, 560+96, 0, 40, "").with_align(Align::Right);
-    let mut total_instrs_label = Frame::new(1040, 560+112, 0, 40, "").with_align(Align::Right);
+    let mut cpi_label = Frame::new(1040, 560, 0, 40, "").with_align(Align::Right);
+    let mut window_cpi_label = Frame::new(1040, 560+16, 0, 40, "").with_align(Align::Right);
+    let mut hit_rate = Frame::new(1040, 560+32, 0, 40, "").with_align(Align::Right);
+    let mut cpu_time = Frame::new(1040, 560+48, 0, 40, "").with_align(Align::Right);
+    let mut fetch_stalls = Frame::new(1040, 560+64, 0, 40, "").with_align(Align::Right);
+    let mut ldst_stalls = Frame::new(1040, 560+80, 0, 40, "").with_align(Align::Right);
+    let mut raw_stalls = Frame::new(1040, 560+96, 0, 40, "").with_align(Align::Right);
+    let mut control_stalls = Frame::new(1040, 560+112, 0, 40, "").with_align(Align::Right);
+    let mut struct_stalls = Frame::new(1040, 560+128, 0, 40, "").with_align(Align::Right);
+    let mut control_rate = Frame::new(1040, 560+144, 0, 40, "").with_align(Align::Right);
+    let mut load_rate = Frame::new(1040, 560+160, 0, 40, "").with_align(Align::Right);
+    let mut store_rate = Frame::new(1040, 560+176, 0, 40, "").with_align(Align::Right);
+    let mut arithmetic_rate = Frame::new(1040, 560+192, 0, 40, "").with_align(Align::Right);
+    let mut total_instrs_label = Frame::new(1040, 560+208, 0, 40, "").with_align(Align::Right);
+    let mut l2_hit_rate = Frame::new(1040, 560+224, 0, 40, "").with_align(Align::Right);
+    let mut misalign_time = Frame::new(1040, 560+240, 0, 40, "").with_align(Align::Right);
+    let mut bus_busy = Frame::new(1040, 560+256, 0, 40, "").with_align(Align::Right);
+    let mut nt_store_rate = Frame::new(1040, 560+272, 0, 40, "").with_align(Align::Right);
+    let mut page_walk_time = Frame::new(1040, 560+288, 0, 40, "").with_align(Align::Right);
+    let mut mispredict_rate = Frame::new(1040, 560+304, 0, 40, "").with_align(Align::Right);
+    let mut wrong_path_label = Frame::new(1040, 560+320, 0, 40, "").with_align(Align::Right);
+    let mut hit_under_miss = Frame::new(1040, 560+336, 0, 40, "").with_align(Align::Right);
+    let mut load_use_stalls = Frame::new(1040, 560+352, 0, 40, "").with_align(Align::Right);
+    let mut energy_label = Frame::new(1040, 560+368, 0, 40, "").with_align(Align::Right);
+    cpi_label.set_label_font(Font::CourierBold);
+    window_cpi_label.set_label_font(Font::CourierBold);
     hit_rate.set_label_font(Font::CourierBold);
     cpu_time.set_label_font(Font::CourierBold);
-    mem_time.set_label_font(Font::CourierBold);
+    fetch_stalls.set_label_font(Font::CourierBold);
+    ldst_stalls.set_label_font(Font::CourierBold);
+    raw_stalls.set_label_font(Font::CourierBold);
+    control_stalls.set_label_font(Font::CourierBold);
+    struct_stalls.set_label_font(Font::CourierBold);
     control_rate.set_label_font(Font::CourierBold);
     load_rate.set_label_font(Font::CourierBold);
     store_rate.set_label_font(Font::CourierBold);
     arithmetic_rate.set_label_font(Font::CourierBold);
     total_instrs_label.set_label_font(Font::CourierBold);
-
-    let mut cache_label    = Frame::new(25, 612, 0, 40, "").with_align(Align::Right);
-    let cache_disp_input   = Input::new(180, 642, 40, 20, "");
-    let mut cache_disp_btn = Button::new(160, 670, 80, 20, "Set-Idx");
-
-    let cache_idx_input   = Input::new(290, 642, 40, 20, "");
-    let mut cache_idx_btn = Button::new(270, 670, 100, 20, "Entry-Idx");
+    l2_hit_rate.set_label_font(Font::CourierBold);
+    misalign_time.set_label_font(Font::CourierBold);
+    bus_busy.set_label_font(Font::CourierBold);
+    nt_store_rate.set_label_font(Font::CourierBold);
+    page_walk_time.set_label_font(Font::CourierBold);
+    mispredict_rate.set_label_font(Font::CourierBold);
+    wrong_path_label.set_label_font(Font::CourierBold);
+    hit_under_miss.set_label_font(Font::CourierBold);
+    load_use_stalls.set_label_font(Font::CourierBold);
+    energy_label.set_label_font(Font::CourierBold);
+
+    let cache_grid = Rc::new(RefCell::new(get_cache_grid_frames()));
+    let mut cache_next_btn  = Button::new(380, 620, 40, 20, "Next");
+    let mut cache_level_btn = Button::new(380, 670, 40, 20, "L1");
+    let mut cache_heat_btn  = Button::new(380, 645, 40, 20, "Heat");
+    cache_next_btn.set_tooltip(&format!("Show the next {} sets", CACHE_GRID_SETS));
 
     let mut cache = Frame::new(130, 700, 0, 40, "").with_align(Align::Right);
     cache.set_label_font(Font::CourierBold);
 
-    let mut cache_description = Frame::new(20, 660, 0, 40, "").with_align(Align::Right);
-    cache.set_label_font(Font::CourierBold);
+    let mut cache_description = Frame::new(20, 700, 0, 40, "").with_align(Align::Right);
 
     let mut mem8  = Button::new(820, 110, 22, 20, "8");
     let mut mem16 = Button::new(842, 110, 22, 20, "16");
@@ -269,14 +1026,186 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     if args.len() == 2 {
         let buf = std::fs::read_to_string(&args[1]).unwrap();
         simulator.borrow_mut().load_input(&buf, &err_log).expect("Failed to load provided input");
+        code_buf.set_text(&buf);
+        program.set_path(PathBuf::from(&args[1]), &err_log);
     }
 
-    let vga_driver = VgaDriver::new();
-    simulator.borrow_mut().vga = vga_driver;
+    // Screens that don't fit into their slot are shown in a window of their own
+    let vga_size = simulator.borrow().vga_size;
+    let (vga_w, vga_h) = VgaDriver::display_size(vga_size);
+    let mut vga_window = None;
+    let vga_driver = if (vga_w, vga_h) == (VGA_SLOT.2, VGA_SLOT.3) {
+        VgaDriver::new(vga_size, VGA_SLOT.0, VGA_SLOT.1)
+    } else {
+        Group::set_current(None::<&Group>);
+        let screen_window = Window::default().with_size(vga_w, vga_h).with_label("Screen");
+        let vga_driver = VgaDriver::new(vga_size, 0, 0);
+        screen_window.end();
+        window.begin();
+        vga_window = Some(screen_window);
+        vga_driver
+    };
+    let mut vga_display = vga_driver.display.clone();
+    simulator.borrow_mut().devices.register(VAddr(VGA_BASE), vga_size.device_size(),
+                                            Box::new(vga_driver))
+        .expect("Failed to register vga-buffer");
+    let uart = Uart::new();
+    simulator.borrow_mut().register_device(VAddr(UART_BASE), UART_SIZE, Some(UART_IRQ),
+                                           Box::new(uart.clone()))
+        .expect("Failed to register uart");
+    let putc = PutcPort::new();
+    simulator.borrow_mut().devices.register(VAddr(PUTC_BASE), PUTC_SIZE, Box::new(putc.clone()))
+        .expect("Failed to register putc port");
+    let gpio = Gpio::new();
+    simulator.borrow_mut().devices.register(VAddr(GPIO_BASE), GPIO_SIZE, Box::new(gpio.clone()))
+        .expect("Failed to register gpio");
+    let display = SevenSegment::new();
+    simulator.borrow_mut().devices.register(VAddr(SEGMENT_BASE), SEGMENT_SIZE,
+                                            Box::new(display.clone()))
+        .expect("Failed to register seven-segment display");
+    let mouse = Mouse::new();
+    simulator.borrow_mut().register_device(VAddr(MOUSE_BASE), MOUSE_SIZE, Some(MOUSE_IRQ),
+                                           Box::new(mouse.clone()))
+        .expect("Failed to register mouse");
+
+    // Feed the mouse with the pointer's position on the screen and the buttons pressed over it.
+    // Other than entering, which asks for move events, events are passed on so text can still be
+    // selected
+    vga_display.handle(move |display, event| {
+        let button = || match app::event_mouse_button() {
+            app::MouseButton::Left => MOUSE_LEFT,
+            app::MouseButton::Right => MOUSE_RIGHT,
+            app::MouseButton::Middle => MOUSE_MIDDLE,
+            _ => 0,
+        };
+        match event {
+            Event::Move | Event::Drag | Event::Push | Event::Released => {
+                let x = (app::event_x() - display.x()).clamp(0, display.w() - 1);
+                let y = (app::event_y() - display.y()).clamp(0, display.h() - 1);
+                mouse.move_to(x as u32, y as u32);
+                match event {
+                    Event::Push => mouse.set_buttons(mouse.buttons() | button()),
+                    Event::Released => mouse.set_buttons(mouse.buttons() & !button()),
+                    _ => {},
+                }
+            },
+            Event::Enter => return true,
+            Event::Leave => mouse.set_buttons(0),
+            _ => {},
+        }
+        false
+    });
+
+    // Split the window into the toolbar and panels, and fit it onto smaller screens
+    let panels = Rc::new(RefCell::new(Panels::new(&mut window)));
+    let (_, _, screen_w, screen_h) = app::screen_work_area(0);
+    window.size_range(MIN_WINDOW_W, MIN_WINDOW_H, 0, 0);
+    window.set_size(std::cmp::min(LAYOUT_W, screen_w).max(MIN_WINDOW_W),
+                    std::cmp::min(LAYOUT_H, screen_h - window.y()).max(MIN_WINDOW_H));
 
-    window.set_color(Color::White);
+    window.set_color(color(theme.borrow().palette().window));
+    panels.borrow_mut().set_color(color(theme.borrow().palette().window));
     window.end();
     window.show();
+    if let Some(vga_window) = vga_window.as_mut() {
+        vga_window.show();
+    }
+
+    left_btn.set_callback({
+        let panels = panels.clone();
+        move |_| panels.borrow_mut().toggle(0)
+    });
+
+    right_btn.set_callback({
+        let panels = panels.clone();
+        move |_| panels.borrow_mut().toggle(2)
+    });
+
+    // Switch to the next theme, widgets in the default colors follow it and the others are
+    // recolored
+    theme_btn.set_callback({
+        let theme        = theme.clone();
+        let mem_view     = mem_view.clone();
+        let panels       = panels.clone();
+        let code_styles  = code_styles.clone();
+        let mut window   = window.clone();
+        let mut disass   = disass.clone();
+        let mut code_box = code_box.clone();
+        move |b| {
+            let next = theme.borrow().next();
+            *theme.borrow_mut() = next;
+            let palette = next.palette();
+            apply_theme(palette);
+            window.set_color(color(palette.window));
+            panels.borrow_mut().set_color(color(palette.window));
+            disass.set_selection_color(color(palette.current));
+            code_box.set_highlight_data_ext(code_styles.clone(), code_style_table(palette));
+            for (i, frame) in mem_view.borrow_mut().iter_mut().enumerate() {
+                frame.set_label_color(row_color(palette, i));
+            }
+            b.set_label(&format!("Theme: {}", next.name()));
+            app::redraw();
+        }
+    });
+
+    // Open a program into the code box and load it, or save the code box to a file. Programs
+    // opened before are listed under `Open Recent`, which is filled in whenever the menu opens
+    file_menu.add("Open...", Shortcut::Ctrl | 'o', MenuFlag::Normal, {
+        let mut program = program.clone();
+        let err_log     = err_log.clone();
+        move |_| {
+            if let Some(path) = choose_program_file(false, program.path().as_deref()) {
+                program.open(path, &err_log);
+            }
+        }
+    });
+    file_menu.add("Open Recent/No recent programs", Shortcut::None, MenuFlag::Inactive, |_| {});
+    file_menu.add("Save", Shortcut::Ctrl | 's', MenuFlag::Normal, {
+        let mut program = program.clone();
+        let err_log     = err_log.clone();
+        move |_| {
+            let path = program.path();
+            program.save(path, &err_log);
+        }
+    });
+    file_menu.add("Save As...", Shortcut::Ctrl | Shortcut::Shift | 's', MenuFlag::Normal, {
+        let mut program = program.clone();
+        let err_log     = err_log.clone();
+        move |_| program.save(None, &err_log)
+    });
+    file_menu.handle({
+        let program = program.clone();
+        let err_log = err_log.clone();
+        move |menu, event| {
+            if event != Event::Push {
+                return false;
+            }
+            let _ = menu.clear_submenu(menu.find_index("Open Recent"));
+            let paths = program.recent.borrow().paths().to_vec();
+            if paths.is_empty() {
+                menu.add("Open Recent/No recent programs", Shortcut::None, MenuFlag::Inactive,
+                         |_| {});
+            }
+            for path in paths {
+                let label = format!("Open Recent/{}", menu_label(&path.display().to_string()));
+                let mut program = program.clone();
+                let err_log     = err_log.clone();
+                menu.add(&label, Shortcut::None, MenuFlag::Normal,
+                         move |_| program.open(path.clone(), &err_log));
+            }
+            false
+        }
+    });
+
+    // Pick up changes made to the program's file in an external editor
+    reload_btn.set_callback({
+        let err_log = err_log.clone();
+        move |_| match program.path() {
+            Some(path) => program.open(path, &err_log),
+            None => gui_err_print("Error: No program file to reload, open or save one first",
+                                  &err_log),
+        }
+    });
 
     mem8.set_callback({
         let mem_size = mem_size.clone();
@@ -302,149 +1231,487 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     mem_disp_btn.set_callback({
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
+        let mut follow_btn = follow_btn.clone();
         move |_| {
             let raw = mem_disp_input.value();
             let without_prefix = raw.trim_start_matches("0x");
-            if let Ok(addr) = u32::from_str_radix(without_prefix, 16) {
-                simulator.borrow_mut().cur_mem = VAddr(addr);
+            let mut sim = simulator.borrow_mut();
+            if let Some(follow) = MemFollow::parse(&raw) {
+                sim.mem_follow = follow;
+            } else if let Ok(addr) = u32::from_str_radix(without_prefix, 16) {
+                sim.cur_mem    = VAddr(addr);
+                sim.mem_follow = MemFollow::Fixed;
             } else {
                 gui_err_print("Error: Invalid Address", &err_log);
             }
+            follow_btn.set_label(&format!("Follow: {}", sim.mem_follow.name()));
         }
     });
 
-    bp_btn.set_callback({
+    // Cycle the memory view between its fixed address, the pc and r15
+    follow_btn.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let follow = simulator.borrow().mem_follow.next();
+            simulator.borrow_mut().mem_follow = follow;
+            b.set_label(&format!("Follow: {}", follow.name()));
+        }
+    });
+
+    // Run until the pc reaches the address in the breakpoint input
+    until_btn.set_callback({
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
+        let bp_input  = bp_input.clone();
         move |_| {
             let raw = bp_input.value();
             let without_prefix = raw.trim_start_matches("0x");
             if let Ok(addr) = u32::from_str_radix(without_prefix, 16) {
-                simulator.borrow_mut().breakpoints.insert(addr, 0);
+                let stop = simulator.borrow_mut().run_until(VAddr(addr), DEBUG_CYCLE_LIMIT,
+                                                            &err_log);
+                report_debug_stop(stop, &err_log);
             } else {
                 gui_err_print("Error: Invalid Address", &err_log);
             }
         }
     });
 
-    cache_disp_btn.set_callback({
+    over_btn.set_callback({
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
         move |_| {
-            let raw = cache_disp_input.value();
-            let index = raw.parse::<usize>().unwrap();
-            if index < 32 {
-                simulator.borrow_mut().cur_cache_set.0 = index;
-            } else {
-                gui_err_print("Error: Cache has 32 sets, so only enter [0-31] for the set-idx", 
-                              &err_log);
-            }
+            let stop = simulator.borrow_mut().step_over(DEBUG_CYCLE_LIMIT, &err_log);
+            report_debug_stop(stop, &err_log);
         }
     });
 
-    cache_idx_btn.set_callback({
+    out_btn.set_callback({
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
         move |_| {
-            let raw = cache_idx_input.value();
-            let index = raw.parse::<usize>().unwrap();
-            if index < 4 {
-                simulator.borrow_mut().cur_cache_set.1 = index;
-            } else {
-                gui_err_print("Error: Cache is 4-way associative, so only enter [0-3] for the \
-                              entry-idx", &err_log);
+            let stop = simulator.borrow_mut().step_out(DEBUG_CYCLE_LIMIT, &err_log);
+            report_debug_stop(stop, &err_log);
+        }
+    });
+
+    // Return to the state of the last breakpoint stop, to run the same region again
+    rerun_btn.set_callback({
+        let simulator = simulator.clone();
+        let run_state = run_state.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            *run_state.borrow_mut() = None;
+            match simulator.borrow_mut().restart_from_stop() {
+                Ok(true) => gui_log_print("Restarted from the last breakpoint stop", &err_log),
+                Ok(false) => gui_err_print("Error: No breakpoint was hit yet", &err_log),
+                Err(err) => gui_err_print(&format!("Error: Could not restart: {}", err),
+                                          &err_log),
             }
         }
     });
 
-    pipeline_enabled.set_callback({
+    // Return the machine to its power-on state, optionally reloading the program and keeping the
+    // breakpoints
+    reset_btn.set_callback({
         let simulator = simulator.clone();
-        move |b| {
-            let pe = simulator.borrow().pipelining_enabled;
-            if pe {
-                simulator.borrow_mut().pipelining_enabled = false;
-                b.set_label("Off");
-            } else {
-                simulator.borrow_mut().pipelining_enabled = true;
-                b.set_label("On");
+        let run_state = run_state.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            let keep_program = match dialog::choice2_default("Reset the machine?", "Cancel",
+                                                             "Clear program", "Keep program") {
+                Some(1) => false,
+                Some(2) => true,
+                _ => return,
+            };
+            *run_state.borrow_mut() = None;
+            match simulator.borrow_mut().reset(keep_program, &err_log) {
+                Ok(()) => gui_log_print("Machine was reset", &err_log),
+                Err(err) => gui_err_print(&format!("Error: Reset failed: {:?}", err), &err_log),
             }
         }
     });
 
-    caches_enabled.set_callback({
+    bp_btn.set_callback({
         let simulator = simulator.clone();
-        move |b| {
-            let ce = simulator.borrow().mmu.cache_enabled;
-            if ce {
-                simulator.borrow_mut().mmu.cache_enabled = false;
-                b.set_label("Off");
+        let err_log   = err_log.clone();
+        move |_| {
+            let raw = bp_input.value();
+            let without_prefix = raw.trim_start_matches("0x");
+            if let Ok(addr) = u32::from_str_radix(without_prefix, 16) {
+                simulator.borrow_mut().breakpoints.insert(addr, 0);
             } else {
-                simulator.borrow_mut().mmu.cache_enabled = true;
-                b.set_label("On");
+                gui_err_print("Error: Invalid Address", &err_log);
             }
         }
     });
 
-    for i in 0..NUM_REGS {
-        let simulator    = simulator.clone();
-        let reg_displays = reg_displays.clone();
-        app::add_idle3(move |_| {
-            let reg_str = if i < 10 {
-                format!("R{i}:  0x{:0>8x}", simulator.borrow().gen_regs[i])
-            } else {
-                format!("R{i}: 0x{:0>8x}", simulator.borrow().gen_regs[i])
-            };
-            reg_displays.borrow_mut()[i].set_label(&reg_str);
+    seed_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            match seed_input.value().trim().parse::<u64>() {
+                Ok(seed) => {
+                    simulator.borrow_mut().set_seed(seed);
+                    gui_log_print(&format!("Seed set to {}, reload the program to reproduce a run",
+                                           seed), &err_log);
+                },
+                Err(_) => gui_err_print("Error: Seed has to be a decimal number", &err_log),
+            }
+        }
+    });
+
+    // Select the clicked entry of the cache grid for the line-display
+    for (idx, cell) in cache_grid.borrow_mut().iter_mut().enumerate() {
+        let simulator = simulator.clone();
+        cell.handle(move |_, event| {
+            if event != Event::Push {
+                return false;
+            }
+            let mut sim = simulator.borrow_mut();
+            let set = (sim.cur_cache_set.0 & !(CACHE_GRID_SETS - 1)) + idx / CACHE_GRID_WAYS;
+            let way = idx % CACHE_GRID_WAYS;
+            let cache = sim.displayed_cache();
+            if set < cache.num_sets && way < cache.ways {
+                sim.cur_cache_set = (set, way);
+            }
+            true
         });
-    };
+    }
 
-    for i in 0..11 {
-        let disass_view = disass_view.clone();
+    // Move the cache grid on to the next block of sets of caches with more sets than fit
+    cache_next_btn.set_callback({
+        let simulator = simulator.clone();
+        move |_| {
+            let mut sim  = simulator.borrow_mut();
+            let num_sets = sim.displayed_cache().num_sets;
+            let first    = sim.cur_cache_set.0 & !(CACHE_GRID_SETS - 1);
+            sim.cur_cache_set = ((first + CACHE_GRID_SETS) % num_sets, 0);
+        }
+    });
+
+    // Switch the cache-panel between displaying the L1 and L2 cache
+    cache_level_btn.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let level = if simulator.borrow().cur_cache_level == 1 { 2 } else { 1 };
+            simulator.borrow_mut().cur_cache_level = level;
+            simulator.borrow_mut().cur_cache_set   = (0, 0);
+            b.set_label(&format!("L{}", level));
+        }
+    });
+
+    // Switch the cache grid between showing valid entries and the eviction heatmap
+    cache_heat_btn.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let heatmap = !simulator.borrow().cache_heatmap;
+            simulator.borrow_mut().cache_heatmap = heatmap;
+            b.set_label(if heatmap { "Valid" } else { "Heat" });
+        }
+    });
+
+    pipeline_enabled.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            // Both modes track in-flight instructions differently, so drop them when switching
+            let pe = simulator.borrow().pipelining_enabled;
+            simulator.borrow_mut().flush_pipeline();
+            if pe {
+                simulator.borrow_mut().pipelining_enabled = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().pipelining_enabled = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    l2_enabled.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let le = simulator.borrow().mmu.l2_enabled;
+            if le {
+                simulator.borrow_mut().mmu.l2_enabled = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().mmu.l2_enabled = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    write_allocate.set_callback({
         let simulator = simulator.clone();
-        // We are displaying 5 instructions around pc (before and after)
+        move |b| {
+            let wa = simulator.borrow().mmu.write_allocate;
+            if wa {
+                simulator.borrow_mut().mmu.write_allocate = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().mmu.write_allocate = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    ooo_enabled.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let enabled = !simulator.borrow().ooo_enabled;
+            simulator.borrow_mut().set_out_of_order(enabled);
+            b.set_label(if enabled { "On" } else { "Off" });
+        }
+    });
+
+    predictor_kind.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let next = simulator.borrow().pipeline.predictor.kind.next();
+            simulator.borrow_mut().pipeline.predictor = BranchPredictor::new(next);
+            b.set_label(next.short_name());
+        }
+    });
+
+    unaligned_emu.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let ue = simulator.borrow().unaligned_emulation;
+            if ue {
+                simulator.borrow_mut().unaligned_emulation = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().unaligned_emulation = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    nonblocking.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let nb = simulator.borrow().nonblocking_cache;
+            if nb {
+                simulator.borrow_mut().nonblocking_cache = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().nonblocking_cache = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    forwarding.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            // Instructions that already passed decode may rely on forwarding
+            let fe = simulator.borrow().forwarding_enabled;
+            simulator.borrow_mut().flush_pipeline();
+            if fe {
+                simulator.borrow_mut().forwarding_enabled = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().forwarding_enabled = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    caches_enabled.set_callback({
+        let simulator = simulator.clone();
+        move |b| {
+            let ce = simulator.borrow().mmu.cache_enabled;
+            if ce {
+                simulator.borrow_mut().mmu.cache_enabled = false;
+                b.set_label("Off");
+            } else {
+                simulator.borrow_mut().mmu.cache_enabled = true;
+                b.set_label("On");
+            }
+        }
+    });
+
+    for i in 0..NUM_REGS {
+        let simulator    = simulator.clone();
+        let reg_displays = reg_displays.clone();
+        let theme        = theme.clone();
         app::add_idle3(move |_| {
-            let cur_pc = if i < 5 {
-                (simulator.borrow().pc.0 - (5 * 4)) + (i * 4)
+            let simulator = simulator.borrow();
+            let palette   = theme.borrow().palette();
+            let mut reg_str = if i < 10 {
+                format!("R{i}:  0x{:0>8x}", simulator.gen_regs[i])
             } else {
-                simulator.borrow().pc.0 + ((i - 5) * 4)
+                format!("R{i}: 0x{:0>8x}", simulator.gen_regs[i])
             };
 
-            // Read bytes for instruction from memory
-            let mut b = vec![0x0u8; 4];
-            let _ = simulator.borrow_mut().gui_mem_read(VAddr(cur_pc), &mut b);
+            // Scoreboard column, shows the stage of the instruction that will write this register
+            if let Some(slot) = simulator.scoreboard()[i] {
+                reg_str.push_str(&format!("  {}", stage_names[slot].trim_end()));
+            }
 
-            let instr = match simulator.borrow_mut().gui_decode_instr(VAddr(cur_pc)) {
-                Ok(e) => e,
-                Err(_) => Instr::None,
-            };
+            // Dependences of the instruction in decode through this register
+            let mut kinds = Vec::new();
+            for hazard in simulator.decode_hazards() {
+                if hazard.reg as usize == i && !kinds.contains(&hazard.kind.short_name()) {
+                    kinds.push(hazard.kind.short_name());
+                }
+            }
+            if !kinds.is_empty() {
+                reg_str.push_str(&format!("  {}", kinds.join("/")));
+            }
 
-            let instr_str = if cur_pc == simulator.borrow().pc.0 {
-                format!("* 0x{:0>8x}: {:0>2x}{:0>2x}{:0>2x}{:0>2x} {}",
-                        cur_pc, b[0], b[1], b[2], b[3], instr)
+            // Highlight registers that the instruction in decode is stalled on, registers it only
+            // has write-after-write/write-after-read dependences through, and registers that were
+            // written during the last cycle
+            let reg = Register::from(i as u32);
+            let history = simulator.reg_history.as_ref();
+            let color = if simulator.hazard_regs().contains(&reg) {
+                Color::Red
+            } else if !kinds.is_empty() && !kinds.contains(&"RAW") {
+                color(palette.dependence)
+            } else if history.is_some_and(|h| h.written_in(reg, simulator.clock.wrapping_sub(1))) {
+                color(palette.written)
             } else {
-                format!("  0x{:0>8x}: {:0>2x}{:0>2x}{:0>2x}{:0>2x} {}",
-                        cur_pc, b[0], b[1], b[2], b[3], instr)
+                row_color(palette, i)
             };
-            disass_view.borrow_mut()[i as usize].redraw_label();
-            disass_view.borrow_mut()[i as usize].set_label(&instr_str);
+
+            // The most recent writes are listed when hovering over the register
+            let tooltip = history.map_or(String::new(), |history| {
+                history.writes(reg).map(|write| {
+                    format!("{:>8}  {:#010x}  {:#x} -> {:#x}", write.cycle, write.pc.0, write.old,
+                            write.new)
+                }).collect::<Vec<_>>().join("\n")
+            });
+
+            let mut reg_displays = reg_displays.borrow_mut();
+            reg_displays[i].set_label_color(color);
+            reg_displays[i].set_label(&reg_str);
+            if reg_displays[i].tooltip().unwrap_or_default() != tooltip {
+                reg_displays[i].set_tooltip(&tooltip);
+            }
         });
     };
 
+    // Keep the disassembly up to date. It follows the pc when it moves, and is only rebuilt when
+    // something it shows changed so it can be scrolled
+    app::add_idle3({
+        let simulator    = simulator.clone();
+        let disass_state = disass_state.clone();
+        let mut disass   = disass.clone();
+        let mut last_pc  = None;
+        move |_| {
+            let mut sim   = simulator.borrow_mut();
+            let mut state = disass_state.borrow_mut();
+            let pc = sim.pc.0;
+            if last_pc != Some(pc) {
+                last_pc = Some(pc);
+                if pc.wrapping_sub(state.base) >= DISASS_ROWS * 4 {
+                    state.base = pc.saturating_sub(DISASS_ROWS / 2 * 4);
+                }
+                state.scroll_to = Some(pc);
+            }
+
+            let base = state.base;
+            let breakpoints = (0..DISASS_ROWS).map(|row| base.wrapping_add(row * 4))
+                .filter(|addr| sim.breakpoints.contains_key(addr))
+                .collect::<Vec<_>>();
+            let shown = (pc, sim.clock, breakpoints);
+            if state.shown.as_ref() == Some(&shown) {
+                return;
+            }
+
+            let position = disass.position();
+            disass.clear();
+            for row in 0..DISASS_ROWS {
+                let addr = base.wrapping_add(row * 4);
+                let mut b = [0u8; 4];
+                let _ = sim.gui_mem_read(VAddr(addr), &mut b);
+                let instr = sim.gui_decode_instr(VAddr(addr)).unwrap_or(Instr::None);
+                let gutter = if shown.2.contains(&addr) { "@C88@.●" } else { "" };
+                disass.add(&format!("{}\t0x{:0>8x}\t{:0>2x}{:0>2x}{:0>2x}{:0>2x}\t{}", gutter, addr,
+                                    b[0], b[1], b[2], b[3], instr));
+            }
+            disass.set_position(position);
+
+            let line = |addr: u32| (addr.wrapping_sub(base) < DISASS_ROWS * 4)
+                .then(|| (addr.wrapping_sub(base) / 4 + 1) as i32);
+            if let Some(line) = line(pc) {
+                disass.select(line);
+            }
+            if let Some(line) = state.scroll_to.take().and_then(line) {
+                disass.make_visible(line);
+            }
+            state.shown = Some(shown);
+        }
+    });
+
+    // Clicking into the gutter toggles a breakpoint, the list is rebuilt to select the pc again
+    disass.set_callback({
+        let simulator    = simulator.clone();
+        let disass_state = disass_state.clone();
+        move |disass| {
+            let mut state = disass_state.borrow_mut();
+            if disass.value() > 0 && app::event_x() < disass.x() + DISASS_GUTTER {
+                let addr = state.base.wrapping_add((disass.value() - 1) as u32 * 4);
+                let breakpoints = &mut simulator.borrow_mut().breakpoints;
+                if breakpoints.remove(&addr).is_none() {
+                    breakpoints.insert(addr, 0);
+                }
+            }
+            state.shown = None;
+        }
+    });
+
+    // Describe the instruction under the mouse. Entering asks for move events
+    disass.handle(move |disass, event| {
+        match event {
+            Event::Enter => return true,
+            Event::Move => {
+                draw::set_font(Font::Helvetica, disass.text_size());
+                let top = disass.y() + disass.frame().dy() - disass.position();
+                let row = (app::event_y() - top) / draw::height();
+                if let Some(text) = disass.text(row + 1).filter(|_| row >= 0) {
+                    show_instr_reference(&mut isa_buf, text.rsplit('\t').next().unwrap());
+                }
+            },
+            _ => {},
+        }
+        false
+    });
+
+    // List the instructions around an address
+    disass_btn.set_callback({
+        let disass_state = disass_state.clone();
+        let err_log      = err_log.clone();
+        move |_| {
+            let raw = disass_input.value();
+            match u32::from_str_radix(raw.trim_start_matches("0x"), 16) {
+                Ok(addr) => {
+                    let mut state = disass_state.borrow_mut();
+                    state.base      = (addr & !3).saturating_sub(DISASS_ROWS / 2 * 4);
+                    state.scroll_to = Some(addr & !3);
+                    state.shown     = None;
+                },
+                Err(_) => gui_err_print("Error: Invalid Address", &err_log),
+            }
+        }
+    });
+
     for i in 0..11 {
         let mem_view  = mem_view.clone();
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
         let mem_size  = mem_size.clone();
         app::add_idle3(move |_| {
-            if (simulator.borrow().cur_mem.0 & 0x3) != 0 {
+            let view_addr = simulator.borrow().mem_view_addr().0;
+            if (view_addr & 0x3) != 0 {
                 gui_err_print("Memory Display Addr not aligned on 4-byte boundary", &err_log);
                 return;
             }
 
             let cur_memline_addr = if i < 5 {
-                simulator.borrow().cur_mem.0.wrapping_sub(5 * 16) + (i * 16)
+                view_addr.wrapping_sub(5 * 16) + (i * 16)
             } else {
-                simulator.borrow().cur_mem.0 + ((i - 5) * 16)
+                view_addr.wrapping_add((i - 5) * 16)
             };
 
             // Load bytes from memory, each line on our display is 16-bytes,
@@ -452,7 +1719,7 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
             let mut buf = Vec::new();
             let mut reader = vec![0u8; 4];
             for i in 0..4 {
-                let _ = simulator.borrow_mut().gui_mem_read(VAddr(cur_memline_addr + i*4), &mut reader);
+                let _ = simulator.borrow_mut().gui_mem_read(VAddr(cur_memline_addr.wrapping_add(i*4)), &mut reader);
                 buf.extend_from_slice(&reader);
             }
 
@@ -497,117 +1764,1020 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
         }
     });
 
-    quit_btn.set_callback(move |_| {
-        app.quit();
-        window.clear();
+    snap_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            simulator.borrow_mut().take_snapshot();
+            gui_log_print("Took memory snapshot", &err_log);
+        }
     });
 
-    step_btn.set_callback({
+    save_btn.set_callback({
         let simulator = simulator.clone();
         let err_log   = err_log.clone();
         move |_| {
-            simulator.borrow_mut().step(&err_log);
+            match simulator.borrow().save_state(Path::new(DEFAULT_STATE_PATH)) {
+                Ok(()) => gui_log_print(&format!("Saved state to {}", DEFAULT_STATE_PATH),
+                                        &err_log),
+                Err(err) => gui_err_print(&format!("Error: Could not save state: {}", err),
+                                          &err_log),
+            }
         }
     });
 
-    run_btn.set_callback({
-        let run_state = run_state.clone();
+    // Buttons whose labels reflect the simulator state, and need to be updated once a saved state
+    // replaces it
+    fn on_off(enabled: bool) -> String {
+        if enabled { "On" } else { "Off" }.to_string()
+    }
+    type StateLabel = fn(&Simulator) -> String;
+    let state_labels: Vec<(Button, StateLabel)> = vec![
+        (caches_enabled.clone(),   |sim| on_off(sim.mmu.cache_enabled)),
+        (pipeline_enabled.clone(), |sim| on_off(sim.pipelining_enabled)),
+        (l2_enabled.clone(),       |sim| on_off(sim.mmu.l2_enabled)),
+        (write_allocate.clone(),   |sim| on_off(sim.mmu.write_allocate)),
+        (unaligned_emu.clone(),    |sim| on_off(sim.unaligned_emulation)),
+        (nonblocking.clone(),      |sim| on_off(sim.nonblocking_cache)),
+        (ooo_enabled.clone(),      |sim| on_off(sim.ooo_enabled)),
+        (forwarding.clone(),       |sim| on_off(sim.forwarding_enabled)),
+        (predictor_kind.clone(),   |sim| sim.pipeline.predictor.kind.short_name().to_string()),
+        (cache_level_btn.clone(),  |sim| format!("L{}", sim.cur_cache_level)),
+        (cache_heat_btn.clone(),   |sim| {
+            if sim.cache_heatmap { "Valid" } else { "Heat" }.to_string()
+        }),
+        (follow_btn.clone(),       |sim| format!("Follow: {}", sim.mem_follow.name())),
+        (trace_btn.clone(),        |_| "Record Trace".to_string()),
+        (exec_btn.clone(),         |_| "Exec Trace".to_string()),
+    ];
+
+    load_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        let mut state_labels = state_labels;
         move |_| {
-            *run_state.borrow_mut() = true;
+            let mut simulator = simulator.borrow_mut();
+            match simulator.load_state(Path::new(DEFAULT_STATE_PATH)) {
+                Ok(()) => {
+                    for (button, label) in state_labels.iter_mut() {
+                        button.set_label(&label(&simulator));
+                    }
+                    gui_log_print(&format!("Loaded state from {}", DEFAULT_STATE_PATH),
+                                  &err_log);
+                },
+                Err(err) => gui_err_print(&format!("Error: Could not load state: {}", err),
+                                          &err_log),
+            }
         }
     });
 
-    // Run Simulator
+    // Open a separate window listing all memory words that changed since the last snapshot
+    diff_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            let changes = match simulator.borrow().snapshot_diff() {
+                Some(changes) => changes,
+                None => {
+                    gui_err_print("Error: No snapshot taken yet", &err_log);
+                    return;
+                }
+            };
+
+            let mut text = format!("{} words changed since last snapshot\n\n", changes.len());
+            for change in changes.iter().take(MAX_DIFF_LINES) {
+                text.push_str(&format!("{:#010x}: {:08x} -> {:08x}\n", change.addr.0, change.old,
+                                       change.new));
+            }
+            if changes.len() > MAX_DIFF_LINES {
+                text.push_str(&format!("... {} more\n", changes.len() - MAX_DIFF_LINES));
+            }
+
+            let mut diff_window = Window::new(200, 200, 400, 500, 
+                                              "What changed since last snapshot?");
+            let mut buf = TextBuffer::default();
+            buf.set_text(&text);
+            let mut display = TextDisplay::new(0, 0, 400, 500, "");
+            display.set_buffer(buf);
+            display.set_text_font(Font::Courier);
+            diff_window.end();
+            diff_window.show();
+        }
+    });
+
+    maps_btn.set_callback({
+        let simulator = simulator.clone();
+        move |_| {
+            let mut text = format!("{:<23} {:<10} {:<5} {}\n", "Virtual", "Physical", "Perms",
+                                   "Size");
+            for mapping in simulator.borrow().mmu.dump_mappings() {
+                let end   = mapping.vaddr.0 as usize + mapping.size - 1;
+                let paddr = match mapping.paddr {
+                    Some(paddr) => format!("{:#010x}", paddr.0),
+                    None        => "lazy".to_string(),
+                };
+                text.push_str(&format!("{:#010x}-{:#010x} {:<10} {:<5} {:#x}{}\n", 
+                                       mapping.vaddr.0, end, paddr, 
+                                       Perms::describe(mapping.perms), mapping.size,
+                                       if mapping.large { " (large)" } else { "" }));
+            }
+
+            let mut maps_window = Window::new(200, 200, 500, 500, "Page-table mappings");
+            let mut buf = TextBuffer::default();
+            buf.set_text(&text);
+            let mut display = TextDisplay::new(0, 0, 500, 500, "");
+            display.set_buffer(buf);
+            display.set_text_font(Font::Courier);
+            maps_window.end();
+            maps_window.show();
+        }
+    });
+
+    // Editable hex dump starting at the displayed memory. Edited bytes are written to memory by
+    // `Apply`, unmapped bytes are shown as `--` and can't be edited
+    hex_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            let mut hex_window = Window::new(200, 200, 620, 430, "Hex editor");
+            let mut addr_input = Input::new(0, 0, 150, 30, "");
+            let mut go_btn     = Button::new(155, 0, 60, 30, "Go");
+            let mut reload_btn = Button::new(220, 0, 60, 30, "Reload");
+            let mut apply_btn  = Button::new(285, 0, 60, 30, "Apply");
+            let mut editor     = TextEditor::new(0, 30, 620, 400, "");
+            let buf            = TextBuffer::default();
+            let cur_addr       = Rc::new(RefCell::new(simulator.borrow().mem_view_addr()));
+            editor.set_buffer(buf.clone());
+            editor.set_text_font(Font::Courier);
+            addr_input.set_value(&format!("{:#x}", cur_addr.borrow().0));
+
+            let mut reload = {
+                let simulator = simulator.clone();
+                let cur_addr  = cur_addr.clone();
+                let mut buf   = buf.clone();
+                move || {
+                    let text = hexedit::dump(&mut simulator.borrow_mut(), *cur_addr.borrow(),
+                                             HEX_EDIT_LINES);
+                    buf.set_text(&text);
+                }
+            };
+            reload();
+            go_btn.set_callback({
+                let err_log    = err_log.clone();
+                let mut reload = reload.clone();
+                move |_| {
+                    let raw = addr_input.value();
+                    match u32::from_str_radix(raw.trim_start_matches("0x"), 16) {
+                        Ok(addr) => {
+                            *cur_addr.borrow_mut() = VAddr(addr);
+                            reload();
+                        },
+                        Err(_) => gui_err_print("Error: Invalid Address", &err_log),
+                    }
+                }
+            });
+            reload_btn.set_callback({
+                let mut reload = reload.clone();
+                move |_| reload()
+            });
+            apply_btn.set_callback({
+                let simulator = simulator.clone();
+                let err_log   = err_log.clone();
+                let mut reload = reload.clone();
+                move |_| {
+                    let result = hexedit::apply(&mut simulator.borrow_mut(), &buf.text());
+                    match result {
+                        Ok(count) => {
+                            gui_log_print(&format!("Patched {} bytes", count), &err_log);
+                            reload();
+                        },
+                        Err(err) => gui_err_print(&format!("Error: {}", err), &err_log),
+                    }
+                }
+            });
+            hex_window.end();
+            hex_window.show();
+        }
+    });
+
+    // Rerun the loaded program under the configurations most commonly compared, and list their
+    // cycles and hit-rates next to each other
+    sweep_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            let sim = simulator.borrow();
+            let Some(source) = sim.source.clone() else {
+                gui_err_print("Error: No program loaded", &err_log);
+                return;
+            };
+            let points     = batch::default_sweep(&MachineConfig::from_simulator(&sim));
+            let max_cycles = sim.run_limits.max_cycles.unwrap_or(DEFAULT_BATCH_CYCLES);
+            let entries    = batch::run_sweep(&source, &points, max_cycles);
+
+            let mut sweep_window = Window::new(200, 200, 600, 200, "Configuration sweep");
+            let mut buf = TextBuffer::default();
+            buf.set_text(&batch::format_sweep(&entries));
+            let mut display = TextDisplay::new(0, 0, 600, 200, "");
+            display.set_buffer(buf);
+            display.set_text_font(Font::Courier);
+            sweep_window.end();
+            sweep_window.show();
+        }
+    });
+
+    rob_btn.set_callback({
+        let rob_view = rob_view.clone();
+        move |_| {
+            let mut rob_window = Window::new(200, 200, 600, 500, 
+                                             "Reorder buffer & reservation stations");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 600, 500, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            rob_window.end();
+            rob_window.show();
+            *rob_view.borrow_mut() = Some((rob_window, buf));
+        }
+    });
+
+    // Keep the reorder buffer view up to date while it is open
     app::add_idle3({
         let simulator = simulator.clone();
-        let run_state = run_state.clone();
+        move |_| {
+            if let Some((rob_window, buf)) = rob_view.borrow_mut().as_mut() {
+                if rob_window.shown() {
+                    let text = simulator.borrow().ooo.describe();
+                    if buf.text() != text {
+                        buf.set_text(&text);
+                    }
+                }
+            }
+        }
+    });
+
+    bt_btn.set_callback({
+        let bt_view = bt_view.clone();
+        move |_| {
+            let mut bt_window = Window::new(200, 200, 500, 400, "Backtrace");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 500, 400, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            bt_window.end();
+            bt_window.show();
+            *bt_view.borrow_mut() = Some((bt_window, buf));
+        }
+    });
+
+    // Keep the backtrace view up to date while it is open
+    app::add_idle3({
+        let simulator = simulator.clone();
+        move |_| {
+            if let Some((bt_window, buf)) = bt_view.borrow_mut().as_mut() {
+                if bt_window.shown() {
+                    let text = simulator.borrow().backtrace().join("\n");
+                    if buf.text() != text {
+                        buf.set_text(&text);
+                    }
+                }
+            }
+        }
+    });
+
+    // List the most recently retired instructions, newest first. Recording starts the first time
+    // this is opened, unless it was already enabled on startup
+    retire_btn.set_callback({
+        let simulator   = simulator.clone();
+        let retire_view = retire_view.clone();
+        move |_| {
+            simulator.borrow_mut().retire_log
+                .get_or_insert_with(|| RetireLog::new(DEFAULT_RETIRE_LOG_DEPTH));
+            let mut retire_window = Window::new(200, 200, 500, 400, "Recently retired");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 500, 400, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            retire_window.end();
+            retire_window.show();
+            *retire_view.borrow_mut() = Some((retire_window, buf));
+        }
+    });
+
+    // Keep the retired view up to date while it is open
+    app::add_idle3({
+        let simulator = simulator.clone();
+        move |_| {
+            if let Some((retire_window, buf)) = retire_view.borrow_mut().as_mut() {
+                if retire_window.shown() {
+                    let sim = simulator.borrow();
+                    let text = sim.retire_log.as_ref().map_or(String::new(), |log| {
+                        log.recent().map(|entry| format!("{:>10}  {:#010x}  {}", entry.cycle,
+                                                         entry.pc.0, entry.instr))
+                            .collect::<Vec<_>>().join("\n")
+                    });
+                    if buf.text() != text {
+                        buf.set_text(&text);
+                    }
+                }
+            }
+        }
+    });
+
+    // Command box for the debugger console, commands and their output are listed above it
+    console_btn.set_callback({
+        let simulator = simulator.clone();
         let err_log   = err_log.clone();
         move |_| {
-            if *run_state.borrow() {
-                let mut first = true;
-                for _ in 0..RUNS_PER_GUI_UPDATE {
-                    // If breakpoint is hit, stop running
-                    if simulator.borrow().breakpoints.get(&simulator.borrow().pc.0).is_some() && 
-                        !first {
-                        *run_state.borrow_mut() = false;
-                        break;
-                    } else {
-                        if first {
-                            first = false;
-                        }
-                        simulator.borrow_mut().step(&err_log);
+            let mut console_window = Window::new(200, 200, 600, 430, "Console");
+            let mut buf = TextBuffer::default();
+            buf.set_text("Enter `help` to list the commands\n");
+            let mut display = TextDisplay::new(0, 0, 600, 400, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            let mut input = Input::new(0, 400, 600, 30, "");
+            input.set_text_font(Font::Courier);
+            input.set_trigger(CallbackTrigger::EnterKeyAlways);
+            input.set_callback({
+                let simulator = simulator.clone();
+                let err_log   = err_log.clone();
+                move |input| {
+                    let line = input.value();
+                    input.set_value("");
+                    let output = match console::execute(&mut simulator.borrow_mut(), &line,
+                                                        &err_log) {
+                        Ok(output) => output,
+                        Err(err) => format!("Error: {}", err),
+                    };
+                    buf.append(&format!("> {}\n", line));
+                    if !output.is_empty() {
+                        buf.append(&format!("{}\n", output));
                     }
+                    display.scroll(display.count_lines(0, buf.length(), true), 0);
+                }
+            });
+            console_window.end();
+            console_window.show();
+        }
+    });
+
+    // Output the guest sent over the uart, lines entered below it are sent to the guest
+    serial_btn.set_callback({
+        let uart        = uart.clone();
+        let serial_view = serial_view.clone();
+        move |_| {
+            let mut serial_window = Window::new(200, 200, 500, 400, "Serial");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 500, 370, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            let mut input = Input::new(0, 370, 500, 30, "");
+            input.set_text_font(Font::Courier);
+            input.set_trigger(CallbackTrigger::EnterKeyAlways);
+            input.set_callback({
+                let uart = uart.clone();
+                move |input| {
+                    uart.send(format!("{}\n", input.value()).as_bytes());
+                    input.set_value("");
+                }
+            });
+            serial_window.end();
+            serial_window.show();
+            *serial_view.borrow_mut() = Some((serial_window, buf));
+        }
+    });
+
+    // Keep the serial view up to date while it is open
+    app::add_idle3(move |_| {
+        if let Some((serial_window, buf)) = serial_view.borrow_mut().as_mut() {
+            if serial_window.shown() {
+                let text = uart.output();
+                if buf.text() != text {
+                    buf.set_text(&text);
                 }
             }
         }
     });
 
-    // Update stats on screen
+    // Debug output the guest stored to the putc port
+    putc_btn.set_callback({
+        let putc_view = putc_view.clone();
+        move |_| {
+            let mut putc_window = Window::new(200, 200, 500, 400, "Putc");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 500, 400, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            putc_window.end();
+            putc_window.show();
+            *putc_view.borrow_mut() = Some((putc_window, buf));
+        }
+    });
+
+    // Keep the putc view up to date while it is open
+    app::add_idle3(move |_| {
+        if let Some((putc_window, buf)) = putc_view.borrow_mut().as_mut() {
+            if putc_window.shown() {
+                let text = putc.output();
+                if buf.text() != text {
+                    buf.set_text(&text);
+                }
+            }
+        }
+    });
+
+    // Hit rate, cpi and memory stalls over time, from the stats sampled during the run
+    charts_btn.set_callback({
+        let charts_view = charts_view.clone();
+        move |_| {
+            let mut charts_window = Window::new(200, 200, 700, 630, "Charts");
+            let charts = CHART_METRICS.iter().enumerate().map(|(idx, metric)| {
+                let mut chart = Chart::new(10, 30 + idx as i32 * 200, 680, 170, metric.title());
+                chart.set_type(ChartType::Line);
+                chart.set_align(Align::Top);
+                chart.set_text_size(10);
+                chart
+            }).collect();
+            charts_window.end();
+            charts_window.show();
+            *charts_view.borrow_mut() = Some((charts_window, charts));
+        }
+    });
+
+    // Redraw the charts while they are open, whenever a new sample was taken or the theme changed
     app::add_idle3({
         let simulator = simulator.clone();
+        let theme     = theme.clone();
+        let mut drawn = None;
         move |_| {
-            let stats = &simulator.borrow().stats;
+            let mut charts_view = charts_view.borrow_mut();
+            let Some((charts_window, charts)) = charts_view.as_mut() else {
+                drawn = None;
+                return;
+            };
+            let sim     = simulator.borrow();
+            let latest  = sim.timeline.samples().last().copied();
+            let palette = theme.borrow().palette();
+            if !charts_window.shown() || drawn == Some((latest, palette)) {
+                return;
+            }
+            drawn = Some((latest, palette));
+
+            for (chart, metric) in charts.iter_mut().zip(CHART_METRICS) {
+                let series = sim.timeline.series(metric);
+                let max = series.iter().map(|&(_, value)| value).fold(0.0, f64::max);
+                let upper = match metric {
+                    ChartMetric::Cpi => (max * 1.1).max(1.0),
+                    _ => 100.0,
+                };
+                chart.clear();
+                chart.set_bounds(0.0, upper);
+                let every = series.len().div_ceil(CHART_LABELS).max(1);
+                for (idx, (clock, value)) in series.iter().enumerate() {
+                    let label = if idx % every == 0 { clock.to_string() } else { String::new() };
+                    chart.add(*value, &label, color(palette.accent));
+                }
+                chart.redraw();
+            }
+        }
+    });
 
-            let cache_hit_rate = if (stats.cache_misses + stats.cache_hits) == 0.0 {
-                0.0
-            } else {
-                stats.cache_hits / (stats.cache_hits + stats.cache_misses)
+    // Interrupt lines and the state of the interrupt controller
+    pic_btn.set_callback({
+        let pic_view = pic_view.clone();
+        move |_| {
+            let mut pic_window = Window::new(200, 200, 500, 300, "Interrupt controller");
+            let buf = TextBuffer::default();
+            let mut display = TextDisplay::new(0, 0, 500, 300, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+            pic_window.end();
+            pic_window.show();
+            *pic_view.borrow_mut() = Some((pic_window, buf));
+        }
+    });
+
+    // Keep the interrupt controller view up to date while it is open
+    app::add_idle3({
+        let simulator = simulator.clone();
+        move |_| {
+            if let Some((pic_window, buf)) = pic_view.borrow_mut().as_mut() {
+                if pic_window.shown() {
+                    let text = pic::report(&mut simulator.borrow_mut().devices);
+                    if buf.text() != text {
+                        buf.set_text(&text);
+                    }
+                }
+            }
+        }
+    });
+
+    // Leds driven by the gpio's output and switches that set its input, pin 0 on the right
+    gpio_btn.set_callback({
+        let gpio      = gpio.clone();
+        let gpio_view = gpio_view.clone();
+        move |_| {
+            let width = GPIO_PINS as i32 * 40 + 20;
+            let mut gpio_window = Window::new(200, 200, width, 110, "GPIO");
+            let mut leds = Vec::new();
+            for pin in 0..GPIO_PINS {
+                let x = 10 + (GPIO_PINS - 1 - pin) as i32 * 40;
+                let mut led = Frame::new(x + 8, 10, 24, 24, "");
+                led.set_frame(FrameType::OFlatFrame);
+                leds.push(led);
+
+                let mut switch = LightButton::new(x, 50, 40, 50, None);
+                switch.set_label(&pin.to_string());
+                switch.set_value(gpio.switches() & (1 << pin) != 0);
+                switch.set_callback({
+                    let gpio = gpio.clone();
+                    move |switch| {
+                        let switches = gpio.switches() & !(1 << pin);
+                        gpio.set_switches(switches | (switch.value() as u32) << pin);
+                    }
+                });
+            }
+            gpio_window.end();
+            gpio_window.show();
+            *gpio_view.borrow_mut() = Some((gpio_window, leds));
+        }
+    });
+
+    // Keep the leds up to date while the gpio panel is open
+    app::add_idle3(move |_| {
+        if let Some((gpio_window, leds)) = gpio_view.borrow_mut().as_mut() {
+            if gpio_window.shown() {
+                let lit = gpio.leds();
+                for (pin, led) in leds.iter_mut().enumerate() {
+                    let color = if lit & (1 << pin) != 0 { Color::Red } else { Color::Dark3 };
+                    if led.color() != color {
+                        led.set_color(color);
+                        led.redraw();
+                    }
+                }
+            }
+        }
+    });
+
+    // Digits of the seven-segment display, drawn segment by segment
+    segment_btn.set_callback({
+        let segment_view = segment_view.clone();
+        move |_| {
+            let width = SEGMENT_DIGITS as i32 * 50 + 20;
+            let mut segment_window = Window::new(200, 200, width, 92, "Seven-segment display");
+            segment_window.set_color(Color::Black);
+            let mut segments = Vec::new();
+            for digit in 0..SEGMENT_DIGITS as i32 {
+                for (x, y, w, h) in SEGMENT_RECTS {
+                    let mut segment = Frame::new(10 + digit * 50 + x, 10 + y, w, h, "");
+                    segment.set_frame(FrameType::FlatBox);
+                    segments.push(segment);
+                }
+            }
+            segment_window.end();
+            segment_window.show();
+            *segment_view.borrow_mut() = Some((segment_window, segments));
+        }
+    });
+
+    // Keep the seven-segment display up to date while it is open
+    app::add_idle3(move |_| {
+        if let Some((segment_window, segments)) = segment_view.borrow_mut().as_mut() {
+            if segment_window.shown() {
+                let lit = display.digits().map(segment::segments);
+                for (idx, frame) in segments.iter_mut().enumerate() {
+                    let on = lit[idx / SEGMENT_RECTS.len()] & (1 << (idx % SEGMENT_RECTS.len()));
+                    let color = if on != 0 { Color::Red } else { Color::from_rgb(0x30, 0, 0) };
+                    if frame.color() != color {
+                        frame.set_color(color);
+                        frame.redraw();
+                    }
+                }
+            }
+        }
+    });
+
+    // Show the most executed addresses and functions. Counting starts the first time this is
+    // opened, unless it was already enabled on startup
+    prof_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            let mut sim = simulator.borrow_mut();
+            let Some(profile) = sim.profile.clone() else {
+                sim.profile = Some(Profile::default());
+                gui_log_print("Counting executed instructions, open the profile again later",
+                              &err_log);
+                return;
             };
 
-            let total_instrs = if stats.total_instrs == 0.0 {
-                1.0
+            let mut prof_window = Window::new(200, 200, 700, 500, "Profile");
+            let mut buf = TextBuffer::default();
+            buf.set_text(&profiler::report(&mut sim, &profile));
+            let mut display = TextDisplay::new(0, 0, 700, 465, "");
+            display.set_buffer(buf);
+            display.set_text_font(Font::Courier);
+
+            // Write the counts of all addresses to a csv file
+            let mut export_btn = Button::new(590, 470, 100, 25, "Export");
+            export_btn.set_callback({
+                let simulator = simulator.clone();
+                let err_log   = err_log.clone();
+                move |_| {
+                    let path = Path::new(DEFAULT_PROFILE_PATH);
+                    match profiler::export(&simulator.borrow(), &profile, path) {
+                        Ok(()) => gui_log_print(&format!("Exported profile to {}",
+                                                         DEFAULT_PROFILE_PATH), &err_log),
+                        Err(err) => gui_err_print(&format!("Error: Could not export profile: {}",
+                                                           err), &err_log),
+                    }
+                }
+            });
+            prof_window.end();
+            prof_window.show();
+        }
+    });
+
+    // Toggle recording of a pipeline trace that can be viewed in Konata
+    trace_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |b| {
+            let mut simulator = simulator.borrow_mut();
+            if simulator.pipeline_trace.is_some() {
+                if let Err(err) = simulator.stop_pipeline_trace() {
+                    gui_err_print(&format!("Error: Could not write pipeline trace: {}", err), 
+                                  &err_log);
+                }
+                b.set_label("Record Trace");
+            } else {
+                match simulator.start_pipeline_trace() {
+                    Ok(()) => {
+                        gui_log_print(&format!("Recording pipeline trace to {}", 
+                                               simulator.trace_path.display()), &err_log);
+                        b.set_label("Stop Trace");
+                    },
+                    Err(err) => {
+                        gui_err_print(&format!("Error: Could not create pipeline trace: {}", err),
+                                      &err_log);
+                    },
+                }
+            }
+        }
+    });
+
+    // Toggle recording of an execution trace with one line per retired instruction
+    exec_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |b| {
+            let mut simulator = simulator.borrow_mut();
+            if simulator.exec_trace.is_some() {
+                if let Err(err) = simulator.stop_exec_trace() {
+                    gui_err_print(&format!("Error: Could not write execution trace: {}", err),
+                                  &err_log);
+                }
+                b.set_label("Exec Trace");
             } else {
-                stats.total_instrs
+                match simulator.start_exec_trace() {
+                    Ok(()) => {
+                        gui_log_print(&format!("Recording execution trace to {}",
+                                               simulator.exec_trace_path.display()), &err_log);
+                        b.set_label("Stop Exec");
+                    },
+                    Err(err) => {
+                        gui_err_print(&format!("Error: Could not create execution trace: {}",
+                                               err), &err_log);
+                    },
+                }
+            }
+        }
+    });
+
+    // Scroll through the pipeline state of the most recent cycles. Recording starts the first
+    // time this is opened, unless it was already enabled on startup
+    hist_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        let theme     = theme.clone();
+        move |_| {
+            let history = match simulator.borrow().pipeline_history.clone() {
+                Some(history) => history,
+                None => {
+                    simulator.borrow_mut().pipeline_history = 
+                        Some(PipelineHistory::new(DEFAULT_HISTORY_LEN));
+                    gui_log_print(&format!("Recording pipeline history of the last {} cycles",
+                                           DEFAULT_HISTORY_LEN), &err_log);
+                    return;
+                },
             };
+            if history.is_empty() {
+                return;
+            }
 
-            let total_clock = if simulator.borrow().clock == 0 {
-                1.0
+            let mut hist_window = Window::new(200, 200, 500, 200, "Pipeline history");
+            let mut buf = TextBuffer::default();
+            buf.set_text(&history.get(history.len() - 1).unwrap().describe());
+            let mut display = TextDisplay::new(0, 0, 500, 160, "");
+            display.set_buffer(buf.clone());
+            display.set_text_font(Font::Courier);
+
+            let mut slider = HorNiceSlider::new(10, 170, 390, 20, "");
+            slider.set_range(0.0, (history.len() - 1) as f64);
+            slider.set_step(1.0, 1);
+            slider.set_value((history.len() - 1) as f64);
+            slider.set_callback({
+                let history = history.clone();
+                move |s| {
+                    if let Some(record) = history.get(s.value() as usize) {
+                        buf.set_text(&record.describe());
+                    }
+                }
+            });
+
+            // Instruction/time diagram of all recorded cycles, stalls are red and flushed
+            // instructions grey
+            let mut diagram_btn = Button::new(410, 165, 80, 30, "Diagram");
+            let theme = theme.clone();
+            diagram_btn.set_callback(move |_| {
+                let (text, styles) = diagram_text(&history);
+                let palette = theme.borrow().palette();
+                let style = |color: Color, bgcolor: Option<Color>| StyleTableEntryExt {
+                    color,
+                    font:    Font::CourierBold,
+                    size:    app::font_size(),
+                    attr:    if bgcolor.is_some() { TextAttr::BgColor } else { TextAttr::None },
+                    bgcolor: bgcolor.unwrap_or(Color::Background2),
+                };
+                let mut diagram_window = Window::new(150, 150, 900, 500, "Pipeline diagram");
+                let mut buf        = TextBuffer::default();
+                let mut style_buf  = TextBuffer::default();
+                let mut display    = TextDisplay::new(0, 0, 900, 500, "");
+                buf.set_text(&text);
+                style_buf.set_text(&styles);
+                display.set_buffer(buf);
+                display.set_text_font(Font::Courier);
+                display.set_highlight_data_ext(style_buf, vec![
+                    style(Color::Foreground, None),
+                    style(color(palette.accent), None),
+                    style(Color::White, Some(Color::Red)),
+                    style(Color::Inactive, None),
+                ]);
+                diagram_window.end();
+                diagram_window.show();
+            });
+            hist_window.end();
+            hist_window.show();
+        }
+    });
+
+    quit_btn.set_callback({
+        let simulator = simulator.clone();
+        move |_| {
+            // Write out the rest of the traces that are still being recorded
+            let _ = simulator.borrow_mut().stop_pipeline_trace();
+            let _ = simulator.borrow_mut().stop_exec_trace();
+            app.quit();
+            window.clear();
+        }
+    });
+
+    step_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        move |_| {
+            simulator.borrow_mut().step(&err_log);
+        }
+    });
+
+    back_btn.set_callback({
+        let simulator = simulator.clone();
+        let err_log   = err_log.clone();
+        let mut trace_btn = trace_btn.clone();
+        let mut exec_btn  = exec_btn.clone();
+        move |_| {
+            let mut simulator = simulator.borrow_mut();
+            if simulator.undo_log.is_none() {
+                gui_err_print("Error: Stepping back is disabled (SEAL_UNDO_DEPTH=0)", &err_log);
+            } else if !simulator.step_back() {
+                gui_err_print("Error: No earlier cycles were recorded", &err_log);
+            }
+            trace_btn.set_label("Record Trace");
+            exec_btn.set_label("Exec Trace");
+        }
+    });
+
+    // Run until a breakpoint or exit, while running the button pauses the machine at the current
+    // cycle instead
+    run_btn.set_callback({
+        let simulator = simulator.clone();
+        let run_state = run_state.clone();
+        let err_log   = err_log.clone();
+        move |btn| {
+            let mut run_state = run_state.borrow_mut();
+            if run_state.take().is_some() {
+                gui_log_print(&format!("Paused at clock {}", simulator.borrow().clock), &err_log);
             } else {
-                simulator.borrow().clock as f64
+                *run_state = Some(RunLimits::start(&simulator.borrow()));
+                btn.set_label("Pause");
+            }
+        }
+    });
+
+    // Run Simulator
+    app::add_idle3({
+        let simulator     = simulator.clone();
+        let run_state     = run_state.clone();
+        let err_log       = err_log.clone();
+        let mut run_btn   = run_btn.clone();
+        let mut last_step = Instant::now();
+        move |_| {
+            let Some(start) = *run_state.borrow() else {
+                if run_btn.label() != "Run" {
+                    run_btn.set_label("Run");
+                }
+                return;
+            };
+
+            // In slow motion a single cycle is stepped once it is due
+            let steps = match *run_speed.borrow() {
+                RunSpeed::PerUpdate(steps) => steps,
+                RunSpeed::PerSecond(rate) => {
+                    if last_step.elapsed() < Duration::from_secs(1) / rate {
+                        return;
+                    }
+                    last_step = Instant::now();
+                    1
+                },
+            };
+            let started = Instant::now();
+            for step in 1..=steps {
+                // Return to the event loop between chunks once the gui waited long enough
+                if step % RUN_CHUNK_STEPS == 0 && started.elapsed() > RUN_CHUNK_TIME {
+                    break;
+                }
+
+                // If breakpoint is hit, stop running. Runs can start at a breakpoint
+                let at_start = start.at_start(&simulator.borrow());
+                if !at_start && simulator.borrow_mut().at_breakpoint() {
+                    *run_state.borrow_mut() = None;
+                    break;
+                }
+                simulator.borrow_mut().step(&err_log);
+
+                // Stop runaway programs, eg. infinite loops
+                let simulator = simulator.borrow();
+                if let Some(msg) = simulator.run_limits.exceeded(&simulator, &start) {
+                    gui_err_print(&format!("Error: {}", msg), &err_log);
+                    *run_state.borrow_mut() = None;
+                    break;
+                }
+            }
+        }
+    });
+
+    // Update stats on screen
+    app::add_idle3({
+        let simulator = simulator.clone();
+        move |_| {
+            let stats = &simulator.borrow().stats;
+
+            let percent = |part: u64, total: u64| {
+                if total == 0 { 0.0 } else { part as f64 / total as f64 * 100.0 }
             };
+            let cache_hit_rate    = percent(stats.cache_hits,
+                                            stats.cache_hits + stats.cache_misses);
+            let l2_cache_hit_rate = percent(stats.l2_cache_hits,
+                                            stats.l2_cache_hits + stats.l2_cache_misses);
+            let total_instrs      = stats.total_instrs.max(1);
+            let predictions       = stats.branch_predictions.max(1);
+            let total_clock       = (simulator.borrow().clock as u64).max(1);
+
+            // Cycles per instruction, with its inverse (instructions per cycle)
+            let cpi = simulator.borrow().cpi();
+            cpi_label.set_label("                                           ");
+            cpi_label.set_label(&match cpi {
+                Some(cpi) => format!("CPI / IPC:   {:.2} / {:.2}", cpi, 1.0 / cpi),
+                None      => "CPI / IPC:   - / -".to_string(),
+            });
+
+            let window_cpi = simulator.borrow().window_cpi();
+            window_cpi_label.set_label("                                           ");
+            window_cpi_label.set_label(&match window_cpi {
+                Some(cpi) => format!("Window CPI:  {:.2}", cpi),
+                None      => "Window CPI:  -".to_string(),
+            });
 
             hit_rate.set_label("                                           ");
-            hit_rate.set_label(&format!("Cache hit-rate:    {:.2}%", cache_hit_rate * 100.0));
+            hit_rate.set_label(&format!("Cache hit-rate:    {:.2}%", cache_hit_rate));
 
             cpu_time.set_label("                                           ");
             cpu_time.set_label(&format!("CPU Clock:         {:.2}%", 
-                                        ((total_clock - stats.mem_clock) / total_clock) * 100.0));
+                                        percent(total_clock.saturating_sub(stats.mem_clock),
+                                                total_clock)));
+
+            fetch_stalls.set_label("                                           ");
+            fetch_stalls.set_label(&format!("Fetch Stalls:      {:.2}%", 
+                                            percent(stats.fetch_stall_clock, total_clock)));
+
+            ldst_stalls.set_label("                                           ");
+            ldst_stalls.set_label(&format!("Ld/St Stalls:      {:.2}%", 
+                                           percent(stats.load_store_stall_clock, total_clock)));
 
-            mem_time.set_label("                                           ");
-            mem_time.set_label(&format!("MEM Clock:         {:.2}%", 
-                                        (stats.mem_clock / total_clock) * 100.0));
+            raw_stalls.set_label("                                           ");
+            raw_stalls.set_label(&format!("RAW Stalls:        {:.2}%", 
+                                          percent(stats.raw_stall_clock, total_clock)));
+
+            control_stalls.set_label("                                           ");
+            control_stalls.set_label(&format!("Ctrl Stalls:       {:.2}%", 
+                                              percent(stats.control_stall_clock
+                                                      + stats.mispredict_clock, total_clock)));
+
+            struct_stalls.set_label("                                           ");
+            struct_stalls.set_label(&format!("Struct Stalls:     {:.2}%",
+                                             percent(stats.structural_stall_clock, total_clock)));
 
             control_rate.set_label("                                           ");
             control_rate.set_label(&format!("Control Instrs:    {:.2}%", 
-                                            (stats.control_instrs / total_instrs) * 100.0));
+                                            percent(stats.control_instrs, total_instrs)));
 
             load_rate.set_label("                                           ");
             load_rate.set_label(&format!("Load Instrs:       {:.2}%", 
-                                         (stats.load_instrs / total_instrs) * 100.0));
+                                         percent(stats.load_instrs, total_instrs)));
 
             store_rate.set_label("                                           ");
             store_rate.set_label(&format!("Store Instrs:      {:.2}%",
-                                          (stats.store_instrs / total_instrs) * 100.0));
+                                          percent(stats.store_instrs, total_instrs)));
 
             arithmetic_rate.set_label("                                           ");
             arithmetic_rate.set_label(&format!("Arithmetic Instrs: {:.2}%", 
-                                               (stats.arithmetic_instrs / total_instrs) * 100.0));
+                                               percent(stats.arithmetic_instrs, total_instrs)));
 
             total_instrs_label.set_label("                                           ");
-            total_instrs_label.set_label(&format!("Total Instrs: {}", (stats.total_instrs as u64).
+            total_instrs_label.set_label(&format!("Total Instrs: {}", stats.total_instrs.
                                                   to_formatted_string(&Locale::en)));
+
+            l2_hit_rate.set_label("                                           ");
+            l2_hit_rate.set_label(&format!("L2 hit-rate:       {:.2}%", 
+                                           l2_cache_hit_rate));
+
+            misalign_time.set_label("                                           ");
+            misalign_time.set_label(&format!("Misalign Clock:    {:.2}%", 
+                                             percent(stats.misaligned_clock, total_clock)));
+
+            bus_busy.set_label("                                           ");
+            bus_busy.set_label(&format!("Bus Busy:          {:.2}%", 
+                                        percent(stats.bus_busy_clock, total_clock)));
+
+            nt_store_rate.set_label("                                           ");
+            nt_store_rate.set_label(&format!("NT-Store Instrs:   {:.2}%",
+                                             percent(stats.nt_store_instrs, total_instrs)));
+
+            page_walk_time.set_label("                                           ");
+            page_walk_time.set_label(&format!("Page-Walk Clock:   {:.2}%",
+                                              percent(stats.page_walk_clock, total_clock)));
+
+            mispredict_rate.set_label("                                           ");
+            mispredict_rate.set_label(&format!("Mispredicts:       {:.2}%",
+                                               percent(stats.branch_mispredicts, predictions)));
+
+            wrong_path_label.set_label("                                           ");
+            wrong_path_label.set_label(&format!("Squashed Instrs: {}", 
+                                                stats.wrong_path_instrs
+                                                .to_formatted_string(&Locale::en)));
+
+            hit_under_miss.set_label("                                           ");
+            hit_under_miss.set_label(&format!("Hit-Under-Miss:  {}", 
+                                              stats.hit_under_miss
+                                              .to_formatted_string(&Locale::en)));
+
+            load_use_stalls.set_label("                                           ");
+            load_use_stalls.set_label(&format!("Load-Use Stalls:   {:.2}%",
+                                               percent(stats.load_use_stall_clock, total_clock)));
+
+            let energy = simulator.borrow().energy();
+            energy_label.set_label("                                           ");
+            energy_label.set_label(&format!("Energy/EDP: {}/{:.1e}", format_energy(energy.total()),
+                                            energy.edp()));
         }
     });
 
     app::add_idle3({
         let simulator = simulator.clone();
         move |_| {
-            let set_index = simulator.borrow().cur_cache_set.0;
-            let entry     = simulator.borrow().cur_cache_set.1;
-            let is_valid  = simulator.borrow().mmu.cache[set_index * entry].is_valid;
+            let sim       = simulator.borrow();
+            let set_index = sim.cur_cache_set.0;
+            let entry     = sim.cur_cache_set.1;
+            let cache     = sim.displayed_cache();
+            let line      = &cache.lines[(set_index * cache.ways) + entry];
+            let counters  = cache.line_counters(set_index, entry);
             cache_description.set_label("                                           ");
-            cache_description.set_label(&format!("Index: {}\nEntry: {}\nis_valid: {}", 
-                                        set_index, entry, is_valid));
+            cache_description.set_label(&format!("Level: L{}\nIndex: {}\nEntry: {}\nis_valid: {}\n\
+                                                 Tag: {:#x}\nAge: {}\nHits: {}/{}",
+                                                 sim.cur_cache_level, set_index, entry,
+                                                 line.is_valid, line.tag,
+                                                 cache.line_age(set_index, entry), counters.hits,
+                                                 counters.accesses));
         }
     });
 
@@ -615,9 +2785,10 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     app::add_idle3({
         let simulator = simulator.clone();
         move |_| {
-            let index = (simulator.borrow().cur_cache_set.0 * 4) + 
-                simulator.borrow().cur_cache_set.1;
-            let bytes = &simulator.borrow().mmu.cache[index].data;
+            let sim   = simulator.borrow();
+            let level = sim.displayed_cache();
+            let index = (sim.cur_cache_set.0 * level.ways) + sim.cur_cache_set.1;
+            let bytes = &level.lines[index].data;
             let mut output = String::new();
             for (i, byte) in bytes.iter().enumerate() {
                 if i % 16 == 0 {
@@ -638,28 +2809,75 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
         }
     });
 
-    // Emit bitmap to gui that showcases which cache-sets have valid entries in them
+    // Color every entry of the displayed block of sets by whether it is valid, or by its
+    // evictions relative to the most conflict-heavy entry to identify conflict-heavy sets. The
+    // selected entry is outlined, hovering over an entry shows its tag and age
     app::add_idle3({
-        let simulator = simulator.clone();
+        let simulator  = simulator.clone();
+        let cache_grid = cache_grid.clone();
+        let theme      = theme.clone();
         move |_| {
-            let mut output = String::new();
-            output.push_str("Valid Sets: ");
-            for i in 0..32 {
-                let index = i * 4;
-                let mut is_valid = false;
-                for j in 0..4 {
-                    if simulator.borrow().mmu.cache[index+j].is_valid {
-                        is_valid = true;
+            let sim       = simulator.borrow();
+            let palette   = theme.borrow().palette();
+            let cache     = sim.displayed_cache();
+            let first_set = sim.cur_cache_set.0 & !(CACHE_GRID_SETS - 1);
+            let height    = CACHE_GRID_HEIGHT / std::cmp::min(cache.ways, CACHE_GRID_WAYS) as i32;
+            // The first entry stays at the top of the grid, wherever the panel is scrolled to
+            let top       = cache_grid.borrow()[0].y();
+            let max_evictions = cache.lines.iter().map(|line| line.counters.evictions).max()
+                .unwrap_or(0);
+
+            for (idx, cell) in cache_grid.borrow_mut().iter_mut().enumerate() {
+                let (set, way) = (first_set + idx / CACHE_GRID_WAYS, idx % CACHE_GRID_WAYS);
+                if set >= cache.num_sets || way >= cache.ways {
+                    if cell.visible() {
+                        cell.hide();
                     }
+                    continue;
                 }
-                if is_valid {
-                    output.push_str("1");
+                if !cell.visible() {
+                    cell.show();
+                }
+                let y = top + way as i32 * height;
+                if (cell.y(), cell.h()) != (y, height - 1) {
+                    cell.resize(cell.x(), y, cell.w(), height - 1);
+                }
+
+                let line     = &cache.lines[(set * cache.ways) + way];
+                let counters = line.counters;
+                let color = if sim.cache_heatmap {
+                    let heat = (counters.evictions * 0xff).checked_div(max_evictions)
+                        .unwrap_or(0) as u8;
+                    Color::from_rgb(0xff, 0xf0 - (heat as u16 * 0xd0 / 0xff) as u8,
+                                    0xc0 - (heat as u16 * 0xa0 / 0xff) as u8)
+                } else if line.is_valid {
+                    color(palette.cache_valid)
+                } else {
+                    color(palette.cache_invalid)
+                };
+                let frame = if sim.cur_cache_set == (set, way) {
+                    FrameType::BorderBox
                 } else {
-                    output.push_str("0");
+                    FrameType::FlatBox
+                };
+                let tooltip = if line.is_valid {
+                    format!("Set {}, way {}\nTag: {:#x} (line {:#010x})\nAge: {} (0 is the most \
+                             recently used)\nHits: {}/{}, evictions: {}", set, way, line.tag,
+                            cache.line_addr(set, way).0, cache.line_age(set, way), counters.hits,
+                            counters.accesses, counters.evictions)
+                } else {
+                    format!("Set {}, way {}: invalid\nEvictions: {}", set, way,
+                            counters.evictions)
+                };
+                if cell.color() != color || cell.frame() != frame {
+                    cell.set_color(color);
+                    cell.set_frame(frame);
+                    cell.redraw();
+                }
+                if cell.tooltip().as_deref() != Some(tooltip.as_str()) {
+                    cell.set_tooltip(&tooltip);
                 }
             }
-            cache_label.set_label("                                           ");
-            cache_label.set_label(&output);
         }
     });
 
@@ -677,16 +2895,34 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     app::add_idle3({
         let simulator = simulator.clone();
         let pipeline  = pipeline.clone();
+        let theme     = theme.clone();
         move |_| {
+            let queued = simulator.borrow().pipeline.fetch_queue.len();
+            pipeline_title.set_label(&format!("|{:^39}|", format!("Pipeline - Queue {}/{}",
+                                                                  queued, FETCH_QUEUE_SIZE)));
+
             let len = pipeline.borrow().len();
             for i in 0..len {
                 pipeline.borrow_mut()[i].set_label("                                           ");
             }
 
             for i in 0..len {
-                pipeline.borrow_mut()[i].set_label(&format!("{}  {:#0X}  {}", stage_names[i],
-                                                    simulator.borrow().pipeline.slots[i].pc.0,
-                                                    simulator.borrow().pipeline.slots[i].instr));
+                // Wrong-path instructions that were squashed after a mispredict are greyed out,
+                // other bubbles show why the stage is empty
+                let slot = simulator.borrow().pipeline.slots[i].clone();
+                let (label, color) = match (slot.squashed, slot.bubble) {
+                    (Some((pc, instr)), _) => {
+                        (format!("{}  {:#0X}  {}", stage_names[i], pc.0, instr), Color::Inactive)
+                    },
+                    (None, Some(bubble)) if !slot.valid => {
+                        (format!("{}  <bubble: {}>", stage_names[i], bubble.describe()),
+                         color(theme.borrow().palette().bubble))
+                    },
+                    _ => (format!("{}  {:#0X}  {}", stage_names[i], slot.pc.0, slot.instr),
+                          Color::Foreground),
+                };
+                pipeline.borrow_mut()[i].set_label_color(color);
+                pipeline.borrow_mut()[i].set_label(&label);
             }
         }
     });
@@ -695,25 +2931,36 @@ pub fn setup_gui(simulator: &mut Rc<RefCell<Simulator>>, args: &Vec<String>) ->
     code_box_btn.set_callback({
         let simulator = simulator.clone();
         move |_| {
-            let code = code_box.value();
+            let code = code_buf.text();
+
+            // Instructions fetched from the old code must not continue at the new entry point
+            simulator.borrow_mut().flush_pipeline();
             if simulator.borrow_mut().load_input(&code, &err_log).is_err() {
                 gui_err_print("Error: Could not decode instruction", &err_log);
             }
+            let error_line = simulator.borrow().load_error_line;
+            code_styles.set_text(&highlight::highlight(&code, error_line));
+            if let Some(line) = error_line {
+                let start = code.split('\n').take(line - 1).map(|line| line.len() + 1);
+                code_box.set_insert_position(start.sum::<usize>() as i32);
+                code_box.show_insert_position();
+            }
         }
     });
     app
 }
 
-/// Helper to print out error msg on simulator gui
-pub fn gui_err_print(msg: &str, err_log: &Rc<RefCell<Frame>>) {
-    err_log.borrow_mut().set_label("");
-    err_log.borrow_mut().set_label_color(Color::Red);
-    err_log.borrow_mut().set_label(msg);
-}
+/// The gui displays the most recent message in its error-log frame
+impl Logger for Rc<RefCell<Frame>> {
+    fn err(&self, msg: &str) {
+        self.borrow_mut().set_label("");
+        self.borrow_mut().set_label_color(Color::Red);
+        self.borrow_mut().set_label(msg);
+    }
 
-/// Helper to print out error msg on simulator gui
-pub fn gui_log_print(msg: &str, err_log: &Rc<RefCell<Frame>>) {
-    err_log.borrow_mut().set_label("");
-    err_log.borrow_mut().set_label_color(Color::Green);
-    err_log.borrow_mut().set_label(msg);
+    fn log(&self, msg: &str) {
+        self.borrow_mut().set_label("");
+        self.borrow_mut().set_label_color(Color::Green);
+        self.borrow_mut().set_label(msg);
+    }
 }
@@ -0,0 +1,132 @@
+use crate::{
+    simulator::{Simulator, SimErr},
+    mmu::VAddr,
+    logger::Logger,
+    energy::format_energy,
+    VgaBuffer, VGA_BASE,
+};
+
+/// Reason a headless run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunEnd {
+    /// The simulator went offline, either because the program exited or because of an error
+    Offline,
+
+    /// The cycle limit was reached while the program was still running
+    CycleLimit,
+}
+
+/// Load `program` into `sim` and step it until it goes offline, or until `max_cycles` elapsed if
+/// a limit is given. The first `sim.fast_forward_instrs` instructions run in turbo mode. Screen
+/// output is kept in the returned vga-buffer instead of being displayed
+pub fn run(sim: &mut Simulator, program: &str, max_cycles: Option<u32>, log: &dyn Logger)
+        -> Result<(RunEnd, VgaBuffer), SimErr> {
+    let vga = load(sim, program, log)?;
+    if let Some(instrs) = sim.fast_forward_instrs {
+        sim.fast_forward(instrs, log);
+    }
+
+    while sim.online {
+        if max_cycles.is_some_and(|max| sim.clock >= max) {
+            return Ok((RunEnd::CycleLimit, vga));
+        }
+        sim.step(log);
+    }
+    Ok((RunEnd::Offline, vga))
+}
+
+/// Load `program` into `sim` without running it. Screen output is kept in the returned
+/// vga-buffer instead of being displayed
+pub fn load(sim: &mut Simulator, program: &str, log: &dyn Logger) -> Result<VgaBuffer, SimErr> {
+    let vga = VgaBuffer::with_size(sim.vga_size);
+    sim.devices.register(VAddr(VGA_BASE), sim.vga_size.device_size(), Box::new(vga.clone()))?;
+    sim.load_input(program, log)?;
+    Ok(vga)
+}
+
+/// Summary of the run statistics, in the same terms as the gui's stats panel
+pub fn stats_report(sim: &Simulator) -> String {
+    let stats = &sim.stats;
+    let ratio = |part: u64, total: u64| {
+        if total == 0 { 0.0 } else { part as f64 / total as f64 * 100.0 }
+    };
+    let clock  = sim.clock as u64;
+    let energy = sim.energy();
+
+    let mut lines = vec![
+        format!("Clock:             {}", sim.clock),
+        format!("Total Instrs:      {}", stats.total_instrs),
+    ];
+    lines.push(match sim.cpi() {
+        Some(cpi) => format!("CPI / IPC:         {:.2} / {:.2}", cpi, 1.0 / cpi),
+        None      => "CPI / IPC:         - / -".to_string(),
+    });
+    lines.extend([
+        format!("Cache hit-rate:    {:.2}%", ratio(stats.cache_hits,
+                                                   stats.cache_hits + stats.cache_misses)),
+        format!("L2 hit-rate:       {:.2}%", ratio(stats.l2_cache_hits,
+                                                   stats.l2_cache_hits + stats.l2_cache_misses)),
+        format!("TLB hit-rate:      {:.2}%", ratio(stats.tlb_hits,
+                                                   stats.tlb_hits + stats.tlb_misses)),
+        format!("CPU Clock:         {:.2}%", ratio(clock - stats.mem_clock, clock)),
+        format!("Fetch Stalls:      {:.2}%", ratio(stats.fetch_stall_clock, clock)),
+        format!("Ld/St Stalls:      {:.2}%", ratio(stats.load_store_stall_clock, clock)),
+        format!("RAW Stalls:        {:.2}%", ratio(stats.raw_stall_clock, clock)),
+        format!("Load-Use Stalls:   {:.2}%", ratio(stats.load_use_stall_clock, clock)),
+        format!("Ctrl Stalls:       {:.2}%", ratio(stats.control_stall_clock
+                                                   + stats.mispredict_clock, clock)),
+        format!("Struct Stalls:     {:.2}%", ratio(stats.structural_stall_clock, clock)),
+        format!("Sleep Clock:       {:.2}%", ratio(stats.sleep_clock, clock)),
+        format!("Mispredicts:       {:.2}%", ratio(stats.branch_mispredicts,
+                                                   stats.branch_predictions)),
+        format!("Squashed Instrs:   {}", stats.wrong_path_instrs),
+        format!("Energy / EDP:      {} / {:.3e} Js", format_energy(energy.total()), energy.edp()),
+        format!("Seed:              {}", sim.seed),
+    ]);
+    lines.extend(stats.metrics.iter().map(|(key, value)| format!("{:<18} {}", key, value)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::MsgLog, simulator::DEFAULT_STACK_BASE, mmu::FrameAllocator};
+
+    #[test]
+    fn runs_to_completion_and_keeps_screen_output() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x48
+                movi r2 0x1000
+                stb r1 r2 0x0
+                movi r1 0x69
+                stb r1 r2 0x1
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.mmu.frame_allocator = FrameAllocator::sequential();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+
+        let (end, vga) = run(&mut sim, program, Some(100_000), &log).unwrap();
+        assert_eq!(end, RunEnd::Offline);
+        assert!(vga.text().starts_with("Hi   "));
+        assert!(stats_report(&sim).starts_with(&format!("Clock:             {}\n", sim.clock)));
+    }
+
+    #[test]
+    fn stops_at_the_cycle_limit() {
+        let program = ".entry _start\n.load 0x10000\n._start\n.loop\njmpr .loop\n.end_section";
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+
+        let (end, _) = run(&mut sim, program, Some(1_000), &MsgLog::default()).unwrap();
+        assert_eq!(end, RunEnd::CycleLimit);
+        assert_eq!(sim.clock, 1_000);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    simulator::Simulator,
+    mmu::VAddr,
+};
+
+/// Bytes shown on every line of a hex dump
+pub const HEX_LINE_BYTES: u32 = 16;
+
+/// Hex dump of `lines` lines of memory, starting at `addr` rounded down to a line. Every line is
+/// the address, the bytes and their ASCII. Bytes that can't be read, eg. because they aren't
+/// mapped, are shown as `--`
+pub fn dump(sim: &mut Simulator, addr: VAddr, lines: u32) -> String {
+    let start = addr.0 & !(HEX_LINE_BYTES - 1);
+    (0..lines).map(|line| {
+        let line_addr = start.wrapping_add(line * HEX_LINE_BYTES);
+        let mut hex   = String::new();
+        let mut ascii = String::new();
+        for offset in 0..HEX_LINE_BYTES {
+            let mut byte = [0u8];
+            match sim.gui_mem_read(VAddr(line_addr.wrapping_add(offset)), &mut byte) {
+                Ok(()) => {
+                    hex.push_str(&format!(" {:02x}", byte[0]));
+                    ascii.push(if byte[0].is_ascii_graphic() { byte[0] as char } else { '.' });
+                },
+                Err(_) => {
+                    hex.push_str(" --");
+                    ascii.push(' ');
+                },
+            }
+        }
+        format!("{:#010x}: {}  |{}|", line_addr, hex, ascii)
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// Parse an edited hex dump into the bytes it lists, the ASCII after `|` is ignored. `--` leaves
+/// a byte unchanged, so only bytes that can be read are returned
+pub fn parse(text: &str) -> Result<Vec<(VAddr, u8)>, String> {
+    let mut bytes = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split('|').next().unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (addr, hex) = line.split_once(':')
+            .ok_or_else(|| format!("Line {}: Missing `:` after the address", idx + 1))?;
+        let addr = u32::from_str_radix(addr.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| format!("Line {}: Invalid address `{}`", idx + 1, addr.trim()))?;
+
+        let tokens = hex.split_whitespace().collect::<Vec<_>>();
+        if tokens.len() > HEX_LINE_BYTES as usize {
+            return Err(format!("Line {}: More than {} bytes", idx + 1, HEX_LINE_BYTES));
+        }
+        for (offset, token) in tokens.iter().enumerate() {
+            if *token == "--" {
+                continue;
+            }
+            let byte = match token.len() {
+                1 | 2 => u8::from_str_radix(token, 16).ok(),
+                _ => None,
+            }.ok_or_else(|| format!("Line {}: Invalid byte `{}`", idx + 1, token))?;
+            bytes.push((VAddr(addr.wrapping_add(offset as u32)), byte));
+        }
+    }
+    Ok(bytes)
+}
+
+/// Patch memory with the bytes of an edited hex dump that differ from the current contents, see
+/// `Simulator::patch_mem`. Returns the number of changed bytes
+pub fn apply(sim: &mut Simulator, text: &str) -> Result<usize, String> {
+    let mut changed = Vec::new();
+    for (addr, byte) in parse(text)? {
+        let mut cur = [0u8];
+        if sim.gui_mem_read(addr, &mut cur).is_err() || cur[0] != byte {
+            changed.push((addr, byte));
+        }
+    }
+
+    // Neighbouring bytes are patched together
+    let mut idx = 0;
+    while idx < changed.len() {
+        let addr = changed[idx].0;
+        let mut run = vec![changed[idx].1];
+        while changed.get(idx + run.len())
+            .is_some_and(|&(next, _)| next.0 == addr.0.wrapping_add(run.len() as u32)) {
+            run.push(changed[idx + run.len()].1);
+        }
+        sim.patch_mem(addr, &run)
+            .map_err(|err| format!("Patching {:#x} failed: {:?}", addr.0, err))?;
+        idx += run.len();
+    }
+    Ok(changed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::DEFAULT_STACK_BASE,
+        difftest::MsgLog,
+    };
+
+    #[test]
+    fn edited_lines_parse_into_bytes() {
+        let text = "0x00001000:  41 -- 7  |A  |\n\n2000: ff";
+        assert_eq!(parse(text), Ok(vec![(VAddr(0x1000), 0x41), (VAddr(0x1002), 0x7),
+                                        (VAddr(0x2000), 0xff)]));
+        assert_eq!(parse("0x1000 41"), Err("Line 1: Missing `:` after the address".to_string()));
+        assert_eq!(parse("\n0x1000: 141"), Err("Line 2: Invalid byte `141`".to_string()));
+        assert!(parse(&format!("0x1000:{}", " 00".repeat(17))).is_err());
+    }
+
+    #[test]
+    fn changed_bytes_are_patched() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(program, &MsgLog::default()).unwrap();
+
+        let text = dump(&mut sim, VAddr(0x10004), 2);
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("0x00010000: "), "{}", text);
+        let unmapped = format!("0x30000000: {}  |{}|", " --".repeat(16), " ".repeat(16));
+        assert_eq!(dump(&mut sim, VAddr(0x30000008), 1), unmapped);
+        assert_eq!(apply(&mut sim, &text), Ok(0));
+
+        // Bytes of the read-only code page can be patched as well
+        let mut old = [0u8; 2];
+        sim.gui_mem_read(VAddr(0x10000), &mut old).unwrap();
+        let edited = format!("0x10000: {:02x} {:02x}\n0x1000c: -- 7f", old[0] ^ 1, old[1]);
+        assert_eq!(apply(&mut sim, &edited), Ok(2));
+        let mut new = [0u8; 14];
+        sim.gui_mem_read(VAddr(0x10000), &mut new).unwrap();
+        assert_eq!((new[0], new[1], new[13]), (old[0] ^ 1, old[1], 0x7f));
+    }
+}
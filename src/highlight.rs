@@ -0,0 +1,106 @@
+use crate::{
+    cpu::NUM_REGS,
+    isa,
+};
+
+/// Style of text that isn't highlighted, styles are the characters of the editor's style buffer
+pub const STYLE_PLAIN: u8 = b'A';
+
+/// Style of instruction mnemonics
+pub const STYLE_MNEMONIC: u8 = b'B';
+
+/// Style of registers
+pub const STYLE_REGISTER: u8 = b'C';
+
+/// Style of labels and directives, which both start with a `.`
+pub const STYLE_LABEL: u8 = b'D';
+
+/// Style of immediates
+pub const STYLE_NUMBER: u8 = b'E';
+
+/// Style of comment lines
+pub const STYLE_COMMENT: u8 = b'F';
+
+/// Style of the whole line that failed to assemble
+pub const STYLE_ERROR: u8 = b'G';
+
+/// Style of a single word, `first` is set for the first word of its line
+fn word_style(word: &str, first: bool) -> u8 {
+    let is_reg = word.strip_prefix('r')
+        .and_then(|idx| idx.parse::<usize>().ok())
+        .is_some_and(|idx| idx < NUM_REGS);
+
+    if word.starts_with('.') {
+        STYLE_LABEL
+    } else if first && isa::lookup(word).is_some() {
+        STYLE_MNEMONIC
+    } else if !first && is_reg {
+        STYLE_REGISTER
+    } else if !first && word.starts_with("0x") {
+        STYLE_NUMBER
+    } else {
+        STYLE_PLAIN
+    }
+}
+
+/// Style buffer for the assembly in `code`, a style character for every byte. Lines starting with
+/// `#` are comments, like for the assembler. `error_line`, counted from 1, is marked as a whole
+pub fn highlight(code: &str, error_line: Option<usize>) -> String {
+    let mut styles = Vec::with_capacity(code.len());
+    for (idx, line) in code.split('\n').enumerate() {
+        if idx > 0 {
+            styles.push(STYLE_PLAIN);
+        }
+        if error_line == Some(idx + 1) {
+            styles.resize(styles.len() + line.len(), STYLE_ERROR);
+            continue;
+        }
+        if line.trim_start().starts_with('#') {
+            styles.resize(styles.len() + line.len(), STYLE_COMMENT);
+            continue;
+        }
+
+        let mut first = true;
+        let mut rest  = line;
+        while !rest.is_empty() {
+            let space = rest.len() - rest.trim_start().len();
+            styles.resize(styles.len() + space, STYLE_PLAIN);
+            rest = &rest[space..];
+
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if len > 0 {
+                styles.resize(styles.len() + len, word_style(&rest[..len], first));
+                first = false;
+            }
+            rest = &rest[len..];
+        }
+    }
+    String::from_utf8(styles).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_styled_by_kind() {
+        let code = "# Entry\n.entry _start\n._start\n    addi r1 r0 0x2a\n    bne r1 r16 .loop";
+        let styles = highlight(code, None);
+        assert_eq!(styles.len(), code.len());
+        let words = styles.split('A').filter(|word| !word.is_empty()).collect::<Vec<_>>();
+
+        // `_start` isn't a label without its `.` and `r16` isn't a register
+        assert_eq!(words, ["FFFFFFF", "DDDDDD", "DDDDDDD", "BBBB", "CC", "CC", "EEEE", "BBB", "CC",
+                           "DDDDD"]);
+    }
+
+    #[test]
+    fn error_line_is_marked_as_a_whole() {
+        let code = "movi r1 0x1\nfoo r1\n";
+        let styles = highlight(code, Some(2));
+        assert_eq!(styles, "BBBBACCAEEEAGGGGGGA");
+
+        // Multi-byte characters get a style per byte
+        assert_eq!(highlight("# é", Some(3)), "FFFF");
+    }
+}
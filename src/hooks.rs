@@ -0,0 +1,210 @@
+use crate::{
+    simulator::Simulator,
+    mmu::VAddr,
+    cpu::Instr,
+};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Event that registered hooks are called for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent<'a> {
+    /// `instr` at `pc` retired, its results are already visible in the registers
+    Retired { pc: VAddr, instr: Instr },
+
+    /// The running program wrote `data` to `addr`
+    MemWrite { addr: VAddr, data: &'a [u8] },
+
+    /// Execution reached the breakpoint at `pc`
+    Breakpoint { pc: VAddr },
+}
+
+/// What the simulator should do after a hook handled an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+
+    /// Take the simulator offline, eg. because an assertion failed
+    Stop,
+}
+
+/// Handle of a registered hook, used to remove it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(u64);
+
+/// Callback of a hook. It can inspect, but not modify the simulator
+pub type HookFn = dyn FnMut(&Simulator, &HookEvent) -> HookAction;
+
+/// Events a hook is registered for
+#[derive(Debug, Clone)]
+enum Filter {
+    Retired,
+
+    /// Writes that overlap the address range
+    MemWrite(Range<u32>),
+
+    Breakpoint,
+}
+
+impl Filter {
+    fn matches(&self, event: &HookEvent) -> bool {
+        match (self, event) {
+            (Filter::Retired, HookEvent::Retired { .. }) => true,
+            (Filter::MemWrite(range), HookEvent::MemWrite { addr, data }) => {
+                addr.0 < range.end && addr.0.saturating_add(data.len() as u32) > range.start
+            },
+            (Filter::Breakpoint, HookEvent::Breakpoint { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Callbacks that are invoked for simulator events, so runs can be checked, logged or graded
+/// without modifying the simulator. Clones of the simulator share the same callbacks
+#[derive(Clone, Default)]
+pub struct Hooks {
+    /// Registered hooks in the order they are called
+    hooks: Vec<(HookId, Filter, Rc<RefCell<HookFn>>)>,
+
+    /// Id assigned to the next registered hook
+    next_id: u64,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.hooks.iter().map(|(id, filter, _)| (id, filter))).finish()
+    }
+}
+
+impl Hooks {
+    fn add(&mut self, filter: Filter, hook: impl FnMut(&Simulator, &HookEvent) -> HookAction
+           + 'static) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push((id, filter, Rc::new(RefCell::new(hook))));
+        id
+    }
+
+    /// Call `hook` for every retired instruction
+    pub fn on_retired(&mut self, hook: impl FnMut(&Simulator, &HookEvent) -> HookAction
+                      + 'static) -> HookId {
+        self.add(Filter::Retired, hook)
+    }
+
+    /// Call `hook` for every write of the running program that touches `range`. Writes of the
+    /// loader are not reported
+    pub fn on_mem_write(&mut self, range: Range<u32>,
+                        hook: impl FnMut(&Simulator, &HookEvent) -> HookAction + 'static)
+            -> HookId {
+        self.add(Filter::MemWrite(range), hook)
+    }
+
+    /// Call `hook` whenever execution reaches a breakpoint
+    pub fn on_breakpoint(&mut self, hook: impl FnMut(&Simulator, &HookEvent) -> HookAction
+                         + 'static) -> HookId {
+        self.add(Filter::Breakpoint, hook)
+    }
+
+    /// Remove a hook. Returns `false` if it was already removed
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(hook_id, _, _)| *hook_id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Hooks registered for `event`, in the order they are called
+    pub(crate) fn matching(&self, event: &HookEvent) -> Vec<Rc<RefCell<HookFn>>> {
+        self.hooks.iter()
+            .filter(|(_, filter, _)| filter.matches(event))
+            .map(|(_, _, hook)| hook.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, cpu::Register, simulator::DEFAULT_STACK_BASE};
+
+    const PROGRAM: &str = "
+        .entry _start
+        .load 0x10000
+        ._start
+            movi r1 0x0
+            movi r2 0x8
+        .loop
+            addi r1 r1 0x1
+            st r1 r15 0x0
+            blt r1 r2 .loop
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+    ";
+
+    #[test]
+    fn hooks_observe_and_stop_the_run() {
+        let retired = Rc::new(RefCell::new(0));
+        let stores  = Rc::new(RefCell::new(Vec::new()));
+        let run = run_program(PROGRAM, true, 10_000, &|sim| {
+            let retired = retired.clone();
+            sim.hooks.on_retired(move |_, _| {
+                *retired.borrow_mut() += 1;
+                HookAction::Continue
+            });
+
+            // Stores to the stack, but not the command-port
+            let stores = stores.clone();
+            sim.hooks.on_mem_write(DEFAULT_STACK_BASE..u32::MAX, move |sim, event| {
+                if let HookEvent::MemWrite { data, .. } = event {
+                    stores.borrow_mut().push((data[0], sim.read_reg(Register::R1)));
+                }
+                HookAction::Continue
+            });
+        });
+        assert!(!run.sim.online);
+        // The final store shuts the simulator down before it retires
+        assert_eq!(*retired.borrow(), 2 + 8 * 3 + 2);
+        assert_eq!(*stores.borrow(), (1..=8).map(|i| (i, i as u32)).collect::<Vec<_>>());
+
+        // Stop as soon as r1 reaches 3
+        let run = run_program(PROGRAM, false, 10_000, &|sim| {
+            let id = sim.hooks.on_retired(|_, _| HookAction::Stop);
+            assert!(sim.hooks.remove(id) && !sim.hooks.remove(id));
+            sim.hooks.on_retired(|sim, _| match sim.read_reg(Register::R1) {
+                3 => HookAction::Stop,
+                _ => HookAction::Continue,
+            });
+        });
+        assert!(!run.sim.online);
+        assert_eq!(run.sim.read_reg(Register::R1), 3);
+    }
+
+    #[test]
+    fn breakpoints_are_reported() {
+        let mut sim = Simulator::new();
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        sim.hooks.on_breakpoint({
+            let hits = hits.clone();
+            move |_, event| {
+                if let HookEvent::Breakpoint { pc } = event {
+                    hits.borrow_mut().push(*pc);
+                }
+                HookAction::Continue
+            }
+        });
+
+        sim.pc = VAddr(0x10004);
+        assert!(!sim.at_breakpoint());
+        sim.breakpoints.insert(0x10004, 0);
+        assert!(sim.at_breakpoint());
+        assert_eq!(*hits.borrow(), [VAddr(0x10004)]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::cpu::InstrCode;
+
+/// Layout an instruction is encoded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrFormat {
+    /// Three registers
+    R,
+
+    /// Two registers and a 16-bit immediate
+    G,
+
+    /// A register and a 21-bit offset
+    J,
+
+    /// Just the opcode
+    B,
+}
+
+impl InstrFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstrFormat::R => "R-Type",
+            InstrFormat::G => "G-Type",
+            InstrFormat::J => "J-Type",
+            InstrFormat::B => "B-Type",
+        }
+    }
+
+    /// Fields following the 6-bit opcode, from the most significant bit down, with their widths
+    pub fn fields(&self) -> &'static [(&'static str, u32)] {
+        match self {
+            InstrFormat::R => &[("rs3", 5), ("rs1", 5), ("rs2", 5), ("unused", 11)],
+            InstrFormat::G => &[("rs3", 5), ("rs1", 5), ("imm", 16)],
+            InstrFormat::J => &[("rs3", 5), ("offset", 21)],
+            InstrFormat::B => &[("unused", 26)],
+        }
+    }
+}
+
+/// Reference entry of an instruction the assembler understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrSpec {
+    /// Mnemonic as written in assembly
+    pub mnemonic: &'static str,
+
+    /// Operands as written in assembly, with their meaning
+    pub operands: &'static [(&'static str, &'static str)],
+
+    /// What the instruction does
+    pub semantics: &'static str,
+
+    pub format: InstrFormat,
+
+    /// Opcode the instruction is encoded with, see `InstrCode`
+    pub opcode: u32,
+
+    /// Instruction an assembler alias expands to
+    pub alias_of: Option<&'static str>,
+}
+
+const RRR: &[(&str, &str)] = &[
+    ("rs3", "destination register"),
+    ("rs1", "first source register"),
+    ("rs2", "second source register"),
+];
+
+const RRI: &[(&str, &str)] = &[
+    ("rs3", "destination register"),
+    ("rs1", "source register"),
+    ("imm", "signed 16-bit immediate, in hex"),
+];
+
+const LOAD: &[(&str, &str)] = &[
+    ("rs3", "register the value is loaded into"),
+    ("rs1", "register holding the base address"),
+    ("imm", "signed offset added to the base address, in hex"),
+];
+
+const STORE: &[(&str, &str)] = &[
+    ("rs3", "register holding the value to store"),
+    ("rs1", "register holding the base address"),
+    ("imm", "signed offset added to the base address, in hex"),
+];
+
+const BRANCH: &[(&str, &str)] = &[
+    ("rs3", "first register compared"),
+    ("rs1", "second register compared"),
+    ("label", "branch target, encoded as an offset from the pc"),
+];
+
+const JUMP: &[(&str, &str)] = &[("label", "jump target, encoded as an offset from the pc")];
+
+/// Reference of every instruction and alias the assembler understands
+pub const ISA: &[InstrSpec] = &[
+    spec("add", RRR, "rs3 = rs1 + rs2, wrapping on overflow", InstrFormat::R,
+         InstrCode::Add as u32),
+    spec("sub", RRR, "rs3 = rs1 - rs2, wrapping on underflow", InstrFormat::R,
+         InstrCode::Sub as u32),
+    spec("xor", RRR, "rs3 = rs1 ^ rs2", InstrFormat::R, InstrCode::Xor as u32),
+    spec("or", RRR, "rs3 = rs1 | rs2", InstrFormat::R, InstrCode::Or as u32),
+    spec("and", RRR, "rs3 = rs1 & rs2", InstrFormat::R, InstrCode::And as u32),
+    spec("shr", RRR, "rs3 = rs1 >> rs2, shifting in zeroes", InstrFormat::R,
+         InstrCode::Shr as u32),
+    spec("shl", RRR, "rs3 = rs1 << rs2", InstrFormat::R, InstrCode::Shl as u32),
+    spec("mul", RRR, "rs3 = rs1 * rs2, keeping the lower 32 bits", InstrFormat::R,
+         InstrCode::Mul as u32),
+    spec("div", RRR, "rs3 = rs1 / rs2, unsigned. Dividing by zero faults", InstrFormat::R,
+         InstrCode::Div as u32),
+    alias("mov", &[("rs3", "destination register"), ("rs1", "source register")], "rs3 = rs1",
+          InstrFormat::R, InstrCode::Add as u32, "add rs3 rs1 r0"),
+
+    spec("addi", RRI, "rs3 = rs1 + imm, wrapping on overflow", InstrFormat::G,
+         InstrCode::Addi as u32),
+    spec("subi", RRI, "rs3 = rs1 - imm, wrapping on underflow", InstrFormat::G,
+         InstrCode::Subi as u32),
+    spec("xori", RRI, "rs3 = rs1 ^ imm", InstrFormat::G, InstrCode::Xori as u32),
+    spec("ori", RRI, "rs3 = rs1 | imm", InstrFormat::G, InstrCode::Ori as u32),
+    spec("andi", RRI, "rs3 = rs1 & imm", InstrFormat::G, InstrCode::Andi as u32),
+    spec("lui", &[("rs3", "destination register"), ("imm", "upper bits of the value, in hex")],
+         "rs3 = imm << 12", InstrFormat::G, InstrCode::Lui as u32),
+    alias("movi", &[("rs3", "destination register"), ("imm", "signed 16-bit value, in hex")],
+          "rs3 = imm", InstrFormat::G, InstrCode::Addi as u32, "addi rs3 r0 imm"),
+
+    spec("ldb", LOAD, "rs3 = the byte at rs1 + imm, zero-extended", InstrFormat::G,
+         InstrCode::Ldb as u32),
+    spec("ldh", LOAD, "rs3 = the halfword at rs1 + imm, zero-extended", InstrFormat::G,
+         InstrCode::Ldh as u32),
+    spec("ld", LOAD, "rs3 = the word at rs1 + imm", InstrFormat::G, InstrCode::Ld as u32),
+    spec("stb", STORE, "Store the lowest byte of rs3 at rs1 + imm", InstrFormat::G,
+         InstrCode::Stb as u32),
+    spec("sth", STORE, "Store the lower halfword of rs3 at rs1 + imm", InstrFormat::G,
+         InstrCode::Sth as u32),
+    spec("st", STORE, "Store rs3 at rs1 + imm", InstrFormat::G, InstrCode::St as u32),
+    spec("stnt", STORE, "Store rs3 at rs1 + imm straight to ram, without allocating a cache-line",
+         InstrFormat::G, InstrCode::Stnt as u32),
+
+    spec("bne", BRANCH, "Branch to label if rs3 != rs1", InstrFormat::G, InstrCode::Bne as u32),
+    spec("beq", BRANCH, "Branch to label if rs3 == rs1", InstrFormat::G, InstrCode::Beq as u32),
+    spec("blt", BRANCH, "Branch to label if rs3 < rs1, compared unsigned", InstrFormat::G,
+         InstrCode::Blt as u32),
+    spec("bgt", BRANCH, "Branch to label if rs3 > rs1, compared unsigned", InstrFormat::G,
+         InstrCode::Bgt as u32),
+
+    spec("jmpr", JUMP, "Jump to label", InstrFormat::J, InstrCode::Jmpr as u32),
+    alias("jmp", JUMP, "Jump to label", InstrFormat::J, InstrCode::Jmpr as u32, "jmpr label"),
+    spec("call", &[("addr", "address of the function, in hex")],
+         "Push r14 onto the stack at r15, set r14 to the return address and jump to addr",
+         InstrFormat::J, InstrCode::Call as u32),
+    spec("ret", &[], "Jump to r14 and pop the previous r14 off the stack at r15", InstrFormat::B,
+         InstrCode::Ret as u32),
+    spec("nop", &[], "Do nothing", InstrFormat::B, InstrCode::Nop as u32),
+    spec("int0", &[], "Invoke interrupt 0, its handler's address is stored at 0x0",
+         InstrFormat::B, InstrCode::Int0 as u32),
+];
+
+const fn spec(mnemonic: &'static str, operands: &'static [(&'static str, &'static str)],
+              semantics: &'static str, format: InstrFormat, opcode: u32) -> InstrSpec {
+    InstrSpec { mnemonic, operands, semantics, format, opcode, alias_of: None }
+}
+
+const fn alias(mnemonic: &'static str, operands: &'static [(&'static str, &'static str)],
+               semantics: &'static str, format: InstrFormat, opcode: u32,
+               alias_of: &'static str) -> InstrSpec {
+    InstrSpec { alias_of: Some(alias_of), ..spec(mnemonic, operands, semantics, format, opcode) }
+}
+
+/// Reference entry of the instruction written as `mnemonic`
+pub fn lookup(mnemonic: &str) -> Option<&'static InstrSpec> {
+    ISA.iter().find(|spec| spec.mnemonic == mnemonic)
+}
+
+impl InstrSpec {
+    /// How the instruction is written in assembly, eg. `addi rs3 rs1 imm`
+    pub fn usage(&self) -> String {
+        std::iter::once(self.mnemonic)
+            .chain(self.operands.iter().map(|(name, _)| *name))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Multi-line description of the instruction: its usage and semantics, what its operands mean
+    /// and how it is encoded
+    pub fn reference(&self) -> String {
+        let mut text = format!("{}\n{}\n", self.usage(), self.semantics);
+        for (name, meaning) in self.operands {
+            text.push_str(&format!("\n{}: {}", name, meaning));
+        }
+        if let Some(alias_of) = self.alias_of {
+            text.push_str(&format!("\n\nAlias of `{}`", alias_of));
+        }
+        let fields = self.format.fields().iter()
+            .map(|(name, bits)| format!(" | {} [{}]", name, bits))
+            .collect::<String>();
+        text.push_str(&format!("\n\n{}: opcode {:06b}{}", self.format.name(), self.opcode, fields));
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_has_one_reference() {
+        for opcode in 0..64 {
+            let specs = ISA.iter()
+                .filter(|spec| spec.opcode == opcode && spec.alias_of.is_none())
+                .count();
+            assert_eq!(specs, InstrCode::try_from(opcode).is_ok() as usize, "opcode {}", opcode);
+        }
+        for spec in ISA {
+            let bits = spec.format.fields().iter().map(|(_, bits)| bits).sum::<u32>();
+            assert_eq!(bits, 26, "{}", spec.mnemonic);
+        }
+    }
+
+    #[test]
+    fn references_describe_operands_and_encoding() {
+        let reference = lookup("addi").unwrap().reference();
+        assert!(reference.starts_with("addi rs3 rs1 imm\nrs3 = rs1 + imm"), "{}", reference);
+        assert!(reference.contains("imm: signed 16-bit immediate, in hex"), "{}", reference);
+        assert!(reference.ends_with("G-Type: opcode 001001 | rs3 [5] | rs1 [5] | imm [16]"),
+                "{}", reference);
+
+        let reference = lookup("movi").unwrap().reference();
+        assert!(reference.contains("Alias of `addi rs3 r0 imm`"), "{}", reference);
+        assert_eq!(lookup("ret").unwrap().usage(), "ret");
+        assert_eq!(lookup("Ret"), None);
+    }
+}
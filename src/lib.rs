@@ -1,18 +1,83 @@
+//! Simulator for the seal isa. The engine (`simulator`, `mmu`, `cpu`, `pipeline`, ...) does not
+//! depend on the gui, so other tools can embed it by depending on this crate with
+//! `default-features = false`. Errors and log messages are reported through a `logger::Logger`:
+//!
+//! ```
+//! use seal_isa::{simulator::{Simulator, DEFAULT_STACK_BASE}, headless, logger::ConsoleLog};
+//!
+//! let program = "
+//!     .entry _start
+//!     .load 0x10000
+//!     ._start
+//!         movi r1 0x41
+//!         movi r2 0x2000
+//!         st r1 r2 0x0
+//!     .end_section
+//! ";
+//! let mut sim = Simulator::new();
+//! sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+//! let (end, _screen) = headless::run(&mut sim, program, Some(10_000), &ConsoleLog).unwrap();
+//! assert_eq!(end, headless::RunEnd::Offline);
+//! ```
+
 #![feature(slice_flatten)]
 
 pub mod simulator;
 pub mod mmu;
 pub mod cpu;
+#[cfg(feature = "gui")]
 pub mod gui;
+pub mod logger;
+pub mod highlight;
+pub mod isa;
+pub mod hexedit;
+pub mod theme;
+pub mod recent;
+pub mod headless;
+pub mod batch;
 pub mod pipeline;
+pub mod mmio;
+pub mod timer;
+pub mod uart;
+pub mod disk;
+pub mod pic;
+pub mod dma;
+pub mod buzzer;
+pub mod gpio;
+pub mod rng;
+pub mod semihost;
+pub mod segment;
+pub mod mouse;
+pub mod power;
+pub mod putc;
+pub mod vga;
+pub mod mailbox;
+pub mod predictor;
+pub mod ooo;
+pub mod trace;
+pub mod undo;
+pub mod hooks;
+pub mod debugger;
+pub mod console;
+pub mod metrics;
+pub mod energy;
+pub mod profiler;
+pub mod reg_history;
+pub mod timeline;
+pub mod retire_log;
+pub mod golden;
+pub mod machine;
+pub mod turbo;
+pub mod difftest;
+pub mod progen;
 
-use crate::mmu::VAddr;
+use crate::mmio::{MmioDevice, MmioAction};
+use crate::metrics::Metrics;
+use crate::vga::{TextMode, VgaSize};
 
-use fltk::{
-    prelude::*,
-    enums::{Color, Font},
-    output::MultilineOutput,
-};
+use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 
 /// Transform `bytes` to a little-endian u32 integer
@@ -25,83 +90,210 @@ fn as_u32_le(bytes: &Vec<u8>) -> u32 {
 }
 
 /// Transform `bytes` to a little-endian u32 integer
+#[cfg(feature = "gui")]
 fn as_u16_le(bytes: &Vec<u8>) -> u16 {
     assert_eq!(bytes.len(), 2);
     ((bytes[0] as u16) <<  0) +
     ((bytes[1] as u16) <<  8)
 }
 
-/// Provides an interface to write to the simulator's output screen
-#[derive(Clone, Debug)]
-pub struct VgaDriver {
-    screen: MultilineOutput,
+/// Base address of the vga-buffer
+pub const VGA_BASE: u32 = 0x1000;
+
+/// Character the vga-buffer displays for `byte`. Bytes outside of printable ASCII, other than
+/// newlines, are shown as a placeholder
+pub fn vga_char(byte: u8) -> char {
+    match byte {
+        0x20..=0x7e | b'\n' => byte as char,
+        _ => 0xfe as char,
+    }
+}
+
+/// Vga-buffer that keeps the screen contents in memory, used when running without the gui.
+/// Clones share the same screen, so the contents can be read after registering the device
+#[derive(Clone, Debug, Default)]
+pub struct VgaBuffer {
+    screen: Rc<RefCell<TextMode>>,
 }
 
-impl VgaDriver {
+impl VgaBuffer {
+    /// Empty screen, every line is blank and ends in a newline like on the gui
     pub fn new() -> Self {
-        let mut screen = MultilineOutput::new(730, 540, 300, 200, "");
-        screen.set_color(Color::Black);
-        screen.set_text_color(Color::White);
-        screen.set_label_font(Font::CourierBold);
-        screen.set_wrap(true);
-
-        // Initialize empty screen
-        for _ in 0..8 {
-            screen.append("                             \n").unwrap();
-        }
+        Self::default()
+    }
 
-        Self {
-            screen,
-        }
+    /// Empty screen of `size`
+    pub fn with_size(size: VgaSize) -> Self {
+        Self { screen: Rc::new(RefCell::new(TextMode::new(size))) }
     }
 
-    /// Write a byte to the located in the buffer denoted by `addr`
-    fn write_byte(&mut self, byte: u8, addr: VAddr) {
-        let index = self.addr_to_vga_index(addr);
-        self.screen.replace(index as i32, (index+1) as i32, 
-                            &(byte as char).to_string()).unwrap();
+    /// Dimensions of the screen
+    pub fn size(&self) -> VgaSize {
+        self.screen.borrow().size()
     }
 
-    /// An address in the vga memory region (0x1000-0x2000)
-    fn write(&mut self, addr: VAddr, output: &Vec<u8>) {
-        assert!(addr.0 as usize + output.len() < (0x1000 + (8*30)));
-        let mut addr_cpy = addr;
-
-        for byte in output {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(*byte, addr_cpy),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe, addr_cpy),
-            }
-            addr_cpy.0 += 1;
-        }
+    /// Current screen contents
+    pub fn text(&self) -> String {
+        self.screen.borrow().text()
     }
 
-    /// Transforms an address to a vga-buffer index
-    fn addr_to_vga_index(&self, addr: VAddr) -> u32 {
-        let index = addr.0 - 0x1000;
-        return index;
+    /// Attribute of every character on the screen, see `vga::attr_colors`
+    pub fn attrs(&self) -> Vec<u8> {
+        self.screen.borrow().attrs.clone()
+    }
+}
+
+impl MmioDevice for VgaBuffer {
+    fn name(&self) -> &str {
+        "vga"
+    }
+
+    fn write(&mut self, offset: u32, output: &[u8], _clock: u32) -> Option<MmioAction> {
+        self.screen.borrow_mut().write(offset, output)
+    }
+
+    fn reset(&mut self) {
+        let size = self.size();
+        *self.screen.borrow_mut() = TextMode::new(size);
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
     }
 }
 
 /// Used to track some statistics about the simulation run
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
-    pub cache_hits: f64,
+    pub cache_hits: u64,
+
+    pub cache_misses: u64,
+
+    pub l2_cache_hits: u64,
+
+    pub l2_cache_misses: u64,
+
+    pub tlb_hits: u64,
+
+    pub tlb_misses: u64,
 
-    pub cache_misses: f64,
+    /// Cycles spent walking the page table on tlb-misses
+    pub page_walk_clock: u64,
 
-    pub mem_clock: f64,
+    /// Cycles in which fetch or the memory stage waited on memory. With the pipeline enabled both
+    /// can wait during the same cycle, so this can be less than the sum of `fetch_stall_clock` and
+    /// `load_store_stall_clock`
+    pub mem_clock: u64,
 
-    pub control_instrs: f64,
+    /// Cycles fetch waited for instruction fetches
+    pub fetch_stall_clock: u64,
 
-    pub load_instrs: f64,
+    /// Cycles the memory stage waited for loads/stores
+    pub load_store_stall_clock: u64,
 
-    pub store_instrs: f64,
+    /// Cycles the decode stage stalled on a read-after-write hazard other than a load-use hazard
+    pub raw_stall_clock: u64,
 
-    pub arithmetic_instrs: f64,
+    /// Cycles the decode stage stalled on a load-use hazard, where the instruction reads the
+    /// result of a load in the execute stage. Only counted with forwarding enabled
+    pub load_use_stall_clock: u64,
 
-    pub total_instrs: f64,
+    /// Cycles fetching was halted until a control-flow instruction resolved the next pc
+    pub control_stall_clock: u64,
+
+    pub control_instrs: u64,
+
+    pub load_instrs: u64,
+
+    pub store_instrs: u64,
+
+    /// Non-temporal stores, these are also included in `store_instrs`
+    pub nt_store_instrs: u64,
+
+    pub arithmetic_instrs: u64,
+
+    pub total_instrs: u64,
+
+    /// Number of lazily mapped pages that were backed by a physical frame on first access
+    pub demand_pages: u64,
+
+    /// Number of misaligned loads/stores that were split up by unaligned emulation
+    pub misaligned_accesses: u64,
+
+    /// Extra cycles spent on the additional accesses of emulated misaligned loads/stores
+    pub misaligned_clock: u64,
+
+    /// Cycles during which at least one ram request was in flight on the memory bus
+    pub bus_busy_clock: u64,
+
+    /// Cycles ram requests spent queueing for the memory bus
+    pub bus_wait_clock: u64,
+
+    /// Conditional branches that were resolved after being predicted
+    pub branch_predictions: u64,
+
+    /// Predicted branches whose prediction turned out to be wrong
+    pub branch_mispredicts: u64,
+
+    /// Cycles lost to squashing wrong-path instructions after mispredicts
+    pub mispredict_clock: u64,
+
+    /// Cycles the core slept or halted at the request of the guest, see `power`
+    pub sleep_clock: u64,
+
+    /// Instructions fetched down a mispredicted path that were squashed before executing
+    pub wrong_path_instrs: u64,
+
+    /// Cycles in which a ready multiplication or division waited for the shared multiplier/divider
+    pub structural_stall_clock: u64,
+
+    /// Load misses the pipeline continued past instead of stalling, see `Mshr`
+    pub hit_under_miss: u64,
+
+    /// Counters registered by subsystems, eg. taken branches and per-device accesses
+    pub metrics: Metrics,
+}
+
+impl Stats {
+    /// All statistics keyed by name: the built-in counters followed by the registered metrics
+    pub fn counters(&self) -> Metrics {
+        let mut counters = self.metrics.clone();
+        for (key, value) in [
+            ("cache.l1.hits",            self.cache_hits),
+            ("cache.l1.misses",          self.cache_misses),
+            ("cache.l2.hits",            self.l2_cache_hits),
+            ("cache.l2.misses",          self.l2_cache_misses),
+            ("cache.hit_under_miss",     self.hit_under_miss),
+            ("tlb.hits",                 self.tlb_hits),
+            ("tlb.misses",               self.tlb_misses),
+            ("clock.page_walk",          self.page_walk_clock),
+            ("clock.mem",                self.mem_clock),
+            ("clock.misaligned",         self.misaligned_clock),
+            ("clock.sleep",              self.sleep_clock),
+            ("stall.fetch",              self.fetch_stall_clock),
+            ("stall.load_store",         self.load_store_stall_clock),
+            ("stall.raw",                self.raw_stall_clock),
+            ("stall.load_use",           self.load_use_stall_clock),
+            ("stall.control",            self.control_stall_clock),
+            ("stall.mispredict",         self.mispredict_clock),
+            ("stall.structural",         self.structural_stall_clock),
+            ("instrs.total",             self.total_instrs),
+            ("instrs.control",           self.control_instrs),
+            ("instrs.load",              self.load_instrs),
+            ("instrs.store",             self.store_instrs),
+            ("instrs.nt_store",          self.nt_store_instrs),
+            ("instrs.arithmetic",        self.arithmetic_instrs),
+            ("instrs.wrong_path",        self.wrong_path_instrs),
+            ("branch.predicted",         self.branch_predictions),
+            ("branch.mispredicted",      self.branch_mispredicts),
+            ("mem.demand_pages",         self.demand_pages),
+            ("mem.misaligned_accesses",  self.misaligned_accesses),
+            ("bus.busy",                 self.bus_busy_clock),
+            ("bus.wait",                 self.bus_wait_clock),
+        ] {
+            counters.add(key, value);
+        }
+        counters
+    }
 }
 
@@ -0,0 +1,33 @@
+/// Receives the error and log messages the simulator reports while it runs
+pub trait Logger {
+    /// Report an error
+    fn err(&self, msg: &str);
+
+    /// Report an informational message
+    fn log(&self, msg: &str);
+}
+
+/// Prints messages to the terminal when running without the gui. Errors go to stderr and log
+/// messages to stdout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleLog;
+
+impl Logger for ConsoleLog {
+    fn err(&self, msg: &str) {
+        eprintln!("{}", msg);
+    }
+
+    fn log(&self, msg: &str) {
+        println!("{}", msg);
+    }
+}
+
+/// Helper to print out error msg on simulator gui
+pub fn gui_err_print(msg: &str, err_log: &dyn Logger) {
+    err_log.err(msg);
+}
+
+/// Helper to print out log msg on simulator gui
+pub fn gui_log_print(msg: &str, err_log: &dyn Logger) {
+    err_log.log(msg);
+}
@@ -0,0 +1,440 @@
+use crate::{
+    simulator::{Simulator, SimErr, DEFAULT_STACK_BASE},
+    mmu::{VAddr, Perms, Cache, PAGE_SIZE, DEFAULT_L1_SETS},
+    cpu::Register,
+    headless::RunEnd,
+    turbo::TurboStop,
+    mmio::MmioDevice,
+    difftest::MsgLog,
+    predictor::{BranchPredictor, PredictorKind},
+    energy::EnergyModel,
+    uart::{Uart, UART_BASE, UART_SIZE, UART_IRQ},
+    vga::VgaSize,
+    VgaBuffer, Stats, VGA_BASE,
+};
+
+/// Configuration a `Machine` is created from
+#[derive(Debug, Clone, Copy)]
+pub struct MachineConfig {
+    /// Seed all randomness is derived from, a random seed is picked if unset
+    pub seed: Option<u64>,
+
+    /// Run instructions through the 5-stage pipeline instead of one at a time
+    pub pipelining: bool,
+
+    /// Execute instructions out-of-order, takes precedence over `pipelining`
+    pub out_of_order: bool,
+
+    /// Forward results to the execute stage instead of stalling until they are written back
+    pub forwarding: bool,
+
+    /// Let the pipeline continue past load misses
+    pub nonblocking_cache: bool,
+
+    /// Strategy used to predict conditional branches
+    pub predictor: PredictorKind,
+
+    /// Size of physical memory in bytes, unlimited if unset
+    pub ram_size: Option<usize>,
+
+    /// Address the stack is mapped at
+    pub stack_base: u32,
+
+    /// Check every retired instruction against the functional golden model
+    pub golden: bool,
+
+    /// Enable the caches, all accesses go to ram otherwise
+    pub caches: bool,
+
+    /// Number of sets in the L1 cache, has to be a power of 2
+    pub l1_sets: usize,
+
+    /// Check the L2 cache on L1 misses before going to ram
+    pub l2: bool,
+
+    /// Energy charged per event, see `Simulator::energy`
+    pub energy: EnergyModel,
+
+    /// Dimensions of the text screen
+    pub vga_size: VgaSize,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            seed:              None,
+            pipelining:        true,
+            out_of_order:      false,
+            forwarding:        false,
+            nonblocking_cache: false,
+            predictor:         PredictorKind::default(),
+            ram_size:          None,
+            stack_base:        DEFAULT_STACK_BASE,
+            golden:            false,
+            caches:            true,
+            l1_sets:           DEFAULT_L1_SETS,
+            l2:                true,
+            energy:            EnergyModel::default(),
+            vga_size:          VgaSize::default(),
+        }
+    }
+}
+
+impl MachineConfig {
+    /// Configuration matching the current settings of `sim`, eg. to rerun its program. The stack
+    /// is placed at the default address
+    pub fn from_simulator(sim: &Simulator) -> Self {
+        Self {
+            seed:              Some(sim.seed),
+            pipelining:        sim.pipelining_enabled,
+            out_of_order:      sim.ooo_enabled,
+            forwarding:        sim.forwarding_enabled,
+            nonblocking_cache: sim.nonblocking_cache,
+            predictor:         sim.pipeline.predictor.kind,
+            ram_size:          sim.mmu.ram_size,
+            stack_base:        DEFAULT_STACK_BASE,
+            golden:            sim.golden.is_some(),
+            caches:            sim.mmu.cache_enabled,
+            l1_sets:           sim.mmu.cache.num_sets,
+            l2:                sim.mmu.l2_enabled,
+            energy:            sim.energy_model,
+            vga_size:          sim.vga_size,
+        }
+    }
+}
+
+/// Simulator with the default memory layout, an in-memory screen and a uart, for driving programs
+/// from tests and other tools. Messages the simulator reports are collected instead of printed
+#[derive(Debug)]
+pub struct Machine {
+    sim:  Simulator,
+    vga:  VgaBuffer,
+    uart: Uart,
+    log:  MsgLog,
+}
+
+impl Machine {
+    /// Simulator configured from `config`, with the default memory layout mapped
+    pub fn new(config: MachineConfig) -> Result<Self, SimErr> {
+        let mut sim = Simulator::new();
+        if let Some(seed) = config.seed {
+            sim.set_seed(seed);
+        }
+        sim.pipelining_enabled = config.pipelining;
+        sim.ooo_enabled        = config.out_of_order;
+        sim.forwarding_enabled = config.forwarding;
+        sim.nonblocking_cache  = config.nonblocking_cache;
+        sim.pipeline.predictor = BranchPredictor::new(config.predictor);
+        sim.mmu.ram_size       = config.ram_size;
+        sim.mmu.cache_enabled  = config.caches;
+        sim.mmu.cache          = Cache::new(config.l1_sets, sim.mmu.cache.ways);
+        sim.mmu.l2_enabled     = config.l2;
+        sim.energy_model       = config.energy;
+        sim.vga_size           = config.vga_size;
+        sim.map_default_layout(config.stack_base)?;
+        if config.golden {
+            sim.start_golden();
+        }
+
+        let vga = VgaBuffer::with_size(config.vga_size);
+        sim.devices.register(VAddr(VGA_BASE), config.vga_size.device_size(),
+                             Box::new(vga.clone()))?;
+        let uart = Uart::new();
+        sim.register_device(VAddr(UART_BASE), UART_SIZE, Some(UART_IRQ), Box::new(uart.clone()))?;
+        Ok(Self { sim, vga, uart, log: MsgLog::default() })
+    }
+
+    /// Assemble `source` and load it, execution starts at its entry point
+    pub fn load_program(&mut self, source: &str) -> Result<(), SimErr> {
+        self.sim.load_input(source, &self.log)
+    }
+
+    /// Load machine code to `addr` and start executing there. The pages it is loaded into are
+    /// mapped with read, write and execute permissions if they aren't mapped yet
+    pub fn load_binary(&mut self, addr: VAddr, code: &[u8]) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        let end   = addr.0 as usize + code.len();
+        for page in (start..end).step_by(PAGE_SIZE) {
+            match self.sim.map_page(VAddr(page as u32), Perms::READ | Perms::WRITE | Perms::EXEC) {
+                Ok(()) | Err(SimErr::MemOverlap) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        self.sim.mmu.mem_write_bulk(addr, code)?;
+
+        self.sim.flush_pipeline();
+        self.sim.pc          = addr;
+        self.sim.pipeline.pc = addr;
+        self.sim.clear_caches();
+        if self.sim.golden.is_some() {
+            self.sim.start_golden();
+        }
+        Ok(())
+    }
+
+    /// Step a single clock-cycle
+    pub fn step(&mut self) {
+        self.sim.step(&self.log);
+    }
+
+    /// Run until the program exits or stops with an error, or until `max_cycles` more cycles
+    /// elapsed
+    pub fn run(&mut self, max_cycles: u32) -> RunEnd {
+        let limit = self.sim.clock.saturating_add(max_cycles);
+        while self.sim.online {
+            if self.sim.clock >= limit {
+                return RunEnd::CycleLimit;
+            }
+            self.sim.step(&self.log);
+        }
+        RunEnd::Offline
+    }
+
+    /// Execute up to `max_instrs` instructions in turbo mode, see `Simulator::fast_forward`
+    pub fn fast_forward(&mut self, max_instrs: u64) -> TurboStop {
+        self.sim.fast_forward(max_instrs, &self.log)
+    }
+
+    /// Whether the program is still running
+    pub fn running(&self) -> bool {
+        self.sim.online
+    }
+
+    pub fn read_reg(&self, reg: Register) -> u32 {
+        self.sim.read_reg(reg)
+    }
+
+    pub fn write_reg(&mut self, reg: Register, val: u32) {
+        self.sim.write_reg(reg, val);
+    }
+
+    /// Address of the next instruction to retire
+    pub fn pc(&self) -> VAddr {
+        self.sim.pc
+    }
+
+    /// Read `len` bytes starting at `addr`, without affecting cache-state
+    pub fn read_mem(&mut self, addr: VAddr, len: usize) -> Result<Vec<u8>, SimErr> {
+        let mut reader = vec![0u8; len];
+        self.populate(addr, len)?;
+        self.sim.mmu.mem_read_bulk(addr, &mut reader)?;
+        Ok(reader)
+    }
+
+    /// Write `data` to memory starting at `addr`
+    pub fn write_mem(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        self.populate(addr, data.len())?;
+        self.sim.mmu.mem_write_bulk(addr, data)
+    }
+
+    /// Back lazily mapped pages overlapping `[addr, addr+len)` with physical memory
+    fn populate(&mut self, addr: VAddr, len: usize) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + len).step_by(PAGE_SIZE) {
+            self.sim.mmu.populate_page(VAddr(page as u32))?;
+        }
+        Ok(())
+    }
+
+    /// Clock-counter at the current point in the simulation
+    pub fn clock(&self) -> u32 {
+        self.sim.clock
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.sim.stats
+    }
+
+    /// Current contents of the vga-buffer
+    pub fn screen(&self) -> String {
+        self.vga.text()
+    }
+
+    /// Everything the program sent over the uart so far
+    pub fn serial_output(&self) -> String {
+        self.uart.output()
+    }
+
+    /// Queue `bytes` for the program to receive over the uart
+    pub fn send_serial(&self, bytes: &[u8]) {
+        self.uart.send(bytes);
+    }
+
+    /// Errors reported so far, oldest first
+    pub fn errors(&self) -> Vec<String> {
+        self.log.errors.borrow().clone()
+    }
+
+    /// Log messages reported so far, oldest first
+    pub fn logs(&self) -> Vec<String> {
+        self.log.logs.borrow().clone()
+    }
+
+    /// Add a custom peripheral for `base..base+size`, see `Simulator::register_device`
+    pub fn register_device(&mut self, base: VAddr, size: u32, irq: Option<u32>,
+                           device: Box<dyn MmioDevice>) -> Result<(), SimErr> {
+        self.sim.register_device(base, size, irq, device)
+    }
+
+    /// The underlying simulator, for configuration this interface doesn't cover
+    pub fn simulator(&mut self) -> &mut Simulator {
+        &mut self.sim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::MmioAction;
+
+    const PROGRAM: &str = "
+        .entry _start
+        .load 0x10000
+        ._start
+            movi r3 0x48
+            movi r4 0x1000
+            stb r3 r4 0x0
+            movi r5 0x3000
+            st r4 r5 0x0
+            movi r1 0x41
+            movi r2 0x2000
+            st r1 r2 0x0
+        .end_section
+    ";
+
+    #[test]
+    fn runs_programs_from_source_and_machine_code() {
+        let config = MachineConfig { seed: Some(1), ..MachineConfig::default() };
+        let mut machine = Machine::new(config).unwrap();
+        machine.simulator().map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+        machine.load_program(PROGRAM).unwrap();
+        assert_eq!(machine.run(100_000), RunEnd::Offline);
+
+        assert_eq!(machine.read_reg(Register::R3), 0x48);
+        assert_eq!(machine.read_mem(VAddr(0x3000), 4).unwrap(), [0x00, 0x10, 0x00, 0x00]);
+        assert!(machine.screen().starts_with("H "));
+        assert!(machine.stats().store_instrs >= 2);
+        assert!(machine.errors().is_empty());
+
+        // The same code loaded as bytes behaves the same
+        let code = machine.read_mem(VAddr(0x10000), 32).unwrap();
+        for out_of_order in [false, true] {
+            let config = MachineConfig { out_of_order, ..config };
+            let mut other = Machine::new(config).unwrap();
+            other.simulator().map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+            other.load_binary(VAddr(0x40000), &code).unwrap();
+            assert_eq!(other.run(100_000), RunEnd::Offline);
+            assert_eq!(other.read_mem(VAddr(0x3000), 4).unwrap(), [0x00, 0x10, 0x00, 0x00]);
+            assert_eq!(other.read_reg(Register::R3), 0x48);
+        }
+    }
+
+    /// Sensor that measures for 50 cycles after being started, then raises its interrupt line.
+    /// The measurement can be read at offset 4
+    #[derive(Debug, Clone, Default)]
+    struct Sensor {
+        ready_at: Option<u32>,
+    }
+
+    impl MmioDevice for Sensor {
+        fn name(&self) -> &str {
+            "sensor"
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8], clock: u32) -> Option<MmioAction> {
+            if offset == 0 && data[0] == 1 {
+                self.ready_at = Some(clock + 50);
+            }
+            None
+        }
+
+        fn read(&mut self, offset: u32, reader: &mut [u8]) {
+            if offset == 4 {
+                reader[0] = 42;
+            }
+        }
+
+        fn tick(&mut self, clock: u32) -> Vec<MmioAction> {
+            if self.ready_at == Some(clock) {
+                self.ready_at = None;
+                return vec![MmioAction::RaiseIrq];
+            }
+            Vec::new()
+        }
+
+        fn clone_box(&self) -> Box<dyn MmioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn custom_devices_interrupt_through_their_line() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r7 0x5000
+                ld r6 r7 0x4
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0xc
+                movi r1 0x1
+                movi r2 0x5000
+                st r1 r2 0x0
+            .loop
+                beq r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let mut machine = Machine::new(MachineConfig::default()).unwrap();
+        machine.register_device(VAddr(0x5000), 8, Some(3), Box::new(Sensor::default())).unwrap();
+        assert_eq!(machine.register_device(VAddr(0x5004), 4, None, Box::new(Sensor::default())),
+                   Err(SimErr::MemOverlap));
+        machine.load_program(program).unwrap();
+        assert_eq!(machine.run(100_000), RunEnd::Offline);
+        assert!(machine.errors().is_empty(), "{:?}", machine.errors());
+        assert_eq!(machine.read_reg(Register::R6), 42);
+    }
+
+    #[test]
+    fn run_limits_count_from_the_current_cycle() {
+        let spin = ".entry _start\n.load 0x10000\n._start\n.loop\njmpr .loop\n.end_section";
+        let mut machine = Machine::new(MachineConfig::default()).unwrap();
+        machine.load_program(spin).unwrap();
+        assert_eq!(machine.run(500), RunEnd::CycleLimit);
+        assert_eq!(machine.run(500), RunEnd::CycleLimit);
+        assert_eq!(machine.clock(), 1_000);
+        assert!(machine.running());
+
+        assert_eq!(Machine::new(MachineConfig::default()).unwrap().load_program("garbage"),
+                   Err(SimErr::LoadErr));
+    }
+
+    /// Same check as the `execute` fuzz target, with a fixed set of random programs
+    #[test]
+    fn random_code_never_panics() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(7);
+        for mode in 0..8u32 {
+            for _ in 0..25 {
+                let code: Vec<u8> = (0..rng.gen_range(1..64 * 4)).map(|_| rng.gen()).collect();
+                let config = MachineConfig {
+                    seed:         Some(0),
+                    pipelining:   mode & 1 != 0,
+                    forwarding:   mode & 2 != 0,
+                    out_of_order: mode & 4 != 0,
+                    ..MachineConfig::default()
+                };
+                let mut machine = Machine::new(config).unwrap();
+                machine.load_binary(VAddr(0x40000), &code).unwrap();
+                machine.run(2_000);
+            }
+        }
+    }
+}
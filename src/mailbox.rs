@@ -0,0 +1,225 @@
+use crate::mmio::{MmioDevice, MmioAction, read_regs, write_regs};
+
+/// Address of the mailbox's registers, in the mmio page of the command-port
+pub const MAILBOX_BASE: u32 = 0x2100;
+
+/// Size of the mailbox's register block
+pub const MAILBOX_SIZE: u32 = 0x14;
+
+/// Interrupt-vector raised when a message arrives, its handler is stored at address 0x20
+pub const MAILBOX_IRQ: u32 = 8;
+
+/// Number of cores that have a mailbox. The simulator models a single core, so the doorbell can
+/// only target the core that rings it. Handing work to another core needs a second core
+pub const MAILBOX_CORES: u32 = 1;
+
+/// Control bit that raises `MAILBOX_IRQ` when a message arrives
+pub const MAILBOX_IRQ_ENABLE: u32 = 1 << 0;
+
+/// Status bit that is set while a message is waiting to be acknowledged
+pub const MAILBOX_PENDING: u32 = 1 << 0;
+
+/// Status bit that is set when a message arrived while another one was pending
+pub const MAILBOX_OVERRUN: u32 = 1 << 1;
+
+/// Status bit that is set when the doorbell was rung for a core that doesn't exist
+pub const MAILBOX_BAD_TARGET: u32 = 1 << 2;
+
+/// Core-local mailbox at `MAILBOX_BASE`, lets a core send a word to the mailbox of a core and
+/// interrupt it. Its 32-bit registers are:
+///  - 0x00 Payload: Word that is sent with the next message
+///  - 0x04 Doorbell: Writing a core number sends the payload to that core's mailbox
+///  - 0x08 Message: Payload of the last message that arrived, read-only
+///  - 0x0c Status: `MAILBOX_PENDING`, `MAILBOX_OVERRUN` and `MAILBOX_BAD_TARGET`, writing a 1 to a
+///    bit clears it
+///  - 0x10 Control: `MAILBOX_IRQ_ENABLE`
+///
+/// A message that arrives while another one is pending replaces it. With a single core the
+/// interrupt can only be sent to the core that rings the doorbell, an inter-processor interrupt
+/// needs more than one core
+#[derive(Debug, Clone, Default)]
+pub struct Mailbox {
+    payload:  u32,
+    doorbell: u32,
+    message:  u32,
+    status:   u32,
+    control:  u32,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 5] {
+        [self.payload, self.doorbell, self.message, self.status, self.control]
+    }
+
+    /// Deliver the payload to the mailbox of `core`, and interrupt it if it enabled interrupts
+    fn send(&mut self, core: u32) -> Option<MmioAction> {
+        if core >= MAILBOX_CORES {
+            self.status |= MAILBOX_BAD_TARGET;
+            return None;
+        }
+        if self.status & MAILBOX_PENDING != 0 {
+            self.status |= MAILBOX_OVERRUN;
+        }
+        self.message  = self.payload;
+        self.status  |= MAILBOX_PENDING;
+        (self.control & MAILBOX_IRQ_ENABLE != 0).then_some(MmioAction::RaiseIrq)
+    }
+}
+
+impl MmioDevice for Mailbox {
+    fn name(&self) -> &str {
+        "mailbox"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        self.payload  = regs[0];
+        self.doorbell = regs[1];
+        self.control  = regs[4];
+
+        // Status bits are cleared by writing a 1 to them
+        let mut cleared = [0u8; 4];
+        for (idx, &byte) in data.iter().enumerate() {
+            if let Some(status_idx) = (offset + idx).checked_sub(12).filter(|&idx| idx < 4) {
+                cleared[status_idx] = byte;
+            }
+        }
+        self.status &= !u32::from_le_bytes(cleared);
+
+        if offset < 8 && offset + data.len() > 4 {
+            self.send(self.doorbell)
+        } else {
+            None
+        }
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, cpu::Register, mmio::read_reg};
+
+    #[test]
+    fn doorbell_delivers_the_payload() {
+        let mut mailbox = Mailbox::new();
+        mailbox.write(0x0, &0xcafe_u32.to_le_bytes(), 0);
+        mailbox.write(0x4, &[0x0], 0);
+        assert_eq!((read_reg(&mut mailbox, 0x8), read_reg(&mut mailbox, 0xc)),
+                   (0xcafe, MAILBOX_PENDING));
+
+        // With interrupts enabled a second message raises the line and overruns the first one
+        mailbox.write(0x10, &[MAILBOX_IRQ_ENABLE as u8], 0);
+        mailbox.write(0x0, &[0x42], 0);
+        assert_eq!(mailbox.write(0x4, &0u32.to_le_bytes(), 0), Some(MmioAction::RaiseIrq));
+        assert_eq!(mailbox.message, 0xca42);
+        assert_eq!(mailbox.status, MAILBOX_PENDING | MAILBOX_OVERRUN);
+
+        // Cores past the last one are rejected, writing a 1 to a status bit clears it
+        mailbox.write(0xc, &[0xff], 0);
+        assert_eq!(mailbox.write(0x4, &[MAILBOX_CORES as u8], 0), None);
+        assert_eq!((mailbox.message, mailbox.status), (0xca42, MAILBOX_BAD_TARGET));
+        assert_eq!(mailbox.control, MAILBOX_IRQ_ENABLE);
+
+        // The message register can't be written
+        mailbox.write(0x8, &[0x0], 0);
+        assert_eq!(mailbox.message, 0xca42);
+    }
+
+    #[test]
+    fn programs_receive_their_own_messages() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x2100
+                movi r1 0x5a
+                st r1 r2 0x0
+                st r0 r2 0x4
+                ld r6 r2 0x8
+                ld r7 r2 0xc
+                movi r1 0x1
+                st r1 r2 0xc
+                st r1 r2 0x4
+                ld r8 r2 0xc
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 10_000, &|_| {});
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{:?}", run.log);
+            assert_eq!(run.sim.read_reg(Register::R6), 0x5a);
+            assert_eq!(run.sim.read_reg(Register::R7), MAILBOX_PENDING);
+            assert_eq!(run.sim.read_reg(Register::R8), MAILBOX_BAD_TARGET);
+        }
+    }
+
+    #[test]
+    fn messages_interrupt_the_receiving_core() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x20
+                movi r2 0x2100
+                movi r1 0x1
+                st r1 r2 0x10
+                movi r1 0x5a
+                st r1 r2 0x0
+                st r0 r2 0x4
+            .loop
+                beq r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+
+            .load 0x40000
+            .handler
+                movi r3 0x2100
+                ld r6 r3 0x8
+                movi r4 0x1
+                st r4 r3 0xc
+                ret
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+            assert_eq!(run.sim.read_reg(Register::R6), 0x5a, "{}", mode);
+
+            let mut sim = run.sim;
+            let region = sim.devices.regions.iter_mut()
+                .find(|region| region.base.0 == MAILBOX_BASE).unwrap();
+            assert_eq!(read_reg(region.device.as_mut(), 0xc), 0, "{}", mode);
+        }
+    }
+}
@@ -1,34 +1,514 @@
+#[cfg(feature = "gui")]
 use seal_isa::{
-    gui::setup_gui, 
-    simulator::Simulator,
-    mmu::{Perms, VAddr, PAGE_SIZE},
-    cpu::Register,
+    gui::setup_gui,
+    undo::{UndoLog, DEFAULT_UNDO_DEPTH},
+    debugger::RunLimits,
+    reg_history::{RegHistory, DEFAULT_REG_HISTORY_DEPTH},
+    retire_log::{RetireLog, DEFAULT_RETIRE_LOG_DEPTH},
+    theme::Theme,
+};
+use seal_isa::{
+    simulator::{Simulator, Aslr, DEFAULT_STACK_BASE},
+    headless::{self, RunEnd},
+    batch::{self, DEFAULT_BATCH_CYCLES},
+    machine::MachineConfig,
+    logger::ConsoleLog,
+    mmu::{VAddr, FrameAllocator, parse_ram_size},
+    predictor::{BranchPredictor, PredictorKind},
+    pipeline::PipelineHistory,
+    profiler::{self, Profile},
+    energy::EnergyModel,
+    console,
+    uart::{Uart, UART_BASE, UART_SIZE, UART_IRQ},
+    disk::{Disk, DISK_BASE, DISK_SIZE, DISK_IRQ},
+    buzzer::{Buzzer, BUZZER_BASE, BUZZER_SIZE, BUZZER_IRQ},
+    semihost::{Semihost, SEMIHOST_BASE, SEMIHOST_SIZE},
+    gpio::{self, Gpio, GPIO_BASE, GPIO_SIZE},
+    segment::{SevenSegment, SEGMENT_BASE, SEGMENT_SIZE},
+    putc::{PutcPort, PUTC_BASE, PUTC_SIZE},
+    vga::VgaSize,
+    VgaBuffer,
 };
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::io::{Read, Write, IsTerminal};
+
+/// Command-line usage, printed when the arguments can't be parsed
+const USAGE: &str = "Usage: seal_isa [--headless] [--max-cycles N] [--seed N] \
+                     [program [args...]]\n       \
+                     seal_isa --debug [--seed N] program [args...]\n       \
+                     seal_isa --batch [--max-cycles N] [--seed N] programs...\n       \
+                     seal_isa --sweep [--max-cycles N] [--seed N] program";
+
+/// Print the usage and exit
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
-    let mut simulator = Rc::new(RefCell::new(Simulator::default()));
+    let mut headless_mode = false;
+    let mut batch_mode    = false;
+    let mut sweep_mode    = false;
+    let mut debug_mode    = false;
+    let mut max_cycles    = None;
+    let mut seed          = None;
+    let mut program       = None;
+    let mut programs      = Vec::new();
+    let mut guest_args    = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--headless" => headless_mode = true,
+            "--batch" => batch_mode = true,
+            "--sweep" => sweep_mode = true,
+            "--debug" => debug_mode = true,
+            "--max-cycles" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(n) => max_cycles = Some(n),
+                None => usage(),
+            },
+            "--seed" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(n) => seed = Some(n),
+                None => usage(),
+            },
+            _ if batch_mode => programs.push(arg.clone()),
+            _ if program.is_none() => program = Some(arg.clone()),
+            _ => guest_args.push(arg.clone()),
+        }
+    }
+
+    let simulator = Rc::new(RefCell::new(Simulator::default()));
+
+    // Seed all randomness so a run can be reproduced, a random seed is picked otherwise
+    if let Some(seed) = seed {
+        simulator.borrow_mut().set_seed(seed);
+    }
+
+    // Select how physical frames are allocated (`random`, `sequential` or `seed=N`). Defaults to
+    // random allocation
+    if let Ok(config) = std::env::var("SEAL_FRAME_ALLOC") {
+        match FrameAllocator::parse(&config) {
+            Some(allocator) => simulator.borrow_mut().mmu.frame_allocator = allocator,
+            None => eprintln!("Invalid SEAL_FRAME_ALLOC `{}`, using random allocation", config),
+        }
+    }
+
+    // Limit physical memory to the given size (eg. `65536`, `64K` or `2M`). Unlimited by default
+    if let Ok(config) = std::env::var("SEAL_RAM_SIZE") {
+        match parse_ram_size(&config) {
+            Some(size) => simulator.borrow_mut().mmu.ram_size = Some(size),
+            None => eprintln!("Invalid SEAL_RAM_SIZE `{}`, physical memory is unlimited", config),
+        }
+    }
+
+    // Select how conditional branches are predicted (`stall`, `not-taken`, `backward-taken` or
+    // `2-bit`). By default the front-end stalls until branches are resolved
+    if let Ok(config) = std::env::var("SEAL_PREDICTOR") {
+        match PredictorKind::parse(&config) {
+            Some(kind) => simulator.borrow_mut().pipeline.predictor = BranchPredictor::new(kind),
+            None => eprintln!("Invalid SEAL_PREDICTOR `{}`, branches are not predicted", config),
+        }
+    }
 
-    // Allocate page for interrupt-vector
-    simulator.borrow_mut().map_page(VAddr(0x0), Perms::READ | Perms::WRITE).unwrap();
+    // Let the pipeline continue past load misses (`1`), instead of stalling until they are serviced
+    if let Ok(config) = std::env::var("SEAL_NONBLOCKING_CACHE") {
+        simulator.borrow_mut().nonblocking_cache = config == "1";
+    }
 
-    // Allocate page for vga-buffer
-    simulator.borrow_mut().map_page(VAddr(0x1000), Perms::READ | Perms::WRITE).unwrap();
+    // Forward results to the execute stage (`1`), instead of stalling until they are written back
+    if let Ok(config) = std::env::var("SEAL_FORWARDING") {
+        simulator.borrow_mut().forwarding_enabled = config == "1";
+    }
 
-    // Allocate page for mmio-region
-    simulator.borrow_mut().map_page(VAddr(0x2000), Perms::READ | Perms::WRITE).unwrap();
+    // File that pipeline traces are recorded to, `pipeline_trace.log` by default
+    if let Ok(path) = std::env::var("SEAL_TRACE_FILE") {
+        simulator.borrow_mut().trace_path = path.into();
+    }
+
+    // File that execution traces are recorded to, `exec_trace.log` by default. Headless runs only
+    // record an execution trace if this is set
+    let exec_trace = std::env::var("SEAL_EXEC_TRACE_FILE").ok();
+    if let Some(path) = &exec_trace {
+        simulator.borrow_mut().exec_trace_path = path.into();
+    }
+
+    // Record the pipeline state of the last N cycles so they can be reviewed in the gui
+    if let Ok(config) = std::env::var("SEAL_PIPELINE_HISTORY") {
+        match config.parse() {
+            Ok(len) => simulator.borrow_mut().pipeline_history = Some(PipelineHistory::new(len)),
+            Err(_) => eprintln!("Invalid SEAL_PIPELINE_HISTORY `{}`, history is not recorded", 
+                                config),
+        }
+    }
 
-    // Allocate a stack and write address to stack pointer `r15`
-    for i in 0..20 {
-        simulator.borrow_mut().map_page(VAddr(0x80000 + (i * PAGE_SIZE as u32)), 
-                                        Perms::READ | Perms::WRITE).unwrap();
+    // Count how often each instruction retires (`1`), headless runs print the hottest ones
+    if std::env::var("SEAL_PROFILE").is_ok_and(|config| config == "1") {
+        simulator.borrow_mut().profile = Some(Profile::default());
     }
-    simulator.borrow_mut().write_reg(Register::R15, 0x80000 + (20 * PAGE_SIZE as u32) - 4);
-    let app = setup_gui(&mut simulator, &args);
 
-    app.run().unwrap();
+    // Energy charged per event in nJ, eg. `alu=0.5,l1=1,l2=5,dram=50,static=0.2,cycle=1`
+    if let Ok(config) = std::env::var("SEAL_ENERGY") {
+        match EnergyModel::parse(&config) {
+            Some(model) => simulator.borrow_mut().energy_model = model,
+            None => eprintln!("Invalid SEAL_ENERGY `{}`, using the default energy model", config),
+        }
+    }
+
+    // Dimensions of the text screen as lines times columns, eg. `25x80`. 8 lines of 29 characters
+    // by default
+    if let Ok(config) = std::env::var("SEAL_VGA_SIZE") {
+        match VgaSize::parse(&config) {
+            Some(size) => simulator.borrow_mut().vga_size = size,
+            None => eprintln!("Invalid SEAL_VGA_SIZE `{}`, using the default screen size", config),
+        }
+    }
+
+    // Execute the first N instructions of headless runs in turbo mode, without modeling timing
+    if let Ok(config) = std::env::var("SEAL_FAST_FORWARD") {
+        match config.parse() {
+            Ok(instrs) => simulator.borrow_mut().fast_forward_instrs = Some(instrs),
+            Err(_) => eprintln!("Invalid SEAL_FAST_FORWARD `{}`, runs are not fast-forwarded",
+                                config),
+        }
+    }
+
+    // Check every retired instruction against the functional golden model (`1`). The simulation
+    // stops at the first divergence
+    if std::env::var("SEAL_GOLDEN").is_ok_and(|config| config == "1") {
+        simulator.borrow_mut().start_golden();
+    }
+
+    // Randomize stack and code addresses if requested (`random` or a seed to reproduce a layout)
+    let mut stack_base = DEFAULT_STACK_BASE;
+    if let Ok(config) = std::env::var("SEAL_ASLR") {
+        let seed = if config == "random" { Some(rand::random()) } else { config.parse().ok() };
+        match seed.map(Aslr::new) {
+            Some(aslr) => {
+                println!("ASLR enabled with seed: {}", aslr.seed);
+                stack_base = aslr.stack_base;
+                simulator.borrow_mut().code_slide = aslr.code_slide;
+            },
+            None => eprintln!("Invalid SEAL_ASLR `{}`, layout is not randomized", config),
+        }
+    }
+
+    // Map the interrupt-vector, vga-buffer and mmio pages, and a stack at `stack_base`
+    simulator.borrow_mut().map_default_layout(stack_base).unwrap();
+
+    // Attach a disk backed by the given image file, sectors the program writes are stored to it
+    if let Ok(path) = std::env::var("SEAL_DISK") {
+        match Disk::open(&path) {
+            Ok(disk) => {
+                if let Err(err) = simulator.borrow_mut().register_device(
+                        VAddr(DISK_BASE), DISK_SIZE, Some(DISK_IRQ), Box::new(disk)) {
+                    eprintln!("Failed to register disk: {:?}", err);
+                }
+            },
+            Err(err) => eprintln!("Failed to open disk image `{}`: {}", path, err),
+        }
+    }
+
+    // Tones the program plays come out of the host's speakers, unless they are muted
+    let muted  = std::env::var("SEAL_MUTE").is_ok_and(|config| config == "1");
+    let buzzer = if muted { Buzzer::new() } else { Buzzer::speaker() };
+    if let Err(err) = simulator.borrow_mut().register_device(
+            VAddr(BUZZER_BASE), BUZZER_SIZE, Some(BUZZER_IRQ), Box::new(buzzer)) {
+        eprintln!("Failed to register buzzer: {:?}", err);
+    }
+
+    // Let the program use host files in `SEAL_SEMIHOST_DIR`, the working directory by default, and
+    // hand it the arguments that follow it on the command-line
+    let root = std::env::var("SEAL_SEMIHOST_DIR").unwrap_or_else(|_| ".".to_string());
+    let argv = program.iter().chain(&guest_args).cloned().collect();
+    if let Err(err) = simulator.borrow_mut().devices.register(
+            VAddr(SEMIHOST_BASE), SEMIHOST_SIZE, Box::new(Semihost::new(root, argv))) {
+        eprintln!("Failed to register semihosting: {:?}", err);
+    }
+
+    // Without the gui, programs can only be run headless
+    if batch_mode {
+        programs.extend(program);
+        run_batch(&simulator.borrow(), &programs, max_cycles.unwrap_or(DEFAULT_BATCH_CYCLES));
+    } else if sweep_mode {
+        run_sweep(&simulator.borrow(), program, max_cycles.unwrap_or(DEFAULT_BATCH_CYCLES));
+    } else if debug_mode {
+        run_console(&mut simulator.borrow_mut(), program);
+    } else if headless_mode || !cfg!(feature = "gui") {
+        run_headless(&mut simulator.borrow_mut(), program, max_cycles, exec_trace.is_some());
+    } else {
+        #[cfg(feature = "gui")]
+        {
+            // Record the last N cycles so they can be stepped back through, 0 disables this
+            let undo_depth = match std::env::var("SEAL_UNDO_DEPTH") {
+                Ok(config) => config.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid SEAL_UNDO_DEPTH `{}`, keeping {} cycles", config,
+                              DEFAULT_UNDO_DEPTH);
+                    DEFAULT_UNDO_DEPTH
+                }),
+                Err(_) => DEFAULT_UNDO_DEPTH,
+            };
+            if undo_depth > 0 {
+                simulator.borrow_mut().undo_log = Some(UndoLog::new(undo_depth));
+            }
+
+            // Keep the last N writes to each register for the register panel, 0 disables this
+            let reg_history = match std::env::var("SEAL_REG_HISTORY") {
+                Ok(config) => config.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid SEAL_REG_HISTORY `{}`, keeping {} writes", config,
+                              DEFAULT_REG_HISTORY_DEPTH);
+                    DEFAULT_REG_HISTORY_DEPTH
+                }),
+                Err(_) => DEFAULT_REG_HISTORY_DEPTH,
+            };
+            if reg_history > 0 {
+                simulator.borrow_mut().reg_history = Some(RegHistory::new(reg_history));
+            }
+
+            // Keep the last N retired instructions for the retired panel, 0 disables this
+            let retire_log = match std::env::var("SEAL_RETIRE_LOG") {
+                Ok(config) => config.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid SEAL_RETIRE_LOG `{}`, keeping {} instructions", config,
+                              DEFAULT_RETIRE_LOG_DEPTH);
+                    DEFAULT_RETIRE_LOG_DEPTH
+                }),
+                Err(_) => DEFAULT_RETIRE_LOG_DEPTH,
+            };
+            if retire_log > 0 {
+                simulator.borrow_mut().retire_log = Some(RetireLog::new(retire_log));
+            }
+
+            // Stop a run after N cycles or instructions, 0 disables a limit. Runs are limited to
+            // `DEFAULT_RUN_CYCLE_LIMIT` cycles by default
+            let limit = |var: &str| std::env::var(var).ok().map(|config| {
+                config.parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!("Invalid {} `{}`, the limit is disabled", var, config);
+                    0
+                })
+            });
+            let mut limits = RunLimits::default();
+            if let Some(max) = limit("SEAL_MAX_CYCLES") {
+                limits.max_cycles = (max > 0).then_some(max.min(u32::MAX as u64) as u32);
+            }
+            if let Some(max) = limit("SEAL_MAX_INSTRS") {
+                limits.max_instrs = (max > 0).then_some(max);
+            }
+            simulator.borrow_mut().run_limits = limits;
+
+            // Colors of the gui (`light` or `dark`), it can be switched on the gui. Light by
+            // default
+            let theme = match std::env::var("SEAL_THEME") {
+                Ok(config) => Theme::parse(&config).unwrap_or_else(|| {
+                    eprintln!("Invalid SEAL_THEME `{}`, using the light theme", config);
+                    Theme::Light
+                }),
+                Err(_) => Theme::default(),
+            };
+
+            let args = [Some(args[0].clone()), program].into_iter().flatten().collect();
+            let app = setup_gui(&mut simulator.clone(), &args, theme);
+            app.run().unwrap();
+        }
+    }
+}
+
+/// Run `program` to completion without the gui, then print the screen contents and stats. If
+/// `exec_trace` is set, the retired instructions are recorded to the execution trace file
+fn run_headless(simulator: &mut Simulator, program: Option<String>, max_cycles: Option<u32>,
+                exec_trace: bool) {
+    let Some(path) = program else { usage() };
+    let input = match std::fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Failed to read `{}`: {}", path, err);
+            std::process::exit(1);
+        },
+    };
+
+    // Bytes the program sends over the uart are printed as they arrive. Input piped into the
+    // simulator is received over it. Debug output from the putc port goes to stderr
+    let uart = register_uart(simulator);
+    register_putc(simulator);
+    let gpio = register_gpio(simulator);
+    let display = register_display(simulator);
+    let mut stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut input = Vec::new();
+        match stdin.read_to_end(&mut input) {
+            Ok(_) => uart.send(&input),
+            Err(err) => eprintln!("Failed to read stdin: {}", err),
+        }
+    }
+
+    if exec_trace {
+        if let Err(err) = simulator.start_exec_trace() {
+            eprintln!("Failed to create execution trace: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let end = match headless::run(simulator, &input, max_cycles, &ConsoleLog) {
+        Ok((end, vga)) => {
+            print_screen(&vga);
+            if gpio.leds() != 0 {
+                println!("LEDs {}", gpio::led_row(gpio.leds()));
+            }
+            if !display.text().trim().is_empty() {
+                println!("Display {}", display.text());
+            }
+            end
+        },
+        Err(err) => {
+            eprintln!("Failed to load `{}`: {:?}", path, err);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(err) = simulator.stop_exec_trace() {
+        eprintln!("Failed to write execution trace: {}", err);
+    }
+    println!("{}", headless::stats_report(simulator));
+    if let Some(profile) = simulator.profile.clone() {
+        println!("\n{}", profiler::report(simulator, &profile));
+    }
+    if end == RunEnd::CycleLimit {
+        eprintln!("Stopped after reaching the cycle limit");
+        std::process::exit(2);
+    }
+}
+
+/// Load `program` and execute debugger console commands read from stdin, until `quit` or the end
+/// of the input. The screen contents are printed once the session ends
+fn run_console(simulator: &mut Simulator, program: Option<String>) {
+    let Some(path) = program else { usage() };
+    let input = match std::fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Failed to read `{}`: {}", path, err);
+            std::process::exit(1);
+        },
+    };
+    register_uart(simulator);
+    register_putc(simulator);
+    register_gpio(simulator);
+    register_display(simulator);
+    let vga = match headless::load(simulator, &input, &ConsoleLog) {
+        Ok(vga) => vga,
+        Err(err) => {
+            eprintln!("Failed to load `{}`: {:?}", path, err);
+            std::process::exit(1);
+        },
+    };
+
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("(seal) ");
+        let _ = std::io::stdout().flush();
+        line.clear();
+        if stdin.read_line(&mut line).map_or(true, |read| read == 0) || line.trim() == "quit" {
+            break;
+        }
+        match console::execute(simulator, &line, &ConsoleLog) {
+            Ok(output) if output.is_empty() => {},
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("Error: {}", err),
+        }
+    }
+    println!();
+    print_screen(&vga);
+}
+
+/// Register a uart that prints the bytes the program sends to stdout
+fn register_uart(simulator: &mut Simulator) -> Uart {
+    let uart = Uart::stdout();
+    if let Err(err) = simulator.register_device(VAddr(UART_BASE), UART_SIZE, Some(UART_IRQ),
+                                                Box::new(uart.clone())) {
+        eprintln!("Failed to register uart: {:?}", err);
+    }
+    uart
+}
+
+/// Register a putc port that prints the bytes the program stores to stderr
+fn register_putc(simulator: &mut Simulator) {
+    if let Err(err) = simulator.devices.register(VAddr(PUTC_BASE), PUTC_SIZE,
+                                                 Box::new(PutcPort::stderr())) {
+        eprintln!("Failed to register putc port: {:?}", err);
+    }
+}
+
+/// Register a gpio whose switches are set from `SEAL_SWITCHES`, a hex number with a bit per switch
+fn register_gpio(simulator: &mut Simulator) -> Gpio {
+    let gpio = Gpio::new();
+    if let Ok(config) = std::env::var("SEAL_SWITCHES") {
+        match u32::from_str_radix(config.trim_start_matches("0x"), 16) {
+            Ok(switches) => gpio.set_switches(switches),
+            Err(_) => eprintln!("Invalid SEAL_SWITCHES `{}`, all switches are off", config),
+        }
+    }
+    if let Err(err) = simulator.devices.register(VAddr(GPIO_BASE), GPIO_SIZE,
+                                                 Box::new(gpio.clone())) {
+        eprintln!("Failed to register gpio: {:?}", err);
+    }
+    gpio
+}
+
+/// Register a seven-segment display
+fn register_display(simulator: &mut Simulator) -> SevenSegment {
+    let display = SevenSegment::new();
+    if let Err(err) = simulator.devices.register(VAddr(SEGMENT_BASE), SEGMENT_SIZE,
+                                                 Box::new(display.clone())) {
+        eprintln!("Failed to register seven-segment display: {:?}", err);
+    }
+    display
+}
+
+/// Print the used lines of the screen
+fn print_screen(vga: &VgaBuffer) {
+    let screen = vga.text();
+    let lines  = screen.lines().map(str::trim_end).collect::<Vec<_>>();
+    let used   = lines.iter().rposition(|line| !line.is_empty()).map_or(0, |i| i + 1);
+    for line in &lines[..used] {
+        println!("{}", line);
+    }
+}
+
+/// Run every program in `paths` on a copy of `template` and print a table of their stats
+fn run_batch(template: &Simulator, paths: &[String], max_cycles: u32) {
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut programs = Vec::new();
+    for path in paths {
+        match std::fs::read_to_string(path) {
+            Ok(input) => programs.push((path.clone(), input)),
+            Err(err) => {
+                eprintln!("Failed to read `{}`: {}", path, err);
+                std::process::exit(1);
+            },
+        }
+    }
+
+    let entries = batch::run_batch(template, &programs, max_cycles, &ConsoleLog);
+    println!("{}", batch::format_table(&entries));
+}
+
+/// Run `program` under the configurations of `batch::default_sweep`, derived from the settings of
+/// `template`, and print a table comparing them
+fn run_sweep(template: &Simulator, program: Option<String>, max_cycles: u32) {
+    let Some(path) = program else { usage() };
+    let input = match std::fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Failed to read `{}`: {}", path, err);
+            std::process::exit(1);
+        },
+    };
+
+    let points  = batch::default_sweep(&MachineConfig::from_simulator(template));
+    let entries = batch::run_sweep(&input, &points, max_cycles);
+    println!("{}", batch::format_sweep(&entries));
 }
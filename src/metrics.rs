@@ -0,0 +1,62 @@
+use serde::{Serialize, Deserialize};
+
+use std::collections::BTreeMap;
+
+/// Registry of named counters. Subsystems register their counters under a dotted key, eg.
+/// `branch.taken` or `device.vga.writes`, instead of adding a field to `Stats` for each of them
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metrics {
+    counters: BTreeMap<String, u64>,
+}
+
+impl Metrics {
+    /// Register the counter `key`, so it is listed with a value of 0 before it is first counted
+    pub fn register(&mut self, key: &str) {
+        if !self.counters.contains_key(key) {
+            self.counters.insert(key.to_string(), 0);
+        }
+    }
+
+    /// Add `n` to the counter `key`, registering it if it does not exist yet
+    pub fn add(&mut self, key: &str, n: u64) {
+        match self.counters.get_mut(key) {
+            Some(value) => *value += n,
+            None        => { self.counters.insert(key.to_string(), n); },
+        }
+    }
+
+    /// Current value of the counter `key`, 0 if it was never registered
+    pub fn get(&self, key: &str) -> u64 {
+        self.counters.get(key).copied().unwrap_or(0)
+    }
+
+    /// All registered counters, sorted by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counters.iter().map(|(key, value)| (key.as_str(), *value))
+    }
+
+    /// Registered counters whose key starts with `prefix`, eg. `stall.` for all stall causes
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, u64)> {
+        self.iter().filter(move |(key, _)| key.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_registered_on_first_use() {
+        let mut metrics = Metrics::default();
+        metrics.register("device.uart.writes");
+        metrics.add("branch.taken", 2);
+        metrics.add("branch.taken", 3);
+        metrics.register("branch.taken");
+
+        assert_eq!(metrics.get("branch.taken"), 5);
+        assert_eq!(metrics.get("branch.unknown"), 0);
+        assert_eq!(metrics.iter().collect::<Vec<_>>(),
+                   [("branch.taken", 5), ("device.uart.writes", 0)]);
+        assert_eq!(metrics.with_prefix("device.").count(), 1);
+    }
+}
@@ -0,0 +1,495 @@
+use crate::{
+    mmu::VAddr,
+    cpu::Register,
+    simulator::SimErr,
+};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use std::collections::BTreeMap;
+
+/// Address of the command-port that lets guest code exit or read the clock
+pub const CMD_PORT_ADDR: u32 = 0x2000;
+
+/// Side-effect on the simulator requested by a device in response to an access or a tick
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MmioAction {
+    /// Stop the simulation
+    Shutdown,
+
+    /// Write a value to a guest register
+    WriteReg(Register, u32),
+
+    /// Interrupt the running program with the handler stored at interrupt-vector[n]. Delivered
+    /// at the start of a cycle, see `Simulator::raise_interrupt`
+    Interrupt(u32),
+
+    /// Write `data` to guest memory at `addr` without going through the cpu
+    Dma { addr: VAddr, data: Vec<u8> },
+
+    /// Raise the device's interrupt line, turned into an `Interrupt` with the vector the device
+    /// was registered with. Ignored if the device has no interrupt line
+    RaiseIrq,
+
+    /// Read `len` bytes of guest memory at `addr` without going through the cpu, they are handed
+    /// to the device's `dma_read`. Turned into a `DeviceDmaRead` by the mmio map
+    DmaRead { addr: VAddr, len: u32 },
+
+    /// `DmaRead` requested by the device registered at `device`
+    DeviceDmaRead { device: VAddr, addr: VAddr, len: u32 },
+
+    /// Stop executing instructions for `n` clock-cycles, or until the next interrupt with `None`.
+    /// Interrupts end a sleep early
+    Sleep(Option<u32>),
+}
+
+/// A memory-mapped peripheral. Devices are registered for an address range in an `MmioMap`, and
+/// receive all accesses that fall into that range with `offset` relative to the range's base
+pub trait MmioDevice: std::fmt::Debug {
+    /// Short name used when listing devices
+    fn name(&self) -> &str;
+
+    /// Called after `data` was written to the device's memory at `offset`
+    fn write(&mut self, offset: u32, data: &[u8], clock: u32) -> Option<MmioAction>;
+
+    /// Called after a read from `offset`. Devices can overwrite the bytes in `reader`, by default
+    /// the value stored in memory is returned unchanged
+    fn read(&mut self, _offset: u32, _reader: &mut [u8]) {}
+
+    /// Called at the start of every clock-cycle. Devices that act on their own, eg. timers or
+    /// devices that finish a transfer some cycles after it was started, return their actions here
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        Vec::new()
+    }
+
+    /// Called on the interrupt controller when a device raises interrupt line `line`, see
+    /// `MmioMap::set_interrupt_controller`. Returns the interrupt to deliver right away, if any
+    fn raise_line(&mut self, _line: u32) -> Option<MmioAction> {
+        None
+    }
+
+    /// Called with the bytes of guest memory read for a `DmaRead` the device requested
+    fn dma_read(&mut self, _addr: VAddr, _data: &[u8]) {}
+
+    /// Called with the simulator's seed when the device is registered and whenever the seed
+    /// changes. Devices that produce random values must derive them from this seed
+    fn reseed(&mut self, _seed: u64) {}
+
+    /// Called when the machine is reset. Devices return to their power-on state, eg. a screen is
+    /// cleared and pending transfers are dropped
+    fn reset(&mut self) {}
+
+    /// Clone this device into a new box, required to clone the simulator
+    fn clone_box(&self) -> Box<dyn MmioDevice>;
+}
+
+impl Clone for Box<dyn MmioDevice> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Little-endian words that make up `bytes`, eg. a device's register file
+pub fn le_words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    std::array::from_fn(|idx| u32::from_le_bytes(bytes[idx * 4..idx * 4 + 4].try_into().unwrap()))
+}
+
+/// Register values `regs` after `data` was written over them at byte `offset`. Lets devices whose
+/// registers can be written a byte at a time keep the bytes that aren't written. Bytes past the
+/// last register are ignored
+pub fn write_regs<const N: usize>(regs: [u32; N], offset: u32, data: &[u8]) -> [u32; N] {
+    let mut bytes = regs.map(u32::to_le_bytes).concat();
+    let start = std::cmp::min(offset as usize, bytes.len());
+    let end   = std::cmp::min(start + data.len(), bytes.len());
+    bytes[start..end].copy_from_slice(&data[..end - start]);
+    le_words(&bytes)
+}
+
+/// Fill `reader` with the bytes of the register values `regs` starting at byte `offset`. Bytes
+/// past the last register read as 0
+pub fn read_regs(regs: &[u32], offset: u32, reader: &mut [u8]) {
+    let bytes = regs.iter().flat_map(|reg| reg.to_le_bytes()).collect::<Vec<u8>>();
+    let start = std::cmp::min(offset as usize, bytes.len());
+    let end   = std::cmp::min(start + reader.len(), bytes.len());
+    reader.fill(0);
+    reader[..end - start].copy_from_slice(&bytes[start..end]);
+}
+
+/// Value of the 32-bit register at `offset` of `device`, for device tests
+#[cfg(test)]
+pub(crate) fn read_reg(device: &mut dyn MmioDevice, offset: u32) -> u32 {
+    let mut reader = [0u8; 4];
+    device.read(offset, &mut reader);
+    u32::from_le_bytes(reader)
+}
+
+/// Device registered for the address range `base..base+size`
+#[derive(Debug, Clone)]
+pub struct MmioRegion {
+    pub base: VAddr,
+    pub size: u32,
+    pub device: Box<dyn MmioDevice>,
+
+    /// Interrupt-vector raised by the device's interrupt line, see `MmioAction::RaiseIrq`
+    pub irq: Option<u32>,
+}
+
+impl MmioRegion {
+    /// Returns the part of a `len` byte access at `addr` that overlaps this region as an
+    /// `(offset into region, offset into access, length)` tuple
+    fn overlap(&self, addr: VAddr, len: usize) -> Option<(u32, usize, usize)> {
+        let (addr, base) = (addr.0 as u64, self.base.0 as u64);
+        let start = std::cmp::max(addr, base);
+        let end   = std::cmp::min(addr + len as u64, base + self.size as u64);
+        if start >= end {
+            return None;
+        }
+        Some(((start - base) as u32, (start - addr) as usize, (end - start) as usize))
+    }
+
+    /// Resolve the device's interrupt line and dma reads in `action`
+    fn route(&self, action: MmioAction) -> Option<MmioAction> {
+        match action {
+            MmioAction::RaiseIrq => self.irq.map(MmioAction::Interrupt),
+            MmioAction::DmaRead { addr, len } => {
+                Some(MmioAction::DeviceDmaRead { device: self.base, addr, len })
+            },
+            action => Some(action),
+        }
+    }
+}
+
+/// Routes memory accesses in registered address ranges to their devices
+#[derive(Debug, Clone, Default)]
+pub struct MmioMap {
+    pub regions: Vec<MmioRegion>,
+
+    /// Seed handed to devices, see `MmioDevice::reseed`
+    seed: u64,
+
+    /// Actions posted for a later cycle, performed when that cycle starts
+    scheduled: BTreeMap<u32, Vec<MmioAction>>,
+
+    /// Base of the device the interrupt lines are connected to. Without one, devices interrupt
+    /// the program with the vector of their line directly
+    interrupt_controller: Option<VAddr>,
+}
+
+impl MmioMap {
+    /// Register `device` for the address range `base..base+size`. Fails with `MemOverlap` if the
+    /// range overlaps with an already registered device
+    pub fn register(&mut self, base: VAddr, size: u32, mut device: Box<dyn MmioDevice>)
+            -> Result<(), SimErr> {
+        device.reseed(self.seed);
+        let region = MmioRegion { base, size, device, irq: None };
+        if size == 0 || self.regions.iter().any(|r| region.overlap(r.base, r.size as usize)
+                                                 .is_some()) {
+            return Err(SimErr::MemOverlap);
+        }
+        self.regions.push(region);
+        Ok(())
+    }
+
+    /// Reseed all registered devices, and the ones registered later on, with `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        for region in self.regions.iter_mut() {
+            region.device.reseed(seed);
+        }
+    }
+
+    /// Reset all registered devices and drop the actions scheduled for later cycles
+    pub fn reset(&mut self) {
+        self.scheduled.clear();
+        for region in self.regions.iter_mut() {
+            region.device.reset();
+        }
+    }
+
+    /// Connect the devices' interrupt lines to the device registered at `base`, or disconnect them
+    /// from the current controller with `None`
+    pub fn set_interrupt_controller(&mut self, base: Option<VAddr>) {
+        self.interrupt_controller = base;
+    }
+
+    /// Resolve `action` requested by the device in `region`. Interrupt lines of devices connected
+    /// to the interrupt controller are added to `lines` instead
+    fn connect(region: &MmioRegion, action: MmioAction, controller: Option<VAddr>,
+               lines: &mut Vec<u32>) -> Option<MmioAction> {
+        match (action, region.irq) {
+            (MmioAction::RaiseIrq, Some(line)) if controller.is_some_and(|c| c != region.base) => {
+                lines.push(line);
+                None
+            },
+            (action, _) => region.route(action),
+        }
+    }
+
+    /// Raise `lines` on the interrupt controller, returns the interrupts it delivers right away
+    fn raise_lines(&mut self, lines: Vec<u32>) -> Vec<MmioAction> {
+        let controller = self.interrupt_controller;
+        let Some(region) = self.regions.iter_mut().find(|r| Some(r.base) == controller) else {
+            return Vec::new();
+        };
+        lines.into_iter().filter_map(|line| region.device.raise_line(line)).collect()
+    }
+
+    /// Remove the device registered at `base`, returning it if one existed
+    pub fn unregister(&mut self, base: VAddr) -> Option<Box<dyn MmioDevice>> {
+        let idx = self.regions.iter().position(|r| r.base == base)?;
+        Some(self.regions.remove(idx).device)
+    }
+
+    /// Forward a write of `data` to `addr` to all devices it overlaps with. Returns the actions
+    /// requested by the devices
+    pub fn write(&mut self, addr: VAddr, data: &[u8], clock: u32) -> Vec<MmioAction> {
+        let (mut actions, mut lines) = (Vec::new(), Vec::new());
+        for region in self.regions.iter_mut() {
+            if let Some((offset, start, len)) = region.overlap(addr, data.len()) {
+                let action = region.device.write(offset, &data[start..start + len], clock);
+                actions.extend(action.and_then(|action| {
+                    Self::connect(region, action, self.interrupt_controller, &mut lines)
+                }));
+            }
+        }
+        actions.extend(self.raise_lines(lines));
+        actions
+    }
+
+    /// Post `action` to be performed at the start of `cycle`
+    pub fn schedule(&mut self, cycle: u32, action: MmioAction) {
+        self.scheduled.entry(cycle).or_default().push(action);
+    }
+
+    /// Start cycle `clock`: Returns the actions posted for it or earlier, followed by the actions
+    /// the devices request from their `tick`
+    pub fn tick(&mut self, clock: u32) -> Vec<MmioAction> {
+        let mut actions = Vec::new();
+        while let Some(entry) = self.scheduled.first_entry().filter(|e| *e.key() <= clock) {
+            actions.extend(entry.remove());
+        }
+        let mut lines = Vec::new();
+        for region in self.regions.iter_mut() {
+            let requested = region.device.tick(clock);
+            if !requested.is_empty() {
+                actions.extend(requested.into_iter().filter_map(|action| {
+                    Self::connect(region, action, self.interrupt_controller, &mut lines)
+                }));
+            }
+        }
+        actions.extend(self.raise_lines(lines));
+        actions
+    }
+
+    /// Hand the bytes read for a `DeviceDmaRead` to the device registered at `device`
+    pub fn dma_read(&mut self, device: VAddr, addr: VAddr, data: &[u8]) {
+        if let Some(region) = self.regions.iter_mut().find(|r| r.base == device) {
+            region.device.dma_read(addr, data);
+        }
+    }
+
+    /// Names of the devices that a `len` byte access at `addr` overlaps with
+    pub fn names_at(&self, addr: VAddr, len: usize) -> impl Iterator<Item = &str> {
+        self.regions.iter().filter(move |r| r.overlap(addr, len).is_some())
+            .map(|r| r.device.name())
+    }
+
+    /// Let all devices that overlap with a read from `addr` update the bytes in `reader`
+    pub fn read(&mut self, addr: VAddr, reader: &mut [u8]) {
+        for region in self.regions.iter_mut() {
+            if let Some((offset, start, len)) = region.overlap(addr, reader.len()) {
+                region.device.read(offset, &mut reader[start..start + len]);
+            }
+        }
+    }
+}
+
+/// Command-port at `CMD_PORT_ADDR`. Writing one of the following bytes triggers a command:
+///  - 0x41: Exit the simulation
+///  - 0x42: Write the current clock-counter to `r1`
+///  - 0x43: Write a random number to `r1`. Deprecated, since it changes a register behind the
+///    back of the pipeline. Load from the random number generator at `RNG_BASE` instead
+#[derive(Debug, Clone)]
+pub struct CommandPort {
+    /// Generator for the random numbers handed out by the deprecated 0x43, seeded through `reseed`
+    rng: StdRng,
+}
+
+impl CommandPort {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl MmioDevice for CommandPort {
+    fn name(&self) -> &str {
+        "command-port"
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8], clock: u32) -> Option<MmioAction> {
+        if offset != 0 {
+            return None;
+        }
+        match data[0] {
+            0x41 => Some(MmioAction::Shutdown),
+            0x42 => Some(MmioAction::WriteReg(Register::R1, clock)),
+            0x43 => Some(MmioAction::WriteReg(Register::R1, self.rng.gen())),
+            _ => None,
+        }
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device that records every write it receives
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        writes: Vec<(u32, Vec<u8>)>,
+    }
+
+    impl MmioDevice for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+            self.writes.push((offset, data.to_vec()));
+            None
+        }
+
+        fn read(&mut self, offset: u32, reader: &mut [u8]) {
+            reader.iter_mut().enumerate().for_each(|(i, b)| *b = (offset as usize + i) as u8);
+        }
+
+        fn clone_box(&self) -> Box<dyn MmioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn register_rejects_overlap() {
+        let mut map = MmioMap::default();
+        map.register(VAddr(0x1000), 0x10, Box::new(Recorder::default())).unwrap();
+        assert!(map.register(VAddr(0x100f), 4, Box::new(Recorder::default())).is_err());
+        assert!(map.register(VAddr(0x1010), 4, Box::new(Recorder::default())).is_ok());
+        assert!(map.unregister(VAddr(0x1000)).is_some());
+        assert!(map.register(VAddr(0x1000), 0x10, Box::new(Recorder::default())).is_ok());
+    }
+
+    #[test]
+    fn accesses_are_clipped_to_region() {
+        let mut map = MmioMap::default();
+        map.register(VAddr(0x1004), 4, Box::new(Recorder::default())).unwrap();
+
+        assert!(map.write(VAddr(0x1002), &[1, 2, 3, 4], 0).is_empty());
+        let mut reader = [0xffu8; 4];
+        map.read(VAddr(0x1006), &mut reader);
+        assert_eq!(reader, [2, 3, 0xff, 0xff]);
+
+        let dev = format!("{:?}", map.regions[0].device);
+        assert!(dev.contains("(0, [3, 4])"));
+    }
+
+    #[test]
+    fn command_port_actions() {
+        let mut port = CommandPort::new(0);
+        assert_eq!(port.write(0, &[0x41], 7), Some(MmioAction::Shutdown));
+        assert_eq!(port.write(0, &[0x42], 7), Some(MmioAction::WriteReg(Register::R1, 7)));
+        assert_eq!(port.write(1, &[0x41], 7), None);
+    }
+
+    #[test]
+    fn random_numbers_follow_the_seed() {
+        let mut map = MmioMap::default();
+        map.reseed(5);
+        map.register(VAddr(CMD_PORT_ADDR), 4, Box::new(CommandPort::new(0))).unwrap();
+        let first = map.write(VAddr(CMD_PORT_ADDR), &[0x43], 0);
+
+        map.reseed(5);
+        assert_eq!(map.write(VAddr(CMD_PORT_ADDR), &[0x43], 0), first);
+        assert_eq!(first, vec![CommandPort::new(5).write(0, &[0x43], 0).unwrap()]);
+    }
+
+    #[test]
+    fn scheduled_actions_run_on_their_cycle() {
+        let mut map = MmioMap::default();
+        map.schedule(5, MmioAction::Interrupt(2));
+        map.schedule(3, MmioAction::Shutdown);
+        assert!(map.tick(2).is_empty());
+        assert_eq!(map.tick(3), [MmioAction::Shutdown]);
+
+        // Actions posted for cycles that were skipped are performed on the next tick
+        assert_eq!(map.tick(7), [MmioAction::Interrupt(2)]);
+        assert!(map.tick(8).is_empty());
+    }
+
+    #[test]
+    fn register_files_ignore_bytes_past_the_last_register() {
+        assert_eq!(write_regs([0x11223344, 0x55667788], 6, &[1, 2, 3, 4]),
+                   [0x11223344, 0x02017788]);
+        assert_eq!(write_regs([0x11223344], 8, &[1]), [0x11223344]);
+
+        let mut reader = [0xffu8; 4];
+        read_regs(&[0x11223344], 2, &mut reader);
+        assert_eq!(reader, [0x22, 0x11, 0, 0]);
+        read_regs(&[0x11223344], 9, &mut reader);
+        assert_eq!(reader, [0; 4]);
+    }
+
+    /// Guest stores can hit any byte of a device's region, none of them may bring down the host
+    #[test]
+    fn stores_to_every_offset_of_the_built_in_devices() {
+        use crate::{
+            simulator::Simulator,
+            uart::{Uart, UART_BASE, UART_SIZE},
+            disk::{Disk, DISK_BASE, DISK_SIZE},
+            buzzer::{Buzzer, BUZZER_BASE, BUZZER_SIZE},
+            gpio::{Gpio, GPIO_BASE, GPIO_SIZE},
+            semihost::{Semihost, SEMIHOST_BASE, SEMIHOST_SIZE},
+            segment::{SevenSegment, SEGMENT_BASE, SEGMENT_SIZE},
+            mouse::{Mouse, MOUSE_BASE, MOUSE_SIZE},
+            putc::{PutcPort, PUTC_BASE, PUTC_SIZE},
+            vga::VgaSize,
+            VgaBuffer, VGA_BASE,
+        };
+
+        let mut map = Simulator::new().devices;
+        let vga_size = VgaSize::default().device_size();
+        let devices: [(u32, u32, Box<dyn MmioDevice>); 9] = [
+            (UART_BASE,     UART_SIZE,     Box::new(Uart::new())),
+            (DISK_BASE,     DISK_SIZE,     Box::new(Disk::new(Vec::new()))),
+            (BUZZER_BASE,   BUZZER_SIZE,   Box::new(Buzzer::new())),
+            (GPIO_BASE,     GPIO_SIZE,     Box::new(Gpio::new())),
+            (SEMIHOST_BASE, SEMIHOST_SIZE, Box::new(Semihost::new(".", Vec::new()))),
+            (SEGMENT_BASE,  SEGMENT_SIZE,  Box::new(SevenSegment::new())),
+            (MOUSE_BASE,    MOUSE_SIZE,    Box::new(Mouse::new())),
+            (PUTC_BASE,     PUTC_SIZE,     Box::new(PutcPort::new())),
+            (VGA_BASE,      vga_size,      Box::new(VgaBuffer::new())),
+        ];
+        for (base, size, device) in devices {
+            map.register(VAddr(base), size, device).unwrap();
+        }
+
+        let regions = map.regions.iter().map(|region| (region.base, region.size))
+            .collect::<Vec<_>>();
+        for (base, size) in regions {
+            for offset in 0..size {
+                let addr = VAddr(base.0 + offset);
+                for len in [1, 2, 4] {
+                    map.write(addr, &[0xff; 4][..len], 0);
+                    map.read(addr, &mut [0u8; 4][..len]);
+                }
+            }
+        }
+    }
+}
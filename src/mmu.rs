@@ -2,7 +2,9 @@ use crate::simulator::SimErr;
 
 use rustc_hash::FxHashMap;
 use std::collections::VecDeque;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Serialize, Deserialize};
 
 /// Size of physical pages allocated to programs
 pub const PAGE_SIZE: usize = 4096;
@@ -10,20 +12,73 @@ pub const PAGE_SIZE: usize = 4096;
 /// Number of entries in page-table levels. The ratio has an inverse relation-ship to page-sizes
 pub const PAGE_TABLE_ENTRIES: usize = PAGE_SIZE / 4;
 
+/// Size of large pages that are mapped directly by a first-level page-table entry
+pub const LARGE_PAGE_SIZE: usize = PAGE_SIZE * PAGE_TABLE_ENTRIES;
+
+/// Number of translations the TLB can hold at once
+pub const TLB_ENTRIES: usize = 16;
+
 /// Stall-time in cycles if an access to Ram occurs
 pub const RAM_STALL: usize = 100;
 
+/// Stall-time in cycles for reading a single page-table level during a page-table walk
+pub const PAGE_WALK_STALL: usize = 20;
+
 /// Stall-time in cycles if an access to L1 Cache occurs
 pub const L1_CACHE_STALL: usize = 10;
 
+/// Stall-time in cycles if an access misses L1 but hits the L2 Cache
+pub const L2_CACHE_STALL: usize = 30;
+
+/// Number of ram requests the memory bus can have in flight at once
+pub const MEM_BUS_REQUESTS: usize = 1;
+
+/// Size of a single cache-line in bytes
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Number of sets in the L1 cache unless configured otherwise
+pub const DEFAULT_L1_SETS: usize = 32;
+
+/// Page-table entry bit marking a page that has been mapped lazily, but not yet been backed by a
+/// physical frame. The frame is allocated on first access
+pub const PTE_LAZY: u32 = 1 << 3;
+
 /// Wrapper around virtual addresses
-#[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct VAddr(pub u32);
 
 /// Wrapper around physical addresses
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct PAddr(pub u32);
 
+/// Parse a physical memory size such as `65536`, `64K` or `2M` into bytes
+pub fn parse_ram_size(config: &str) -> Option<usize> {
+    let config = config.trim();
+    let (num, scale) = match config.chars().last()?.to_ascii_uppercase() {
+        'K' => (&config[..config.len() - 1], 1 << 10),
+        'M' => (&config[..config.len() - 1], 1 << 20),
+        _   => (config, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+/// Split a `len` byte access at `addr` into the largest naturally aligned accesses possible
+pub fn split_aligned(addr: VAddr, len: usize) -> Vec<(VAddr, usize)> {
+    let mut chunks = Vec::new();
+    let mut addr   = addr.0;
+    let mut len    = len;
+
+    while len > 0 {
+        let size = [4, 2, 1].into_iter()
+            .find(|&size| size <= len && addr & (size as u32 - 1) == 0)
+            .unwrap();
+        chunks.push((VAddr(addr), size));
+        addr = addr.wrapping_add(size as u32);
+        len -= size;
+    }
+    chunks
+}
+
 /// Permission bits as represented on the page tables
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy)]
@@ -33,10 +88,17 @@ impl Perms {
     pub const EXEC:  u8 = 1;
     pub const WRITE: u8 = 2;
     pub const READ:  u8 = 4;
+
+    /// Render `perms` in the `rwx` notation, using `-` for unset permissions
+    pub fn describe(perms: u8) -> String {
+        [(Perms::READ, 'r'), (Perms::WRITE, 'w'), (Perms::EXEC, 'x')].iter()
+            .map(|&(perm, c)| if perms & perm != 0 { c } else { '-' })
+            .collect()
+    }
 }
 
 /// Represents a cache-line that contains 32 DWords of memory
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheLine {
     /// Bit used to determine if the data in this cacheline is valid or has been invalidated
     pub is_valid: bool,
@@ -46,6 +108,31 @@ pub struct CacheLine {
 
     /// Data-backing for 16-Dword entries in a cacheline
     pub data: Vec<u8>,
+
+    /// Access statistics for this entry. These persist across the different lines that are
+    /// stored in this entry over time
+    pub counters: LineCounters,
+}
+
+/// Access statistics of a cache entry or set
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineCounters {
+    /// Number of times the entry was used, either by hitting on it or by filling it on a miss
+    pub accesses: u64,
+
+    /// Number of accesses that hit on the line already stored in the entry
+    pub hits: u64,
+
+    /// Number of times a valid line had to be evicted from the entry to make room for another
+    pub evictions: u64,
+}
+
+impl std::ops::AddAssign for LineCounters {
+    fn add_assign(&mut self, other: Self) {
+        self.accesses  += other.accesses;
+        self.hits      += other.hits;
+        self.evictions += other.evictions;
+    }
 }
 
 impl Default for CacheLine {
@@ -54,12 +141,415 @@ impl Default for CacheLine {
         Self {
             is_valid: false,
             tag: 0,
-            data: vec![0u8; 64],
+            data: vec![0u8; CACHE_LINE_SIZE],
+            counters: LineCounters::default(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Entry in the first level of the page table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PageDirEntry {
+    /// Second-level table that maps regular pages
+    Table(#[serde(with = "page_table_serde")] Box<[PAddr; PAGE_TABLE_ENTRIES]>),
+
+    /// Large page that maps a contiguous `LARGE_PAGE_SIZE` region. Permissions are encoded the same
+    /// way as in regular page-table entries
+    LargePage(PAddr),
+}
+
+/// Serializes second-level page tables as sequences, since serde only supports arrays of up to 32
+/// elements
+mod page_table_serde {
+    use super::{PAddr, PAGE_TABLE_ENTRIES};
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(table: &[PAddr; PAGE_TABLE_ENTRIES], serializer: S)
+            -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(table.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+            -> Result<Box<[PAddr; PAGE_TABLE_ENTRIES]>, D::Error> {
+        Vec::<PAddr>::deserialize(deserializer)?.into_boxed_slice().try_into()
+            .map_err(|_| D::Error::custom("Page table has the wrong number of entries"))
+    }
+}
+
+/// Policy used to pick physical frames when pages are mapped
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum FrameAllocator {
+    /// Frames are picked at random, so physical layouts differ between runs
+    #[default]
+    Random,
+
+    /// Frames are handed out in ascending order starting at physical address 0
+    Sequential { next: u32 },
+
+    /// Frames are picked pseudo-randomly from a seeded generator, so runs are reproducible
+    Seeded(Box<ChaCha12Rng>),
+}
+
+impl FrameAllocator {
+    /// Sequential allocator starting at the lowest physical frame
+    pub fn sequential() -> Self {
+        FrameAllocator::Sequential { next: 0 }
+    }
+
+    /// Pseudo-random allocator seeded with `seed`
+    pub fn seeded(seed: u64) -> Self {
+        FrameAllocator::Seeded(Box::new(ChaCha12Rng::seed_from_u64(seed)))
+    }
+
+    /// Parse an allocator from its config representation: `random`, `sequential` or `seed=N`
+    pub fn parse(config: &str) -> Option<Self> {
+        match config.trim() {
+            "random"     => Some(FrameAllocator::Random),
+            "sequential" => Some(FrameAllocator::sequential()),
+            s => s.strip_prefix("seed=")?.parse().ok().map(FrameAllocator::seeded),
+        }
+    }
+
+    /// Return the next candidate frame aligned to `size`. The caller is responsible for checking
+    /// whether the frame is actually free
+    fn next_candidate(&mut self, size: usize) -> PAddr {
+        let mask = !(size as u32 - 1);
+        match self {
+            FrameAllocator::Random => PAddr(rand::thread_rng().gen::<u32>() & mask),
+            FrameAllocator::Seeded(rng) => PAddr(rng.gen::<u32>() & mask),
+            FrameAllocator::Sequential { next } => {
+                let frame = next.wrapping_add(size as u32 - 1) & mask;
+                *next = frame.wrapping_add(size as u32);
+                PAddr(frame)
+            },
+        }
+    }
+}
+
+/// Copy of the contents of all mapped pages at some point in time
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemSnapshot {
+    /// Page-contents keyed by the virtual base-address of the page
+    pub pages: FxHashMap<VAddr, Vec<u8>>,
+}
+
+/// 4-byte word of memory that differs between a snapshot and the current memory state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemChange {
+    /// Virtual address of the word
+    pub addr: VAddr,
+
+    /// Value of the word when the snapshot was taken. Pages that were not mapped at that point
+    /// are treated as zeroed
+    pub old: u32,
+
+    /// Current value of the word
+    pub new: u32,
+}
+
+/// Contiguous range of virtual memory that is mapped with the same permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// Virtual base-address of the range
+    pub vaddr: VAddr,
+
+    /// Size of the range in bytes
+    pub size: usize,
+
+    /// Physical address backing `vaddr`, or `None` if the range is lazily mapped and has not been
+    /// touched yet
+    pub paddr: Option<PAddr>,
+
+    /// Permission bits of the range
+    pub perms: u8,
+
+    /// Range is mapped through large pages
+    pub large: bool,
+}
+
+impl Mapping {
+    /// Check if `next` directly follows this range and can be merged into it
+    fn extends_to(&self, next: &Mapping) -> bool {
+        let contiguous_paddr = match (self.paddr, next.paddr) {
+            (Some(paddr), Some(next)) => paddr.0 as usize + self.size == next.0 as usize,
+            (None, None) => true,
+            _ => false,
+        };
+        self.vaddr.0 as usize + self.size == next.vaddr.0 as usize && self.perms == next.perms &&
+            self.large == next.large && contiguous_paddr
+    }
+}
+
+/// Translation lookaside buffer. Translations are always performed functionally through the page
+/// table, this only models which translations would be cached to account for page-walk costs
+/// Each entry covers either a regular page or a large page
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tlb {
+    /// (virtual base-address, page-size) of cached translations, least recently used first
+    pub entries: VecDeque<(u32, usize)>,
+}
+
+impl Tlb {
+    /// Look up `addr`, returns `true` on a tlb-hit
+    pub fn lookup(&mut self, addr: VAddr) -> bool {
+        let pos = self.entries.iter().position(|&(base, size)| {
+            addr.0 & !(size as u32 - 1) == base
+        });
+
+        // Move hit entry to the back of the queue so it survives the longest
+        if let Some(pos) = pos {
+            let entry = self.entries.remove(pos).unwrap();
+            self.entries.push_back(entry);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Insert a translation for the page of size `size` containing `addr`
+    pub fn insert(&mut self, addr: VAddr, size: usize) {
+        if self.entries.len() == TLB_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((addr.0 & !(size as u32 - 1), size));
+    }
+
+    /// Drop all cached translations that overlap the page of size `size` containing `addr`
+    pub fn flush_page(&mut self, addr: VAddr, size: usize) {
+        let page = addr.0 & !(size as u32 - 1);
+        self.entries.retain(|&(base, entry_size)| {
+            page & !(entry_size as u32 - 1) != base && base & !(size as u32 - 1) != page
+        });
+    }
+
+    /// Drop all cached translations
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Level of the memory hierarchy that ended up servicing a memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemLevel {
+    L1,
+    L2,
+    Ram,
+}
+
+/// Shared bus between the caches and ram. Only a limited number of ram requests can be in flight
+/// at once, additional requests queue until a request-slot frees up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemBus {
+    /// Clock-cycle at which each request-slot becomes free again
+    pub busy_until: Vec<u64>,
+
+    /// Clock-cycle until which the bus has been accounted as busy
+    pub busy_end: u64,
+}
+
+impl MemBus {
+    /// Create a bus that can have `max_requests` ram requests in flight at once
+    pub fn new(max_requests: usize) -> Self {
+        Self {
+            busy_until: vec![0; max_requests],
+            busy_end:   0,
+        }
+    }
+
+    /// Issue a request at clock-cycle `now` that occupies the bus for `latency` cycles
+    /// Returns (cycles spent queueing for a request-slot, cycles the bus was newly busy for)
+    pub fn request(&mut self, now: u64, latency: usize) -> (usize, usize) {
+        let slot = (0..self.busy_until.len()).min_by_key(|&i| self.busy_until[i]).unwrap();
+
+        let start  = std::cmp::max(now, self.busy_until[slot]);
+        let finish = start + latency as u64;
+        self.busy_until[slot] = finish;
+
+        let busy = finish.saturating_sub(std::cmp::max(start, self.busy_end));
+        self.busy_end = std::cmp::max(self.busy_end, finish);
+
+        ((start - now) as usize, busy as usize)
+    }
+
+    /// Drop all in-flight requests
+    pub fn clear(&mut self) {
+        self.busy_until.iter_mut().for_each(|e| *e = 0);
+        self.busy_end = 0;
+    }
+}
+
+/// A set-associative cache made up of `num_sets * ways` cache-lines
+/// Address -> Cache translation: [ tag ][ index:log2(num_sets) ][ offset:6 ]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cache {
+    /// Cache-lines, stored set by set. Set `i` occupies `lines[i*ways..(i+1)*ways]`
+    pub lines: Vec<CacheLine>,
+
+    /// Number of sets in this cache, has to be a power of 2
+    pub num_sets: usize,
+
+    /// Associativity of this cache
+    pub ways: usize,
+
+    /// Least-recently-used queues that are used for the cache-eviction algorithm, one per set.
+    /// The entry at the front of a queue is the next one to be evicted from that set
+    pub lru_queues: Vec<VecDeque<u32>>,
+}
+
+impl Cache {
+    /// Create a new empty cache with `num_sets` sets of `ways` entries each
+    pub fn new(num_sets: usize, ways: usize) -> Self {
+        assert!(num_sets.is_power_of_two(), "Number of cache-sets needs to be a power of 2");
+        Self {
+            lines:      vec![CacheLine::default(); num_sets * ways],
+            num_sets,
+            ways,
+            lru_queues: vec![(0..ways as u32).collect(); num_sets],
+        }
+    }
+
+    /// Invalidate all entries in the cache
+    pub fn clear(&mut self) {
+        *self = Self::new(self.num_sets, self.ways);
+    }
+
+    /// Split up `addr` into its (offset, index, tag) components for this cache
+    fn split_addr(&self, addr: PAddr) -> (usize, usize, u32) {
+        let index_bits = self.num_sets.trailing_zeros();
+        let offset = (addr.0 as usize) & (CACHE_LINE_SIZE - 1);
+        let index  = ((addr.0 as usize) / CACHE_LINE_SIZE) & (self.num_sets - 1);
+        let tag    = addr.0 >> (CACHE_LINE_SIZE.trailing_zeros() + index_bits);
+        (offset, index, tag)
+    }
+
+    /// Return the entry-index in `lines` holding `addr` if it is currently cached
+    fn find(&self, addr: PAddr) -> Option<usize> {
+        let (_, index, tag) = self.split_addr(addr);
+
+        // Loop through the entries in this cache-set and see if we are already in here
+        (0..self.ways).map(|i| (index * self.ways) + i).find(|&i| {
+            self.lines[i].tag == tag && self.lines[i].is_valid
+        })
+    }
+
+    /// Return `true` if `addr` is currently cached
+    pub fn contains(&self, addr: PAddr) -> bool {
+        self.find(addr).is_some()
+    }
+
+    /// Mark entry `way` of set `index` as the most recently used one
+    fn touch(&mut self, index: usize, way: u32) {
+        // Update LRU list by removing entry from middle and moving it to the back where it
+        // will survive the longest before being marked for eviction
+        let queue = &mut self.lru_queues[index];
+        if let Some(j) = queue.iter().position(|&e| e == way) {
+            queue.remove(j);
+            queue.push_back(way);
+        }
+    }
+
+    /// Read bytes at `addr` into `reader` if the line is present. Returns `true` on a cache-hit
+    pub fn read(&mut self, addr: PAddr, reader: &mut [u8]) -> bool {
+        let (offset, index, _) = self.split_addr(addr);
+        if let Some(i) = self.find(addr) {
+            reader.copy_from_slice(&self.lines[i].data[offset..(reader.len() + offset)]);
+            self.lines[i].counters.accesses += 1;
+            self.lines[i].counters.hits     += 1;
+            self.touch(index, (i % self.ways) as u32);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the data of the cache-line holding `addr` if it is present
+    pub fn line_data(&self, addr: PAddr) -> Option<&Vec<u8>> {
+        self.find(addr).map(|i| &self.lines[i].data)
+    }
+
+    /// Access statistics of entry `way` in set `index`
+    pub fn line_counters(&self, index: usize, way: usize) -> LineCounters {
+        self.lines[(index * self.ways) + way].counters
+    }
+
+    /// Age of entry `way` in set `index`, the number of entries in its set that were used more
+    /// recently. The oldest entry is evicted next
+    pub fn line_age(&self, index: usize, way: usize) -> usize {
+        self.lru_queues[index].iter().rev().position(|&e| e as usize == way).unwrap()
+    }
+
+    /// Physical address of the line stored in entry `way` of set `index`
+    pub fn line_addr(&self, index: usize, way: usize) -> PAddr {
+        let index_bits = self.num_sets.trailing_zeros();
+        let tag        = self.lines[(index * self.ways) + way].tag;
+        PAddr((tag << (CACHE_LINE_SIZE.trailing_zeros() + index_bits)) |
+              (index * CACHE_LINE_SIZE) as u32)
+    }
+
+    /// Access statistics of set `index`, accumulated over all of its entries
+    pub fn set_counters(&self, index: usize) -> LineCounters {
+        let mut counters = LineCounters::default();
+        for way in 0..self.ways {
+            counters += self.line_counters(index, way);
+        }
+        counters
+    }
+
+    /// Insert the cache-line `data` for `addr` into the cache, evicting an entry if the set is
+    /// already full. If the line is already cached, its data is updated in place
+    pub fn fill(&mut self, addr: PAddr, data: Vec<u8>) {
+        let (_, index, tag) = self.split_addr(addr);
+
+        // See if there exists an entry that isn't valid that we can just use, otherwise evict the
+        // entry at the beginning of this set's lru-queue
+        let free_way = (0..self.ways).find(|&i| !self.lines[(index * self.ways) + i].is_valid);
+        let (way, present) = match (self.find(addr), free_way) {
+            (Some(i), _)       => ((i % self.ways) as u32, true),
+            (None, Some(way))  => (way as u32, false),
+            (None, None)       => (*self.lru_queues[index].front().unwrap(), false),
+        };
+        self.touch(index, way);
+
+        let line = &mut self.lines[(index * self.ways) + way as usize];
+        line.counters.accesses += 1;
+        if present {
+            line.counters.hits += 1;
+        } else if line.is_valid {
+            line.counters.evictions += 1;
+        }
+        line.data     = data;
+        line.tag      = tag;
+        line.is_valid = true;
+    }
+
+    /// Invalidate potential cache entry for `addr`
+    pub fn invalidate(&mut self, addr: PAddr) {
+        if let Some(i) = self.find(addr) {
+            self.lines[i].is_valid = false;
+        }
+    }
+
+    /// Update the cached copy of `addr` with `data` if the line is present. Neither allocates a
+    /// new line nor changes the lru-order. Returns `true` if the line was cached
+    pub fn write(&mut self, addr: PAddr, data: &[u8]) -> bool {
+        let (offset, _, _) = self.split_addr(addr);
+        if let Some(i) = self.find(addr) {
+            self.lines[i].data[offset..offset + data.len()].copy_from_slice(data);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Invalidate all cache entries for the physical page starting at `page_base`
+    pub fn invalidate_page(&mut self, page_base: PAddr) {
+        for line in (0..PAGE_SIZE).step_by(CACHE_LINE_SIZE) {
+            self.invalidate(PAddr(page_base.0 + line as u32));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// This takes care of managing memory and related structures such as caches or page-tables
 pub struct Mmu {
     /// Since we don't just want to allocate 2**32 bytes of memory, we use a hashmap to pull pages
@@ -69,20 +559,55 @@ pub struct Mmu {
     /// Page table that is used to translate virtual addresses into physical addresses and keep 
     /// track of mapped memory
     /// Address Decoding: [ L1:10 ][ L2:10 ][ offset:12 ]
+    /// Large pages:      [ L1:10 ][ offset:22 ]
     /// .0 - EXEC  Permission
     /// .1 - WRITE Permission
     /// .2 - READ  Permission
-    pub page_table: Vec<Option<[PAddr; PAGE_TABLE_ENTRIES]>>,
+    pub page_table: Vec<Option<PageDirEntry>>,
+
+    /// Caches recently used translations of both regular and large pages
+    pub tlb: Tlb,
+
+    /// Bus that ram accesses contend on
+    pub bus: MemBus,
     
     /// Memory loads will attempt to find data in caches first before resolving to retrieving them 
     /// from ram
-    pub cache: Vec<CacheLine>,
+    /// 4-way set-associative, 32 sets
+    pub cache: Cache,
 
-    /// Least-recently-used queue that is used for cache-eviction algorithm
-    pub lru_queue: VecDeque<u32>,
+    /// Larger but slower second cache-level that is checked on L1 misses before going to ram
+    /// 8-way set-associative, 64 sets
+    pub l2_cache: Cache,
 
     /// Used to enable/disable caching
     pub cache_enabled: bool,
+
+    /// Used to enable/disable the L2 cache-level
+    pub l2_enabled: bool,
+
+    /// Write-allocate policy. If set, stores pull the written line into the caches, otherwise
+    /// cached copies are invalidated and the write only goes to ram
+    pub write_allocate: bool,
+
+    /// Policy used to pick physical frames for newly mapped pages. Needs to be set before pages
+    /// are mapped for runs to be reproducible
+    pub frame_allocator: FrameAllocator,
+
+    /// Capacity of physical memory in bytes. Mapping pages beyond this fails with `OutOfMemory`,
+    /// `None` means physical memory is unlimited
+    pub ram_size: Option<usize>,
+
+    /// If set, the physical address and previous contents of every memory write are appended
+    /// here so the writes can be undone, see `undo_write`
+    #[serde(skip)]
+    pub write_journal: Option<Vec<(PAddr, Vec<u8>)>>,
+
+    /// If set, every physical page is copied here before it is first written, allocated or freed,
+    /// `None` for pages that did not exist yet. Memory can then be returned to the state it had
+    /// when this was set through `restore_pages`
+    #[serde(skip)]
+    pub page_backups: Option<FxHashMap<PAddr, Option<Vec<u8>>>>,
 }
 
 impl Default for Mmu {
@@ -95,18 +620,26 @@ impl Mmu {
     /// Initialize a new default Mmu
     pub fn new() -> Self {
         Self {
-            mem:            FxHashMap::default(),
-            page_table:     vec![Option::None; PAGE_TABLE_ENTRIES],
-            cache:          vec![CacheLine::default(); 32 * 4],
-            lru_queue:      VecDeque::from([0, 1, 2, 3]),
-            cache_enabled:  true,
+            mem:             FxHashMap::default(),
+            page_table:      vec![Option::None; PAGE_TABLE_ENTRIES],
+            tlb:             Tlb::default(),
+            bus:             MemBus::new(MEM_BUS_REQUESTS),
+            cache:           Cache::new(DEFAULT_L1_SETS, 4),
+            l2_cache:        Cache::new(64, 8),
+            cache_enabled:   true,
+            l2_enabled:      true,
+            write_allocate:  false,
+            frame_allocator: FrameAllocator::default(),
+            ram_size:        None,
+            write_journal:   None,
+            page_backups:    None,
         }
     }
 
-    /// Completely flush cache
+    /// Completely flush caches
     pub fn clear_caches(&mut self) {
-        self.cache = vec![CacheLine::default(); 32 * 4];
-        self.lru_queue = VecDeque::from([0, 1, 2, 3]);
+        self.cache.clear();
+        self.l2_cache.clear();
     }
 
     /// This performs a page-table walk to translate a given virtual address to a physical
@@ -117,231 +650,707 @@ impl Mmu {
         let idx_2  = ((addr.0 & 0x003ff000) >> 12) as usize;
         let offset =  addr.0 & (PAGE_SIZE as u32 - 1);
 
-        if let Some(table_1) = &self.page_table[idx_1] {
-            if (table_1[idx_2].0 & perms as u32) as u8 != perms {
-                return Err(SimErr::Permission);
-            }
-            let page_base = table_1[idx_2].0 & !(PAGE_SIZE as u32 - 1);
-            Ok(PAddr(page_base + offset))
-        } else {
-            Err(SimErr::AddrTranslation)
+        match &self.page_table[idx_1] {
+            Some(PageDirEntry::Table(table_1)) => {
+                // Lazily mapped pages need to be populated through `populate_page` first
+                if table_1[idx_2] == PAddr(0) || table_1[idx_2].0 & PTE_LAZY != 0 {
+                    return Err(SimErr::AddrTranslation);
+                }
+                if (table_1[idx_2].0 & perms as u32) as u8 != perms {
+                    return Err(SimErr::Permission);
+                }
+                let page_base = table_1[idx_2].0 & !(PAGE_SIZE as u32 - 1);
+                Ok(PAddr(page_base + offset))
+            },
+            Some(PageDirEntry::LargePage(pte)) => {
+                if (pte.0 & perms as u32) as u8 != perms {
+                    return Err(SimErr::Permission);
+                }
+                let page_base = pte.0 & !(LARGE_PAGE_SIZE as u32 - 1);
+                Ok(PAddr(page_base + (addr.0 & (LARGE_PAGE_SIZE as u32 - 1))))
+            },
+            None => Err(SimErr::AddrTranslation),
+        }
+    }
+
+    /// Size of the page that `addr` is mapped with
+    pub fn page_size(&self, addr: VAddr) -> usize {
+        match self.page_table[(addr.0 >> 22) as usize] {
+            Some(PageDirEntry::LargePage(_)) => LARGE_PAGE_SIZE,
+            _ => PAGE_SIZE,
         }
     }
 
+    /// Number of page-table levels that have to be read to translate `addr`. Large pages are
+    /// mapped directly by the first level
+    pub fn walk_levels(&self, addr: VAddr) -> usize {
+        match self.page_table[(addr.0 >> 22) as usize] {
+            Some(PageDirEntry::LargePage(_)) => 1,
+            _ => 2,
+        }
+    }
+
+    /// Look up the translation for `addr` in the tlb, and insert it on a miss. Returns `true` on a
+    /// tlb-hit, `false` if a page-table walk would have been required
+    pub fn tlb_access(&mut self, addr: VAddr) -> bool {
+        if self.tlb.lookup(addr) {
+            return true;
+        }
+        if self.translate_addr(addr, Perms::UNSET).is_ok() {
+            self.tlb.insert(addr, self.page_size(addr));
+        }
+        false
+    }
+
+    /// Map a `LARGE_PAGE_SIZE` region starting at `addr` using a single first-level page-table
+    /// entry. `addr` needs to be aligned to the large-page size, and no page of the region can be
+    /// mapped already
+    pub fn map_large_page(&mut self, addr: VAddr, perms: u8) -> Result<(), SimErr> {
+        if addr.0 & (LARGE_PAGE_SIZE as u32 - 1) != 0 {
+            return Err(SimErr::Misaligned);
+        }
+
+        let idx_1 = (addr.0 >> 22) as usize;
+        if !self.dir_entry_empty(idx_1) {
+            return Err(SimErr::MemOverlap);
+        }
+
+        let new_page = self.alloc_large_frame()?;
+        self.page_table[idx_1] = Some(PageDirEntry::LargePage(PAddr(new_page.0 | perms as u32)));
+        Ok(())
+    }
+
     /// Take a virtual address and create a page-table entry to map it to a physical entry
     pub fn map_page(&mut self, addr: VAddr, perms: u8) -> Result<(), SimErr> {
+        let pte = self.get_pte(addr);
+
+        // Encode permissions into stored address
+        if *pte != PAddr(0) {
+            return Err(SimErr::MemOverlap);
+        }
+
+        let new_page = self.alloc_frame()?;
+        *self.get_pte(addr) = PAddr(new_page.0 | perms as u32);
+
+        Ok(())
+    }
+
+    /// Map all pages overlapping `[addr, addr+len)` with permissions `perms`. If `lazy` is set, the
+    /// pages only get a physical frame assigned once they are first accessed. If a page can't be
+    /// mapped, the pages mapped before it are unmapped again
+    pub fn map_region(&mut self, addr: VAddr, len: usize, perms: u8, lazy: bool) 
+            -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        let end   = (addr.0 as usize + len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        for page in (start..end).step_by(PAGE_SIZE) {
+            let mapped = if lazy {
+                let pte = self.get_pte(VAddr(page as u32));
+                if *pte != PAddr(0) {
+                    Err(SimErr::MemOverlap)
+                } else {
+                    *pte = PAddr(PTE_LAZY | perms as u32);
+                    Ok(())
+                }
+            } else {
+                self.map_page(VAddr(page as u32), perms)
+            };
+
+            if let Err(err) = mapped {
+                for prev in (start..page).step_by(PAGE_SIZE) {
+                    let _ = self.unmap_page(VAddr(prev as u32));
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Back the page containing `addr` with a physical frame if it was mapped lazily and has not
+    /// been accessed yet. Returns `true` if a frame was allocated
+    pub fn populate_page(&mut self, addr: VAddr) -> Result<bool, SimErr> {
+        let idx_1 = ((addr.0 & 0xffc00000) >> 22) as usize;
+        let idx_2 = ((addr.0 & 0x003ff000) >> 12) as usize;
+
+        let pte = match &self.page_table[idx_1] {
+            Some(PageDirEntry::Table(table_1)) => table_1[idx_2],
+            _ => return Ok(false),
+        };
+        if pte.0 & PTE_LAZY == 0 {
+            return Ok(false);
+        }
+
+        let new_page = self.alloc_frame()?;
+        *self.get_pte(addr) = PAddr(new_page.0 | (pte.0 & !PTE_LAZY));
+        Ok(true)
+    }
+
+    /// Remove the mapping for the page containing `addr` and free its physical frame. Cached lines
+    /// of the freed frame are invalidated so they can't be hit once the frame gets reused
+    /// Unmapping an address inside of a large page unmaps the entire large page
+    pub fn unmap_page(&mut self, addr: VAddr) -> Result<(), SimErr> {
+        let idx_1 = (addr.0 >> 22) as usize;
+        if let Some(PageDirEntry::LargePage(pte)) = self.page_table[idx_1] {
+            let large_base = pte.0 & !(LARGE_PAGE_SIZE as u32 - 1);
+            for page in (0..LARGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+                let page_base = PAddr(large_base + page as u32);
+                self.cache.invalidate_page(page_base);
+                self.l2_cache.invalidate_page(page_base);
+                self.backup_page(page_base);
+                self.mem.remove(&page_base);
+            }
+            self.page_table[idx_1] = None;
+            self.tlb.flush_page(addr, LARGE_PAGE_SIZE);
+            return Ok(());
+        }
+
+        let pte = *self.get_pte(addr);
+        if pte == PAddr(0) {
+            return Err(SimErr::AddrTranslation);
+        }
+        *self.get_pte(addr) = PAddr(0);
+        self.tlb.flush_page(addr, PAGE_SIZE);
+
+        // Lazily mapped pages that were never touched don't have a frame to free
+        if pte.0 & PTE_LAZY == 0 {
+            let page_base = PAddr(pte.0 & !(PAGE_SIZE as u32 - 1));
+            self.cache.invalidate_page(page_base);
+            self.l2_cache.invalidate_page(page_base);
+            self.backup_page(page_base);
+            self.mem.remove(&page_base);
+        }
+        Ok(())
+    }
+
+    /// Move the mapping of the page containing `from` to the page containing `to`, keeping its
+    /// physical frame, contents and permissions. The target page must not already be mapped
+    /// Large pages are moved as a whole to the large-page region containing `to`
+    pub fn remap_page(&mut self, from: VAddr, to: VAddr) -> Result<(), SimErr> {
+        let (from_idx, to_idx) = ((from.0 >> 22) as usize, (to.0 >> 22) as usize);
+        if let Some(PageDirEntry::LargePage(_)) = self.page_table[from_idx] {
+            if !self.dir_entry_empty(to_idx) {
+                return Err(SimErr::MemOverlap);
+            }
+            self.page_table[to_idx] = self.page_table[from_idx].take();
+            self.tlb.flush_page(from, LARGE_PAGE_SIZE);
+            return Ok(());
+        }
+
+        let pte = self.lookup_pte(from);
+        if pte == PAddr(0) {
+            return Err(SimErr::AddrTranslation);
+        }
+        if self.lookup_pte(to) != PAddr(0) {
+            return Err(SimErr::MemOverlap);
+        }
+
+        // Caches are physically tagged, so moving the virtual address keeps them coherent
+        *self.get_pte(from) = PAddr(0);
+        *self.get_pte(to)   = pte;
+        self.tlb.flush_page(from, PAGE_SIZE);
+        Ok(())
+    }
+
+    /// Change the permissions of all pages overlapping `[addr, addr+len)` to `perms`. Fails
+    /// without modifying anything if part of the range is not mapped
+    /// Caches are physically tagged and permissions are checked during translation, so no cached
+    /// state has to be invalidated
+    pub fn protect(&mut self, addr: VAddr, len: usize, perms: u8) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        let end   = (addr.0 as usize + len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        let unmapped = (start..end).step_by(PAGE_SIZE)
+            .any(|page| self.lookup_pte(VAddr(page as u32)) == PAddr(0));
+        if unmapped {
+            return Err(SimErr::AddrTranslation);
+        }
+
+        for page in (start..end).step_by(PAGE_SIZE) {
+            let pte = self.get_pte(VAddr(page as u32));
+            *pte = PAddr((pte.0 & !0b111) | perms as u32);
+        }
+        self.tlb.flush();
+        Ok(())
+    }
+
+    /// Return a reference to the page-table entry for `addr`, creating the second-level table if
+    /// it does not exist yet. For addresses in a large page this is the first-level entry
+    fn get_pte(&mut self, addr: VAddr) -> &mut PAddr {
         let idx_1  = ((addr.0 & 0xffc00000) >> 22) as usize;
         let idx_2  = ((addr.0 & 0x003ff000) >> 12) as usize;
 
         if self.page_table[idx_1].is_none() {
-            self.page_table[idx_1] = Some([PAddr(0u32); PAGE_TABLE_ENTRIES]);
+            let table_1 = Box::new([PAddr(0u32); PAGE_TABLE_ENTRIES]);
+            self.page_table[idx_1] = Some(PageDirEntry::Table(table_1));
         } 
 
-        let table_2 = &mut self.page_table[idx_1].as_mut().unwrap();
+        match self.page_table[idx_1].as_mut().unwrap() {
+            PageDirEntry::Table(table_1) => &mut table_1[idx_2],
+            PageDirEntry::LargePage(pte) => pte,
+        }
+    }
 
-        // Get a free-page from memory and increment paddr_base to indicate that this page is taken
-        let mut rng = rand::thread_rng();
+    /// Page-table entry for `addr` without creating missing second-level tables, `PAddr(0)` if the
+    /// page is not mapped. For addresses in a large page this is the first-level entry
+    fn lookup_pte(&self, addr: VAddr) -> PAddr {
+        let idx_1 = ((addr.0 & 0xffc00000) >> 22) as usize;
+        let idx_2 = ((addr.0 & 0x003ff000) >> 12) as usize;
 
-        // Find a random free page
+        match &self.page_table[idx_1] {
+            Some(PageDirEntry::Table(table_1)) => table_1[idx_2],
+            Some(PageDirEntry::LargePage(pte)) => *pte,
+            None => PAddr(0),
+        }
+    }
+
+    /// Whether the first-level entry `idx_1` maps nothing, so a large page can be placed there.
+    /// Second-level tables stay allocated once their pages are unmapped, so they count as empty
+    /// as long as none of their entries are in use
+    fn dir_entry_empty(&self, idx_1: usize) -> bool {
+        match &self.page_table[idx_1] {
+            None => true,
+            Some(PageDirEntry::Table(table_1)) => table_1.iter().all(|pte| *pte == PAddr(0)),
+            Some(PageDirEntry::LargePage(_)) => false,
+        }
+    }
+
+    /// Get a free, `LARGE_PAGE_SIZE` aligned and contiguous region of physical frames from memory
+    /// and mark all of them as taken
+    fn alloc_large_frame(&mut self) -> Result<PAddr, SimErr> {
+        self.reserve_frames(LARGE_PAGE_SIZE / PAGE_SIZE)?;
+        loop {
+            let base = self.frame_allocator.next_candidate(LARGE_PAGE_SIZE).0;
+            let free = (0..LARGE_PAGE_SIZE).step_by(PAGE_SIZE)
+                .all(|page| !self.mem.contains_key(&PAddr(base + page as u32)));
+            if free {
+                for page in (0..LARGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+                    self.backup_page(PAddr(base + page as u32));
+                    self.mem.insert(PAddr(base + page as u32), vec![0u8; PAGE_SIZE]);
+                }
+                return Ok(PAddr(base));
+            }
+        }
+    }
+
+    /// Check that `count` more frames fit into physical memory
+    fn reserve_frames(&self, count: usize) -> Result<(), SimErr> {
+        match self.ram_size {
+            Some(size) if (self.mem.len() + count) * PAGE_SIZE > size => Err(SimErr::OutOfMemory),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a free physical frame from memory and mark it as taken
+    fn alloc_frame(&mut self) -> Result<PAddr, SimErr> {
+        self.reserve_frames(1)?;
+        // Find a free page according to the configured allocation policy
         let mut new_page: PAddr;
         loop {
-            let rand_num: u32 = rng.gen();
-            new_page = PAddr(rand_num & !((1 << 12) - 1));
+            new_page = self.frame_allocator.next_candidate(PAGE_SIZE);
             assert_eq!(new_page.0 % PAGE_SIZE as u32, 0);
             if self.mem.get(&new_page).is_none() {
+                self.backup_page(new_page);
                 self.mem.insert(new_page, vec![0u8; PAGE_SIZE]);
                 break;
             }
         }
+        Ok(new_page)
+    }
 
-        // Encode permissions into stored address
-        if table_2[idx_2] != PAddr(0) {
-            return Err(SimErr::MemOverlap);
+    /// Return (virtual, physical) base-addresses of all pages that are currently backed by
+    /// physical memory, sorted by virtual address. Large pages are split up into regular pages
+    pub fn mapped_pages(&self) -> Vec<(VAddr, PAddr)> {
+        let mut pages = Vec::new();
+        for (idx_1, entry) in self.page_table.iter().enumerate() {
+            let base = (idx_1 << 22) as u32;
+            match entry {
+                Some(PageDirEntry::Table(table_1)) => {
+                    for (idx_2, pte) in table_1.iter().enumerate() {
+                        if pte.0 != 0 && pte.0 & PTE_LAZY == 0 {
+                            pages.push((VAddr(base | (idx_2 << 12) as u32),
+                                        PAddr(pte.0 & !(PAGE_SIZE as u32 - 1))));
+                        }
+                    }
+                },
+                Some(PageDirEntry::LargePage(pte)) => {
+                    let frame = pte.0 & !(LARGE_PAGE_SIZE as u32 - 1);
+                    for offset in (0..LARGE_PAGE_SIZE as u32).step_by(PAGE_SIZE) {
+                        pages.push((VAddr(base + offset), PAddr(frame + offset)));
+                    }
+                },
+                None => {},
+            }
         }
+        pages
+    }
 
-        table_2[idx_2] = PAddr(new_page.0 | perms as u32);
+    /// List all mapped address ranges sorted by virtual address, including lazily mapped pages.
+    /// Neighbouring pages are merged into one range if their permissions match and they are backed
+    /// by contiguous physical memory
+    pub fn dump_mappings(&self) -> Vec<Mapping> {
+        let mut mappings: Vec<Mapping> = Vec::new();
+        let mut push = |mapping: Mapping| {
+            match mappings.last_mut() {
+                Some(last) if last.extends_to(&mapping) => last.size += mapping.size,
+                _ => mappings.push(mapping),
+            }
+        };
+
+        for (idx_1, entry) in self.page_table.iter().enumerate() {
+            let base = (idx_1 << 22) as u32;
+            match entry {
+                Some(PageDirEntry::Table(table_1)) => {
+                    for (idx_2, pte) in table_1.iter().enumerate() {
+                        if pte.0 == 0 {
+                            continue;
+                        }
+                        let paddr = if pte.0 & PTE_LAZY != 0 {
+                            None
+                        } else {
+                            Some(PAddr(pte.0 & !(PAGE_SIZE as u32 - 1)))
+                        };
+                        push(Mapping {
+                            vaddr: VAddr(base | (idx_2 << 12) as u32),
+                            size:  PAGE_SIZE,
+                            paddr,
+                            perms: (pte.0 & 0b111) as u8,
+                            large: false,
+                        });
+                    }
+                },
+                Some(PageDirEntry::LargePage(pte)) => {
+                    push(Mapping {
+                        vaddr: VAddr(base),
+                        size:  LARGE_PAGE_SIZE,
+                        paddr: Some(PAddr(pte.0 & !(LARGE_PAGE_SIZE as u32 - 1))),
+                        perms: (pte.0 & 0b111) as u8,
+                        large: true,
+                    });
+                },
+                None => {},
+            }
+        }
+        mappings
+    }
 
-        Ok(())
+    /// Take a snapshot of the contents of all mapped pages
+    pub fn snapshot(&self) -> MemSnapshot {
+        let pages = self.mapped_pages().into_iter()
+            .map(|(vaddr, paddr)| (vaddr, self.mem[&paddr].clone()))
+            .collect();
+        MemSnapshot { pages }
+    }
+
+    /// List all 4-byte words of mapped memory that changed since `snapshot` was taken, sorted by
+    /// address
+    pub fn diff(&self, snapshot: &MemSnapshot) -> Vec<MemChange> {
+        let zero_page = vec![0u8; PAGE_SIZE];
+        let mut changes = Vec::new();
+
+        for (vaddr, paddr) in self.mapped_pages() {
+            let old_page = snapshot.pages.get(&vaddr).unwrap_or(&zero_page);
+            let new_page = &self.mem[&paddr];
+            for offset in (0..PAGE_SIZE).step_by(4) {
+                let old = u32::from_le_bytes(old_page[offset..offset + 4].try_into().unwrap());
+                let new = u32::from_le_bytes(new_page[offset..offset + 4].try_into().unwrap());
+                if old != new {
+                    changes.push(MemChange { addr: VAddr(vaddr.0 + offset as u32), old, new });
+                }
+            }
+        }
+        changes
     }
 
     /// Load a page from ram
-    pub fn mem_load_from_ram(&self, addr: PAddr, reader: &mut [u8]) -> Result<bool, SimErr> {
+    pub fn mem_load_from_ram(&self, addr: PAddr, reader: &mut [u8]) -> Result<MemLevel, SimErr> {
         let page_base = PAddr(addr.0 & !(PAGE_SIZE as u32 - 1));
         let offset    = (addr.0 & (PAGE_SIZE as u32 - 1)) as usize;
 
         let page = &self.mem.get(&page_base).ok_or(SimErr::AddrTranslation)?;
 
         reader.copy_from_slice(&page[offset..offset+reader.len()]);
-        Ok(false)
+        Ok(MemLevel::Ram)
     }
 
-    /// Return `true` if `addr` is already cached and false if it is not and we need to hit 
-    /// physical mem for it
+    /// Return `true` if `addr` is already cached in L1 and false if it is not and we need to go
+    /// further down the memory hierarchy for it
     pub fn addr_in_cache(&self, addr: PAddr) -> bool {
-        if !self.cache_enabled {
-            return false;
-        }
+        self.cache_enabled && self.cache.contains(addr)
+    }
 
-        let index  = (addr.0 & 0b11111000000) >> 6;
-        let tag    = addr.0 >> 11;
+    /// Return `true` if `addr` is already cached in L2
+    pub fn addr_in_l2_cache(&self, addr: PAddr) -> bool {
+        self.cache_enabled && self.l2_enabled && self.l2_cache.contains(addr)
+    }
 
-        // Align address to 2^6 bounds to match the offset
-        let cache_aligned_addr = PAddr(addr.0 & !((1 << 6) - 1));
-        assert_eq!(cache_aligned_addr.0 % 64, 0);
-
-        // 4-way associative, so lets loop through the 4 entries in this cache-set and see if we are
-        // already in here, if so return true
-        for i in 0..4 {
-            let cacheline = &self.cache[((index * 4) + i) as usize];
-            if tag == cacheline.tag as u32 && cacheline.is_valid {
-                return true;
-            }
+    /// Number of cycles an access to `addr` stalls for, based on the level of the memory hierarchy
+    /// that it will be serviced from
+    pub fn access_stall(&self, addr: PAddr) -> usize {
+        match self.access_level(addr) {
+            MemLevel::L1  => L1_CACHE_STALL,
+            MemLevel::L2  => L2_CACHE_STALL,
+            MemLevel::Ram => RAM_STALL,
         }
+    }
 
-        // Requested memory was not found in cache so return false
-        false
+    /// Level of the memory hierarchy an access to `addr` would currently be serviced from
+    pub fn access_level(&self, addr: PAddr) -> MemLevel {
+        if self.addr_in_cache(addr) {
+            MemLevel::L1
+        } else if self.addr_in_l2_cache(addr) {
+            MemLevel::L2
+        } else {
+            MemLevel::Ram
+        }
     }
 
-    /// Takes a physical address `addr`, and loads `size` bytes
-    /// 4-way set-associative
-    /// 21 tag-bits,    
-    /// 5 index-bits,  32 cache-set entries
-    /// 6 offset-bits, 64 Bytes per line
-    /// Returns true if cache-hit, false otherwise
-    pub fn mem_load_from_cache(&mut self, addr: PAddr, reader: &mut [u8]) -> Result<bool, SimErr> {
-        let offset = (addr.0 & 0b111111) as usize;
-        let index  = (addr.0 & 0b11111000000) >> 6;
-        let tag    = addr.0 >> 11;
+    /// Takes a physical address `addr`, and loads `reader.len()` bytes through the cache hierarchy
+    /// L1: 4-way set-associative, 32 sets, 64 Bytes per line
+    /// L2: 8-way set-associative, 64 sets, 64 Bytes per line
+    /// On a miss the line is pulled into every cache-level it was missing from
+    /// Returns the level of the hierarchy that serviced the access
+    pub fn mem_load_from_cache(&mut self, addr: PAddr, reader: &mut [u8]) 
+            -> Result<MemLevel, SimErr> {
+        if self.cache.read(addr, reader) {
+            return Ok(MemLevel::L1);
+        }
 
         // Align address to 2^6 bounds to match the offset
-        let cache_aligned_addr = PAddr(addr.0 & !((1 << 6) - 1));
-        assert_eq!(cache_aligned_addr.0 % 64, 0);
-
-        // 4-way associative, so lets loop through the 4 entries in this cache-set and see if we are
-        // already in here, if so we can just read the data and return
-        for i in 0..4 {
-            let cacheline = &self.cache[((index * 4) + i) as usize];
-            if tag == cacheline.tag as u32 && cacheline.is_valid {
-                reader.copy_from_slice(&cacheline.data[offset..(reader.len() + offset)]);
-                return Ok(true);
+        let cache_aligned_addr = PAddr(addr.0 & !(CACHE_LINE_SIZE as u32 - 1));
+
+        // Check L2 before falling back to ram
+        let mut line = vec![0x0; CACHE_LINE_SIZE];
+        let level = if self.l2_enabled && self.l2_cache.read(cache_aligned_addr, &mut line) {
+            MemLevel::L2
+        } else {
+            self.mem_load_from_ram(cache_aligned_addr, &mut line)?;
+            if self.l2_enabled {
+                self.l2_cache.fill(addr, line.clone());
             }
-        }
+            MemLevel::Ram
+        };
 
-        // Loop through again and see if there exists an entry that isn't valid that we can just 
-        // evict
-        for i in 0..4 {
-            if !&self.cache[((index * 4) + i) as usize].is_valid {
-                // Load data from ram into this cache-set and mark it as valid
-                let mut r1 = vec![0x0; 64];
-                self.mem_load_from_ram(cache_aligned_addr, &mut r1)?;
-
-                self.cache[((index * 4) + i) as usize].data = r1;
-                self.cache[((index * 4) + i) as usize].tag = tag;
-                self.cache[((index * 4) + i) as usize].is_valid = true;
-
-                // Update LRU list by removing entry from middle and moving it to the back where it
-                // will survive the longest before being marked for eviction
-                for j in 0..self.lru_queue.len() {
-                    if self.lru_queue[j] == i {
-                        self.lru_queue.remove(j);
-                        self.lru_queue.push_back(i);
-                        break;
-                    }
-                }
+        // Fill `reader` with the bytes loaded into the cache
+        let offset = (addr.0 as usize) & (CACHE_LINE_SIZE - 1);
+        reader.copy_from_slice(&line[offset..offset + reader.len()]);
 
-                // Fill `reader` with bytes loaded into the cache from dram
-                reader.copy_from_slice(&self.cache[((index * 4) + i) as usize]
-                                       .data[offset..offset + reader.len()]);
+        self.cache.fill(addr, line);
+        Ok(level)
+    }
 
-                return Ok(false);
-            }
-        }
+    /// Invalidate potential cache entries for `addr`
+    pub fn mem_invalidate_cache(&mut self, addr: PAddr) -> Result<(), SimErr> {
+        // If there is an entry in the caches for this address we invalidate it since we are now
+        // writing new data
+        self.cache.invalidate(addr);
+        self.l2_cache.invalidate(addr);
+        Ok(())
+    }
 
-        // Evict from cache to insert
+    /// Accesses need to be aligned to their size (1, 2 or 4 bytes). Other sizes are aligned to the
+    /// next power of two so an access never straddles a 4-byte word
+    fn check_alignment(addr: PAddr, len: usize) -> Result<(), SimErr> {
+        if addr.0 & (len.next_power_of_two() as u32 - 1) != 0 {
+            return Err(SimErr::Misaligned);
+        }
+        Ok(())
+    }
 
-        // Get the entry at beginning of queue and move it to the end. We will be using this entry
-        // for the cache-line so it should not be evicted anytime soon
-        let lru = self.lru_queue.pop_front().unwrap();
-        self.lru_queue.push_back(lru);
+    /// Write `data` into memory at virtual address `addr`
+    /// Writes go directly through to ram. Without write-allocate the cached copies for `addr` are
+    /// invalidated, with write-allocate the updated line is (re)loaded into the caches
+    pub fn mem_write(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let paddr     = self.translate_addr(addr, Perms::WRITE)?;
+        let page_base = PAddr(paddr.0 & !(PAGE_SIZE as u32 - 1));
+        let offset    = (paddr.0 & (PAGE_SIZE as u32 - 1)) as usize;
 
-        // Populate entry
-        let mut r1 = vec![0x0; 64];
-        self.mem_load_from_ram(cache_aligned_addr, &mut r1)?;
-        self.cache[((index * 4) + lru) as usize].data = r1;
-        self.cache[((index * 4) + lru) as usize].tag = tag;
-        self.cache[((index * 4) + lru) as usize].is_valid = true;
+        // 32-bit architecture in which no instruction can write more than 4-bytes of memory at once
+        assert!(data.len() <= 4, "Writes of more than 4-bytes at once are not supported");
+        Self::check_alignment(paddr, data.len())?;
 
-        reader.copy_from_slice(&self.cache[((index * 4) + lru) as usize]
-                               .data[offset..offset + reader.len()]);
+        if self.cache_enabled && !self.write_allocate {
+            self.mem_invalidate_cache(paddr)?;
+        }
 
-        return Ok(false);
-    }
+        // Write to memory
+        self.backup_page(page_base);
+        let page = self.mem.get_mut(&page_base).ok_or(SimErr::AddrTranslation)?;
+        if let Some(journal) = self.write_journal.as_mut() {
+            journal.push((paddr, page[offset..(data.len() + offset)].to_vec()));
+        }
+        page[offset..(data.len() + offset)].copy_from_slice(data);
 
-    /// Invalidate potential cache entry for `addr`
-    pub fn mem_invalidate_cache(&mut self, addr: PAddr) -> Result<(), SimErr> {
-        //let index  = (addr.0 & 0b11111) as usize;
-        let index = (addr.0 & 0b11111000000) >> 6;
-        let tag   = addr.0 >> 11;
-
-        // Go through cache-sets for the index of this `addr` to see if there is an entry in the 
-        // cache for this address. If there is, we invalidate it since we are now writing new data
-        for i in 0..4 {
-            let cacheline = &mut self.cache[((index * 4) + i) as usize];
-            if tag == cacheline.tag as u32 && cacheline.is_valid {
-                self.cache[((index * 4) + i) as usize].is_valid = false;
+        if self.cache_enabled && self.write_allocate {
+            let mut line = vec![0x0; CACHE_LINE_SIZE];
+            self.mem_load_from_ram(PAddr(paddr.0 & !(CACHE_LINE_SIZE as u32 - 1)), &mut line)?;
+            if self.l2_enabled {
+                self.l2_cache.fill(paddr, line.clone());
+            } else {
+                // A disabled L2 can still hold the old line, which is hit once it is enabled again
+                self.l2_cache.invalidate(paddr);
             }
+            self.cache.fill(paddr, line);
         }
+
         Ok(())
     }
 
-    /// Write `data` into memory at virtual address `addr`
-    /// Currently we just invalidate caches for `addr` and write directly through to ram
-    pub fn mem_write(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+    /// Non-temporal write of `data` to virtual address `addr`. The write goes straight to ram
+    /// without allocating cache-lines. Lines that are already cached are updated in place so they
+    /// stay coherent without having to be refetched
+    pub fn mem_write_nt(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
         let paddr     = self.translate_addr(addr, Perms::WRITE)?;
         let page_base = PAddr(paddr.0 & !(PAGE_SIZE as u32 - 1));
         let offset    = (paddr.0 & (PAGE_SIZE as u32 - 1)) as usize;
 
         // 32-bit architecture in which no instruction can write more than 4-bytes of memory at once
-        assert!(data.len() <= 4, "Reads of more than 4-bytes at once are not supported");
-
-        match data.len() {
-            1 => {},
-            2 => {
-                // We only support 4-byte aligned accesses
-                assert!((paddr.0 & 0x1) == 0, 
-                        "2-byte reads need to be aligned on a 2-byte boundary. Provided address: \
-                        {:x?}, is not", addr);
-            },
-            4 => {
-                // We only support 4-byte aligned accesses
-                assert!((paddr.0 & 0x3) == 0, 
-                        "4-byte reads need to be aligned on a 4-byte boundary. Provided address: \
-                        {:x?}, is not", addr);
-            },
-            _ => unreachable!(),
+        assert!(data.len() <= 4, "Writes of more than 4-bytes at once are not supported");
+        Self::check_alignment(paddr, data.len())?;
+
+        self.backup_page(page_base);
+        let page = self.mem.get_mut(&page_base).ok_or(SimErr::AddrTranslation)?;
+        if let Some(journal) = self.write_journal.as_mut() {
+            journal.push((paddr, page[offset..(data.len() + offset)].to_vec()));
         }
+        page[offset..(data.len() + offset)].copy_from_slice(data);
 
-        if self.cache_enabled {
-            self.mem_invalidate_cache(paddr).unwrap();
+        self.cache.write(paddr, data);
+        self.l2_cache.write(paddr, data);
+        Ok(())
+    }
+
+    /// Write all of `data` to memory starting at virtual address `addr`. The data is copied one
+    /// page at a time instead of going through 4-byte accesses. Cached lines overlapping the
+    /// written range are invalidated, or refreshed with the new data if write-allocate is enabled
+    pub fn mem_write_bulk(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let vaddr     = VAddr(addr.0.wrapping_add(offset as u32));
+            let paddr     = self.translate_addr(vaddr, Perms::WRITE)?;
+            let page_base = PAddr(paddr.0 & !(PAGE_SIZE as u32 - 1));
+            let page_off  = (paddr.0 & (PAGE_SIZE as u32 - 1)) as usize;
+            let len       = std::cmp::min(data.len() - offset, PAGE_SIZE - page_off);
+
+            self.backup_page(page_base);
+            let page = self.mem.get_mut(&page_base).ok_or(SimErr::AddrTranslation)?;
+            if let Some(journal) = self.write_journal.as_mut() {
+                journal.push((paddr, page[page_off..page_off + len].to_vec()));
+            }
+            page[page_off..page_off + len].copy_from_slice(&data[offset..offset + len]);
+
+            // Update every cache-line the write touched. The end is computed in 64 bits since
+            // writes to the last physical frame end at 2^32
+            if self.cache_enabled {
+                let first_line = (paddr.0 & !(CACHE_LINE_SIZE as u32 - 1)) as u64;
+                let end        = paddr.0 as u64 + len as u64;
+                for line_addr in (first_line..end).step_by(CACHE_LINE_SIZE) {
+                    let line_addr = PAddr(line_addr as u32);
+                    if self.write_allocate {
+                        let mut line = vec![0x0; CACHE_LINE_SIZE];
+                        self.mem_load_from_ram(line_addr, &mut line)?;
+                        if self.l2_enabled {
+                            self.l2_cache.fill(line_addr, line.clone());
+                        } else {
+                            self.l2_cache.invalidate(line_addr);
+                        }
+                        self.cache.fill(line_addr, line);
+                    } else {
+                        self.mem_invalidate_cache(line_addr)?;
+                    }
+                }
+            }
+
+            offset += len;
         }
+        Ok(())
+    }
 
-        // Write to memory
-        let page = self.mem.get_mut(&page_base).unwrap();
-        page[offset..(data.len() + offset)].copy_from_slice(data);
+    /// Write `data` to `addr` on behalf of the debugger, one page at a time. Pages only need to be
+    /// mapped, not writable, so instructions can be patched as well. Cached copies of the written
+    /// lines are invalidated
+    pub fn patch_bulk(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let vaddr    = VAddr(addr.0.wrapping_add(offset as u32));
+            let paddr    = self.translate_addr(vaddr, Perms::UNSET)?;
+            let page_off = (paddr.0 & (PAGE_SIZE as u32 - 1)) as usize;
+            let len      = std::cmp::min(data.len() - offset, PAGE_SIZE - page_off);
+
+            self.undo_write(paddr, &data[offset..offset + len]);
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Restore `old`, the previous contents of a journaled write to `addr`. Cached copies of the
+    /// written lines are invalidated since they may hold the overwritten data
+    pub fn undo_write(&mut self, addr: PAddr, old: &[u8]) {
+        let page_base = PAddr(addr.0 & !(PAGE_SIZE as u32 - 1));
+        let offset    = (addr.0 & (PAGE_SIZE as u32 - 1)) as usize;
+        self.backup_page(page_base);
+        if let Some(page) = self.mem.get_mut(&page_base) {
+            page[offset..offset + old.len()].copy_from_slice(old);
+        }
+
+        let first_line = (addr.0 & !(CACHE_LINE_SIZE as u32 - 1)) as u64;
+        let end        = addr.0 as u64 + old.len() as u64;
+        for line_addr in (first_line..end).step_by(CACHE_LINE_SIZE) {
+            self.cache.invalidate(PAddr(line_addr as u32));
+            self.l2_cache.invalidate(PAddr(line_addr as u32));
+        }
+    }
+
+    /// Copy the page at `page_base` to `page_backups` before it is modified for the first time
+    fn backup_page(&mut self, page_base: PAddr) {
+        if let Some(backups) = self.page_backups.as_mut() {
+            backups.entry(page_base).or_insert_with(|| self.mem.get(&page_base).cloned());
+        }
+    }
 
+    /// Return every page recorded in `page_backups` to its backed up contents, and free pages that
+    /// were allocated since. Recording continues from the restored state. Cached lines are
+    /// dropped since they may hold newer data
+    pub fn restore_pages(&mut self) {
+        let Some(backups) = self.page_backups.as_mut() else {
+            return;
+        };
+        for (page_base, old) in backups.drain() {
+            match old {
+                Some(old) => self.mem.insert(page_base, old),
+                None      => self.mem.remove(&page_base),
+            };
+        }
+        self.clear_caches();
+        self.tlb.flush();
+    }
+
+    /// Read `reader.len()` bytes starting at virtual address `addr`, one page at a time. Writes
+    /// always go through to ram, so this reads ram directly without touching cache-state
+    pub fn mem_read_bulk(&self, addr: VAddr, reader: &mut [u8]) -> Result<(), SimErr> {
+        let mut offset = 0;
+
+        while offset < reader.len() {
+            let vaddr    = VAddr(addr.0.wrapping_add(offset as u32));
+            let paddr    = self.translate_addr(vaddr, Perms::READ)?;
+            let page_off = (paddr.0 & (PAGE_SIZE as u32 - 1)) as usize;
+            let len      = std::cmp::min(reader.len() - offset, PAGE_SIZE - page_off);
+
+            self.mem_load_from_ram(paddr, &mut reader[offset..offset + len])?;
+            offset += len;
+        }
         Ok(())
     }
 
     /// Load `len` bytes from `addr` and return the bytes through the reader
-    pub fn mem_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<bool, SimErr> {
+    /// Returns `SimErr::Misaligned` if `addr` is not aligned to the size of the access
+    pub fn mem_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<MemLevel, SimErr> {
         let paddr = self.translate_addr(addr, Perms::READ)?;
 
         // 32-bit architecture in which no instruction can read more than 4-bytes of memory at once
         assert!(reader.len() <= 4, "Reads of more than 4-bytes at once are not supported");
-
-        // We only support 4-byte aligned accesses
-        assert!((paddr.0 & 0x3) == 0, 
-                "Provided address: {:x?} is not aligned on 4-byte boundary", addr);
+        Self::check_alignment(paddr, reader.len())?;
 
         if self.cache_enabled {
             self.mem_load_from_cache(paddr, reader)
@@ -352,16 +1361,435 @@ impl Mmu {
 
     /// Load `len` bytes from `addr` and return the bytes through the reader
     /// Additional wrapper for gui to not mess up caches
-    pub fn gui_mem_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<bool, SimErr> {
+    pub fn gui_mem_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<MemLevel, SimErr> {
         let paddr = self.translate_addr(addr, Perms::READ)?;
 
         // 32-bit architecture in which no instruction can read more than 4-bytes of memory at once
         assert!(reader.len() <= 4, "Reads of more than 4-bytes at once are not supported");
-
-        // We only support 4-byte aligned accesses
-        assert!((paddr.0 & 0x3) == 0, 
-                "Provided address: {:x?} is not aligned on 4-byte boundary", addr);
+        Self::check_alignment(paddr, reader.len())?;
 
         self.mem_load_from_ram(paddr, reader)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address that maps to cache-set `index` of a 32-set cache with the given `tag`
+    fn set_addr(index: u32, tag: u32) -> PAddr {
+        PAddr((tag << 11) | (index << 6))
+    }
+
+    /// Fill the line for `addr` with a marker byte so we can tell lines apart
+    fn fill(cache: &mut Cache, addr: PAddr) {
+        cache.fill(addr, vec![addr.0 as u8; CACHE_LINE_SIZE]);
+    }
+
+    #[test]
+    fn evicts_least_recently_filled_line() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..5 {
+            fill(&mut cache, set_addr(3, tag));
+        }
+
+        // Tag 0 was the oldest entry, so it is the one that got replaced by tag 4
+        assert!(!cache.contains(set_addr(3, 0)));
+        for tag in 1..5 {
+            assert!(cache.contains(set_addr(3, tag)));
+        }
+    }
+
+    #[test]
+    fn hits_refresh_lru_order() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..4 {
+            fill(&mut cache, set_addr(7, tag));
+        }
+
+        // Reading tag 0 makes tag 1 the least recently used entry
+        let mut reader = [0u8; 4];
+        assert!(cache.read(set_addr(7, 0), &mut reader));
+
+        fill(&mut cache, set_addr(7, 4));
+        assert!(cache.contains(set_addr(7, 0)));
+        assert!(!cache.contains(set_addr(7, 1)));
+
+        fill(&mut cache, set_addr(7, 5));
+        assert!(!cache.contains(set_addr(7, 2)));
+        assert_eq!(cache.lru_queues[7].iter().copied().collect::<Vec<u32>>(), vec![3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn sets_track_lru_independently() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..4 {
+            fill(&mut cache, set_addr(1, tag));
+        }
+
+        // Heavy traffic on another set must not influence eviction order of set 1
+        for tag in 0..16 {
+            fill(&mut cache, set_addr(2, tag));
+        }
+        assert_eq!(cache.lru_queues[1].iter().copied().collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+
+        fill(&mut cache, set_addr(1, 4));
+        assert!(!cache.contains(set_addr(1, 0)));
+        for tag in 1..5 {
+            assert!(cache.contains(set_addr(1, tag)));
+        }
+    }
+
+    #[test]
+    fn invalidated_lines_are_reused_first() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..4 {
+            fill(&mut cache, set_addr(0, tag));
+        }
+
+        cache.invalidate(set_addr(0, 2));
+        fill(&mut cache, set_addr(0, 4));
+
+        // The invalidated entry is reused instead of evicting the least recently used one
+        assert!(cache.contains(set_addr(0, 0)));
+        assert_eq!(cache.lines[2].tag, 4);
+        assert_eq!(cache.lru_queues[0].back(), Some(&2));
+    }
+
+    #[test]
+    fn counters_track_hits_and_evictions() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..5 {
+            fill(&mut cache, set_addr(3, tag));
+        }
+
+        let mut reader = [0u8; 4];
+        assert!(cache.read(set_addr(3, 4), &mut reader));
+        assert!(!cache.read(set_addr(3, 0), &mut reader));
+
+        // Tag 4 replaced tag 0 in entry 0
+        assert_eq!(cache.line_counters(3, 0), LineCounters { accesses: 3, hits: 1, evictions: 1 });
+        assert_eq!(cache.line_counters(3, 1), LineCounters { accesses: 1, hits: 0, evictions: 0 });
+        assert_eq!(cache.set_counters(3), LineCounters { accesses: 6, hits: 1, evictions: 1 });
+        assert_eq!(cache.set_counters(4), LineCounters::default());
+    }
+
+    #[test]
+    fn lines_report_their_age_and_address() {
+        let mut cache = Cache::new(32, 4);
+        for tag in 0..3 {
+            fill(&mut cache, set_addr(5, tag + 0x10));
+        }
+        let mut reader = [0u8; 4];
+        assert!(cache.read(set_addr(5, 0x10), &mut reader));
+
+        // Entry 3 was never filled, so it is the oldest
+        assert_eq!((0..4).map(|way| cache.line_age(5, way)).collect::<Vec<_>>(), [0, 2, 1, 3]);
+        assert_eq!(cache.line_addr(5, 2), set_addr(5, 0x12));
+    }
+
+    /// Mmu with a single read/write page mapped at 0x1000 holding the bytes 0x00..0xff repeated
+    fn mmu_with_page() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.map_page(VAddr(0x1000), Perms::READ | Perms::WRITE).unwrap();
+        for addr in (0x1000..0x2000).step_by(4) {
+            let bytes: Vec<u8> = (0..4).map(|i| (addr + i) as u8).collect();
+            mmu.mem_write(VAddr(addr), &bytes).unwrap();
+        }
+        mmu
+    }
+
+    #[test]
+    fn byte_reads_ignore_alignment() {
+        let mut mmu = mmu_with_page();
+        for addr in [0x1001, 0x1002, 0x1003, 0x103f, 0x1040, 0x1fff] {
+            let mut reader = [0u8; 1];
+            mmu.mem_read(VAddr(addr), &mut reader).unwrap();
+            assert_eq!(reader[0], addr as u8);
+        }
+    }
+
+    #[test]
+    fn half_reads_require_2_byte_alignment() {
+        let mut mmu = mmu_with_page();
+        let mut reader = [0u8; 2];
+        mmu.mem_read(VAddr(0x1002), &mut reader).unwrap();
+        assert_eq!(reader, [0x02, 0x03]);
+        mmu.mem_read(VAddr(0x1ffe), &mut reader).unwrap();
+        assert_eq!(reader, [0xfe, 0xff]);
+
+        assert!(matches!(mmu.mem_read(VAddr(0x1001), &mut reader), Err(SimErr::Misaligned)));
+        assert!(matches!(mmu.mem_read(VAddr(0x103f), &mut reader), Err(SimErr::Misaligned)));
+    }
+
+    #[test]
+    fn word_reads_require_4_byte_alignment() {
+        let mut mmu = mmu_with_page();
+        let mut reader = [0u8; 4];
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        assert_eq!(reader, [0xfc, 0xfd, 0xfe, 0xff]);
+
+        for addr in [0x1001, 0x1002, 0x1003, 0x103e] {
+            assert!(matches!(mmu.mem_read(VAddr(addr), &mut reader), Err(SimErr::Misaligned)));
+        }
+    }
+
+    #[test]
+    fn unaligned_reads_without_caches() {
+        let mut mmu = mmu_with_page();
+        mmu.cache_enabled = false;
+
+        let mut reader = [0u8; 1];
+        mmu.mem_read(VAddr(0x1fff), &mut reader).unwrap();
+        assert_eq!(reader[0], 0xff);
+
+        let mut reader = [0u8; 2];
+        assert!(matches!(mmu.mem_read(VAddr(0x1fff), &mut reader), Err(SimErr::Misaligned)));
+        assert!(matches!(mmu.gui_mem_read(VAddr(0x1fff), &mut reader), Err(SimErr::Misaligned)));
+    }
+
+    #[test]
+    fn byte_reads_stop_at_page_boundaries() {
+        let mut mmu = mmu_with_page();
+        let mut reader = [0u8; 1];
+        assert!(mmu.mem_read(VAddr(0x2000), &mut reader).is_err());
+        assert!(mmu.mem_read(VAddr(0xfff), &mut reader).is_err());
+    }
+
+    #[test]
+    fn bulk_writes_span_pages_and_invalidate_lines() {
+        let mut mmu = mmu_with_page();
+        mmu.map_page(VAddr(0x2000), Perms::READ | Perms::WRITE).unwrap();
+
+        // Pull the last line of the first page into the caches
+        let mut reader = [0u8; 4];
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        let paddr = mmu.translate_addr(VAddr(0x1ffc), Perms::READ).unwrap();
+        assert!(mmu.addr_in_cache(paddr));
+
+        let data: Vec<u8> = (0..0x20).map(|i| 0x80 | i as u8).collect();
+        mmu.mem_write_bulk(VAddr(0x1ff0), &data).unwrap();
+        assert!(!mmu.addr_in_cache(paddr));
+        assert!(!mmu.addr_in_l2_cache(paddr));
+
+        let mut bulk = vec![0u8; 0x20];
+        mmu.mem_read_bulk(VAddr(0x1ff0), &mut bulk).unwrap();
+        assert_eq!(bulk, data);
+
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        assert_eq!(reader, [0x8c, 0x8d, 0x8e, 0x8f]);
+    }
+
+    #[test]
+    fn writes_to_the_last_physical_frame_invalidate_their_lines() {
+        let mut mmu = Mmu::new();
+        mmu.frame_allocator = FrameAllocator::Sequential { next: 0xfffff000 };
+        mmu.map_page(VAddr(0x1000), Perms::READ | Perms::WRITE).unwrap();
+        let paddr = mmu.translate_addr(VAddr(0x1ffc), Perms::READ).unwrap();
+        assert_eq!(paddr, PAddr(0xfffffffc));
+
+        let mut reader = [0u8; 4];
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        mmu.mem_write_bulk(VAddr(0x1fc0), &[0x41; 0x40]).unwrap();
+        assert!(!mmu.addr_in_cache(paddr));
+        assert!(!mmu.addr_in_l2_cache(paddr));
+
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        mmu.undo_write(paddr, &[1, 2, 3, 4]);
+        assert!(!mmu.addr_in_cache(paddr));
+        mmu.mem_read(VAddr(0x1ffc), &mut reader).unwrap();
+        assert_eq!(reader, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn write_allocate_fills_the_caches_on_store_misses() {
+        let mut mmu = mmu_with_page();
+        let paddr = mmu.translate_addr(VAddr(0x1100), Perms::WRITE).unwrap();
+
+        // Without write-allocate stores invalidate the line
+        mmu.mem_write(VAddr(0x1100), &[0x41]).unwrap();
+        assert!(!mmu.addr_in_cache(paddr));
+        assert!(!mmu.addr_in_l2_cache(paddr));
+
+        mmu.write_allocate = true;
+        mmu.mem_write(VAddr(0x1100), &[0x42]).unwrap();
+        assert!(mmu.addr_in_cache(paddr));
+        assert!(mmu.addr_in_l2_cache(paddr));
+
+        // Stores with L2 disabled drop its copy, so it can't be hit with old data later
+        mmu.l2_enabled = false;
+        mmu.mem_write(VAddr(0x1100), &[0x43]).unwrap();
+        assert!(mmu.addr_in_cache(paddr));
+        assert!(!mmu.l2_cache.contains(paddr));
+
+        mmu.l2_enabled = true;
+        mmu.cache.clear();
+        let mut reader = [0u8; 1];
+        mmu.mem_read(VAddr(0x1100), &mut reader).unwrap();
+        assert_eq!(reader, [0x43]);
+    }
+
+    #[test]
+    fn bus_requests_queue_when_slots_are_taken() {
+        let mut bus = MemBus::new(1);
+        assert_eq!(bus.request(0, RAM_STALL), (0, RAM_STALL));
+
+        // Second request issued while the first one is in flight has to wait for it
+        assert_eq!(bus.request(40, RAM_STALL), (60, RAM_STALL));
+
+        // Idle gaps on the bus are not counted as busy
+        assert_eq!(bus.request(500, RAM_STALL), (0, RAM_STALL));
+
+        let mut bus = MemBus::new(2);
+        assert_eq!(bus.request(0, RAM_STALL), (0, RAM_STALL));
+        assert_eq!(bus.request(40, RAM_STALL), (0, 40));
+    }
+
+    #[test]
+    fn snapshot_diff_lists_changed_words() {
+        let mut mmu = mmu_with_page();
+        let snapshot = mmu.snapshot();
+        assert!(mmu.diff(&snapshot).is_empty());
+
+        mmu.mem_write(VAddr(0x1010), &[0x41]).unwrap();
+        mmu.map_page(VAddr(0x5000), Perms::READ | Perms::WRITE).unwrap();
+        mmu.mem_write(VAddr(0x5004), &0xdeadbeefu32.to_le_bytes()).unwrap();
+
+        // Pages mapped after the snapshot are compared against zeroed memory
+        assert_eq!(mmu.diff(&snapshot), vec![
+            MemChange { addr: VAddr(0x1010), old: 0x13121110, new: 0x13121141 },
+            MemChange { addr: VAddr(0x5004), old: 0,          new: 0xdeadbeef },
+        ]);
+    }
+
+    #[test]
+    fn mapping_fails_once_ram_is_exhausted() {
+        let mut mmu = Mmu::new();
+        mmu.ram_size = parse_ram_size("8K");
+        mmu.map_page(VAddr(0x1000), Perms::READ).unwrap();
+        mmu.map_region(VAddr(0x4000), 2 * PAGE_SIZE, Perms::READ, true).unwrap();
+
+        assert_eq!(mmu.populate_page(VAddr(0x4000)), Ok(true));
+        assert_eq!(mmu.populate_page(VAddr(0x5000)), Err(SimErr::OutOfMemory));
+        assert_eq!(mmu.map_page(VAddr(0x2000), Perms::READ), Err(SimErr::OutOfMemory));
+
+        // Freed frames can be reused
+        mmu.unmap_page(VAddr(0x1000)).unwrap();
+        assert!(mmu.map_page(VAddr(0x2000), Perms::READ).is_ok());
+    }
+
+    #[test]
+    fn lazy_pages_get_a_frame_on_first_touch() {
+        let mut mmu = Mmu::new();
+        mmu.map_region(VAddr(0x4000), 2 * PAGE_SIZE, Perms::READ | Perms::WRITE, true).unwrap();
+        assert!(mmu.mem.is_empty());
+        assert_eq!(mmu.translate_addr(VAddr(0x4000), Perms::READ), Err(SimErr::AddrTranslation));
+
+        assert_eq!(mmu.populate_page(VAddr(0x4010)), Ok(true));
+        assert_eq!(mmu.populate_page(VAddr(0x4020)), Ok(false));
+        assert_eq!(mmu.mem.len(), 1);
+        mmu.mem_write(VAddr(0x4010), &[0x41]).unwrap();
+        assert!(mmu.translate_addr(VAddr(0x5000), Perms::READ).is_err());
+    }
+
+    #[test]
+    fn failed_region_mappings_leave_nothing_mapped() {
+        for lazy in [true, false] {
+            let mut mmu = Mmu::new();
+            mmu.map_page(VAddr(0x6000), Perms::READ).unwrap();
+            assert_eq!(mmu.map_region(VAddr(0x4000), 3 * PAGE_SIZE, Perms::READ, lazy),
+                       Err(SimErr::MemOverlap));
+            assert_eq!(mmu.mem.len(), 1);
+            assert_eq!(mmu.lookup_pte(VAddr(0x4000)), PAddr(0));
+            assert_eq!(mmu.lookup_pte(VAddr(0x5000)), PAddr(0));
+            assert!(mmu.translate_addr(VAddr(0x6000), Perms::READ).is_ok());
+
+            // Running out of memory partway unmaps the region as well
+            mmu.ram_size = Some(2 * PAGE_SIZE);
+            assert_eq!(mmu.map_region(VAddr(0x10000), 2 * PAGE_SIZE, Perms::READ, false),
+                       Err(SimErr::OutOfMemory));
+            assert_eq!(mmu.lookup_pte(VAddr(0x10000)), PAddr(0));
+            assert!(mmu.map_region(VAddr(0x10000), PAGE_SIZE, Perms::READ, false).is_ok());
+        }
+    }
+
+    #[test]
+    fn protected_pages_fault_until_their_permissions_are_restored() {
+        let mut mmu = mmu_with_page();
+        mmu.map_page(VAddr(0x2000), Perms::READ | Perms::WRITE).unwrap();
+
+        // Cache the translation, the tlb must not let writes bypass the new permissions
+        assert!(!mmu.tlb_access(VAddr(0x1000)));
+        assert!(mmu.tlb_access(VAddr(0x1000)));
+        mmu.protect(VAddr(0x1ffc), 8, Perms::READ).unwrap();
+        assert!(!mmu.tlb_access(VAddr(0x1000)));
+
+        for addr in [0x1010, 0x2010] {
+            assert_eq!(mmu.mem_write(VAddr(addr), &[0x41]), Err(SimErr::Permission));
+            let mut reader = [0u8; 1];
+            assert!(mmu.mem_read(VAddr(addr), &mut reader).is_ok());
+        }
+
+        // The read-only translation cached above must not outlive the restored permissions
+        assert!(mmu.tlb_access(VAddr(0x1000)));
+        mmu.protect(VAddr(0x1000), 2 * PAGE_SIZE, Perms::READ | Perms::WRITE).unwrap();
+        for addr in [0x1010, 0x2010] {
+            assert_eq!(mmu.mem_write(VAddr(addr), &[0x41]), Ok(()));
+        }
+    }
+
+    #[test]
+    fn dump_mappings_merges_contiguous_pages() {
+        let mut mmu = Mmu::new();
+        mmu.frame_allocator = FrameAllocator::sequential();
+        mmu.map_region(VAddr(0x10000), 2 * PAGE_SIZE, Perms::READ | Perms::EXEC, false).unwrap();
+        mmu.map_page(VAddr(0x12000), Perms::READ | Perms::WRITE).unwrap();
+        mmu.map_region(VAddr(0x20000), 3 * PAGE_SIZE, Perms::READ | Perms::WRITE, true).unwrap();
+
+        let mappings = mmu.dump_mappings();
+        assert_eq!(mappings.len(), 3);
+        assert_eq!((mappings[0].vaddr, mappings[0].size), (VAddr(0x10000), 2 * PAGE_SIZE));
+        assert_eq!(Perms::describe(mappings[0].perms), "r-x");
+        assert_eq!(mappings[1].paddr.unwrap().0, mappings[0].paddr.unwrap().0 + 0x2000);
+        assert_eq!((mappings[2].size, mappings[2].paddr), (3 * PAGE_SIZE, None));
+    }
+
+    #[test]
+    fn restored_pages_drop_later_writes_and_frames() {
+        let mut mmu = Mmu::new();
+        mmu.map_page(VAddr(0x1000), Perms::READ | Perms::WRITE).unwrap();
+        mmu.mem_write(VAddr(0x1000), &[1, 2, 3, 4]).unwrap();
+        mmu.page_backups = Some(FxHashMap::default());
+
+        mmu.mem_write(VAddr(0x1000), &[5, 6, 7, 8]).unwrap();
+        mmu.mem_write(VAddr(0x1004), &[9]).unwrap();
+        mmu.map_page(VAddr(0x2000), Perms::READ).unwrap();
+        assert_eq!(mmu.page_backups.as_ref().unwrap().len(), 2);
+
+        mmu.restore_pages();
+        let mut reader = [0u8; 8];
+        mmu.mem_read_bulk(VAddr(0x1000), &mut reader).unwrap();
+        assert_eq!(reader, [1, 2, 3, 4, 0, 0, 0, 0]);
+        assert_eq!(mmu.mem.len(), 1);
+        assert!(mmu.page_backups.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn large_pages_remap_onto_untouched_regions() {
+        let mut mmu = Mmu::new();
+        mmu.map_large_page(VAddr(0x400000), Perms::READ).unwrap();
+        mmu.map_page(VAddr(0xc00000), Perms::READ).unwrap();
+
+        // Looking up the unmapped target must not make it look occupied
+        assert_eq!(mmu.protect(VAddr(0x800000), PAGE_SIZE, Perms::READ),
+                   Err(SimErr::AddrTranslation));
+        assert_eq!(mmu.remap_page(VAddr(0x400000), VAddr(0xc00000)), Err(SimErr::MemOverlap));
+        assert_eq!(mmu.remap_page(VAddr(0x400000), VAddr(0x800000)), Ok(()));
+        assert!(mmu.translate_addr(VAddr(0x800000), Perms::READ).is_ok());
+        assert!(mmu.translate_addr(VAddr(0x400000), Perms::READ).is_err());
+
+        // Tables left behind by unmapping their last page are empty again
+        mmu.unmap_page(VAddr(0xc00000)).unwrap();
+        assert_eq!(mmu.remap_page(VAddr(0x800000), VAddr(0xc00000)), Ok(()));
+    }
+}
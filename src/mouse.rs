@@ -0,0 +1,221 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Address of the mouse's registers, next to the seven-segment display
+pub const MOUSE_BASE: u32 = 0x20e0;
+
+/// Size of the mouse's register block
+pub const MOUSE_SIZE: u32 = 0x10;
+
+/// Interrupt-vector raised when the mouse moves or a button changes, its handler is stored at
+/// address 0x1c
+pub const MOUSE_IRQ: u32 = 7;
+
+/// Button bit of the left button
+pub const MOUSE_LEFT: u32 = 1 << 0;
+
+/// Button bit of the right button
+pub const MOUSE_RIGHT: u32 = 1 << 1;
+
+/// Button bit of the middle button
+pub const MOUSE_MIDDLE: u32 = 1 << 2;
+
+/// Control bit that raises `MOUSE_IRQ` whenever the mouse moves or a button changes
+pub const MOUSE_IRQ_ENABLE: u32 = 1 << 0;
+
+/// State shared between all clones of a `Mouse`
+#[derive(Debug, Default)]
+struct MouseState {
+    x:       u32,
+    y:       u32,
+    buttons: u32,
+    control: u32,
+
+    /// Set when the mouse moved or a button changed since the last tick, to raise the interrupt
+    changed: bool,
+}
+
+/// Pointer at `MOUSE_BASE`, fed by the host with the position and buttons of its mouse. Its 32-bit
+/// registers are:
+///  - 0x0 X: Horizontal position in pixels, from the left edge of the screen
+///  - 0x4 Y: Vertical position in pixels, from the top edge of the screen
+///  - 0x8 Buttons: `MOUSE_LEFT`, `MOUSE_RIGHT` and `MOUSE_MIDDLE` while they are held
+///  - 0xc Control: `MOUSE_IRQ_ENABLE`
+///
+/// Clones share the same state, so the host can feed events after registering the device
+#[derive(Clone, Debug, Default)]
+pub struct Mouse {
+    state: Rc<RefCell<MouseState>>,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the pointer to `x`, `y`
+    pub fn move_to(&self, x: u32, y: u32) {
+        let mut state = self.state.borrow_mut();
+        if (state.x, state.y) != (x, y) {
+            (state.x, state.y) = (x, y);
+            state.changed = true;
+        }
+    }
+
+    /// Press the buttons whose bits are set in `buttons` and release the others
+    pub fn set_buttons(&self, buttons: u32) {
+        let mut state = self.state.borrow_mut();
+        let buttons = buttons & (MOUSE_LEFT | MOUSE_RIGHT | MOUSE_MIDDLE);
+        if state.buttons != buttons {
+            state.buttons = buttons;
+            state.changed = true;
+        }
+    }
+
+    /// Buttons that are held
+    pub fn buttons(&self) -> u32 {
+        self.state.borrow().buttons
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        let state = self.state.borrow();
+        [state.x, state.y, state.buttons, state.control]
+    }
+}
+
+impl MmioDevice for Mouse {
+    fn name(&self) -> &str {
+        "mouse"
+    }
+
+    /// Only the control register can be written, a byte at a time
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let mut bytes = self.regs().map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+        self.state.borrow_mut().control = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        let bytes = self.regs().map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        reader.copy_from_slice(&bytes[offset..offset + reader.len()]);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        if std::mem::take(&mut state.changed) && state.control & MOUSE_IRQ_ENABLE != 0 {
+            vec![MmioAction::RaiseIrq]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Interrupts are disabled, the pointer keeps its position and buttons
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.control = 0;
+        state.changed = false;
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::{Simulator, DEFAULT_STACK_BASE},
+        difftest::MsgLog,
+        mmu::VAddr,
+        cpu::Register,
+    };
+
+    #[test]
+    fn changes_interrupt_once_enabled() {
+        let mut mouse = Mouse::new();
+        mouse.move_to(3, 4);
+        assert!(mouse.tick(0).is_empty());
+        mouse.write(0x0, &[9; 12], 0);
+        assert_eq!(mouse.regs(), [3, 4, 0, 0]);
+
+        mouse.write(0xc, &[MOUSE_IRQ_ENABLE as u8], 0);
+        mouse.move_to(3, 4);
+        assert!(mouse.tick(1).is_empty());
+        mouse.set_buttons(MOUSE_LEFT | 0x10);
+        assert_eq!(mouse.tick(2), [MmioAction::RaiseIrq]);
+        assert!(mouse.tick(3).is_empty());
+        assert_eq!(mouse.buttons(), MOUSE_LEFT);
+
+        mouse.reset();
+        mouse.move_to(5, 6);
+        assert!(mouse.tick(4).is_empty());
+        assert_eq!(mouse.regs(), [5, 6, MOUSE_LEFT, 0]);
+    }
+
+    /// Wait for a click in an interrupt handler and read where it happened
+    #[test]
+    fn handler_sees_the_click() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                ld r3 r2 0x8
+                beq r3 r0 .done
+                ld r4 r2 0x0
+                ld r5 r2 0x4
+                movi r6 0x1
+            .done
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x1c
+                movi r2 0x20e0
+                movi r1 0x1
+                st r1 r2 0xc
+            .wait
+                beq r6 r0 .wait
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            let mouse = Mouse::new();
+            sim.register_device(VAddr(MOUSE_BASE), MOUSE_SIZE, Some(MOUSE_IRQ),
+                                Box::new(mouse.clone())).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+
+            // Moving raises an interrupt that the handler ignores, the click ends the program
+            while sim.online && sim.clock < 100_000 {
+                if sim.clock >= 2000 {
+                    mouse.move_to(40, 20);
+                }
+                if sim.clock >= 4000 {
+                    mouse.set_buttons(MOUSE_RIGHT);
+                }
+                sim.step(&log);
+            }
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert!(sim.clock > 4000, "{}", mode);
+            assert_eq!([Register::R3, Register::R4, Register::R5].map(|reg| sim.read_reg(reg)),
+                       [MOUSE_RIGHT, 40, 20], "{}", mode);
+        }
+    }
+}
@@ -0,0 +1,787 @@
+use crate::{
+    simulator::{Simulator, SimErr},
+    mmu::{VAddr, Perms},
+    cpu::{Instr, Register, NUM_REGS},
+    logger::Logger,
+    as_u32_le,
+};
+
+use serde::{Serialize, Deserialize};
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// Number of in-flight instructions the reorder buffer can hold
+pub const ROB_ENTRIES: usize = 16;
+
+/// Number of reservation stations shared by all functional units
+pub const RS_ENTRIES: usize = 8;
+
+/// Cycles a multiplication spends in its functional unit
+pub const MUL_LATENCY: usize = 3;
+
+/// Cycles a division spends in its functional unit
+pub const DIV_LATENCY: usize = 10;
+
+/// Source operand of an instruction waiting in a reservation station
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operand {
+    /// Value is available
+    Ready(u32),
+
+    /// Value is produced by the reorder buffer entry with this tag
+    Waiting(u64),
+}
+
+/// Reorder buffer entry. Entries are allocated in program order and retire from the head once
+/// they are done
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobEntry {
+    /// Unique tag that register renaming refers to
+    pub tag: u64,
+
+    pub instr: Instr,
+
+    /// Address of the instruction
+    pub pc: VAddr,
+
+    /// Register written when the instruction retires
+    pub dest: Option<Register>,
+
+    /// Result of the instruction. Only valid once `done` is set
+    pub value: u32,
+
+    /// Memory address for loads/stores, resolved next pc for branches
+    pub addr: VAddr,
+
+    /// Result has been written back and the entry can retire
+    pub done: bool,
+
+    /// Fault that is raised once this entry retires
+    pub fault: Option<SimErr>,
+}
+
+/// Reservation station holding an issued instruction until its operands are available and its
+/// functional unit is done
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RsEntry {
+    /// Tag of the reorder buffer entry this result belongs to
+    pub tag: u64,
+
+    pub instr: Instr,
+
+    /// Source operands in the order returned by `Instr::uses_regs`
+    pub ops: Vec<Operand>,
+
+    /// Cycles until the result is written back, `None` while waiting for operands
+    pub remaining: Option<usize>,
+
+    /// Result computed when execution started
+    pub value: u32,
+
+    /// Address computed when execution started
+    pub addr: VAddr,
+
+    /// Fault that occured during execution
+    pub fault: Option<SimErr>,
+}
+
+impl RsEntry {
+    /// Check if all operands are available
+    fn ready(&self) -> bool {
+        self.ops.iter().all(|op| matches!(op, Operand::Ready(_)))
+    }
+
+    /// Value of operand `idx`, only valid once the entry is ready
+    fn op(&self, idx: usize) -> u32 {
+        match self.ops[idx] {
+            Operand::Ready(val) => val,
+            Operand::Waiting(_) => unreachable!(),
+        }
+    }
+}
+
+/// Out-of-order execution engine based on Tomasulo's algorithm. Instructions are issued in order
+/// into reservation stations, execute as soon as their operands are available, and retire in
+/// order from the reorder buffer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OooEngine {
+    /// Reorder buffer, oldest instruction first
+    pub rob: VecDeque<RobEntry>,
+
+    /// Occupied reservation stations
+    pub stations: Vec<RsEntry>,
+
+    /// Register alias table. Maps registers to the tag of the youngest in-flight instruction
+    /// writing them
+    pub rat: [Option<u64>; NUM_REGS],
+
+    /// Address of the next instruction to fetch, `None` to continue at the architectural pc
+    pub fetch_pc: Option<VAddr>,
+
+    /// Instruction that was fetched but could not be issued yet, along with a fault that
+    /// occured while fetching it
+    pub fetched: Option<(VAddr, Instr, Option<SimErr>)>,
+
+    /// Issue is halted until the entry with this tag resolves the next pc
+    pub wait_for: Option<u64>,
+
+    /// Tag assigned to the next issued instruction
+    pub next_tag: u64,
+}
+
+impl OooEngine {
+    /// Drop all in-flight instructions. Fetching continues at the architectural pc
+    pub fn flush(&mut self) {
+        let next_tag = self.next_tag;
+        *self = Self::default();
+        self.next_tag = next_tag;
+    }
+
+    /// Render the reorder buffer and reservation stations for the gui
+    pub fn describe(&self) -> String {
+        let operand = |op: &Operand| match op {
+            Operand::Ready(val)  => format!("{:#x}", val),
+            Operand::Waiting(tag) => format!("#{}", tag),
+        };
+
+        let mut text = format!("Reorder Buffer ({}/{})\n", self.rob.len(), ROB_ENTRIES);
+        text.push_str("Tag   PC         Instr                    State\n");
+        for entry in &self.rob {
+            let state = match (entry.done, entry.fault) {
+                (_, Some(fault)) => format!("fault: {:?}", fault),
+                (true, None)     => format!("done {:#x}", entry.value),
+                (false, None)    => "pending".to_string(),
+            };
+            let _ = writeln!(text, "#{:<4} {:#010x} {:<24} {}", entry.tag, entry.pc.0,
+                             entry.instr.to_string(), state);
+        }
+
+        let _ = writeln!(text, "\nReservation Stations ({}/{})", self.stations.len(), RS_ENTRIES);
+        text.push_str("Tag   Instr                    Operands               State\n");
+        for rs in &self.stations {
+            let ops = rs.ops.iter().map(operand).collect::<Vec<_>>().join(", ");
+            let state = match rs.remaining {
+                Some(cycles) => format!("executing ({})", cycles),
+                None         => "waiting".to_string(),
+            };
+            let _ = writeln!(text, "#{:<4} {:<24} {:<22} {}", rs.tag, rs.instr.to_string(), ops,
+                             state);
+        }
+        text
+    }
+}
+
+/// Register written by `instr` when it retires. Registers that devices write in response to a
+/// store are not known ahead of time, so stores don't rename anything. Instead, younger
+/// instructions are fetched again if a store changed a register, see `ooo_commit`
+fn dest_reg(instr: &Instr) -> Option<Register> {
+    if is_store(instr) {
+        return None;
+    }
+    instr.writes_to_rs3().first().copied().filter(|reg| *reg != Register::R0)
+}
+
+/// Immediate operand of `instr`
+fn imm(instr: &Instr) -> i32 {
+    match *instr {
+        Instr::Addi { imm, .. } | Instr::Subi { imm, .. } | Instr::Xori { imm, .. } |
+        Instr::Ori  { imm, .. } | Instr::Andi { imm, .. } | Instr::Lui  { imm, .. } |
+        Instr::Ldb  { imm, .. } | Instr::Ldh  { imm, .. } | Instr::Ld   { imm, .. } |
+        Instr::Stb  { imm, .. } | Instr::Sth  { imm, .. } | Instr::St   { imm, .. } |
+        Instr::Stnt { imm, .. } | Instr::Bne  { imm, .. } | Instr::Beq  { imm, .. } |
+        Instr::Blt  { imm, .. } | Instr::Bgt  { imm, .. } => imm,
+        _ => 0,
+    }
+}
+
+/// Instructions that change control-flow through the stack or link-register are executed
+/// serially once all older instructions have retired
+fn is_serializing(instr: &Instr) -> bool {
+    matches!(instr, Instr::Call { .. } | Instr::Ret { } | Instr::Jmpr { .. } | Instr::Int0 { })
+}
+
+fn is_branch(instr: &Instr) -> bool {
+    matches!(instr, Instr::Bne { .. } | Instr::Beq { .. } | Instr::Blt { .. } | Instr::Bgt { .. })
+}
+
+fn is_muldiv(instr: &Instr) -> bool {
+    matches!(instr, Instr::Mul { .. } | Instr::Div { .. })
+}
+
+fn is_load(instr: &Instr) -> bool {
+    matches!(instr, Instr::Ldb { .. } | Instr::Ldh { .. } | Instr::Ld { .. })
+}
+
+fn is_store(instr: &Instr) -> bool {
+    matches!(instr, Instr::Stb { .. } | Instr::Sth { .. } | Instr::St { .. } | Instr::Stnt { .. })
+}
+
+/// Size in bytes of the memory access performed by a load or store
+fn access_len(instr: &Instr) -> usize {
+    match instr {
+        Instr::Ldb { .. } | Instr::Stb { .. } => 1,
+        Instr::Ldh { .. } | Instr::Sth { .. } => 2,
+        _ => 4,
+    }
+}
+
+/// Compute the result of an arithmetic instruction from its operands, along with the number of
+/// cycles it occupies its functional unit
+fn alu(instr: &Instr, ops: &RsEntry) -> Result<(u32, usize), SimErr> {
+    let imm = imm(instr);
+    Ok(match instr {
+        Instr::Add  { .. } => (ops.op(0).wrapping_add(ops.op(1)), 1),
+        Instr::Sub  { .. } => (ops.op(0).wrapping_sub(ops.op(1)), 1),
+        Instr::Xor  { .. } => (ops.op(0) ^ ops.op(1), 1),
+        Instr::Or   { .. } => (ops.op(0) | ops.op(1), 1),
+        Instr::And  { .. } => (ops.op(0) & ops.op(1), 1),
+        Instr::Shr  { .. } => (ops.op(0).wrapping_shr(ops.op(1)), 1),
+        Instr::Shl  { .. } => (ops.op(0).wrapping_shl(ops.op(1)), 1),
+        Instr::Mul  { .. } => (ops.op(0).wrapping_mul(ops.op(1)), MUL_LATENCY),
+        Instr::Div  { .. } => {
+            if ops.op(1) == 0 {
+                return Err(SimErr::DivByZero);
+            }
+            (ops.op(0) / ops.op(1), DIV_LATENCY)
+        },
+        Instr::Addi { .. } => ((ops.op(0) as i32).wrapping_add(imm) as u32, 1),
+        Instr::Subi { .. } => ((ops.op(0) as i32).wrapping_sub(imm) as u32, 1),
+        Instr::Xori { .. } => (((ops.op(0) as i32) ^ imm) as u32, 1),
+        Instr::Ori  { .. } => (((ops.op(0) as i32) | imm) as u32, 1),
+        Instr::Andi { .. } => (((ops.op(0) as i32) & imm) as u32, 1),
+        Instr::Lui  { .. } => ((imm << 12) as u32, 1),
+        _ => unreachable!(),
+    })
+}
+
+impl Simulator {
+    /// Switch between the in-order pipeline and the out-of-order engine. In-flight instructions
+    /// of the engine that is switched away from are dropped, and execution continues at the
+    /// architectural pc
+    pub fn set_out_of_order(&mut self, enabled: bool) {
+        if enabled == self.ooo_enabled {
+            return;
+        }
+
+        self.flush_pipeline();
+        self.ooo_enabled = enabled;
+    }
+
+    /// Single-step one clock-cycle with the out-of-order engine
+    pub fn step_ooo(&mut self, err_log: &dyn Logger) {
+        if let Err(err) = self.ooo_commit() {
+            self.ooo_fault(err, err_log);
+            return;
+        }
+        self.ooo_write_result();
+        self.ooo_execute();
+        if let Err(err) = self.ooo_issue() {
+            self.ooo_fault(err, err_log);
+        }
+    }
+
+    /// Handle a fault raised by a retiring instruction
+    fn ooo_fault(&mut self, err: SimErr, err_log: &dyn Logger) {
+        self.guest_fault(err, self.pc, err_log);
+        if err == SimErr::ExecFault {
+            self.ooo.flush();
+        }
+    }
+
+    /// Value of `reg` for a newly issued instruction, either from the register file or from the
+    /// in-flight instruction that produces it
+    fn ooo_operand(&self, reg: Register) -> Operand {
+        let tag = match self.ooo.rat.get(reg as usize) {
+            Some(Some(tag)) => *tag,
+            _ => return Operand::Ready(self.read_reg(reg)),
+        };
+        match self.ooo.rob.iter().find(|entry| entry.tag == tag) {
+            Some(entry) if entry.done => Operand::Ready(entry.value),
+            _ => Operand::Waiting(tag),
+        }
+    }
+
+    /// Forward the result of the instruction with `tag` to all reservation stations waiting on it
+    fn ooo_broadcast(&mut self, tag: u64, value: u32) {
+        for rs in self.ooo.stations.iter_mut() {
+            for op in rs.ops.iter_mut() {
+                if *op == Operand::Waiting(tag) {
+                    *op = Operand::Ready(value);
+                }
+            }
+        }
+    }
+
+    /// Fetch the next instruction and issue it into the reorder buffer and a reservation station
+    fn ooo_issue(&mut self) -> Result<(), SimErr> {
+        if self.ooo.wait_for.is_some() {
+            return Ok(());
+        }
+
+        // Fetch into the issue latch
+        if self.ooo.fetched.is_none() {
+            let pc = *self.ooo.fetch_pc.get_or_insert(self.pc);
+            let fetched = match self.mmu.translate_addr(pc, Perms::READ | Perms::EXEC) {
+                Err(SimErr::Permission) => (pc, Instr::Invalid, Some(SimErr::ExecFault)),
+                Err(err) => (pc, Instr::Invalid, Some(err)),
+                Ok(_) => {
+                    let mut reader = vec![0x0u8; 4];
+                    self.mem_read(pc, &mut reader)?;
+                    match self.decode_cache.decode(pc.0, as_u32_le(&reader)) {
+                        Ok(instr) => (pc, instr, None),
+                        Err(_) => (pc, Instr::Invalid, Some(SimErr::InstrDecode)),
+                    }
+                },
+            };
+            self.ooo.fetched = Some(fetched);
+        }
+        let (pc, instr, fault) = self.ooo.fetched.unwrap();
+
+        if is_serializing(&instr) && fault.is_none() {
+            if !self.ooo.rob.is_empty() {
+                return Ok(());
+            }
+            self.ooo.fetched  = None;
+            self.ooo.fetch_pc = Some(self.ooo_serialized(instr, pc)?);
+            self.instr_retired(pc, instr);
+            self.pc = self.ooo.fetch_pc.unwrap();
+            return Ok(());
+        }
+
+        let needs_rs = fault.is_none() && !matches!(instr, Instr::Nop);
+        if self.ooo.rob.len() >= ROB_ENTRIES || (needs_rs && self.ooo.stations.len() >= RS_ENTRIES)
+        {
+            return Ok(());
+        }
+
+        let tag  = self.ooo.next_tag;
+        let dest = dest_reg(&instr);
+        let ops  = instr.uses_regs().into_iter().map(|reg| self.ooo_operand(reg)).collect();
+        self.ooo.next_tag += 1;
+        self.ooo.fetched   = None;
+
+        self.ooo.rob.push_back(RobEntry {
+            tag,
+            instr,
+            pc,
+            dest,
+            value: 0,
+            addr:  VAddr(0),
+            done:  !needs_rs,
+            fault,
+        });
+        if needs_rs {
+            self.ooo.stations.push(RsEntry {
+                tag,
+                instr,
+                ops,
+                remaining: None,
+                value:     0,
+                addr:      VAddr(0),
+                fault:     None,
+            });
+        }
+        if let Some(dest) = dest {
+            self.ooo.rat[dest as usize] = Some(tag);
+        }
+
+        // Without speculation, fetching stops until the next pc is known
+        if is_branch(&instr) || fault.is_some() {
+            self.ooo.wait_for = Some(tag);
+        } else {
+            self.ooo.fetch_pc = Some(VAddr(pc.0.wrapping_add(4)));
+        }
+        Ok(())
+    }
+
+    /// Execute a serializing control-flow instruction at `pc`. All older instructions have
+    /// retired, so it operates directly on the architectural state. Returns the next pc
+    fn ooo_serialized(&mut self, instr: Instr, pc: VAddr) -> Result<VAddr, SimErr> {
+        self.stats.total_instrs   += 1;
+        self.stats.control_instrs += 1;
+
+        match instr {
+            Instr::Jmpr { offset, .. } => Ok(VAddr((pc.0 as i32).wrapping_add(offset) as u32)),
+            Instr::Call { offset, .. } => {
+                // Push link register and update it to the return address
+                self.write_reg(Register::R15, self.read_reg(Register::R15).wrapping_sub(4));
+                let mut prev_ra = self.read_reg(Register::R14).to_le_bytes().to_vec();
+                self.mem_write(VAddr(self.read_reg(Register::R15)), &mut prev_ra)?;
+                self.write_reg(Register::R14, pc.0.wrapping_add(4));
+                self.enter_call(pc, VAddr(offset as u32));
+                Ok(VAddr(offset as u32))
+            },
+            Instr::Ret { } => {
+                // Return to the link register and pop the previous one from the stack
+                let target = VAddr(self.read_reg(Register::R14));
+                let mut reader = vec![0x0; 4];
+                self.mem_read(VAddr(self.read_reg(Register::R15)), &mut reader)?;
+                self.write_reg(Register::R14, as_u32_le(&reader));
+                self.write_reg(Register::R15, self.read_reg(Register::R15).wrapping_add(4));
+                self.leave_call();
+                Ok(target)
+            },
+            Instr::Int0 { } => {
+                let mut reader = vec![0x0; 4];
+                self.mem_read(VAddr(0x0), &mut reader)?;
+                Ok(VAddr(as_u32_le(&reader)))
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Advance functional units and start executing reservation stations whose operands are
+    /// available. Multiplications and divisions share a single multiplier/divider that is not
+    /// pipelined, so only one of them can execute at a time
+    fn ooo_execute(&mut self) {
+        let mut muldiv_busy = self.ooo.stations.iter()
+            .any(|rs| is_muldiv(&rs.instr) && matches!(rs.remaining, Some(1..)));
+        let mut structural_stall = false;
+
+        for idx in 0..self.ooo.stations.len() {
+            let rs = &mut self.ooo.stations[idx];
+            match rs.remaining {
+                Some(cycles) => rs.remaining = Some(cycles.saturating_sub(1)),
+                None if is_muldiv(&rs.instr) && rs.ready() => {
+                    if muldiv_busy {
+                        structural_stall = true;
+                        continue;
+                    }
+                    muldiv_busy = true;
+                    self.ooo_start(idx);
+                },
+                None => self.ooo_start(idx),
+            }
+        }
+
+        if structural_stall {
+            self.stats.structural_stall_clock += 1;
+        }
+    }
+
+    /// Start executing reservation station `idx` if its operands are available. Loads wait until
+    /// all older stores retired, so they always observe the correct memory contents. Loads from
+    /// devices can have side effects, so they wait until they are the oldest instruction
+    fn ooo_start(&mut self, idx: usize) {
+        let rs = self.ooo.stations[idx].clone();
+        if !rs.ready() {
+            return;
+        }
+
+        let instr = rs.instr;
+        let result: Result<(u32, VAddr, usize), SimErr> = if is_load(&instr) {
+            let older_store = self.ooo.rob.iter()
+                .take_while(|entry| entry.tag != rs.tag)
+                .any(|entry| is_store(&entry.instr));
+            if older_store {
+                return;
+            }
+
+            let addr = VAddr((rs.op(0) as i32).wrapping_add(imm(&instr)) as u32);
+            let oldest = self.ooo.rob.front().map(|entry| entry.tag) == Some(rs.tag);
+            if !oldest && self.devices.names_at(addr, access_len(&instr)).next().is_some() {
+                return;
+            }
+            self.ooo_load(addr, access_len(&instr)).map(|(value, latency)| (value, addr, latency))
+        } else if is_store(&instr) {
+            // Stores only compute their address here and write memory once they retire
+            Ok((0, VAddr((rs.op(1) as i32).wrapping_add(imm(&instr)) as u32), 1))
+        } else if is_branch(&instr) {
+            let taken = match instr {
+                Instr::Bne { .. } => rs.op(0) != rs.op(1),
+                Instr::Beq { .. } => rs.op(0) == rs.op(1),
+                Instr::Blt { .. } => rs.op(0) <  rs.op(1),
+                Instr::Bgt { .. } => rs.op(0) >  rs.op(1),
+                _ => unreachable!(),
+            };
+            let pc = self.ooo.rob.iter().find(|entry| entry.tag == rs.tag).unwrap().pc;
+            let next_pc = if taken {
+                VAddr(((pc.0 as i64) + imm(&instr) as i64) as u32)
+            } else {
+                VAddr(pc.0.wrapping_add(4))
+            };
+            Ok((0, next_pc, 1))
+        } else {
+            alu(&instr, &rs).map(|(value, latency)| (value, VAddr(0), latency))
+        };
+
+        let rs = &mut self.ooo.stations[idx];
+        match result {
+            Ok((value, addr, latency)) => {
+                rs.value     = value;
+                rs.addr      = addr;
+                rs.remaining = Some(latency.saturating_sub(1));
+            },
+            Err(fault) => {
+                rs.fault     = Some(fault);
+                rs.remaining = Some(0);
+            },
+        }
+    }
+
+    /// Perform a `len` byte load from `addr`. Returns the loaded value and its latency
+    fn ooo_load(&mut self, addr: VAddr, len: usize) -> Result<(u32, usize), SimErr> {
+        self.populate_page(addr)?;
+        let walk    = self.tlb_access(addr);
+        let paddr   = self.mmu.translate_addr(addr, Perms::READ)?;
+        let latency = walk + self.mem_access_stall(paddr, walk);
+
+        let mut reader = vec![0x0; len];
+        self.mem_read(addr, &mut reader)?;
+        reader.resize(4, 0);
+        Ok((as_u32_le(&reader), latency))
+    }
+
+    /// Write back the result of the oldest finished reservation station over the common data bus
+    fn ooo_write_result(&mut self) {
+        let idx = self.ooo.stations.iter().enumerate()
+            .filter(|(_, rs)| rs.remaining == Some(0))
+            .min_by_key(|(_, rs)| rs.tag)
+            .map(|(idx, _)| idx);
+        let rs = match idx {
+            Some(idx) => self.ooo.stations.remove(idx),
+            None => return,
+        };
+
+        let entry = self.ooo.rob.iter_mut().find(|entry| entry.tag == rs.tag).unwrap();
+        entry.value = rs.value;
+        entry.addr  = rs.addr;
+        entry.fault = rs.fault;
+        entry.done  = true;
+
+        self.ooo_broadcast(rs.tag, rs.value);
+
+        // Resolved branches let fetching continue at the correct pc
+        if self.ooo.wait_for == Some(rs.tag) && rs.fault.is_none() {
+            self.ooo.wait_for = None;
+            self.ooo.fetch_pc = Some(rs.addr);
+        }
+    }
+
+    /// Retire the instruction at the head of the reorder buffer if it is done
+    fn ooo_commit(&mut self) -> Result<(), SimErr> {
+        let entry = match self.ooo.rob.front() {
+            Some(entry) if entry.done => self.ooo.rob.pop_front().unwrap(),
+            _ => return Ok(()),
+        };
+
+        if let Some(dest) = entry.dest {
+            if self.ooo.rat[dest as usize] == Some(entry.tag) {
+                self.ooo.rat[dest as usize] = None;
+            }
+        }
+
+        if let Some(fault) = entry.fault {
+            self.pc = entry.pc;
+            return Err(fault);
+        }
+
+        let instr = entry.instr;
+        self.pc = VAddr(entry.pc.0.wrapping_add(4));
+        match instr {
+            Instr::Stb { rs3, .. } | Instr::Sth { rs3, .. } | Instr::St { rs3, .. } |
+            Instr::Stnt { rs3, .. } => {
+                self.stats.store_instrs += 1;
+                let regs       = self.gen_regs;
+                let mut writer = self.read_reg(rs3).to_le_bytes()[..access_len(&instr)].to_vec();
+                if let Instr::Stnt { .. } = instr {
+                    self.stats.nt_store_instrs += 1;
+                    self.mem_write_nt(entry.addr, &writer)?;
+                } else {
+                    self.record_store_access(entry.addr);
+                    self.mem_write(entry.addr, &mut writer)?;
+                }
+
+                // Younger instructions may have read registers a device just wrote
+                if self.gen_regs != regs {
+                    self.ooo.flush();
+                }
+            },
+            Instr::Bne { .. } | Instr::Beq { .. } | Instr::Blt { .. } | Instr::Bgt { .. } => {
+                self.stats.control_instrs += 1;
+                if entry.addr.0 != entry.pc.0.wrapping_add(4) {
+                    self.stats.metrics.add("branch.taken", 1);
+                }
+                self.pc = entry.addr;
+            },
+            Instr::Ldb { .. } | Instr::Ldh { .. } | Instr::Ld { .. } => {
+                self.stats.load_instrs += 1;
+            },
+            Instr::Nop => {},
+            _ => self.stats.arithmetic_instrs += 1,
+        }
+
+        if let Some(dest) = entry.dest {
+            self.write_reg(dest, entry.value);
+        }
+        self.stats.total_instrs += 1;
+        self.instr_retired(entry.pc, instr);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::InstrCode,
+        mmio::{MmioDevice, MmioAction, CMD_PORT_ADDR},
+    };
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Encode an instruction, `last` is `rs2` for r-type and `imm` for g-type instructions
+    fn enc(op: InstrCode, rs3: u32, rs1: u32, last: u32) -> u32 {
+        let is_mul = op == InstrCode::Mul;
+        let last   = if is_mul { last << 11 } else { last & 0xffff };
+        ((op as u32) << 26) | (rs3 << 21) | (rs1 << 16) | last
+    }
+
+    /// Simulator with `code` loaded at 0x10000 and a data page at 0x3000
+    fn setup(code: &[u32]) -> Simulator {
+        let mut sim = Simulator::new();
+        sim.map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+        sim.map_page(VAddr(0x10000), Perms::READ | Perms::WRITE | Perms::EXEC).unwrap();
+        let bytes = code.iter().flat_map(|instr| instr.to_le_bytes()).collect::<Vec<u8>>();
+        sim.mmu.mem_write_bulk(VAddr(0x10000), &bytes).unwrap();
+        sim.pc = VAddr(0x10000);
+        sim.set_out_of_order(true);
+        sim
+    }
+
+    /// Step the out-of-order engine until the pc reaches `end`. Returns the number of cycles
+    fn run_until(sim: &mut Simulator, end: VAddr) -> usize {
+        for cycle in 0..1000 {
+            if sim.pc == end {
+                return cycle;
+            }
+            sim.ooo_commit().unwrap();
+            sim.ooo_write_result();
+            sim.ooo_execute();
+            sim.ooo_issue().unwrap();
+        }
+        panic!("Program did not finish");
+    }
+
+    #[test]
+    fn loop_with_memory_dependency() {
+        let mut sim = setup(&[
+            enc(InstrCode::Addi, 1, 0, 3),
+            enc(InstrCode::Addi, 2, 0, 0),
+            enc(InstrCode::Addi, 2, 2, 5),
+            enc(InstrCode::Subi, 1, 1, 1),
+            enc(InstrCode::Bne,  1, 0, -8i32 as u32),
+            enc(InstrCode::St,   2, 0, 0x3000),
+            enc(InstrCode::Ld,   3, 0, 0x3000),
+            enc(InstrCode::Mul,  4, 3, 3),
+        ]);
+        run_until(&mut sim, VAddr(0x10020));
+
+        assert_eq!(sim.read_reg(Register::R1), 0);
+        assert_eq!(sim.read_reg(Register::R3), 15);
+        assert_eq!(sim.read_reg(Register::R4), 225);
+        assert_eq!(sim.stats.total_instrs, 14);
+    }
+
+    #[test]
+    fn independent_instructions_overlap_long_latency_ops() {
+        // The additions don't depend on the multiplications, so they complete in their shadow
+        let code = [
+            enc(InstrCode::Addi, 1, 0, 7),
+            enc(InstrCode::Mul,  2, 1, 1),
+            enc(InstrCode::Mul,  3, 2, 2),
+            enc(InstrCode::Addi, 4, 0, 1),
+            enc(InstrCode::Addi, 5, 4, 1),
+            enc(InstrCode::Addi, 6, 5, 1),
+        ];
+        let mut sim = setup(&code);
+        let cycles = run_until(&mut sim, VAddr(0x10018));
+
+        assert_eq!(sim.read_reg(Register::R3), 7 * 7 * 7 * 7);
+        assert_eq!(sim.read_reg(Register::R6), 3);
+        assert!(cycles < code.len() + 2 * MUL_LATENCY + 4);
+    }
+
+    #[test]
+    fn back_to_back_muls_share_the_multiplier() {
+        // Independent multiplications still have to take turns on the single multiplier
+        let code = [
+            enc(InstrCode::Mul, 1, 0, 0),
+            enc(InstrCode::Mul, 2, 0, 0),
+            enc(InstrCode::Mul, 3, 0, 0),
+        ];
+        let mut sim = setup(&code);
+        let cycles = run_until(&mut sim, VAddr(0x1000c));
+
+        assert!(cycles >= code.len() * MUL_LATENCY);
+        assert_eq!(sim.stats.structural_stall_clock, (2 * MUL_LATENCY - 1) as u64);
+    }
+
+    #[test]
+    fn device_register_writes_refetch_younger_instructions() {
+        // The command port writes a random value to r1, which the addition issued behind the
+        // store has to observe
+        let mut sim = setup(&[
+            enc(InstrCode::Addi, 2, 0, CMD_PORT_ADDR),
+            enc(InstrCode::Addi, 5, 0, 0x43),
+            enc(InstrCode::St,   5, 2, 0),
+            enc(InstrCode::Add,  3, 1, 0),
+        ]);
+        sim.map_page(VAddr(CMD_PORT_ADDR), Perms::READ | Perms::WRITE).unwrap();
+        run_until(&mut sim, VAddr(0x10010));
+
+        assert_ne!(sim.read_reg(Register::R1), 0);
+        assert_eq!(sim.read_reg(Register::R3), sim.read_reg(Register::R1));
+        assert_eq!(sim.read_reg(Register::R5), 0x43);
+    }
+
+    /// Device that counts the reads it receives
+    #[derive(Debug, Clone, Default)]
+    struct ReadCounter(Rc<Cell<u32>>);
+
+    impl MmioDevice for ReadCounter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn write(&mut self, _offset: u32, _data: &[u8], _clock: u32) -> Option<MmioAction> {
+            None
+        }
+
+        fn read(&mut self, _offset: u32, _reader: &mut [u8]) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn clone_box(&self) -> Box<dyn MmioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn device_loads_wait_until_they_are_the_oldest() {
+        let ops = [(enc(InstrCode::Div, 3, 3, 0), 0), (enc(InstrCode::Mul, 3, 3, 0), 1)];
+        for (op, reads) in ops {
+            let mut sim = setup(&[
+                enc(InstrCode::Addi, 2, 0, 0x4000),
+                op,
+                enc(InstrCode::Ld,   4, 2, 0),
+            ]);
+            let counter = ReadCounter::default();
+            sim.register_device(VAddr(0x4000), 0x10, None, Box::new(counter.clone())).unwrap();
+
+            // The load is issued in the shadow of the long latency op, but must not touch the
+            // device before the division by zero faulted
+            for _ in 0..100 {
+                if sim.pc == VAddr(0x1000c) || sim.ooo_commit().is_err() {
+                    break;
+                }
+                sim.ooo_write_result();
+                sim.ooo_execute();
+                sim.ooo_issue().unwrap();
+            }
+            assert_eq!(counter.0.get(), reads, "{:#x}", op);
+        }
+    }
+}
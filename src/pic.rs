@@ -0,0 +1,242 @@
+use crate::{
+    mmio::{MmioDevice, MmioAction, MmioMap, le_words, read_regs, write_regs},
+    mmu::VAddr,
+};
+
+/// Address of the interrupt controller's registers, next to the disk
+pub const PIC_BASE: u32 = 0x2040;
+
+/// Size of the interrupt controller's register block
+pub const PIC_SIZE: u32 = 0x14;
+
+/// Number of interrupt lines, line `n` interrupts the program with interrupt-vector[n]
+pub const PIC_LINES: u32 = 32;
+
+/// Control bit that lets the controller arbitrate between the lines. While it is clear, raised
+/// lines interrupt the program right away, like without a controller
+pub const PIC_ENABLE: u32 = 1 << 0;
+
+/// Programmable interrupt controller at `PIC_BASE`, the interrupt lines of all devices are
+/// connected to it. Lower lines have a higher priority. Its 32-bit registers are:
+///  - 0x00 Control: `PIC_ENABLE`
+///  - 0x04 Mask: Bit `n` masks line `n`, raised lines stay pending while they are masked
+///  - 0x08 Pending: Lines that were raised but not delivered yet, writing a 1 to a bit clears it
+///  - 0x0c In-service: Lines whose handler is running
+///  - 0x10 EOI: Writing ends the interrupt of the highest priority line in service
+///
+/// A pending line is delivered once no line of the same or a higher priority is in service, so
+/// higher priority interrupts can preempt the handlers of lower ones
+#[derive(Debug, Clone, Default)]
+pub struct Pic {
+    control:    u32,
+    mask:       u32,
+    pending:    u32,
+    in_service: u32,
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 5] {
+        [self.control, self.mask, self.pending, self.in_service, 0]
+    }
+}
+
+impl MmioDevice for Pic {
+    fn name(&self) -> &str {
+        "pic"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        self.control = regs[0];
+        self.mask    = regs[1];
+
+        // Pending bits are cleared by writing a 1 to them
+        let mut cleared = [0u8; 4];
+        for (idx, &byte) in data.iter().enumerate() {
+            if let Some(pending_idx) = (offset + idx).checked_sub(8).filter(|&idx| idx < 4) {
+                cleared[pending_idx] = byte;
+            }
+        }
+        self.pending &= !u32::from_le_bytes(cleared);
+
+        if (offset..offset + data.len()).any(|offset| (0x10..0x14).contains(&offset)) {
+            self.in_service &= self.in_service.wrapping_sub(1);
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    /// Deliver the highest priority pending line, if no line of the same or a higher priority is
+    /// in service
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let ready = self.pending & !self.mask;
+        if self.control & PIC_ENABLE == 0 || ready == 0 {
+            return Vec::new();
+        }
+        let line = ready.trailing_zeros();
+        if line >= self.in_service.trailing_zeros() {
+            return Vec::new();
+        }
+        self.pending    &= !(1 << line);
+        self.in_service |= 1 << line;
+        vec![MmioAction::Interrupt(line)]
+    }
+
+    fn raise_line(&mut self, line: u32) -> Option<MmioAction> {
+        if line >= PIC_LINES {
+            return None;
+        }
+        if self.control & PIC_ENABLE == 0 {
+            return Some(MmioAction::Interrupt(line));
+        }
+        self.pending |= 1 << line;
+        None
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+/// Table of the interrupt lines that have a device connected or are masked, pending or in service,
+/// read from the controller registered at `PIC_BASE`
+pub fn report(devices: &mut MmioMap) -> String {
+    let mut bytes = [0u8; 0x10];
+    devices.read(VAddr(PIC_BASE), &mut bytes);
+    let [control, mask, pending, in_service] = le_words(&bytes);
+
+    let state = if control & PIC_ENABLE != 0 { "enabled" } else { "disabled, lines bypass it" };
+    let mut lines = vec![format!("Controller {}", state), String::new(),
+                         "Line  Device        Masked  Pending  In service".to_string()];
+    let flag = |bits: u32, line: u32| if bits & (1 << line) != 0 { "yes" } else { "no" };
+    for line in 0..PIC_LINES {
+        let names = devices.regions.iter().filter(|r| r.irq == Some(line))
+            .map(|r| r.device.name()).collect::<Vec<_>>().join(", ");
+        if names.is_empty() && (mask | pending | in_service) & (1 << line) == 0 {
+            continue;
+        }
+        lines.push(format!("{:>4}  {:<12}  {:<6}  {:<7}  {}", line, names, flag(mask, line),
+                           flag(pending, line), flag(in_service, line)));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmio::read_reg, cpu::Register};
+
+    #[test]
+    fn arbitrates_by_priority() {
+        let mut pic = Pic::new();
+        assert_eq!(pic.raise_line(3), Some(MmioAction::Interrupt(3)));
+        assert_eq!(pic.pending, 0);
+
+        pic.write(0x0, &[PIC_ENABLE as u8], 0);
+        pic.write(0x4, &[1 << 5], 0);
+        assert_eq!(pic.raise_line(3), None);
+        assert_eq!(pic.tick(0), [MmioAction::Interrupt(3)]);
+
+        // Higher priority lines preempt the handler, lower and masked ones wait
+        pic.raise_line(4);
+        pic.raise_line(5);
+        pic.raise_line(2);
+        assert_eq!(pic.tick(1), [MmioAction::Interrupt(2)]);
+        assert!(pic.tick(2).is_empty());
+        assert_eq!((pic.pending, pic.in_service), (0b11_0000, 0b1100));
+
+        pic.write(0x10, &[0], 3);
+        assert!(pic.tick(3).is_empty());
+        pic.write(0x10, &[0], 4);
+        assert_eq!(pic.tick(4), [MmioAction::Interrupt(4)]);
+
+        // Pending lines can be dropped, control and mask are kept
+        pic.write(0x8, &[1 << 5], 5);
+        let regs = [0x0, 0x4, 0x8, 0xc, 0x10].map(|offset| read_reg(&mut pic, offset));
+        assert_eq!(regs, [1, 1 << 5, 0, 1 << 4, 0]);
+    }
+
+    /// Timer interrupts are only delivered again once the handler signalled the end of the
+    /// previous one
+    #[test]
+    fn waits_for_the_end_of_interrupt() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                addi r6 r6 0x1
+                movi r4 0x1
+                st r4 r2 0xc
+                bne r7 r0 .eoi
+                ret
+            .eoi
+                st r0 r3 0x10
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x8
+                movi r3 0x2040
+                movi r1 0x1
+                st r1 r3 0x0
+                movi r2 0x2010
+                movi r1 0x400
+                st r1 r2 0x4
+                movi r1 0x7
+                st r1 r2 0x8
+            .first
+                beq r6 r0 .first
+                movi r5 0x800
+            .wait
+                subi r5 r5 0x1
+                bne r5 r0 .wait
+                addi r8 r6 0x0
+                movi r7 0x1
+                st r0 r3 0x10
+            .more
+                subi r1 r6 0x4
+                bne r1 r0 .more
+                st r0 r2 0x8
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 1_000_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            // The timer kept firing until the first end of interrupt, but only one interrupt
+            // arrived
+            assert_eq!(run.sim.read_reg(Register::R8), 1, "{}", mode);
+            assert_eq!(run.sim.read_reg(Register::R6), 4, "{}", mode);
+
+            let mut sim = run.sim;
+            let report = report(&mut sim.devices);
+            assert!(report.starts_with("Controller enabled"), "{}: {}", mode, report);
+            assert!(report.contains("   2  timer         no      no       no"), "{}: {}", mode,
+                    report);
+        }
+    }
+}
@@ -1,9 +1,23 @@
 use crate::{
-    mmu::VAddr,
-    cpu::Instr,
+    mmu::{VAddr, PAddr},
+    simulator::SimErr,
+    cpu::{Instr, Register},
+    predictor::BranchPredictor,
 };
 
-#[derive(Debug, Clone, Default)]
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+
+/// Number of cycles kept in the pipeline history unless configured otherwise
+pub const DEFAULT_HISTORY_LEN: usize = 256;
+
+/// Number of fetched instructions that can wait for the decode stage
+pub const FETCH_QUEUE_SIZE: usize = 4;
+
+/// Number of load misses that can be outstanding at once with a non-blocking cache
+pub const MSHR_ENTRIES: usize = 4;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Pipeline {
     /// PC internal to the pipeline
     /// Generally 4 ahead of actual pc since its updated in the `fetch` stage of the pipeline
@@ -19,12 +33,42 @@ pub struct Pipeline {
     /// Keeps track of which slot in the pipeline threw the hazard
     pub hazard_thrower: Option<usize>,
 
+    /// Kind of bubble the data hazard of `hazard_thrower` inserts into the pipeline
+    pub hazard_bubble: Option<Bubble>,
+
     /// This field is only used when the pipeline is disabled. Only one instruction can be in the 
     /// pipeline at once, and this field keeps track of which field that is
     pub cur_stage: usize,
+
+    /// Predicts conditional branches so fetching can continue while they are in flight
+    pub predictor: BranchPredictor,
+
+    /// Sequence number assigned to the next fetched instruction
+    pub next_seq: u64,
+
+    /// Fetched instructions waiting to enter the decode stage, oldest first. This lets fetching
+    /// continue while the decode stage is stalled
+    pub fetch_queue: VecDeque<Slot>,
+
+    /// Load misses the pipeline continued past, only used with a non-blocking cache
+    pub mshrs: Vec<Mshr>,
+}
+
+/// Miss status holding register. Tracks a load miss that is still being serviced after the load
+/// left the memory stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mshr {
+    /// Cache-line the load accesses
+    pub line: PAddr,
+
+    /// Register the load writes to, instructions reading it wait until the miss is serviced
+    pub reg: Register,
+
+    /// Cycles until the miss is serviced
+    pub remaining: usize,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Slot {
     /// Indicates if this slot is currently valid or not
     pub valid: bool,
@@ -61,5 +105,420 @@ pub struct Slot {
     pub disable: bool,
 
     pub mem_stall: Option<usize>,
+
+    /// Error the fetch of this instruction ran into, `ExecFault` for pages without execute
+    /// permissions and `AddrTranslation` for unmapped ones. The fault is only raised once the
+    /// instruction reaches the execute stage, so wrong-path fetches that end up flushed don't fault
+    pub fetch_fault: Option<SimErr>,
+
+    /// Address the front-end continued fetching at after this branch, if it was predicted
+    pub predicted_pc: Option<VAddr>,
+
+    /// Sequence number in fetch order, identifies the instruction while it moves through the
+    /// pipeline
+    pub seq: u64,
+
+    /// Address and instruction of a wrong-path instruction that was squashed from this slot after
+    /// a mispredict. The slot is otherwise empty and moves down the pipeline as a bubble
+    pub squashed: Option<(VAddr, Instr)>,
+
+    /// Set if this slot holds no instruction because the pipeline inserted a bubble, along with
+    /// the reason for it. Slots that were never filled leave this unset
+    pub bubble: Option<Bubble>,
+}
+
+impl Slot {
+    /// Empty slot that marks a bubble inserted for `reason`
+    pub fn bubble(reason: Bubble) -> Self {
+        Self { bubble: Some(reason), ..Self::default() }
+    }
+}
+
+/// Reason the pipeline inserted a bubble instead of advancing an instruction into a stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bubble {
+    /// The instruction in decode waits for a register an older instruction still has to write
+    DataHazard,
+
+    /// The instruction in decode reads the result of the load in the execute stage, which is only
+    /// available after the load accessed memory, even with forwarding
+    LoadUse,
+
+    /// The instruction was discarded because the front-end was redirected, eg. after a
+    /// mispredict, an interrupt or a fault
+    Flush,
+
+    /// The stage ahead waited on memory, either for an instruction fetch or a load/store
+    MemStall,
+
+    /// Fetching is paused until a control-flow instruction resolves its target
+    Control,
+}
+
+impl Bubble {
+    /// Description displayed on the gui
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Bubble::DataHazard => "data hazard",
+            Bubble::LoadUse    => "load-use",
+            Bubble::Flush      => "flush",
+            Bubble::MemStall   => "memory stall",
+            Bubble::Control    => "branch stall",
+        }
+    }
+}
+
+/// Kind of data dependence of a younger instruction on an older one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HazardKind {
+    /// Read-after-write: The younger instruction reads a register the older one writes
+    Raw,
+
+    /// Write-after-write: Both instructions write the same register
+    Waw,
+
+    /// Write-after-read: The younger instruction writes a register the older one reads
+    War,
+}
+
+impl HazardKind {
+    /// Short name displayed on the gui
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            HazardKind::Raw => "RAW",
+            HazardKind::Waw => "WAW",
+            HazardKind::War => "WAR",
+        }
+    }
+
+    /// Classify the data dependences of `younger` on `older`. The in-order pipeline only has to
+    /// stall on `Raw` hazards, `Waw` and `War` hazards only matter once instructions can complete
+    /// out of order
+    pub fn classify(younger: &Instr, older: &Instr) -> Vec<(HazardKind, Register)> {
+        let (reads, writes) = (younger.uses_regs(), younger.writes_to_rs3());
+        let (older_reads, older_writes) = (older.uses_regs(), older.writes_to_rs3());
+
+        let mut hazards = Vec::new();
+        for &reg in older_writes.iter() {
+            if reads.contains(&reg) {
+                hazards.push((HazardKind::Raw, reg));
+            }
+            if writes.contains(&reg) {
+                hazards.push((HazardKind::Waw, reg));
+            }
+        }
+        for &reg in older_reads.iter() {
+            if writes.contains(&reg) && !hazards.contains(&(HazardKind::War, reg)) {
+                hazards.push((HazardKind::War, reg));
+            }
+        }
+        hazards
+    }
+}
+
+/// Data dependence of the instruction in decode on an older instruction in the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hazard {
+    pub kind: HazardKind,
+
+    /// Register the instructions depend on each other through
+    pub reg: Register,
+
+    /// Pipeline slot of the older instruction
+    pub stage: usize,
+}
+
+
+/// Copy of all pipeline slots at the end of a clock-cycle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineRecord {
+    /// Clock-cycle the slots were recorded in
+    pub clock: u32,
+
+    /// Pipeline slots after all stages executed, but before they advanced to the next cycle
+    pub slots: [Slot; 5],
+
+    /// Instructions waiting in the fetch queue during that cycle, oldest first
+    pub queued: Vec<Slot>,
+}
+
+impl PipelineRecord {
+    /// Render the recorded slots for the gui, one line per stage and queued instruction
+    pub fn describe(&self) -> String {
+        let stage_names = ["Fetch ", "Decode", "Exec  ", "Mem   ", "WriteB"];
+        let mut text = format!("Cycle {}\n", self.clock);
+        for (i, (name, slot)) in stage_names.iter().zip(self.slots.iter()).enumerate() {
+            if i == 1 {
+                for queued in &self.queued {
+                    text.push_str(&format!("Queue   {:#010x}\n", queued.pc.0));
+                }
+            }
+            if let Some((pc, instr)) = slot.squashed {
+                text.push_str(&format!("{}  {:#010x}  {:<24} (squashed)\n", name, pc.0,
+                                       instr.to_string()));
+                continue;
+            }
+            if !slot.valid {
+                match slot.bubble {
+                    Some(bubble) => text.push_str(&format!("{}  -  (bubble: {})\n", name,
+                                                           bubble.describe())),
+                    None => text.push_str(&format!("{}  -\n", name)),
+                }
+                continue;
+            }
+            let stalled = if slot.disable || matches!(slot.mem_stall, Some(1..)) {
+                " (stalled)"
+            } else {
+                ""
+            };
+            text.push_str(&format!("{}  {:#010x}  {:<24}{}\n", name, slot.pc.0,
+                                   slot.instr.to_string(), stalled));
+        }
+        text
+    }
+}
+
+/// Stages of the pipeline diagram, `Q` is the fetch queue between fetch and decode
+pub const DIAGRAM_STAGES: [&str; 6] = ["F", "Q", "D", "E", "M", "W"];
+
+/// What an instruction did during a cycle of the pipeline diagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramCell {
+    /// Moved into the stage, indexes `DIAGRAM_STAGES`
+    Stage(usize),
+
+    /// Stayed in the stage it occupied during the previous cycle
+    Stall(usize),
+
+    /// Left the pipeline before writeback because it was flushed
+    Flushed,
+}
+
+impl DiagramCell {
+    /// Label displayed in the diagram, stalls are lowercase
+    pub fn label(&self) -> String {
+        match self {
+            DiagramCell::Stage(stage) => DIAGRAM_STAGES[*stage].to_string(),
+            DiagramCell::Stall(stage) => DIAGRAM_STAGES[*stage].to_lowercase(),
+            DiagramCell::Flushed      => "x".to_string(),
+        }
+    }
 }
 
+/// Row of the pipeline diagram, a single instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagramRow {
+    /// Fetch sequence number of the instruction, see `Slot::seq`
+    pub seq: u64,
+
+    pub pc: VAddr,
+
+    /// Only known once the instruction was decoded
+    pub instr: Option<Instr>,
+
+    /// What the instruction did during each recorded cycle, `None` while it wasn't in the pipeline
+    pub cells: Vec<Option<DiagramCell>>,
+}
+
+/// Ring buffer of the pipeline state during the most recent clock-cycles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineHistory {
+    /// Maximum number of cycles that are kept, older cycles are dropped
+    capacity: usize,
+
+    /// Recorded cycles, oldest first
+    records: VecDeque<PipelineRecord>,
+}
+
+impl PipelineHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Record the state of `pipeline` during clock-cycle `clock`, dropping the oldest cycle if the
+    /// history is full
+    pub fn push(&mut self, clock: u32, pipeline: &Pipeline) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(PipelineRecord {
+            clock,
+            slots:  pipeline.slots.clone(),
+            queued: pipeline.fetch_queue.iter().cloned().collect(),
+        });
+    }
+
+    /// Drop all cycles recorded at or after `clock`, after the simulator was rewound to it
+    pub fn rewind(&mut self, clock: u32) {
+        self.records.retain(|record| record.clock < clock);
+    }
+
+    /// Returns the `idx`th recorded cycle, oldest first
+    pub fn get(&self, idx: usize) -> Option<&PipelineRecord> {
+        self.records.get(idx)
+    }
+
+    /// Iterate over the recorded cycles, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &PipelineRecord> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Instruction/time diagram of the recorded cycles, a row per instruction in fetch order and a
+    /// column per cycle. Instructions that leave the pipeline before writeback are marked as
+    /// flushed in the cycle after they were last seen
+    pub fn diagram(&self) -> Vec<DiagramRow> {
+        let mut rows: Vec<DiagramRow> = Vec::new();
+        for (col, record) in self.records.iter().enumerate() {
+            let slots = std::iter::once((0, &record.slots[0]))
+                .chain(record.queued.iter().map(|slot| (1, slot)))
+                .chain(record.slots[1..].iter().enumerate().map(|(i, slot)| (i + 2, slot)))
+                .filter(|(_, slot)| slot.valid);
+            for (stage, slot) in slots {
+                let idx = match rows.iter().rposition(|row| row.seq == slot.seq) {
+                    Some(idx) => idx,
+                    None => {
+                        rows.push(DiagramRow {
+                            seq:   slot.seq,
+                            pc:    slot.pc,
+                            instr: None,
+                            cells: vec![None; self.records.len()],
+                        });
+                        rows.len() - 1
+                    },
+                };
+                let row = &mut rows[idx];
+                if stage >= 2 {
+                    row.instr = Some(slot.instr);
+                }
+                let stalled = col > 0 && matches!(row.cells[col - 1],
+                    Some(DiagramCell::Stage(prev) | DiagramCell::Stall(prev)) if prev == stage);
+                row.cells[col] = Some(if stalled {
+                    DiagramCell::Stall(stage)
+                } else {
+                    DiagramCell::Stage(stage)
+                });
+            }
+        }
+
+        for row in rows.iter_mut() {
+            let last = row.cells.iter().rposition(Option::is_some).unwrap();
+            let retired = matches!(row.cells[last],
+                Some(DiagramCell::Stage(stage) | DiagramCell::Stall(stage))
+                    if stage == DIAGRAM_STAGES.len() - 1);
+            if !retired && last + 1 < row.cells.len() {
+                row.cells[last + 1] = Some(DiagramCell::Flushed);
+            }
+        }
+        rows.sort_by_key(|row| row.seq);
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_most_recent_cycles() {
+        let mut history = PipelineHistory::new(3);
+        let mut pipeline = Pipeline::default();
+        for clock in 0..5 {
+            pipeline.slots[0].pc = VAddr(clock * 4);
+            pipeline.fetch_queue.push_back(Slot { valid: true, ..Default::default() });
+            history.push(clock, &pipeline);
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.iter().map(|r| r.clock).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(history.get(0).unwrap().slots[0].pc, VAddr(8));
+        assert_eq!(history.get(0).unwrap().queued.len(), 3);
+        assert!(history.get(3).is_none());
+    }
+
+    #[test]
+    fn describes_instructions_stalls_and_bubbles() {
+        let add = Instr::Add { rs3: Register::R1, rs1: Register::R2, rs2: Register::R3 };
+        let mut slots: [Slot; 5] = Default::default();
+        slots[1] = Slot { valid: true, pc: VAddr(0x104), instr: add, disable: true,
+                          ..Default::default() };
+        slots[2] = Slot::bubble(Bubble::DataHazard);
+        slots[3] = Slot { squashed: Some((VAddr(0x100), add)), ..Slot::bubble(Bubble::Flush) };
+        let queued = vec![Slot { valid: true, pc: VAddr(0x108), ..Default::default() }];
+
+        let text = PipelineRecord { clock: 7, slots, queued }.describe();
+        assert_eq!(text.lines().collect::<Vec<_>>(), [
+            "Cycle 7",
+            "Fetch   -",
+            "Queue   0x00000108",
+            &format!("Decode  0x00000104  {:<24} (stalled)", add.to_string()),
+            "Exec    -  (bubble: data hazard)",
+            &format!("Mem     0x00000100  {:<24} (squashed)", add.to_string()),
+            "WriteB  -",
+        ]);
+    }
+
+    /// `a` stalls in decode while `b` waits in the queue, then `c` is flushed from fetch
+    #[test]
+    fn diagram_shows_stalls_and_flushes() {
+        let add  = Instr::Add { rs3: Register::R1, rs1: Register::R2, rs2: Register::R3 };
+        let slot = |seq: u64| Slot { valid: true, pc: VAddr(seq as u32 * 4), instr: add, seq,
+                                     ..Default::default() };
+        let mut history  = PipelineHistory::new(8);
+        let mut pipeline = Pipeline::default();
+        pipeline.slots[0] = slot(0);
+        history.push(10, &pipeline);
+        pipeline.slots[1] = slot(0);
+        pipeline.slots[0] = slot(1);
+        history.push(11, &pipeline);
+        pipeline.fetch_queue.push_back(slot(1));
+        pipeline.slots[0] = slot(2);
+        history.push(12, &pipeline);
+        pipeline.fetch_queue.clear();
+        pipeline.slots[2] = slot(0);
+        pipeline.slots[1] = slot(1);
+        pipeline.slots[0] = Slot::bubble(Bubble::Flush);
+        history.push(13, &pipeline);
+
+        let diagram = history.diagram();
+        let labels = diagram.iter().map(|row| {
+            row.cells.iter().map(|cell| cell.map_or(".".to_string(), |cell| cell.label()))
+                .collect::<String>()
+        }).collect::<Vec<_>>();
+        assert_eq!(labels, ["FDdE", ".FQD", "..Fx"]);
+        assert_eq!(diagram.iter().map(|row| row.instr).collect::<Vec<_>>(),
+                   [Some(add), Some(add), None]);
+    }
+
+    #[test]
+    fn classifies_raw_waw_and_war() {
+        let older = Instr::Add { rs3: Register::R1, rs1: Register::R2, rs2: Register::R3 };
+
+        let raw = Instr::Addi { rs3: Register::R4, rs1: Register::R1, imm: 1 };
+        assert_eq!(HazardKind::classify(&raw, &older), vec![(HazardKind::Raw, Register::R1)]);
+
+        let waw_war = Instr::Sub { rs3: Register::R1, rs1: Register::R1, rs2: Register::R5 };
+        assert_eq!(HazardKind::classify(&waw_war, &older),
+                   vec![(HazardKind::Raw, Register::R1), (HazardKind::Waw, Register::R1)]);
+
+        let war = Instr::Lui { rs3: Register::R3, imm: 1 };
+        assert_eq!(HazardKind::classify(&war, &older), vec![(HazardKind::War, Register::R3)]);
+    }
+}
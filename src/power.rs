@@ -0,0 +1,160 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+/// Address of the power-management registers, next to the mouse
+pub const POWER_BASE: u32 = 0x20f0;
+
+/// Size of the power-management register block
+pub const POWER_SIZE: u32 = 0x8;
+
+/// Power management at `POWER_BASE`, lets the guest put the core to sleep when it has nothing to
+/// do. Its 32-bit registers are:
+///  - 0x0 Sleep: Writing `n` stops executing instructions for `n` clock-cycles
+///  - 0x4 Halt: Writing any value stops executing instructions until the next interrupt
+///
+/// Interrupts also end a sleep early. The core resumes after the store, or returns there from the
+/// interrupt handler. Slept cycles are counted in `Stats::sleep_clock`
+#[derive(Debug, Clone, Default)]
+pub struct PowerManager {
+    /// Duration of the last requested sleep
+    cycles: u32,
+}
+
+impl PowerManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MmioDevice for PowerManager {
+    fn name(&self) -> &str {
+        "power"
+    }
+
+    /// Writes to the sleep register can be partial, bytes that aren't written keep the value of
+    /// the last sleep
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let mut bytes = [self.cycles, 0].map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+
+        if offset >= 4 {
+            return Some(MmioAction::Sleep(None));
+        }
+        self.cycles = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        (self.cycles != 0).then_some(MmioAction::Sleep(Some(self.cycles)))
+    }
+
+    /// The sleep register reads as the duration of the last sleep, the halt register as 0
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        let bytes = [self.cycles, 0].map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        reader.copy_from_slice(&bytes[offset..offset + reader.len()]);
+    }
+
+    fn reset(&mut self) {
+        self.cycles = 0;
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::{Simulator, DEFAULT_STACK_BASE},
+        difftest::{MsgLog, run_program},
+        cpu::Register,
+    };
+
+    #[test]
+    fn writes_request_sleep() {
+        let mut power = PowerManager::new();
+        assert_eq!(power.write(0x0, &0x1234u32.to_le_bytes(), 0),
+                   Some(MmioAction::Sleep(Some(0x1234))));
+        assert_eq!(power.write(0x1, &[0x56], 0), Some(MmioAction::Sleep(Some(0x5634))));
+        assert_eq!(power.write(0x4, &[0], 0), Some(MmioAction::Sleep(None)));
+        let mut cycles = [0u8; 4];
+        power.read(0x0, &mut cycles);
+        assert_eq!(u32::from_le_bytes(cycles), 0x5634);
+
+        power.reset();
+        assert_eq!(power.write(0x0, &[0], 0), None);
+    }
+
+    /// Sleep for 0x1000 cycles, then check the clock
+    #[test]
+    fn sleep_stops_executing() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x20f0
+                movi r1 0x1000
+                st r1 r2 0x0
+                movi r3 0x2000
+                movi r1 0x42
+                st r1 r3 0x0
+                movi r1 0x41
+                st r1 r3 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 100_000, &|_| {});
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "pipelined: {}: {:?}", pipelined, run.log);
+            assert_eq!(run.sim.stats.sleep_clock, 0x1000, "pipelined: {}", pipelined);
+            assert_eq!(run.sim.read_reg(Register::R1), 0x41, "pipelined: {}", pipelined);
+            assert!(run.sim.clock > 0x1000, "pipelined: {}", pipelined);
+            assert!(run.sim.clock < 0x1400, "pipelined: {}", pipelined);
+        }
+    }
+
+    /// Halt until the timer fires, the handler returns to the instruction after the halt
+    #[test]
+    fn halt_waits_for_an_interrupt() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r5 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x8
+                movi r2 0x2010
+                movi r1 0x800
+                st r1 r2 0x4
+                movi r1 0x5
+                st r1 r2 0x8
+                movi r2 0x20f0
+                st r0 r2 0x4
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert_eq!(sim.read_reg(Register::R5), 1, "{}", mode);
+            assert!((0x700..0x800).contains(&sim.stats.sleep_clock), "{}: {}", mode,
+                    sim.stats.sleep_clock);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::mmu::VAddr;
+
+use serde::{Serialize, Deserialize};
+/// Number of entries in the branch target buffer
+pub const BTB_ENTRIES: usize = 64;
+
+/// Cycles lost on a mispredicted branch. The decode and fetch stages hold wrong-path instructions
+/// by the time the branch resolves in the execute stage, and both get squashed
+pub const MISPREDICT_PENALTY: usize = 2;
+
+/// Strategy used to predict conditional branches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PredictorKind {
+    /// No prediction, the front-end stalls until the branch is resolved
+    #[default]
+    Stall,
+
+    /// Always predict the fall-through path
+    NotTaken,
+
+    /// Predict backward branches (loops) as taken and forward branches as not taken
+    BackwardTaken,
+
+    /// 2-bit saturating counters kept in the branch target buffer
+    TwoBit,
+}
+
+impl PredictorKind {
+    /// Parse a predictor from its config representation: `stall`, `not-taken`, `backward-taken`
+    /// or `2-bit`
+    pub fn parse(config: &str) -> Option<Self> {
+        match config.trim() {
+            "stall"          => Some(PredictorKind::Stall),
+            "not-taken"      => Some(PredictorKind::NotTaken),
+            "backward-taken" => Some(PredictorKind::BackwardTaken),
+            "2-bit"          => Some(PredictorKind::TwoBit),
+            _ => None,
+        }
+    }
+
+    /// Short name displayed on the gui
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            PredictorKind::Stall         => "Off",
+            PredictorKind::NotTaken      => "NT",
+            PredictorKind::BackwardTaken => "BT",
+            PredictorKind::TwoBit        => "2b",
+        }
+    }
+
+    /// Predictor that follows this one when cycling through them on the gui
+    pub fn next(&self) -> Self {
+        match self {
+            PredictorKind::Stall         => PredictorKind::NotTaken,
+            PredictorKind::NotTaken      => PredictorKind::BackwardTaken,
+            PredictorKind::BackwardTaken => PredictorKind::TwoBit,
+            PredictorKind::TwoBit        => PredictorKind::Stall,
+        }
+    }
+}
+
+/// Branch target buffer entry
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BtbEntry {
+    /// Address of the branch instruction
+    pc: VAddr,
+
+    /// Address the branch jumps to if it is taken
+    target: VAddr,
+
+    /// 2-bit saturating counter, the branch is predicted taken if this is 2 or 3
+    counter: u8,
+}
+
+/// Predicts the outcome of conditional branches so the front-end can keep fetching while the
+/// branch is in flight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchPredictor {
+    /// Prediction strategy in use
+    pub kind: PredictorKind,
+
+    /// Direct-mapped branch target buffer indexed by the branch address
+    btb: Vec<Option<BtbEntry>>,
+}
+
+impl Default for BranchPredictor {
+    fn default() -> Self {
+        Self::new(PredictorKind::default())
+    }
+}
+
+impl BranchPredictor {
+    pub fn new(kind: PredictorKind) -> Self {
+        Self {
+            kind,
+            btb: vec![None; BTB_ENTRIES],
+        }
+    }
+
+    /// Index of `pc` in the branch target buffer
+    fn btb_index(pc: VAddr) -> usize {
+        (pc.0 as usize >> 2) % BTB_ENTRIES
+    }
+
+    /// Predict the address to fetch after the branch at `pc` that jumps to `target` if taken.
+    /// Returns `None` if the front-end should stall until the branch is resolved
+    pub fn predict(&self, pc: VAddr, target: VAddr) -> Option<VAddr> {
+        let fall_through = VAddr(pc.0 + 4);
+        match self.kind {
+            PredictorKind::Stall => None,
+            PredictorKind::NotTaken => Some(fall_through),
+            PredictorKind::BackwardTaken if target.0 <= pc.0 => Some(target),
+            PredictorKind::BackwardTaken => Some(fall_through),
+            PredictorKind::TwoBit => match self.btb[Self::btb_index(pc)] {
+                Some(entry) if entry.pc == pc && entry.counter >= 2 => Some(entry.target),
+                _ => Some(fall_through),
+            },
+        }
+    }
+
+    /// Train the predictor with the resolved outcome of the branch at `pc`. Taken branches that
+    /// miss in the branch target buffer are inserted as weakly taken
+    pub fn update(&mut self, pc: VAddr, target: VAddr, taken: bool) {
+        if self.kind != PredictorKind::TwoBit {
+            return;
+        }
+
+        let slot = &mut self.btb[Self::btb_index(pc)];
+        match slot {
+            Some(entry) if entry.pc == pc => {
+                entry.counter = if taken {
+                    std::cmp::min(entry.counter + 1, 3)
+                } else {
+                    entry.counter.saturating_sub(1)
+                };
+                entry.target = target;
+            },
+            _ if taken => *slot = Some(BtbEntry { pc, target, counter: 2 }),
+            _ => {},
+        }
+    }
+
+    /// Forget all learned branch history
+    pub fn clear(&mut self) {
+        self.btb.iter_mut().for_each(|entry| *entry = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_predictors() {
+        let pc = VAddr(0x1000);
+        let not_taken = BranchPredictor::new(PredictorKind::NotTaken);
+        assert_eq!(not_taken.predict(pc, VAddr(0xff0)), Some(VAddr(0x1004)));
+
+        let backward = BranchPredictor::new(PredictorKind::BackwardTaken);
+        assert_eq!(backward.predict(pc, VAddr(0xff0)), Some(VAddr(0xff0)));
+        assert_eq!(backward.predict(pc, VAddr(0x1010)), Some(VAddr(0x1004)));
+
+        assert_eq!(BranchPredictor::default().predict(pc, VAddr(0xff0)), None);
+    }
+
+    #[test]
+    fn two_bit_counters_need_two_mispredicts_to_flip() {
+        let (pc, target) = (VAddr(0x1000), VAddr(0x2000));
+        let mut predictor = BranchPredictor::new(PredictorKind::TwoBit);
+
+        // Unknown branches are predicted not taken, and get inserted once they are taken
+        assert_eq!(predictor.predict(pc, target), Some(VAddr(0x1004)));
+        predictor.update(pc, target, true);
+        predictor.update(pc, target, true);
+        assert_eq!(predictor.predict(pc, target), Some(target));
+
+        predictor.update(pc, target, false);
+        assert_eq!(predictor.predict(pc, target), Some(target));
+        predictor.update(pc, target, false);
+        assert_eq!(predictor.predict(pc, target), Some(VAddr(0x1004)));
+
+        // Aliasing branches don't use each other's history
+        let alias = VAddr(pc.0 + (BTB_ENTRIES * 4) as u32);
+        assert_eq!(predictor.predict(alias, target), Some(VAddr(alias.0 + 4)));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    simulator::Simulator,
+    mmu::VAddr,
+};
+
+use rustc_hash::FxHashMap;
+use serde::{Serialize, Deserialize};
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// File the full profile is exported to unless configured otherwise
+pub const DEFAULT_PROFILE_PATH: &str = "profile.csv";
+
+/// Number of addresses and functions listed in the report
+pub const REPORT_LEN: usize = 10;
+
+/// Number of times each instruction retired, by pc
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    counts: FxHashMap<u32, u64>,
+
+    /// Sum of all counts
+    total: u64,
+}
+
+impl Profile {
+    /// Count a retired instruction at `pc`
+    pub fn record(&mut self, pc: VAddr) {
+        *self.counts.entry(pc.0).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+    }
+
+    /// Number of instructions counted
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// All addresses with their counts, most executed first
+    pub fn hot_addrs(&self) -> Vec<(VAddr, u64)> {
+        let mut addrs = self.counts.iter().map(|(&pc, &count)| (VAddr(pc), count))
+            .collect::<Vec<_>>();
+        addrs.sort_by_key(|&(pc, count)| (std::cmp::Reverse(count), pc.0));
+        addrs
+    }
+
+    /// Counts summed up per function, most executed first. `functions` holds the start address
+    /// of each function, addresses below the first one are listed as `?`
+    pub fn hot_functions(&self, functions: &BTreeMap<u32, String>) -> Vec<(String, u64)> {
+        let mut sums: BTreeMap<&str, u64> = BTreeMap::new();
+        for (&pc, &count) in self.counts.iter() {
+            let name = functions.range(..=pc).next_back().map_or("?", |(_, name)| name);
+            *sums.entry(name).or_insert(0) += count;
+        }
+        let mut sums = sums.into_iter().map(|(name, count)| (name.to_string(), count))
+            .collect::<Vec<_>>();
+        sums.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        sums
+    }
+}
+
+/// Table of the `REPORT_LEN` most executed addresses and functions, with their share of all
+/// counted instructions
+pub fn report(sim: &mut Simulator, profile: &Profile) -> String {
+    let ratio = |count: u64| count as f64 / profile.total().max(1) as f64 * 100.0;
+
+    let mut text = format!("Top addresses ({} instructions)\n", profile.total());
+    for (pc, count) in profile.hot_addrs().into_iter().take(REPORT_LEN) {
+        let instr = sim.gui_decode_instr(pc).map_or(String::from("?"), |instr| instr.to_string());
+        let _ = writeln!(text, "  {:#010x} {:<16} {:<20} {:>10} {:>6.2}%", pc.0,
+                         sim.symbolize(pc), instr, count, ratio(count));
+    }
+    text.push_str("\nTop functions\n");
+    for (name, count) in profile.hot_functions(&sim.functions).into_iter().take(REPORT_LEN) {
+        let _ = writeln!(text, "  {:<16} {:>10} {:>6.2}%", name, count, ratio(count));
+    }
+    text
+}
+
+/// Write the count of every address to `path` as csv, most executed first
+pub fn export(sim: &Simulator, profile: &Profile, path: &Path) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    writeln!(file, "pc,symbol,count")?;
+    for (pc, count) in profile.hot_addrs() {
+        writeln!(file, "{:#x},{},{}", pc.0, sim.symbolize(pc), count)?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftest::run_program;
+
+    #[test]
+    fn counts_hot_addresses_and_functions() {
+        let program = "
+            .entry _start
+            .load 0x20000
+            .square
+                mul r1 r1 r1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                movi r4 0x0
+                movi r5 0x5
+            .loop
+                mov r1 r4
+                call 0x20000
+                addi r4 r4 0x1
+                blt r4 r5 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let mut run = run_program(program, true, 10_000, &|sim| {
+            sim.profile = Some(Profile::default());
+        });
+        let profile = run.sim.profile.clone().unwrap();
+
+        // The final store shuts the simulator down before it retires
+        assert_eq!(profile.total(), 2 + 5 * 6 + 2);
+        assert_eq!(profile.hot_addrs()[0], (VAddr(0x10008), 5));
+        assert_eq!(profile.hot_functions(&run.sim.functions),
+                   [("_start".to_string(), 24), ("square".to_string(), 10)]);
+
+        let report = report(&mut run.sim, &profile);
+        assert!(report.contains("0x00010008 loop             add r1 r4 r0"), "{}", report);
+
+        let path = std::env::temp_dir().join(format!("seal_profile_{}.csv", std::process::id()));
+        export(&run.sim, &profile, &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 10);
+        assert_eq!(csv.lines().nth(1), Some("0x10008,loop,5"));
+    }
+}
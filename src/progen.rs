@@ -0,0 +1,253 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use std::fmt::Write;
+
+/// Load address of the first generated function, the others follow `FUNC_STRIDE` bytes apart
+const FUNC_BASE: u32 = 0x20000;
+
+/// Distance between generated functions, large enough for any function body
+const FUNC_STRIDE: u32 = 0x1000;
+
+/// Size of the stack buffer that generated loads and stores access through `r13`
+const BUF_SIZE: u32 = 0x100;
+
+/// Registers generated instructions compute with. `r12` is reserved for loop counters, `r13`
+/// holds the buffer address and `r14`/`r15` are only changed by calls
+const DATA_REGS: [&str; 11] = ["r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11"];
+
+/// Limits for generated programs
+#[derive(Debug, Clone, Copy)]
+pub struct GenConfig {
+    /// Number of blocks in the main function. A block is straight-line code, a forward branch or
+    /// jump, or a counted loop
+    pub blocks: usize,
+
+    /// Maximum number of instructions in a straight-line run
+    pub block_len: usize,
+
+    /// Number of functions main and other functions can call. Functions only call functions
+    /// with a higher index, so there is no recursion
+    pub functions: usize,
+
+    /// Maximum number of iterations of a loop
+    pub max_iters: u32,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        Self {
+            blocks:    12,
+            block_len: 6,
+            functions: 3,
+            max_iters: 8,
+        }
+    }
+}
+
+/// Generate a random program from `seed`, in the assembler's syntax. Programs only use valid
+/// registers, access memory through a mapped stack buffer, divide by non-zero values, only branch
+/// forward or back to the start of a counted loop, and exit through the command-port. They need
+/// the default memory layout
+pub fn generate_program(seed: u64, config: &GenConfig) -> String {
+    let mut gen = Generator { rng: ChaCha8Rng::seed_from_u64(seed), out: String::new(),
+                              labels: 0, config: *config };
+    gen.line(".entry _start");
+
+    for func in 0..config.functions {
+        gen.line(&format!(".load {:#x}", FUNC_BASE + func as u32 * FUNC_STRIDE));
+        gen.line(&format!(".func{}", func));
+        for _ in 0..gen.rng.gen_range(1..=3) {
+            gen.block(func + 1, false);
+        }
+        gen.line("ret");
+        gen.line(".end_section");
+    }
+
+    gen.line(".load 0x10000");
+    gen.line("._start");
+    gen.line(&format!("subi r15 r15 {:#x}", BUF_SIZE));
+    gen.line("mov r13 r15");
+    for reg in DATA_REGS {
+        let imm = gen.imm();
+        gen.line(&format!("movi {} {:#x}", reg, imm));
+    }
+    for _ in 0..config.blocks {
+        gen.block(0, true);
+    }
+    gen.line(&format!("addi r15 r15 {:#x}", BUF_SIZE));
+    gen.line("movi r1 0x41");
+    gen.line("movi r2 0x2000");
+    gen.line("st r1 r2 0x0");
+    gen.line(".end_section");
+    gen.out
+}
+
+/// State while generating a single program
+struct Generator {
+    rng:    ChaCha8Rng,
+    out:    String,
+    labels: usize,
+    config: GenConfig,
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    fn reg(&mut self) -> &'static str {
+        DATA_REGS[self.rng.gen_range(0..DATA_REGS.len())]
+    }
+
+    /// Source register, occasionally the zero register
+    fn src(&mut self) -> &'static str {
+        if self.rng.gen_ratio(1, 10) { "r0" } else { self.reg() }
+    }
+
+    /// Immediate, biased towards small values and the edges of the 16-bit range
+    fn imm(&mut self) -> u32 {
+        match self.rng.gen_range(0..4) {
+            0 => self.rng.gen_range(0..0x10),
+            1 => self.rng.gen_range(0xfff0..=0xffff),
+            _ => self.rng.gen_range(0..=0xffff),
+        }
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!(".{}{}", kind, self.labels)
+    }
+
+    /// Emit a block. Calls go to functions from index `first_callee` on. Loops are only
+    /// generated if `loops` is set, they are never nested
+    fn block(&mut self, first_callee: usize, loops: bool) {
+        match self.rng.gen_range(0..10) {
+            0 | 1 => {
+                let skip = self.label("skip");
+                let cond = ["bne", "beq", "blt", "bgt"][self.rng.gen_range(0..4)];
+                let (a, b) = (self.src(), self.src());
+                self.line(&format!("{} {} {} {}", cond, a, b, skip));
+                self.straight(first_callee);
+                self.line(&skip);
+            },
+            2 => {
+                let skip = self.label("skip");
+                self.line(&format!("jmpr {}", skip));
+                self.straight(first_callee);
+                self.line(&skip);
+            },
+            3 if loops => {
+                let head = self.label("loop");
+                let iters = self.rng.gen_range(1..=self.config.max_iters);
+                self.line(&format!("movi r12 {:#x}", iters));
+                self.line(&head);
+                self.straight(first_callee);
+                if self.rng.gen() {
+                    let skip = self.label("skip");
+                    let (a, b) = (self.src(), self.src());
+                    self.line(&format!("beq {} {} {}", a, b, skip));
+                    self.straight(first_callee);
+                    self.line(&skip);
+                }
+                self.line("subi r12 r12 0x1");
+                self.line(&format!("bne r12 r0 {}", head));
+            },
+            _ => self.straight(first_callee),
+        }
+    }
+
+    /// Emit up to `block_len` instructions without control flow, apart from calls
+    fn straight(&mut self, first_callee: usize) {
+        for _ in 0..self.rng.gen_range(1..=self.config.block_len) {
+            self.instr(first_callee);
+        }
+    }
+
+    fn instr(&mut self, first_callee: usize) {
+        let line = match self.rng.gen_range(0..16) {
+            0..=4 => {
+                let op = ["add", "sub", "xor", "or", "and", "shr", "shl", "mul"]
+                    [self.rng.gen_range(0..8)];
+                format!("{} {} {} {}", op, self.reg(), self.src(), self.src())
+            },
+            5 => {
+                // The divisor is forced to be odd, which also makes it non-zero
+                let (dst, src, divisor) = (self.reg(), self.src(), self.src());
+                self.line(&format!("ori {} {} 0x1", dst, divisor));
+                format!("div {} {} {}", dst, src, dst)
+            },
+            6..=8 => {
+                let op = ["addi", "subi", "xori", "ori", "andi"][self.rng.gen_range(0..5)];
+                let imm = self.imm();
+                format!("{} {} {} {:#x}", op, self.reg(), self.src(), imm)
+            },
+            9 => {
+                let imm = self.imm();
+                format!("lui {} {:#x}", self.reg(), imm)
+            },
+            10..=11 => {
+                let (op, size) = [("ldb", 1), ("ldh", 2), ("ld", 4)][self.rng.gen_range(0..3)];
+                format!("{} {} r13 {:#x}", op, self.reg(), self.offset(size))
+            },
+            12..=13 => {
+                let (op, size) = [("stb", 1), ("sth", 2), ("st", 4), ("stnt", 4)]
+                    [self.rng.gen_range(0..4)];
+                format!("{} {} r13 {:#x}", op, self.src(), self.offset(size))
+            },
+            _ if first_callee < self.config.functions => {
+                let func = self.rng.gen_range(first_callee..self.config.functions);
+                format!("call {:#x}", FUNC_BASE + func as u32 * FUNC_STRIDE)
+            },
+            _ => format!("mov {} {}", self.reg(), self.src()),
+        };
+        self.line(&line);
+    }
+
+    /// Aligned offset of a `size` byte access into the buffer
+    fn offset(&mut self, size: u32) -> u32 {
+        self.rng.gen_range(0..BUF_SIZE / size) * size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        difftest::run_program,
+        golden::GoldenModel,
+        predictor::{BranchPredictor, PredictorKind},
+    };
+
+    #[test]
+    fn programs_follow_the_seed() {
+        let config = GenConfig::default();
+        assert_eq!(generate_program(3, &config), generate_program(3, &config));
+        assert_ne!(generate_program(3, &config), generate_program(4, &config));
+    }
+
+    /// Run generated programs in lockstep with the golden model, cycling through the execution
+    /// modes and hazard-handling options
+    #[test]
+    fn generated_programs_match_the_golden_model() {
+        let config = GenConfig::default();
+        let modes  = [(false, false), (true, false), (true, true)];
+        for seed in 0..2_000 {
+            let program          = generate_program(seed, &config);
+            let (pipelined, ooo) = modes[seed as usize % modes.len()];
+            let forwarding       = seed % 2 == 1;
+            let nonblocking      = seed % 5 < 2;
+            let run = run_program(&program, pipelined, 1_000_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.forwarding_enabled = forwarding;
+                sim.nonblocking_cache  = nonblocking;
+                sim.pipeline.predictor = BranchPredictor::new(PredictorKind::TwoBit);
+                sim.golden = Some(GoldenModel::new(sim));
+            });
+            let mode = format!("seed {}, pipelined: {}, ooo: {}, forwarding: {}, nonblocking: {}",
+                               seed, pipelined, ooo, forwarding, nonblocking);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}\n{}", mode, run.log, program);
+            assert!(!run.sim.online, "{} did not finish\n{}", mode, program);
+        }
+    }
+}
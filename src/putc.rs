@@ -0,0 +1,128 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Address of the putc port, next to power management
+pub const PUTC_BASE: u32 = 0x20f8;
+
+/// Size of the putc port's register block
+pub const PUTC_SIZE: u32 = 0x4;
+
+/// State shared between all clones of a `PutcPort`
+#[derive(Debug, Default)]
+struct PutcState {
+    /// Bytes the guest printed, oldest first
+    output: Vec<u8>,
+
+    /// Print the bytes to stderr as well
+    echo: bool,
+}
+
+/// Debug output port at `PUTC_BASE`. Storing to it appends the low byte to a host-side log, so
+/// guests can print without managing a cursor on the screen or polling the uart. Reads return 0.
+/// Clones share the same output
+#[derive(Clone, Debug, Default)]
+pub struct PutcPort {
+    state: Rc<RefCell<PutcState>>,
+}
+
+impl PutcPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Port that also prints the bytes to stderr, for runs without the gui
+    pub fn stderr() -> Self {
+        let port = Self::new();
+        port.state.borrow_mut().echo = true;
+        port
+    }
+
+    /// Everything the guest printed so far
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.state.borrow().output).into_owned()
+    }
+}
+
+impl MmioDevice for PutcPort {
+    fn name(&self) -> &str {
+        "putc"
+    }
+
+    /// Only the byte at offset 0 is printed, so `stb` and `st` both print a single character
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        if offset != 0 {
+            return None;
+        }
+        let mut state = self.state.borrow_mut();
+        state.output.push(data[0]);
+        if state.echo {
+            let mut stderr = std::io::stderr();
+            let _ = stderr.write_all(&data[..1]);
+            let _ = stderr.flush();
+        }
+        None
+    }
+
+    fn read(&mut self, _offset: u32, reader: &mut [u8]) {
+        reader.fill(0);
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmu::VAddr};
+
+    #[test]
+    fn stores_append_their_low_byte() {
+        let mut port = PutcPort::new();
+        port.write(0x0, b"hxyz", 0);
+        port.write(0x1, b"!", 0);
+        port.write(0x0, b"i", 0);
+        assert_eq!(port.output(), "hi");
+
+        // The output is a log and survives resets
+        port.reset();
+        let mut reader = [1u8; 4];
+        port.read(0x0, &mut reader);
+        assert_eq!((port.output().as_str(), reader), ("hi", [0; 4]));
+    }
+
+    /// Print a line a character at a time, with both byte and word stores
+    #[test]
+    fn program_prints_a_line() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x20f8
+                movi r1 0x4f
+                stb r1 r2 0x0
+                movi r1 0x6b
+                st r1 r2 0x0
+                movi r1 0xa
+                stb r1 r2 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let port = PutcPort::new();
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.devices.register(VAddr(PUTC_BASE), PUTC_SIZE, Box::new(port.clone())).unwrap();
+                sim.start_golden();
+            });
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "pipelined: {}: {:?}", pipelined, run.log);
+            assert_eq!(port.output(), "Ok\n", "pipelined: {}", pipelined);
+        }
+    }
+}
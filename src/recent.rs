@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of programs kept in the recent-programs list
+pub const RECENT_CAPACITY: usize = 10;
+
+/// File the recent-programs list is kept in, inside of `config_dir`
+const RECENT_FILE: &str = "recent_programs";
+
+/// Directory the gui keeps its settings in, `seal_isa` inside of `XDG_CONFIG_HOME`, `~/.config`
+/// or `APPDATA`. `None` if none of them are set
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("seal_isa"))
+}
+
+/// Programs recently opened or saved on the gui, most recent first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    /// File the list is kept in by default, see `config_dir`
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(RECENT_FILE))
+    }
+
+    /// Read the list from `path`, one program per line. A missing file is an empty list
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut recent = Self::default();
+        for line in text.lines().rev().filter(|line| !line.trim().is_empty()) {
+            recent.push(Path::new(line));
+        }
+        Ok(recent)
+    }
+
+    /// Write the list to `path`, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lines = self.paths.iter().map(|path| format!("{}\n", path.display()));
+        fs::write(path, lines.collect::<String>())
+    }
+
+    /// Move `path` to the front of the list, the oldest program is dropped once more than
+    /// `RECENT_CAPACITY` are listed. Paths are made absolute so they still resolve from other
+    /// working directories
+    pub fn push(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.paths.retain(|known| *known != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(RECENT_CAPACITY);
+    }
+
+    /// Programs, most recent first
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushed_programs_move_to_the_front() {
+        let mut recent = RecentFiles::default();
+        for idx in 0..RECENT_CAPACITY + 2 {
+            recent.push(Path::new(&format!("/nonexistent/prog{}", idx)));
+        }
+        recent.push(Path::new("/nonexistent/prog5"));
+
+        assert_eq!(recent.paths().len(), RECENT_CAPACITY);
+        assert_eq!(recent.paths()[0], Path::new("/nonexistent/prog5"));
+        assert_eq!(recent.paths()[1], Path::new(&format!("/nonexistent/prog{}",
+                                                         RECENT_CAPACITY + 1)));
+        assert!(!recent.paths().contains(&PathBuf::from("/nonexistent/prog1")));
+    }
+
+    #[test]
+    fn lists_survive_saving_and_loading() {
+        let dir = std::env::temp_dir().join(format!("seal_recent_{}", std::process::id()));
+        let path = dir.join(RECENT_FILE);
+        assert_eq!(RecentFiles::load(&path).unwrap(), RecentFiles::default());
+
+        let mut recent = RecentFiles::default();
+        recent.push(Path::new("/nonexistent/a"));
+        recent.push(Path::new("/nonexistent/b"));
+        recent.save(&path).unwrap();
+        let loaded = RecentFiles::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap(), recent);
+    }
+}
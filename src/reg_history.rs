@@ -0,0 +1,146 @@
+use crate::{
+    mmu::VAddr,
+    cpu::{Instr, Register, NUM_REGS},
+};
+
+use serde::{Serialize, Deserialize};
+
+use std::collections::VecDeque;
+
+/// Number of writes kept per register unless configured otherwise
+pub const DEFAULT_REG_HISTORY_DEPTH: usize = 8;
+
+/// Write of a retired instruction to a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegWrite {
+    /// Clock-cycle the instruction retired in
+    pub cycle: u32,
+
+    /// Address of the instruction
+    pub pc: VAddr,
+
+    pub old: u32,
+
+    pub new: u32,
+}
+
+/// The most recent writes to each register, recorded as instructions retire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegHistory {
+    /// Maximum number of writes kept per register, older writes are dropped
+    depth: usize,
+
+    /// Writes to each register, oldest first
+    writes: Vec<VecDeque<RegWrite>>,
+
+    /// Register values as of the most recently retired write to each of them. Call and return
+    /// update the stack pointer before they retire, so the register file can be ahead of this
+    regs: [u32; NUM_REGS],
+}
+
+impl RegHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            writes: vec![VecDeque::new(); NUM_REGS],
+            regs:   [0; NUM_REGS],
+        }
+    }
+
+    /// Forget all writes and continue from `regs`, after the registers were changed from outside
+    /// the running program
+    pub fn reset(&mut self, regs: [u32; NUM_REGS]) {
+        self.writes.iter_mut().for_each(VecDeque::clear);
+        self.regs = regs;
+    }
+
+    /// Record the registers `instr` at `pc` wrote when it retired during `cycle`, now that they
+    /// hold `regs`. Stores only count if an mmio command changed `rs3`
+    pub fn record(&mut self, cycle: u32, pc: VAddr, instr: Instr, regs: &[u32; NUM_REGS]) {
+        let is_store = matches!(instr, Instr::Stb { .. } | Instr::Sth { .. } | Instr::St { .. } |
+                                       Instr::Stnt { .. });
+        for reg in instr.writes_to_rs3() {
+            let (old, new) = (self.regs[reg as usize], regs[reg as usize]);
+            if reg == Register::R0 || (is_store && old == new) {
+                continue;
+            }
+            let writes = &mut self.writes[reg as usize];
+            if writes.len() == self.depth {
+                writes.pop_front();
+            }
+            if self.depth > 0 {
+                writes.push_back(RegWrite { cycle, pc, old, new });
+            }
+            self.regs[reg as usize] = new;
+        }
+    }
+
+    /// Drop the writes of cycles at or after `clock`, after the simulator was rewound to it
+    pub fn rewind(&mut self, clock: u32) {
+        for (reg, writes) in self.writes.iter_mut().enumerate() {
+            while let Some(write) = writes.back().filter(|write| write.cycle >= clock) {
+                self.regs[reg] = write.old;
+                writes.pop_back();
+            }
+        }
+    }
+
+    /// Recorded writes to `reg`, most recent first
+    pub fn writes(&self, reg: Register) -> impl Iterator<Item = &RegWrite> {
+        self.writes[reg as usize].iter().rev()
+    }
+
+    /// Whether an instruction that retired during `cycle` wrote `reg`
+    pub fn written_in(&self, reg: Register, cycle: u32) -> bool {
+        self.writes(reg).next().is_some_and(|write| write.cycle == cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftest::run_program;
+
+    #[test]
+    fn records_writes_per_register() {
+        let program = "
+            .entry _start
+            .load 0x20000
+            .double
+                add r1 r1 r1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                movi r1 0x3
+                call 0x20000
+                call 0x20000
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 10_000, &|sim| {
+                sim.reg_history = Some(RegHistory::new(3));
+            });
+            let history = run.sim.reg_history.as_ref().unwrap();
+
+            let r1 = history.writes(Register::R1).map(|w| (w.pc.0, w.old, w.new))
+                .collect::<Vec<_>>();
+            assert_eq!(r1, [(0x1000c, 0xc, 0x41), (0x20000, 0x6, 0xc), (0x20000, 0x3, 0x6)]);
+
+            // Each call and return moves the stack pointer
+            let r15 = history.writes(Register::R15).map(|w| w.pc.0).collect::<Vec<_>>();
+            assert_eq!(r15, [0x20004, 0x10008, 0x20004], "pipelined: {}", pipelined);
+            let last = history.writes(Register::R2).next().unwrap();
+            assert!(history.written_in(Register::R2, last.cycle));
+            assert!(!history.written_in(Register::R1, last.cycle));
+
+            let mut history = history.clone();
+            history.rewind(last.cycle);
+            assert_eq!(history.writes(Register::R2).count(), 0);
+            assert_eq!(history.regs[Register::R2 as usize], 0);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    mmu::VAddr,
+    cpu::Instr,
+};
+
+use std::collections::VecDeque;
+
+/// Number of retired instructions kept unless configured otherwise
+pub const DEFAULT_RETIRE_LOG_DEPTH: usize = 256;
+
+/// Instruction that completed writeback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetiredInstr {
+    /// Clock-cycle the instruction retired in
+    pub cycle: u32,
+
+    /// Address of the instruction
+    pub pc: VAddr,
+
+    pub instr: Instr,
+}
+
+/// The most recently retired instructions, to look back at what executed before a stop
+#[derive(Debug, Clone)]
+pub struct RetireLog {
+    /// Maximum number of instructions kept, older ones are dropped
+    depth: usize,
+
+    /// Retired instructions, oldest first
+    entries: VecDeque<RetiredInstr>,
+}
+
+impl RetireLog {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            entries: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// Record that `instr` at `pc` retired during `cycle`
+    pub fn record(&mut self, cycle: u32, pc: VAddr, instr: Instr) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(RetiredInstr { cycle, pc, instr });
+    }
+
+    /// Forget all recorded instructions
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop the instructions retired at or after `clock`, after the simulator was rewound to it
+    pub fn rewind(&mut self, clock: u32) {
+        while self.entries.back().is_some_and(|entry| entry.cycle >= clock) {
+            self.entries.pop_back();
+        }
+    }
+
+    /// Recorded instructions, most recent first
+    pub fn recent(&self) -> impl Iterator<Item = &RetiredInstr> {
+        self.entries.iter().rev()
+    }
+
+    /// Recorded instructions that retired during or after `cycle`, most recent first. Eg.
+    /// `since(clock - 200)` lists what executed in the last 200 cycles
+    pub fn since(&self, cycle: u32) -> impl Iterator<Item = &RetiredInstr> {
+        self.recent().take_while(move |entry| entry.cycle >= cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Register, difftest::run_program};
+
+    #[test]
+    fn keeps_the_most_recent_instructions() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r6 0x3
+            .loop
+                subi r6 r6 0x1
+                bne r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 10_000, &|sim| {
+                sim.retire_log = Some(RetireLog::new(4));
+            });
+            let log = run.sim.retire_log.as_ref().unwrap();
+
+            // The exit request only completes writeback without the pipeline
+            let newest = log.recent().find(|entry| entry.pc != VAddr(0x10014)).unwrap();
+            assert_eq!(newest.instr, Instr::Addi { rs3: Register::R2, rs1: Register::R0,
+                                                   imm: 0x2000 });
+            let pcs = log.recent().map(|entry| entry.pc.0).filter(|&pc| pc != 0x10014)
+                .collect::<Vec<_>>();
+            assert!(pcs.starts_with(&[0x10010, 0x1000c, 0x10008]), "pipelined: {}", pipelined);
+            assert_eq!(log.since(newest.cycle).last(), Some(newest));
+            assert_eq!(log.since(0).count(), 4);
+            assert!(log.recent().all(|entry| entry.cycle <= run.sim.clock));
+
+            let mut log = log.clone();
+            log.rewind(newest.cycle);
+            assert_eq!(log.recent().next().unwrap().pc, VAddr(0x1000c));
+        }
+    }
+}
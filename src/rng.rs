@@ -0,0 +1,123 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+/// Address of the random number generator's register, next to the gpio
+pub const RNG_BASE: u32 = 0x20a0;
+
+/// Size of the random number generator's register block
+pub const RNG_SIZE: u32 = 0x4;
+
+/// Random number generator at `RNG_BASE`, a read-only 32-bit register that holds a new random
+/// number every clock-cycle. Guests load it instead of writing 0x43 to the command-port.
+///
+/// The number is derived from the simulator's seed and the clock-counter, so reads have no side
+/// effects and reproduce with the seed. Two reads in the same cycle return the same number
+#[derive(Debug, Clone, Default)]
+pub struct RandomSource {
+    seed:  u64,
+    clock: u32,
+}
+
+impl RandomSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number the register holds in the current cycle
+    pub fn value(&self) -> u32 {
+        // splitmix64 of the seed advanced by the clock-counter
+        let step  = (self.clock as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15);
+        let mut z = self.seed.wrapping_add(step);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) as u32
+    }
+}
+
+impl MmioDevice for RandomSource {
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    /// The register is read-only
+    fn write(&mut self, _offset: u32, _data: &[u8], _clock: u32) -> Option<MmioAction> {
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        let bytes = self.value().to_le_bytes();
+        let offset = offset as usize;
+        reader.copy_from_slice(&bytes[offset..offset + reader.len()]);
+    }
+
+    fn tick(&mut self, clock: u32) -> Vec<MmioAction> {
+        self.clock = clock;
+        Vec::new()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, cpu::Register};
+
+    #[test]
+    fn numbers_follow_the_seed_and_clock() {
+        let mut rng = RandomSource::new();
+        rng.reseed(5);
+        rng.tick(10);
+        let mut first = [0u8; 4];
+        rng.read(0, &mut first);
+
+        // Reads and writes don't change the number, the next cycle does
+        let mut again = [0u8; 4];
+        rng.write(0, &[1, 2, 3, 4], 10);
+        rng.read(0, &mut again);
+        assert_eq!(first, again);
+        rng.tick(11);
+        assert_ne!(rng.value(), u32::from_le_bytes(first));
+
+        let mut other = RandomSource::new();
+        other.reseed(5);
+        other.tick(10);
+        assert_eq!(other.value(), u32::from_le_bytes(first));
+        other.reseed(6);
+        assert_ne!(other.value(), u32::from_le_bytes(first));
+    }
+
+    /// Load two numbers from the register, without touching any other register
+    #[test]
+    fn loads_return_random_numbers() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x20a0
+                ld r3 r2 0x0
+                ld r4 r2 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 100_000, &|sim| sim.set_seed(9));
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "pipelined: {}: {:?}", pipelined, run.log);
+            let (r3, r4) = (run.sim.read_reg(Register::R3), run.sim.read_reg(Register::R4));
+            assert_ne!(r3, r4, "pipelined: {}", pipelined);
+
+            // The same seed gives the same numbers
+            let rerun = run_program(program, pipelined, 100_000, &|sim| sim.set_seed(9));
+            assert_eq!((rerun.sim.read_reg(Register::R3), rerun.sim.read_reg(Register::R4)),
+                       (r3, r4), "pipelined: {}", pipelined);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Address of the seven-segment display's registers, next to the semihosting interface
+pub const SEGMENT_BASE: u32 = 0x20d0;
+
+/// Number of digits on the display, the leftmost one is digit 0
+pub const SEGMENT_DIGITS: usize = 4;
+
+/// Size of the display's register block, a register per digit
+pub const SEGMENT_SIZE: u32 = SEGMENT_DIGITS as u32 * 4;
+
+/// Digit bit that lights the decimal point to the right of the digit
+pub const SEGMENT_DOT: u32 = 1 << 4;
+
+/// Digit bit that turns the digit off, set on power-on
+pub const SEGMENT_BLANK: u32 = 1 << 5;
+
+/// Segments lit for each hex digit, bit 0 is segment `a` (top) through bit 6 for `g` (middle)
+const HEX_SEGMENTS: [u8; 16] = [
+    0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f, 0x77, 0x7c, 0x39, 0x5e, 0x79, 0x71,
+];
+
+/// Segments lit by the digit register `value`, bits 0 to 6 are `a` to `g` and bit 7 is the
+/// decimal point
+pub fn segments(value: u32) -> u8 {
+    if value & SEGMENT_BLANK != 0 {
+        return 0;
+    }
+    let dot = if value & SEGMENT_DOT != 0 { 0x80 } else { 0 };
+    HEX_SEGMENTS[(value & 0xf) as usize] | dot
+}
+
+/// Hex seven-segment display at `SEGMENT_BASE`. Register `n` is a 32-bit register that controls
+/// digit `n`: bits 0-3 select the hex digit shown, `SEGMENT_DOT` lights its decimal point and
+/// `SEGMENT_BLANK` turns it off. Clones share the same digits
+#[derive(Clone, Debug)]
+pub struct SevenSegment {
+    digits: Rc<RefCell<[u32; SEGMENT_DIGITS]>>,
+}
+
+impl Default for SevenSegment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SevenSegment {
+    /// Display with every digit blank
+    pub fn new() -> Self {
+        Self { digits: Rc::new(RefCell::new([SEGMENT_BLANK; SEGMENT_DIGITS])) }
+    }
+
+    /// Current value of every digit register, leftmost digit first
+    pub fn digits(&self) -> [u32; SEGMENT_DIGITS] {
+        *self.digits.borrow()
+    }
+
+    /// Display as text, blank digits are spaces and lit decimal points follow their digit
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for value in self.digits() {
+            text.push(match value & SEGMENT_BLANK {
+                0 => "0123456789AbCdEF".as_bytes()[(value & 0xf) as usize] as char,
+                _ => ' ',
+            });
+            if segments(value) & 0x80 != 0 {
+                text.push('.');
+            }
+        }
+        text
+    }
+}
+
+impl MmioDevice for SevenSegment {
+    fn name(&self) -> &str {
+        "seven-segment"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let mut bytes = self.digits().map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+        for (digit, value) in self.digits.borrow_mut().iter_mut().enumerate() {
+            *value = u32::from_le_bytes(bytes[digit * 4..digit * 4 + 4].try_into().unwrap());
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        let bytes = self.digits().map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        reader.copy_from_slice(&bytes[offset..offset + reader.len()]);
+    }
+
+    fn reset(&mut self) {
+        *self.digits.borrow_mut() = [SEGMENT_BLANK; SEGMENT_DIGITS];
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmu::VAddr};
+
+    #[test]
+    fn digits_show_their_low_nibble() {
+        let mut display = SevenSegment::new();
+        assert_eq!(display.text(), "    ");
+        display.write(0x4, &[0x1b | SEGMENT_DOT as u8], 0);
+        display.write(0xc, &[0x7], 0);
+        assert_eq!(display.text(), " b. 7");
+        assert_eq!(segments(display.digits()[1]), 0x7c | 0x80);
+        assert_eq!(segments(display.digits()[0]), 0);
+        assert_eq!(segments(0x8), 0x7f);
+
+        display.reset();
+        assert_eq!(display.digits(), [SEGMENT_BLANK; SEGMENT_DIGITS]);
+    }
+
+    /// Count down from 3 on the rightmost digit, then show `C0dE`
+    #[test]
+    fn program_counts_down() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x20d0
+                movi r1 0x3
+            .count
+                st r1 r2 0xc
+                subi r1 r1 0x1
+                bne r1 r0 .count
+                movi r1 0xc
+                st r1 r2 0x0
+                st r0 r2 0x4
+                movi r1 0xd
+                st r1 r2 0x8
+                movi r1 0xe
+                st r1 r2 0xc
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let display = SevenSegment::new();
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.devices.register(VAddr(SEGMENT_BASE), SEGMENT_SIZE, Box::new(display.clone()))
+                    .unwrap();
+                sim.start_golden();
+            });
+            assert!(!run.sim.online, "pipelined: {}: {:?}", pipelined, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "pipelined: {}: {:?}", pipelined, run.log);
+            assert_eq!(display.text(), "C0dE", "pipelined: {}", pipelined);
+        }
+    }
+}
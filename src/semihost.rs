@@ -0,0 +1,454 @@
+use crate::{
+    mmio::{MmioDevice, MmioAction, read_regs, write_regs},
+    mmu::VAddr,
+};
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Address of the semihosting interface's registers, next to the random number generator
+pub const SEMIHOST_BASE: u32 = 0x20b0;
+
+/// Size of the semihosting interface's register block
+pub const SEMIHOST_SIZE: u32 = 0x18;
+
+/// Call that opens the file whose path is at `arg0` and `arg1` bytes long, with mode `arg2`. The
+/// result is the new file descriptor
+pub const SEMI_OPEN: u32 = 1;
+
+/// Call that closes file descriptor `arg0`
+pub const SEMI_CLOSE: u32 = 2;
+
+/// Call that reads up to `arg2` bytes from file descriptor `arg0` into the buffer at `arg1`. The
+/// result is the number of bytes read, 0 at the end of the file
+pub const SEMI_READ: u32 = 3;
+
+/// Call that writes the `arg2` bytes at `arg1` to file descriptor `arg0`. The result is the number
+/// of bytes written
+pub const SEMI_WRITE: u32 = 4;
+
+/// Call whose result is the number of command-line arguments, including the program's path
+pub const SEMI_ARGC: u32 = 5;
+
+/// Call that copies argument `arg0` into the `arg2` byte buffer at `arg1`, cut off if it doesn't
+/// fit. The result is the argument's full length
+pub const SEMI_ARGV: u32 = 6;
+
+/// Open mode that reads an existing file
+pub const SEMI_MODE_READ: u32 = 0;
+
+/// Open mode that creates or truncates a file for writing
+pub const SEMI_MODE_WRITE: u32 = 1;
+
+/// Open mode that creates a file or appends to it
+pub const SEMI_MODE_APPEND: u32 = 2;
+
+/// Result of calls that failed
+pub const SEMI_ERROR: u32 = u32::MAX;
+
+/// Status bit that is set while a call is in progress
+pub const SEMI_BUSY: u32 = 1 << 0;
+
+/// Most bytes a single read or write transfers, larger requests are cut off
+pub const SEMI_MAX_TRANSFER: u32 = 0x10000;
+
+/// File descriptor that writes to the host's stdout
+const STDOUT_FD: u32 = 1;
+
+/// File descriptor that writes to the host's stderr
+const STDERR_FD: u32 = 2;
+
+/// File descriptor handed out for the first opened file
+const FIRST_FD: u32 = 3;
+
+/// State shared between all clones of a `Semihost`
+#[derive(Debug, Default)]
+struct SemihostState {
+    /// Directory that guest paths are resolved in
+    root: PathBuf,
+
+    /// Command-line arguments handed to the guest, its path first
+    args: Vec<String>,
+
+    /// Open files, file descriptor `FIRST_FD + n` is entry n
+    files: Vec<Option<File>>,
+
+    args_regs: [u32; 3],
+    result:    u32,
+    status:    u32,
+
+    /// Call that runs on the next tick
+    call: Option<u32>,
+
+    /// Call waiting for the guest memory it requested, completed in `dma_read`
+    transfer: Option<u32>,
+}
+
+impl SemihostState {
+    /// File opened as `fd`
+    fn file(&mut self, fd: u32) -> Option<&mut File> {
+        let idx = fd.checked_sub(FIRST_FD)? as usize;
+        self.files.get_mut(idx)?.as_mut()
+    }
+
+    /// Run `call`. Returns the actions that move data between the host and guest memory
+    fn run(&mut self, call: u32) -> Vec<MmioAction> {
+        let [arg0, arg1, arg2] = self.args_regs;
+        let len = arg2.min(SEMI_MAX_TRANSFER);
+        self.result = SEMI_ERROR;
+        match call {
+            SEMI_OPEN | SEMI_WRITE => {
+                let (addr, len) = match call {
+                    SEMI_OPEN => (arg0, arg1.min(SEMI_MAX_TRANSFER)),
+                    _ => (arg1, len),
+                };
+                self.transfer = Some(call);
+                return vec![MmioAction::DmaRead { addr: VAddr(addr), len }];
+            },
+            SEMI_CLOSE => {
+                if let Some(file) = arg0.checked_sub(FIRST_FD)
+                        .and_then(|idx| self.files.get_mut(idx as usize)) {
+                    if file.take().is_some() {
+                        self.result = 0;
+                    }
+                }
+            },
+            SEMI_READ => {
+                let mut data = vec![0u8; len as usize];
+                let read = self.file(arg0).map(|file| file.read(&mut data));
+                if let Some(Ok(read)) = read {
+                    self.result = read as u32;
+                    data.truncate(read);
+                    if read > 0 {
+                        return vec![MmioAction::Dma { addr: VAddr(arg1), data }];
+                    }
+                }
+            },
+            SEMI_ARGC => self.result = self.args.len() as u32,
+            SEMI_ARGV => {
+                if let Some(arg) = self.args.get(arg0 as usize) {
+                    self.result = arg.len() as u32;
+                    let data = arg.as_bytes()[..arg.len().min(arg2 as usize)].to_vec();
+                    if !data.is_empty() {
+                        return vec![MmioAction::Dma { addr: VAddr(arg1), data }];
+                    }
+                }
+            },
+            _ => {},
+        }
+        Vec::new()
+    }
+
+    /// Open the file at the guest's `path` with `mode`, returns its file descriptor. Paths are
+    /// relative to the root and can't leave it
+    fn open(&mut self, path: &[u8], mode: u32) -> Option<u32> {
+        let path = Path::new(std::str::from_utf8(path).ok()?);
+        if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return None;
+        }
+        let path = self.root.join(path);
+        let file = match mode {
+            SEMI_MODE_READ => File::open(path),
+            SEMI_MODE_WRITE => File::create(path),
+            SEMI_MODE_APPEND => File::options().append(true).create(true).open(path),
+            _ => return None,
+        }.ok()?;
+
+        let idx = match self.files.iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            },
+        };
+        self.files[idx] = Some(file);
+        Some(FIRST_FD + idx as u32)
+    }
+}
+
+/// Semihosting interface at `SEMIHOST_BASE`, lets guest programs use files of the host and read
+/// their command-line arguments. Its 32-bit registers are:
+///  - 0x00, 0x04, 0x08 Arguments: `arg0` to `arg2` of the next call
+///  - 0x0c Call: Writing one of the `SEMI_*` calls starts it
+///  - 0x10 Result: Result of the last call, `SEMI_ERROR` if it failed
+///  - 0x14 Status: `SEMI_BUSY`
+///
+/// Calls run on the next cycle and copy their data by dma, the program polls the status until they
+/// are done. Writes are ignored while a call is in progress. File descriptors 1 and 2 write to the
+/// host's stdout and stderr. Clones share the same state
+#[derive(Clone, Debug, Default)]
+pub struct Semihost {
+    state: Rc<RefCell<SemihostState>>,
+}
+
+impl Semihost {
+    /// Interface whose paths are resolved in `root`, handing `args` to the guest
+    pub fn new(root: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        let state = SemihostState { root: root.into(), args, ..SemihostState::default() };
+        Self { state: Rc::new(RefCell::new(state)) }
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 6] {
+        let state = self.state.borrow();
+        let [arg0, arg1, arg2] = state.args_regs;
+        [arg0, arg1, arg2, 0, state.result, state.status]
+    }
+}
+
+impl MmioDevice for Semihost {
+    fn name(&self) -> &str {
+        "semihost"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value. The
+    /// result and status can't be written
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        let mut state = self.state.borrow_mut();
+        if state.status & SEMI_BUSY != 0 {
+            return None;
+        }
+        state.args_regs = [regs[0], regs[1], regs[2]];
+        if (offset..offset + data.len()).any(|offset| (0xc..0x10).contains(&offset)) {
+            state.call   = Some(regs[3]);
+            state.status = SEMI_BUSY;
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    /// Run the call that was started. The busy bit is cleared on the tick after, once its dma took
+    /// effect
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        match state.call.take() {
+            Some(call) => state.run(call),
+            None => {
+                if state.transfer.is_none() {
+                    state.status &= !SEMI_BUSY;
+                }
+                Vec::new()
+            },
+        }
+    }
+
+    fn dma_read(&mut self, _addr: VAddr, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let [arg0, _, arg2] = state.args_regs;
+        match state.transfer.take() {
+            Some(SEMI_OPEN) => {
+                state.result = state.open(data, arg2).unwrap_or(SEMI_ERROR);
+            },
+            Some(SEMI_WRITE) => {
+                let written = match arg0 {
+                    STDOUT_FD => std::io::stdout().write_all(data).and_then(|_| {
+                        std::io::stdout().flush()
+                    }),
+                    STDERR_FD => std::io::stderr().write_all(data),
+                    fd => match state.file(fd) {
+                        Some(file) => file.write_all(data),
+                        None => return,
+                    },
+                };
+                if written.is_ok() {
+                    state.result = data.len() as u32;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Open files are closed and calls in progress are dropped
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let (root, args) = (std::mem::take(&mut state.root), std::mem::take(&mut state.args));
+        *state = SemihostState { root, args, ..SemihostState::default() };
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmu::Perms, cpu::Register};
+
+    /// Directory for the files of one test, removed when it is dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("seal_semihost_{}_{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Start `call` with `args` and run it, returns the actions it requested. The interface stays
+    /// busy until the next tick
+    fn call(semihost: &mut Semihost, call: u32, args: [u32; 3]) -> Vec<MmioAction> {
+        let bytes = args.map(u32::to_le_bytes).concat();
+        semihost.write(0x0, &bytes, 0);
+        semihost.write(0xc, &call.to_le_bytes(), 0);
+        let actions = semihost.tick(0);
+        assert_eq!(semihost.regs()[5], SEMI_BUSY);
+        actions
+    }
+
+    /// Finish the call in progress and return its result
+    fn finish(semihost: &mut Semihost) -> u32 {
+        assert!(semihost.tick(1).is_empty());
+        assert_eq!(semihost.regs()[5], 0);
+        semihost.regs()[4]
+    }
+
+    #[test]
+    fn files_stay_inside_the_root() {
+        let dir = TempDir::new("root");
+        std::fs::write(dir.0.join("in"), b"hello").unwrap();
+        let mut semihost = Semihost::new(&dir.0, vec!["prog".to_string()]);
+
+        let read = |addr, len| MmioAction::DmaRead { addr: VAddr(addr), len };
+        assert_eq!(call(&mut semihost, SEMI_OPEN, [0x3000, 2, SEMI_MODE_READ]), [read(0x3000, 2)]);
+        semihost.dma_read(VAddr(0x3000), b"in");
+        assert_eq!(finish(&mut semihost), FIRST_FD);
+
+        assert_eq!(call(&mut semihost, SEMI_READ, [FIRST_FD, 0x4000, 3]),
+                   [MmioAction::Dma { addr: VAddr(0x4000), data: b"hel".to_vec() }]);
+        assert_eq!(finish(&mut semihost), 3);
+
+        // Paths can't leave the root, closed files can't be used
+        for path in ["../in", "/in"] {
+            call(&mut semihost, SEMI_OPEN, [0x3000, path.len() as u32, SEMI_MODE_READ]);
+            semihost.dma_read(VAddr(0x3000), path.as_bytes());
+            assert_eq!(finish(&mut semihost), SEMI_ERROR, "{}", path);
+        }
+        call(&mut semihost, SEMI_CLOSE, [FIRST_FD, 0, 0]);
+        assert_eq!(finish(&mut semihost), 0);
+        call(&mut semihost, SEMI_READ, [FIRST_FD, 0x4000, 3]);
+        assert_eq!(finish(&mut semihost), SEMI_ERROR);
+    }
+
+    /// Copy the host file `in` to `out` and read the first command-line argument
+    #[test]
+    fn program_copies_a_file() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x20b0
+                movi r3 0x3000
+                movi r1 0x6e69
+                sth r1 r3 0x0
+                st r3 r2 0x0
+                movi r1 0x2
+                st r1 r2 0x4
+                st r0 r2 0x8
+                movi r1 0x1
+                st r1 r2 0xc
+            .open_in
+                ld r5 r2 0x14
+                bne r5 r0 .open_in
+                ld r6 r2 0x10
+                st r6 r2 0x0
+                movi r1 0x3100
+                st r1 r2 0x4
+                movi r1 0x40
+                st r1 r2 0x8
+                movi r1 0x3
+                st r1 r2 0xc
+            .read
+                ld r5 r2 0x14
+                bne r5 r0 .read
+                ld r7 r2 0x10
+                movi r1 0x2
+                st r1 r2 0xc
+            .close
+                ld r5 r2 0x14
+                bne r5 r0 .close
+                movi r1 0x756f
+                sth r1 r3 0x0
+                st r3 r2 0x0
+                movi r1 0x2
+                st r1 r2 0x4
+                movi r1 0x1
+                st r1 r2 0x8
+                st r1 r2 0xc
+            .open_out
+                ld r5 r2 0x14
+                bne r5 r0 .open_out
+                ld r8 r2 0x10
+                st r8 r2 0x0
+                movi r1 0x3100
+                st r1 r2 0x4
+                st r7 r2 0x8
+                movi r1 0x4
+                st r1 r2 0xc
+            .write
+                ld r5 r2 0x14
+                bne r5 r0 .write
+                movi r1 0x1
+                st r1 r2 0x0
+                movi r1 0x3200
+                st r1 r2 0x4
+                movi r1 0x10
+                st r1 r2 0x8
+                movi r1 0x6
+                st r1 r2 0xc
+            .argv
+                ld r5 r2 0x14
+                bne r5 r0 .argv
+                ld r9 r2 0x10
+                movi r1 0x5
+                st r1 r2 0xc
+            .argc
+                ld r5 r2 0x14
+                bne r5 r0 .argc
+                ld r10 r2 0x10
+                movi r4 0x3200
+                ld r11 r4 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let dir = TempDir::new(&format!("copy_{}", pipelined));
+            std::fs::write(dir.0.join("in"), b"seal semihosting\n").unwrap();
+            let args = vec!["prog".to_string(), "data".to_string()];
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+                sim.devices.register(VAddr(SEMIHOST_BASE), SEMIHOST_SIZE,
+                                     Box::new(Semihost::new(&dir.0, args.clone()))).unwrap();
+            });
+            let mode = format!("pipelined: {}", pipelined);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            let regs = [Register::R6, Register::R7, Register::R8, Register::R9, Register::R10,
+                        Register::R11].map(|reg| run.sim.read_reg(reg));
+            assert_eq!(regs, [FIRST_FD, 17, FIRST_FD, 4, 2, u32::from_le_bytes(*b"data")],
+                       "{}", mode);
+            assert_eq!(std::fs::read(dir.0.join("ou")).unwrap(), b"seal semihosting\n",
+                       "{}", mode);
+        }
+    }
+}
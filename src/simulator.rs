@@ -1,19 +1,43 @@
 use crate::{
-    mmu::{Mmu, VAddr, Perms, PAGE_SIZE, RAM_STALL, L1_CACHE_STALL},
-    cpu::{Register, Instr, InstrCode},
+    mmu::{Mmu, Cache, MemBus, VAddr, PAddr, Perms, MemLevel, MemSnapshot, MemChange,
+          FrameAllocator, PAGE_SIZE, RAM_STALL, PAGE_WALK_STALL, CACHE_LINE_SIZE, split_aligned},
+    cpu::{Register, Instr, InstrCode, DecodeCache, NUM_REGS},
     cpu, as_u32_le,
-    gui::{gui_err_print, gui_log_print},
-    pipeline::{Pipeline, Slot},
-    VgaDriver, Stats,
+    logger::{Logger, gui_err_print, gui_log_print},
+    pipeline::{Pipeline, PipelineHistory, Slot, Bubble, Mshr, Hazard, HazardKind,
+               FETCH_QUEUE_SIZE, MSHR_ENTRIES},
+    mmio::{MmioMap, MmioAction, MmioDevice, CommandPort, CMD_PORT_ADDR},
+    predictor::{BranchPredictor, MISPREDICT_PENALTY},
+    mailbox::{Mailbox, MAILBOX_BASE, MAILBOX_SIZE, MAILBOX_IRQ},
+    ooo::OooEngine,
+    trace::{PipelineTrace, ExecTrace, DEFAULT_TRACE_PATH, DEFAULT_EXEC_TRACE_PATH},
+    undo::{UndoLog, UndoRecord},
+    debugger::{CallFrame, RunLimits, StopCheckpoint},
+    profiler::Profile,
+    reg_history::RegHistory,
+    retire_log::RetireLog,
+    timeline::StatsTimeline,
+    energy::{EnergyModel, Energy},
+    golden::GoldenModel,
+    timer::{Timer, TIMER_BASE, TIMER_SIZE, TIMER_IRQ},
+    pic::{Pic, PIC_BASE, PIC_SIZE},
+    dma::{DmaEngine, DMA_BASE, DMA_SIZE, DMA_IRQ},
+    rng::{RandomSource, RNG_BASE, RNG_SIZE},
+    power::{PowerManager, POWER_BASE, POWER_SIZE},
+    hooks::{Hooks, HookEvent, HookAction},
+    vga::VgaSize,
+    Stats, VGA_BASE,
 };
 
-use fltk::frame::Frame;
 use rustc_hash::FxHashMap;
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Serialize, Deserialize};
 
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::io;
+use std::fs::File;
+use std::collections::{VecDeque, BTreeMap};
 
 /// Address where code is being loaded
 pub static CODE_LOAD_ADDR: Mutex<VAddr> = Mutex::new(VAddr(0x0));
@@ -22,8 +46,103 @@ pub static CODE_LOAD_ADDR: Mutex<VAddr> = Mutex::new(VAddr(0x0));
 /// updated on almost every instruction so its disabled by default
 pub const MEM_DBG_PRINTS: bool = false;
 
+/// Address of the interrupt-vector entry that handles instruction fetches from non-executable pages
+pub const EXEC_FAULT_VECTOR: u32 = 0x4;
+
+/// Address the stack is mapped at unless the layout is randomized
+pub const DEFAULT_STACK_BASE: u32 = 0x80000;
+
+/// Number of pages in the stack
+pub const STACK_PAGES: usize = 20;
+
+/// File the gui saves the simulator state to and loads it from
+pub const DEFAULT_STATE_PATH: &str = "simulator_state.bin";
+
+/// Version of the saved-state format, has to be bumped whenever the simulator state changes
+const STATE_VERSION: u32 = 9;
+
+/// Number of cycles the running-window CPI is computed over
+pub const CPI_WINDOW: u32 = 10_000;
+
+/// Cycles between the samples the running-window CPI is computed from
+const CPI_SAMPLE_INTERVAL: u32 = 1_000;
+
+/// Randomized address-space layout. Everything is derived from `seed`, so a layout can be
+/// reproduced by reusing the seed
+#[derive(Debug, Clone, Copy)]
+pub struct Aslr {
+    /// Seed the layout was generated from
+    pub seed: u64,
+
+    /// Page-aligned base address of the stack region
+    pub stack_base: u32,
+
+    /// Page-aligned offset added to the load address of all code sections
+    pub code_slide: u32,
+}
+
+impl Aslr {
+    /// Generate a layout from `seed`. Code is slid by up to 4MB, while the stack is placed in
+    /// [0x800000-0xc00000) so the two can never overlap
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            seed,
+            stack_base: 0x800000 + rng.gen_range(0..0x400) * PAGE_SIZE as u32,
+            code_slide: rng.gen_range(0..0x400) * PAGE_SIZE as u32,
+        }
+    }
+}
+
+/// Address the memory view on the gui is centered on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemFollow {
+    /// The fixed address in `cur_mem`
+    #[default]
+    Fixed,
+
+    /// The pc, so the view moves along with every step
+    Pc,
+
+    /// The address held in a register, eg. the stack pointer in `r15`
+    Reg(Register),
+}
+
+impl MemFollow {
+    /// Parse `pc` or a register such as `r15`
+    pub fn parse(config: &str) -> Option<Self> {
+        let config = config.trim().to_lowercase();
+        if config == "pc" {
+            return Some(MemFollow::Pc);
+        }
+        match config.strip_prefix('r').and_then(|idx| idx.parse::<u32>().ok()) {
+            Some(idx) if (idx as usize) < NUM_REGS => Some(MemFollow::Reg(Register::from(idx))),
+            _ => None,
+        }
+    }
+
+    /// Name displayed on the gui
+    pub fn name(&self) -> String {
+        match self {
+            MemFollow::Fixed    => "Off".to_string(),
+            MemFollow::Pc       => "PC".to_string(),
+            MemFollow::Reg(reg) => reg.to_string(),
+        }
+    }
+
+    /// Mode that follows this one when cycling through them on the gui, other registers can be
+    /// entered as the memory address
+    pub fn next(&self) -> Self {
+        match self {
+            MemFollow::Fixed  => MemFollow::Pc,
+            MemFollow::Pc     => MemFollow::Reg(Register::R15),
+            MemFollow::Reg(_) => MemFollow::Fixed,
+        }
+    }
+}
+
 /// Descirbes errors that can occur during simulation
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SimErr {
     AddrTranslation,
     Permission,
@@ -33,10 +152,13 @@ pub enum SimErr {
     MemOverlap,
     MemStall,
     DivByZero,
+    Misaligned,
+    ExecFault,
+    OutOfMemory,
 }
 
 /// Simulator struct that holds all state relevant for the simulation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Simulator {
     /// Memory management unit. This is responsible for managing/traversing page tables, using
     /// caches, performing memory reads/writes, etc
@@ -57,14 +179,29 @@ pub struct Simulator {
     /// Current memory location being looked at by simulator gui
     pub cur_mem: VAddr,
 
+    /// What the memory view follows instead of `cur_mem`, see `mem_view_addr`
+    pub mem_follow: MemFollow,
+
     /// Current cache-set to be displayed on the gui
     pub cur_cache_set: (usize, usize),
 
+    /// Cache-level (1 or 2) whose sets are displayed on the gui
+    pub cur_cache_level: usize,
+
+    /// Display a per-set eviction heatmap instead of the valid-sets bitmap on the gui
+    pub cache_heatmap: bool,
+
     /// Indicates wether the simulator is running or not. Turned off when target uses exit-mmio
     pub online: bool,
 
-    /// Screen that the executed code can write to
-    pub vga: VgaDriver,
+    /// Clock-cycle at which the core wakes up while it sleeps, `u32::MAX` while it halts until an
+    /// interrupt. Requested by the guest through `power`
+    pub sleep_until: Option<u32>,
+
+    /// Memory-mapped devices, eg. the command-port and the screen that executed code can write to.
+    /// These are not part of saved states, see `load_state`
+    #[serde(skip)]
+    pub devices: MmioMap,
 
     /// Indicates wheter the simulation runs with instruction pipelining on or off
     pub pipelining_enabled: bool,
@@ -72,6 +209,128 @@ pub struct Simulator {
     /// Mapping of addresses that have a breakpoint set for them
     pub breakpoints: FxHashMap<u32, usize>,
 
+    /// Offset that is added to the load address of every code section. Non-zero if the address
+    /// space layout is randomized
+    pub code_slide: u32,
+
+    /// Address the stack was mapped at by `map_default_layout`, used to map it again on `reset`
+    #[serde(skip)]
+    pub stack_base: u32,
+
+    /// If set, misaligned loads/stores are split into multiple aligned accesses that each incur
+    /// their own memory stall, instead of faulting
+    pub unaligned_emulation: bool,
+
+    /// Memory snapshot that the gui compares the current memory state against
+    pub snapshot: Option<MemSnapshot>,
+
+    /// If set, load misses in the pipelined mode don't stall the pipeline. They are tracked in
+    /// `pipeline.mshrs` and only instructions that read the loaded register wait for them
+    pub nonblocking_cache: bool,
+
+    /// If set, results are forwarded to the execute stage as soon as they are computed, so
+    /// dependent instructions only stall on loads that did not access memory yet
+    pub forwarding_enabled: bool,
+
+    /// Execute instructions out-of-order with `ooo` instead of the in-order pipeline
+    pub ooo_enabled: bool,
+
+    /// Out-of-order execution engine
+    pub ooo: OooEngine,
+
+    /// Pipeline trace that is currently being recorded
+    #[serde(skip)]
+    pub pipeline_trace: Option<PipelineTrace>,
+
+    /// File pipeline traces are written to
+    pub trace_path: PathBuf,
+
+    /// Execution trace of the retired instructions that is currently being recorded
+    #[serde(skip)]
+    pub exec_trace: Option<ExecTrace>,
+
+    /// File execution traces are written to
+    pub exec_trace_path: PathBuf,
+
+    /// Pipeline slots during the most recent cycles, only recorded if set
+    pub pipeline_history: Option<PipelineHistory>,
+
+    /// `(clock, total_instrs)` samples covering the last `CPI_WINDOW` cycles, oldest first
+    pub cpi_samples: VecDeque<(u32, u64)>,
+
+    /// Samples of the stats over the whole run, for charting them over time
+    pub timeline: StatsTimeline,
+
+    /// Records the most recent cycles so they can be rewound with `step_back`, only if set
+    #[serde(skip)]
+    pub undo_log: Option<UndoLog>,
+
+    /// State captured the last time execution stopped at a breakpoint, see `restart_from_stop`
+    #[serde(skip)]
+    pub last_stop: Option<Box<StopCheckpoint>>,
+
+    /// Number of executed calls minus executed returns, updated along with the pc. Used to step
+    /// over and out of functions
+    pub call_depth: i32,
+
+    /// Shadow call stack of the functions that were called but did not return yet, innermost last
+    pub call_stack: Vec<CallFrame>,
+
+    /// Source of the most recently loaded program, used to rerun it under other configurations
+    #[serde(skip)]
+    pub source: Option<String>,
+
+    /// Line of the source, counted from 1, that failed to assemble in the last `load_input`
+    #[serde(skip)]
+    pub load_error_line: Option<usize>,
+
+    /// Names of the sections and labels of the loaded program by address
+    pub symbols: BTreeMap<u32, String>,
+
+    /// Names of the loaded program's sections by start address, each section holds one function
+    pub functions: BTreeMap<u32, String>,
+
+    /// Number of times each instruction retired, only counted if set
+    pub profile: Option<Profile>,
+
+    /// Most recent writes to each register, only recorded if set
+    pub reg_history: Option<RegHistory>,
+
+    /// Most recently retired instructions, only recorded if set
+    #[serde(skip)]
+    pub retire_log: Option<RetireLog>,
+
+    /// Decoded instructions by the address they were fetched from
+    #[serde(skip)]
+    pub decode_cache: DecodeCache,
+
+    /// Functional model every retired instruction is checked against, only if set
+    #[serde(skip)]
+    pub golden: Option<GoldenModel>,
+
+    /// Limits after which the gui stops running the simulator
+    pub run_limits: RunLimits,
+
+    /// Number of instructions headless runs execute in turbo mode before switching to the
+    /// detailed model, see `fast_forward`
+    #[serde(skip)]
+    pub fast_forward_instrs: Option<u64>,
+
+    /// Energy charged per event, see `energy`
+    #[serde(skip)]
+    pub energy_model: EnergyModel,
+
+    /// Dimensions of the text screen registered at `VGA_BASE`
+    #[serde(skip)]
+    pub vga_size: VgaSize,
+
+    /// Callbacks invoked for retired instructions, memory writes and breakpoints
+    #[serde(skip)]
+    pub hooks: Hooks,
+
+    /// Seed all randomness in the simulation is derived from, see `set_seed`
+    pub seed: u64,
+
     /// Statistics tracking
     pub stats: Stats,
 }
@@ -83,49 +342,278 @@ impl Default for Simulator {
 }
 
 impl Simulator {
-    /// Initialize a new empty simulation environment
+    /// Initialize a new empty simulation environment with a random seed
     pub fn new() -> Self {
-        Self {
-            mmu:                Mmu::new(),
-            gen_regs:           [0u32; 16],
-            clock:              0,
-            pc:                 VAddr(0),
-            cur_mem:            VAddr(0),
-            cur_cache_set:      (0, 0),
-            pipeline:           Pipeline::default(),
-            online:             true,
-            vga:                VgaDriver::new(),
-            pipelining_enabled: true,
-            breakpoints:        FxHashMap::default(),
-            stats:              Stats::default(),
+        let mut devices = MmioMap::default();
+        devices.register(VAddr(CMD_PORT_ADDR), 4, Box::new(CommandPort::new(0)))
+            .expect("Failed to register command-port");
+        devices.register(VAddr(TIMER_BASE), TIMER_SIZE, Box::new(Timer::new()))
+            .expect("Failed to register timer");
+        if let Some(region) = devices.regions.last_mut() {
+            region.irq = Some(TIMER_IRQ);
+        }
+        devices.register(VAddr(DMA_BASE), DMA_SIZE, Box::new(DmaEngine::new()))
+            .expect("Failed to register dma engine");
+        if let Some(region) = devices.regions.last_mut() {
+            region.irq = Some(DMA_IRQ);
+        }
+        devices.register(VAddr(PIC_BASE), PIC_SIZE, Box::new(Pic::new()))
+            .expect("Failed to register interrupt controller");
+        devices.set_interrupt_controller(Some(VAddr(PIC_BASE)));
+        devices.register(VAddr(RNG_BASE), RNG_SIZE, Box::new(RandomSource::new()))
+            .expect("Failed to register random number generator");
+        devices.register(VAddr(POWER_BASE), POWER_SIZE, Box::new(PowerManager::new()))
+            .expect("Failed to register power management");
+        devices.register(VAddr(MAILBOX_BASE), MAILBOX_SIZE, Box::new(Mailbox::new()))
+            .expect("Failed to register mailbox");
+        if let Some(region) = devices.regions.last_mut() {
+            region.irq = Some(MAILBOX_IRQ);
         }
+
+        let mut sim = Self {
+            mmu:                 Mmu::new(),
+            gen_regs:            [0u32; 16],
+            clock:               0,
+            pc:                  VAddr(0),
+            cur_mem:             VAddr(0),
+            mem_follow:          MemFollow::Fixed,
+            cur_cache_set:       (0, 0),
+            cur_cache_level:     1,
+            cache_heatmap:       false,
+            pipeline:            Pipeline::default(),
+            online:              true,
+            sleep_until:         None,
+            devices,
+            pipelining_enabled:  true,
+            breakpoints:         FxHashMap::default(),
+            code_slide:          0,
+            stack_base:          DEFAULT_STACK_BASE,
+            unaligned_emulation: false,
+            nonblocking_cache:   false,
+            forwarding_enabled:  false,
+            snapshot:            None,
+            ooo_enabled:         false,
+            ooo:                 OooEngine::default(),
+            pipeline_trace:      None,
+            trace_path:          PathBuf::from(DEFAULT_TRACE_PATH),
+            exec_trace:          None,
+            exec_trace_path:     PathBuf::from(DEFAULT_EXEC_TRACE_PATH),
+            pipeline_history:    None,
+            cpi_samples:         VecDeque::new(),
+            timeline:            StatsTimeline::default(),
+            undo_log:            None,
+            last_stop:           None,
+            call_depth:          0,
+            call_stack:          Vec::new(),
+            source:              None,
+            load_error_line:     None,
+            symbols:             BTreeMap::new(),
+            functions:           BTreeMap::new(),
+            profile:             None,
+            reg_history:         None,
+            retire_log:          None,
+            decode_cache:        DecodeCache::default(),
+            golden:              None,
+            run_limits:          RunLimits::default(),
+            fast_forward_instrs: None,
+            energy_model:        EnergyModel::default(),
+            vga_size:            VgaSize::default(),
+            hooks:               Hooks::default(),
+            seed:                0,
+            stats:               Stats::default(),
+        };
+        sim.set_seed(rand::random());
+        sim
+    }
+
+    /// Seed the frame allocator and the mmio devices from `seed`, so runs with the same seed and
+    /// inputs are identical. Sequential frame allocation is kept since it doesn't use randomness
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        if !matches!(self.mmu.frame_allocator, FrameAllocator::Sequential { .. }) {
+            self.mmu.frame_allocator = FrameAllocator::seeded(seed);
+        }
+        self.devices.reseed(seed);
     }
 
     /// Single-step one clock-cycle
-    pub fn step(&mut self, err_log: &Rc<RefCell<Frame>>) {
+    pub fn step(&mut self, err_log: &dyn Logger) {
         if !self.online {
             return;
         }
 
-        if self.pipelining_enabled {
+        // Keep the state before the cycle, and the old contents of memory written during it
+        let undo = self.undo_log.is_some().then(|| UndoRecord {
+            clock:       self.clock,
+            pc:          self.pc,
+            gen_regs:    self.gen_regs,
+            online:      self.online,
+            sleep_until: self.sleep_until,
+            pipeline:    self.pipeline.clone(),
+            ooo:         self.ooo.clone(),
+            stats:       self.stats.clone(),
+            cpi_samples: self.cpi_samples.clone(),
+            call_depth:  self.call_depth,
+            call_stack:  self.call_stack.clone(),
+            mem_writes:  Vec::new(),
+        });
+        if undo.is_some() {
+            self.mmu.write_journal = Some(Vec::new());
+        }
+
+        self.tick_devices(err_log);
+        self.tick_mshrs();
+        if self.sleep_until.is_some_and(|until| self.clock >= until) {
+            self.sleep_until = None;
+        }
+        if !self.online {
+            // A device stopped the simulation before the cycle started
+        } else if self.sleep_until.is_some() {
+            self.stats.sleep_clock += 1;
+        } else if self.ooo_enabled {
+            self.step_ooo(err_log);
+        } else if self.pipelining_enabled {
             self.step_pipeline(err_log);
         } else {
             self.step_no_pipeline(err_log);
         }
+        if let Some(msg) = self.golden.as_mut().and_then(|golden| golden.divergence.take()) {
+            self.golden = None;
+            gui_err_print(&format!("Error: Diverged from the golden model: {}", msg), err_log);
+        }
+        if let Some(trace) = self.exec_trace.as_mut() {
+            if let Err(err) = trace.flush_if_full() {
+                self.exec_trace = None;
+                gui_err_print(&format!("Error: Could not write execution trace: {}", err), err_log);
+            }
+        }
 
         self.clock += 1;
+        if self.clock.is_multiple_of(CPI_SAMPLE_INTERVAL) {
+            self.sample_cpi();
+        }
+        self.timeline.record(self.clock, &self.stats);
+
+        if let (Some(mut record), Some(log)) = (undo, self.undo_log.as_mut()) {
+            record.mem_writes = self.mmu.write_journal.take().unwrap_or_default();
+            log.push(record);
+        }
+    }
+
+    /// Rewind the simulator by one clock-cycle. Returns `false` if there is no recorded cycle to
+    /// rewind. Caches and mmio devices are not rewound: cached copies of restored memory are
+    /// invalidated, and pages that were mapped on demand stay mapped. Active pipeline and execution
+    /// traces are stopped since the clock jumps
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.undo_log.as_mut().and_then(UndoLog::pop) else {
+            return false;
+        };
+
+        for (addr, old) in record.mem_writes.iter().rev() {
+            self.mmu.undo_write(*addr, old);
+        }
+        self.clock       = record.clock;
+        self.pc          = record.pc;
+        self.gen_regs    = record.gen_regs;
+        self.online      = record.online;
+        self.sleep_until = record.sleep_until;
+        self.pipeline    = record.pipeline;
+        self.ooo         = record.ooo;
+        self.stats       = record.stats;
+        self.cpi_samples = record.cpi_samples;
+        self.call_depth  = record.call_depth;
+        self.call_stack  = record.call_stack;
+
+        self.timeline.rewind(self.clock);
+        if let Some(history) = self.pipeline_history.as_mut() {
+            history.rewind(self.clock);
+        }
+        if let Some(history) = self.reg_history.as_mut() {
+            history.rewind(self.clock);
+        }
+        if let Some(log) = self.retire_log.as_mut() {
+            log.rewind(self.clock);
+        }
+        // The golden model can't be rewound
+        self.golden = None;
+        let _ = self.stop_pipeline_trace();
+        let _ = self.stop_exec_trace();
+        true
+    }
+
+    /// Advance all outstanding load misses by one cycle and release the serviced ones
+    fn tick_mshrs(&mut self) {
+        self.pipeline.mshrs.retain_mut(|mshr| {
+            mshr.remaining -= 1;
+            mshr.remaining > 0
+        });
+    }
+
+    /// Record the current clock and instruction count for the running-window CPI
+    fn sample_cpi(&mut self) {
+        self.cpi_samples.push_back((self.clock, self.stats.total_instrs));
+        if self.cpi_samples.len() > (CPI_WINDOW / CPI_SAMPLE_INTERVAL) as usize + 1 {
+            self.cpi_samples.pop_front();
+        }
+    }
+
+    /// Average cycles per instruction since the start of the simulation
+    pub fn cpi(&self) -> Option<f64> {
+        if self.stats.total_instrs == 0 {
+            return None;
+        }
+        Some(self.clock as f64 / self.stats.total_instrs as f64)
+    }
+
+    /// Energy spent since the start of the simulation according to `energy_model`
+    pub fn energy(&self) -> Energy {
+        self.energy_model.estimate(&self.stats, self.clock as u64)
+    }
+
+    /// Cycles per instruction over the last `CPI_WINDOW` cycles
+    pub fn window_cpi(&self) -> Option<f64> {
+        let &(clock, instrs) = self.cpi_samples.front()?;
+        let retired = self.stats.total_instrs - instrs;
+        if retired == 0 {
+            return None;
+        }
+        Some((self.clock - clock) as f64 / retired as f64)
     }
 
     /// Single-step one clock-cycle with the pipeline enabled
-    pub fn step_pipeline(&mut self, err_log: &Rc<RefCell<Frame>>) {
-        // If we are waiting for a memory load/write to finish, just return until that is done
-        if self.process_mem_stalls(true, true, err_log).unwrap() {
+    pub fn step_pipeline(&mut self, err_log: &dyn Logger) {
+        // Fetch and the memory stage access memory through separate ports, so either one can wait
+        // for memory while the other keeps going
+        let fetch_stalled = self.mem_stalled(true, false, err_log);
+        let mem_stalled   = self.mem_stalled(false, true, err_log);
+        if !self.online {
             return;
         }
+        if fetch_stalled || mem_stalled {
+            self.stats.mem_clock += 1;
+        }
 
-        // Execute pipeline stages
-        if !self.pipeline.disable {
-            self.pl_fetch_stage().unwrap();
+        // Execute pipeline stages. Fetching pauses while the fetch queue is full, since the fetched
+        // instruction can't leave the fetch stage
+        if self.pipeline.disable {
+            // Fetching is halted until a control-flow instruction resolves the next pc
+            self.stats.control_stall_clock += 1;
+        } else if !fetch_stalled && !self.pipeline.slots[0].valid {
+            if let Err(err) = self.pl_fetch_stage() {
+                self.guest_fault(err, self.pipeline.pc, err_log);
+            }
+        }
+
+        // A waiting memory stage holds up every instruction behind it, only writeback continues
+        if mem_stalled {
+            if let Err(err) = self.pl_writeback_stage() {
+                self.guest_fault(err, self.pipeline.slots[4].pc, err_log);
+            }
+            self.record_cycle(err_log);
+            if let Err(err) = self.advance_pipeline(true) {
+                self.guest_fault(err, self.pc, err_log);
+            }
+            return;
         }
 
         // If we failed to decode, insert an `invalid` instruction into the pipeline. If this 
@@ -135,41 +623,43 @@ impl Simulator {
         }
 
         if let Err(err) = self.pl_execute_stage() {
-            match err {
-                SimErr::DivByZero => { 
-                    self.online = false;
-                    gui_err_print("Error: Divide By Zero Occured", err_log);
-                },
-                _ => panic!("Unhandled error occured during pipeline exec-stage"),
-            }
+            self.guest_fault(err, self.pipeline.slots[2].pc, err_log);
         }
 
         if let Err(err) = self.pl_mem_stage() {
-            match err {
-                SimErr::Shutdown => {
-                    gui_log_print("Guest invoked shutdown request - Simulator stopped", err_log);
-                }
-                _ => {
-                    gui_err_print(&format!("Unhandled error occured during pipeline memory-stage: \
-                                           {:#?}", err), err_log);
-                    panic!("");
-                }
-            }
+            self.guest_fault(err, self.pipeline.slots[3].pc, err_log);
         }
 
-        self.pl_writeback_stage().unwrap();
+        if let Err(err) = self.pl_writeback_stage() {
+            self.guest_fault(err, self.pipeline.slots[4].pc, err_log);
+        }
+        self.record_cycle(err_log);
 
         // Advance pipeline to ready it for the next clock-cycle
-        self.advance_pipeline().unwrap();
+        if let Err(err) = self.advance_pipeline(false) {
+            self.guest_fault(err, self.pc, err_log);
+        }
     }
 
     /// Advance pipeline values to get it ready for the next clock-cycle
-    /// This is executed after a cycle is completed
-    pub fn advance_pipeline(&mut self) -> Result<(), SimErr> {
+    /// This is executed after a cycle is completed. If `mem_stalled` is set, the memory stage is
+    /// waiting on memory and everything from decode to the memory stage stays in place
+    pub fn advance_pipeline(&mut self, mem_stalled: bool) -> Result<(), SimErr> {
+        let decode_free = !mem_stalled && !self.pipeline.slots[1].disable;
+
+        // The fetch stall ends with the fetch, the memory stage tracks its own stall
+        if self.pipeline.slots[0].valid {
+            self.pipeline.slots[0].mem_stall = None;
+        }
         let mut counter = 4;
 
-        while counter > 0 {
+        while counter > 1 && !mem_stalled {
             if self.pipeline.slots[counter-1].disable {
+                // The stalled instruction leaves a bubble behind in the next stage
+                if !self.pipeline.slots[counter].valid {
+                    let reason = self.pipeline.hazard_bubble.unwrap_or(Bubble::DataHazard);
+                    self.pipeline.slots[counter] = Slot::bubble(reason);
+                }
                 counter-=1;
                 continue;
             }
@@ -179,51 +669,116 @@ impl Simulator {
 
             counter-=1;
         }
+        if mem_stalled {
+            self.pipeline.slots[4] = Slot::bubble(Bubble::MemStall);
+        }
+
+        // Decode takes the oldest queued instruction, or the fetched one if the queue is empty. A
+        // fetch that is still waiting on memory stays in the fetch stage
+        if decode_free {
+            self.pipeline.slots[1] = match self.pipeline.fetch_queue.pop_front() {
+                Some(slot) => slot,
+                None if self.pipeline.slots[0].valid => std::mem::take(&mut self.pipeline.slots[0]),
+                None => {
+                    let fetch  = &mut self.pipeline.slots[0];
+                    let bubble = fetch.bubble.take().or_else(|| {
+                        if fetch.mem_stall.is_some() {
+                            Some(Bubble::MemStall)
+                        } else if self.pipeline.disable {
+                            Some(Bubble::Control)
+                        } else {
+                            None
+                        }
+                    });
+                    Slot { squashed: fetch.squashed.take(), bubble, ..Slot::default() }
+                },
+            };
+        }
+
+        // Otherwise the fetched instruction waits in the queue. If the queue is full it stays in
+        // the fetch stage, which blocks further fetches
+        if self.pipeline.slots[0].valid && self.pipeline.fetch_queue.len() < FETCH_QUEUE_SIZE {
+            let slot = std::mem::take(&mut self.pipeline.slots[0]);
+            self.pipeline.fetch_queue.push_back(slot);
+        }
         Ok(())
     }
 
+    /// Drop the instructions in the fetch stage and the fetch queue, eg. after the front-end was
+    /// redirected to a new pc
+    fn flush_front_end(&mut self) {
+        self.pipeline.slots[0] = Slot::bubble(Bubble::Flush);
+        self.pipeline.fetch_queue.clear();
+    }
+
+    /// Discard all in-flight instructions and continue fetching at the architectural pc. Has to be
+    /// called before pc, registers or memory are changed from outside the running program, since
+    /// in-flight instructions may already have read the old state. The instruction in the
+    /// writeback stage already updated the pc, so it completes first. Cycles recorded before the
+    /// flush can't be rewound anymore
+    pub fn flush_pipeline(&mut self) {
+        if self.pipeline.slots[4].valid {
+            let _ = self.pl_writeback_stage();
+        }
+
+        for slot in self.pipeline.slots.iter_mut() {
+            *slot = if slot.valid { Slot::bubble(Bubble::Flush) } else { Slot::default() };
+        }
+        self.pipeline.fetch_queue.clear();
+        self.pipeline.mshrs.clear();
+        self.pipeline.pc             = self.pc;
+        self.pipeline.disable        = false;
+        self.pipeline.hazard_thrower = None;
+        self.pipeline.hazard_bubble  = None;
+        self.pipeline.cur_stage      = 0;
+        self.ooo.flush();
+        if let Some(log) = self.undo_log.as_mut() {
+            log.clear();
+        }
+    }
+
     /// Single-step one clock-cycle without pipelining
-    pub fn step_no_pipeline(&mut self, err_log: &Rc<RefCell<Frame>>) {
+    pub fn step_no_pipeline(&mut self, err_log: &dyn Logger) {
         match self.pipeline.cur_stage {
             0 => {
-                if self.process_mem_stalls(true, false, err_log).unwrap() {
+                if self.mem_stalled(true, false, err_log) {
+                    self.stats.mem_clock += 1;
+                    self.record_cycle(err_log);
                     return;
                 }
-                self.pl_fetch_stage().unwrap();
+                if let Err(err) = self.pl_fetch_stage() {
+                    self.guest_fault(err, self.pipeline.pc, err_log);
+                }
+            },
+            1 => {
+                // Like with pipelining, instructions that fail to decode fault once they retire
+                if self.pl_decode_stage().is_err() {
+                    self.pipeline.slots[1].instr = Instr::Invalid;
+                }
             },
-            1 => self.pl_decode_stage().unwrap(),
             2 => {
                 if let Err(err) = self.pl_execute_stage() {
-                    match err {
-                        SimErr::DivByZero => { 
-                            self.online = false;
-                            gui_err_print("Error: Divide By Zero Occured", err_log);
-                        },
-                        _ => panic!("Unhandled error occured during pipeline exec-stage"),
-                    }
+                    self.guest_fault(err, self.pipeline.slots[2].pc, err_log);
                 }
             },
             3 => {
-                if self.process_mem_stalls(false, true, err_log).unwrap() {
+                if self.mem_stalled(false, true, err_log) {
+                    self.stats.mem_clock += 1;
+                    self.record_cycle(err_log);
                     return;
                 }
                 if let Err(err) = self.pl_mem_stage() {
-                    match err {
-                        SimErr::Shutdown => {
-                            gui_log_print("Guest invoked shutdown request - Simulator stopped", 
-                                          err_log);
-                        }
-                        _ => {
-                            gui_err_print(&format!("Unhandled error occured during pipeline \
-                                memory-stage: {:#?}", err), err_log);
-                            panic!("");
-                        }
-                    }
+                    self.guest_fault(err, self.pipeline.slots[3].pc, err_log);
                 }
             }
-            4 => self.pl_writeback_stage().unwrap(),
+            4 => {
+                if let Err(err) = self.pl_writeback_stage() {
+                    self.guest_fault(err, self.pipeline.slots[4].pc, err_log);
+                }
+            },
             _ => unreachable!(),
         }
+        self.record_cycle(err_log);
 
         // Advance pipeline to ready it for the next clock-cycle
         let mut counter: isize = 4;
@@ -231,6 +786,11 @@ impl Simulator {
             if counter as usize == self.pipeline.cur_stage && counter != 4 {
                 self.pipeline.slots[counter as usize + 1] 
                     = self.pipeline.slots[counter as usize].clone();
+
+                // The fetch stall ends with the fetch, the memory stage tracks its own stall
+                if counter == 0 {
+                    self.pipeline.slots[1].mem_stall = None;
+                }
             }
 
             self.pipeline.slots[counter as usize] = Slot::default();
@@ -241,21 +801,333 @@ impl Simulator {
         self.pipeline.cur_stage = (self.pipeline.cur_stage + 1) % 5;
     }
 
+    /// Start recording a pipeline trace to `trace_path`
+    pub fn start_pipeline_trace(&mut self) -> io::Result<()> {
+        self.pipeline_trace = Some(PipelineTrace::create(&self.trace_path)?);
+        Ok(())
+    }
+
+    /// Stop recording the pipeline trace and write the remaining data to its file
+    pub fn stop_pipeline_trace(&mut self) -> io::Result<()> {
+        match self.pipeline_trace.take() {
+            Some(mut trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Start recording an execution trace of all retired instructions to `exec_trace_path`
+    pub fn start_exec_trace(&mut self) -> io::Result<()> {
+        self.exec_trace = Some(ExecTrace::create(&self.exec_trace_path, self.gen_regs)?);
+        Ok(())
+    }
+
+    /// Stop recording the execution trace and write the remaining data to its file
+    pub fn stop_exec_trace(&mut self) -> io::Result<()> {
+        match self.exec_trace.take() {
+            Some(mut trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the golden model in lockstep with the simulator from the current state on. The
+    /// pipeline is flushed so the model starts from the architectural state
+    pub fn start_golden(&mut self) {
+        self.flush_pipeline();
+        self.golden = Some(GoldenModel::new(self));
+    }
+
+    /// Add an instruction that just retired to the execution trace and profile if they are being
+    /// recorded, check it against the golden model and report it to the hooks
+    pub(crate) fn instr_retired(&mut self, pc: VAddr, instr: Instr) {
+        if let Some(golden) = self.golden.as_mut().filter(|golden| golden.divergence.is_none()) {
+            golden.divergence = golden.check(pc, instr, &self.gen_regs);
+            if golden.divergence.is_some() {
+                self.online = false;
+            }
+        }
+        if let Some(trace) = self.exec_trace.as_mut() {
+            trace.record(self.clock, pc, instr, &self.gen_regs);
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(pc);
+        }
+        if let Some(history) = self.reg_history.as_mut() {
+            history.record(self.clock, pc, instr, &self.gen_regs);
+        }
+        if let Some(log) = self.retire_log.as_mut() {
+            log.record(self.clock, pc, instr);
+        }
+        self.fire_hooks(&HookEvent::Retired { pc, instr });
+    }
+
+    /// Call the hooks registered for `event`. The simulator goes offline if any of them asks to
+    /// stop
+    fn fire_hooks(&mut self, event: &HookEvent) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        for hook in self.hooks.matching(event) {
+            if (hook.borrow_mut())(self, event) == HookAction::Stop {
+                self.online = false;
+            }
+        }
+    }
+
+    /// Check whether execution reached a breakpoint. If so, a checkpoint is captured so the run can
+    /// be restarted from here, and the breakpoint is reported to the hooks
+    pub fn at_breakpoint(&mut self) -> bool {
+        if !self.breakpoints.contains_key(&self.pc.0) {
+            return false;
+        }
+        self.checkpoint_stop();
+        self.fire_hooks(&HookEvent::Breakpoint { pc: self.pc });
+        true
+    }
+
+    /// Save the complete simulator state to `path`, so the run can later be resumed through
+    /// `load_state`
+    pub fn save_state(&self, path: &Path) -> io::Result<()> {
+        let file = io::BufWriter::new(File::create(path)?);
+        bincode::serialize_into(file, &(STATE_VERSION, self)).map_err(io::Error::other)
+    }
+
+    /// Return the machine to its power-on state without restarting: registers, pc, clock, memory,
+    /// pipeline, caches and stats are cleared, devices are reset and the default layout is mapped
+    /// again. The configuration, hooks and enabled recorders are kept. If `keep_program` is set,
+    /// the most recently loaded program is assembled again and breakpoints are kept
+    pub fn reset(&mut self, keep_program: bool, err_log: &dyn Logger) -> Result<(), SimErr> {
+        let _ = self.stop_pipeline_trace();
+        let _ = self.stop_exec_trace();
+
+        let mut fresh = Simulator::new();
+        fresh.mmu.cache          = Cache::new(self.mmu.cache.num_sets, self.mmu.cache.ways);
+        fresh.mmu.l2_cache       = Cache::new(self.mmu.l2_cache.num_sets, self.mmu.l2_cache.ways);
+        fresh.mmu.bus            = MemBus::new(self.mmu.bus.busy_until.len());
+        fresh.mmu.cache_enabled  = self.mmu.cache_enabled;
+        fresh.mmu.l2_enabled     = self.mmu.l2_enabled;
+        fresh.mmu.write_allocate = self.mmu.write_allocate;
+        fresh.mmu.ram_size       = self.mmu.ram_size;
+        if matches!(self.mmu.frame_allocator, FrameAllocator::Sequential { .. }) {
+            fresh.mmu.frame_allocator = FrameAllocator::sequential();
+        }
+        fresh.devices = std::mem::take(&mut self.devices);
+        fresh.devices.reset();
+        fresh.set_seed(self.seed);
+
+        fresh.pipelining_enabled  = self.pipelining_enabled;
+        fresh.ooo_enabled         = self.ooo_enabled;
+        fresh.forwarding_enabled  = self.forwarding_enabled;
+        fresh.nonblocking_cache   = self.nonblocking_cache;
+        fresh.unaligned_emulation = self.unaligned_emulation;
+        fresh.pipeline.predictor  = BranchPredictor::new(self.pipeline.predictor.kind);
+        fresh.code_slide          = self.code_slide;
+        fresh.stack_base          = self.stack_base;
+        fresh.cur_cache_level     = self.cur_cache_level;
+        fresh.cache_heatmap       = self.cache_heatmap;
+        fresh.mem_follow          = self.mem_follow;
+        fresh.trace_path          = std::mem::take(&mut self.trace_path);
+        fresh.exec_trace_path     = std::mem::take(&mut self.exec_trace_path);
+        fresh.run_limits          = self.run_limits;
+        fresh.fast_forward_instrs = self.fast_forward_instrs;
+        fresh.energy_model        = self.energy_model;
+        fresh.vga_size            = self.vga_size;
+        fresh.hooks               = std::mem::take(&mut self.hooks);
+
+        // Recorders that were enabled stay enabled, but start out empty
+        fresh.pipeline_history = self.pipeline_history.take().map(|mut history| {
+            history.rewind(0);
+            history
+        });
+        fresh.undo_log = self.undo_log.take().map(|mut log| {
+            log.clear();
+            log
+        });
+        fresh.profile = self.profile.take().map(|mut profile| {
+            profile.clear();
+            profile
+        });
+        fresh.reg_history = self.reg_history.take().map(|mut history| {
+            history.reset([0; NUM_REGS]);
+            history
+        });
+        fresh.retire_log = self.retire_log.take().map(|mut log| {
+            log.clear();
+            log
+        });
+
+        let source = self.source.take().filter(|_| keep_program);
+        if keep_program {
+            fresh.breakpoints = std::mem::take(&mut self.breakpoints);
+        }
+        let golden = self.golden.is_some();
+        *self = fresh;
+        self.map_default_layout(self.stack_base)?;
+
+        if let Some(source) = source {
+            self.load_input(&source, err_log)?;
+        }
+        if golden {
+            self.start_golden();
+        }
+        Ok(())
+    }
+
+    /// Replace the simulator state with the one saved at `path`. Mmio devices are not part of
+    /// saved states, the registered ones are kept and reseeded with the saved seed. Active pipeline
+    /// and execution traces are stopped since the clock jumps
+    pub fn load_state(&mut self, path: &Path) -> io::Result<()> {
+        let file = io::BufReader::new(File::open(path)?);
+        let (version, mut state): (u32, Simulator) = bincode::deserialize_from(file)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if version != STATE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Unsupported state version {}", version)));
+        }
+
+        self.stop_pipeline_trace()?;
+        self.stop_exec_trace()?;
+        state.devices = std::mem::take(&mut self.devices);
+        state.devices.reseed(state.seed);
+        state.stack_base = self.stack_base;
+        state.vga_size   = self.vga_size;
+        state.undo_log = self.undo_log.take().map(|mut log| {
+            log.clear();
+            log
+        });
+        state.retire_log = self.retire_log.take().map(|mut log| {
+            log.clear();
+            log
+        });
+        *self = state;
+        Ok(())
+    }
+
+    /// Add the current clock-cycle to the pipeline history and trace if they are being recorded.
+    /// Trace recording stops if the trace can't be written
+    fn record_cycle(&mut self, err_log: &dyn Logger) {
+        if let Some(history) = self.pipeline_history.as_mut() {
+            history.push(self.clock, &self.pipeline);
+        }
+        if let Some(trace) = self.pipeline_trace.as_mut() {
+            if let Err(err) = trace.record(self.clock, &self.pipeline) {
+                self.pipeline_trace = None;
+                gui_err_print(&format!("Error: Could not write pipeline trace: {}", err), err_log);
+            }
+        }
+    }
+
+
+    /// Deliver a fault for the instruction in the execute stage that was fetched from a
+    /// non-executable page. Execution is redirected to the handler registered at
+    /// `EXEC_FAULT_VECTOR`, if no handler is registered the simulation is stopped
+    pub(crate) fn raise_exec_fault(&mut self, fault_pc: VAddr, err_log: &dyn Logger) {
+
+        let mut reader = vec![0x0; 4];
+        let handler = match self.mem_read(VAddr(EXEC_FAULT_VECTOR), &mut reader) {
+            Ok(()) => as_u32_le(&reader),
+            Err(_) => 0,
+        };
+
+        if handler == 0 {
+            self.online = false;
+            gui_err_print(&format!("Error: Fetch from non-executable page at {:#x}", fault_pc.0),
+                          err_log);
+            return;
+        }
+
+        // Flush the faulting instruction and everything behind it, then continue at the handler
+        self.flush_front_end();
+        self.pipeline.slots[1] = Slot::bubble(Bubble::Flush);
+        self.pipeline.slots[2] = Slot::bubble(Bubble::Flush);
+        self.pipeline.disable  = false;
+
+        self.pipeline.pc = VAddr(handler);
+        self.pc          = VAddr(handler);
+    }
+
+    /// Handle an error the running program caused at `pc`. Execution faults trap into the
+    /// program's handler, everything else stops the simulation without affecting the host
+    pub(crate) fn guest_fault(&mut self, err: SimErr, pc: VAddr, err_log: &dyn Logger) {
+        let msg = match err {
+            SimErr::Shutdown => {
+                gui_log_print("Guest invoked shutdown request - Simulator stopped", err_log);
+                return;
+            },
+            SimErr::ExecFault => {
+                self.raise_exec_fault(pc, err_log);
+                return;
+            },
+            SimErr::DivByZero       => String::from("Divide By Zero Occured"),
+            SimErr::Misaligned      => String::from("Misaligned memory access"),
+            SimErr::OutOfMemory     => String::from("Out of physical memory"),
+            SimErr::InstrDecode     => format!("Invalid instruction at {:#x}", pc.0),
+            SimErr::AddrTranslation => format!("Access to unmapped memory at {:#x}", pc.0),
+            SimErr::Permission      => format!("Memory access violates permissions at {:#x}", pc.0),
+            _ => format!("{:?} at {:#x}", err, pc.0),
+        };
+        self.online = false;
+        gui_err_print(&format!("Error: {}", msg), err_log);
+    }
+
+    /// Wrapper around `process_mem_stalls` that stops the simulation if a lazily mapped page can't
+    /// be backed by physical memory. Returns `true` if execution should not continue this cycle
+    fn mem_stalled(&mut self, check_stage_0: bool, check_stage_3: bool, 
+                   err_log: &dyn Logger) -> bool {
+        match self.process_mem_stalls(check_stage_0, check_stage_3, err_log) {
+            Ok(stalled) => stalled,
+            Err(err) => {
+                self.guest_fault(err, self.pc, err_log);
+                true
+            },
+        }
+    }
+
+    /// Track a miss of the load in the memory stage in a free MSHR, so the pipeline can continue
+    /// past it. A load to a line that already has a miss outstanding waits for that miss too.
+    /// Returns `false` if the access has to stall the pipeline instead
+    fn track_load_miss(&mut self, line: PAddr, stall: usize) -> bool {
+        let reg = match self.pipeline.slots[3].instr {
+            Instr::Ld { rs3, .. } | Instr::Ldh { rs3, .. } | Instr::Ldb { rs3, .. } => rs3,
+            _ => return false,
+        };
+        if !self.pipelining_enabled || self.pipeline.mshrs.len() >= MSHR_ENTRIES {
+            return false;
+        }
+
+        let pending = self.pipeline.mshrs.iter()
+            .filter(|mshr| mshr.line == line)
+            .map(|mshr| mshr.remaining)
+            .max()
+            .unwrap_or(0);
+        self.pipeline.mshrs.push(Mshr { line, reg, remaining: stall.max(pending) });
+        self.stats.hit_under_miss += 1;
+        true
+    }
+
     /// Return of `true` indicates that we are still stalling on a memory read
     /// Return of `false indicates that we are good to execute the stages on this clock-cycle
     fn process_mem_stalls(&mut self, check_stage_0: bool, check_stage_3: bool, 
-                          err_log: &Rc<RefCell<Frame>>) -> Result<bool, SimErr> {
+                          err_log: &dyn Logger) -> Result<bool, SimErr> {
 
-        // Handle memmory stall occuring through fetch stage
-        if !self.pipeline.disable && check_stage_0 {
+        // Handle memmory stall occuring through fetch stage. Nothing is fetched while the fetch
+        // stage still holds an instruction that could not enter the full fetch queue
+        if !self.pipeline.disable && !self.pipeline.slots[0].valid && check_stage_0 {
             if self.pipeline.slots[0].mem_stall.is_none() {
-                self.pipeline.slots[0].mem_stall = if self.mmu.addr_in_cache(
-                        self.mmu.translate_addr(self.pipeline.pc, Perms::READ)?) {
-                    Some(L1_CACHE_STALL - 1)
-                } else {
-                    Some(RAM_STALL - 1)
+                self.populate_page(self.pipeline.pc)?;
+                let walk  = self.tlb_access(self.pipeline.pc);
+
+                // Fetches from unmapped memory don't stall, they fault once they reach the
+                // execute stage. This can happen when speculatively fetching down a wrong path
+                let paddr = match self.mmu.translate_addr(self.pipeline.pc, Perms::READ) {
+                    Ok(paddr) => paddr,
+                    Err(_) => {
+                        self.pipeline.slots[0].mem_stall = Some(0);
+                        return Ok(false);
+                    },
                 };
-                self.stats.mem_clock += 1.0;
+                self.pipeline.slots[0].mem_stall = 
+                    Some(walk + self.mem_access_stall(paddr, walk) - 1);
+                self.stats.fetch_stall_clock += 1;
                 if MEM_DBG_PRINTS {
                     gui_log_print("Waiting for memory fetch in Stage-0", err_log);
                 }
@@ -263,7 +1135,7 @@ impl Simulator {
             } else if let Some(stall_time) = self.pipeline.slots[0].mem_stall {
                 if stall_time != 0 {
                     self.pipeline.slots[0].mem_stall = Some(stall_time - 1);
-                    self.stats.mem_clock += 1.0;
+                    self.stats.fetch_stall_clock += 1;
                     if MEM_DBG_PRINTS {
                         gui_log_print("Waiting for memory fetch in Stage-0", err_log);
                     }
@@ -274,41 +1146,79 @@ impl Simulator {
 
         // Handle memmory stall occuring through memory stage
         if check_stage_3 {
-            let mut accessed_addr: Option<VAddr> = None;
+            let mut accessed_addr: Option<(VAddr, usize)> = None;
 
             if self.pipeline.slots[3].mem_stall.is_none() {
                 // Figure out the address that this instruction accesses
                 match self.pipeline.slots[3].instr {
                     Instr::Ret { .. } => {
-                        accessed_addr = Some(VAddr(self.read_reg(Register::R15)));
+                        accessed_addr = Some((VAddr(self.read_reg(Register::R15)), 4));
                     },
                     Instr::Call { .. } => {
-                        accessed_addr = Some(VAddr(self.read_reg(Register::R15) - 4));
+                        let sp = self.read_reg(Register::R15).wrapping_sub(4);
+                        accessed_addr = Some((VAddr(sp), 4));
                     },
                     Instr::Int0 { .. } => {
-                        accessed_addr = Some(VAddr(0x0));
+                        accessed_addr = Some((VAddr(0x0), 4));
                     },
                     Instr::Ldb { .. } |
+                    Instr::Stb { .. } => {
+                        accessed_addr = Some((self.pipeline.slots[3].addr, 1));
+                    }
                     Instr::Ldh { .. } |
-                    Instr::Ld  { .. } |
-                    Instr::Stb { .. } |
-                    Instr::Sth { .. } |
-                    Instr::St  { .. } => {
-                        accessed_addr = Some(self.pipeline.slots[3].addr);
-
+                    Instr::Sth { .. } => {
+                        accessed_addr = Some((self.pipeline.slots[3].addr, 2));
+                    }
+                    Instr::Ld   { .. } |
+                    Instr::St   { .. } |
+                    Instr::Stnt { .. } => {
+                        accessed_addr = Some((self.pipeline.slots[3].addr, 4));
                     }
                     _ => {},
                 }
 
-                if let Some(addr) = accessed_addr {
-                    self.pipeline.slots[3].mem_stall = 
-                            if self.mmu.addr_in_cache(self.mmu.translate_addr(addr, Perms::READ)?) {
-                        Some(L1_CACHE_STALL - 1)
-                    } else {
-                        Some(RAM_STALL - 1)
-                    };
+                // Non-temporal stores always go straight to ram
+                let non_temporal = matches!(self.pipeline.slots[3].instr, Instr::Stnt { .. });
+
+                if let Some((addr, len)) = accessed_addr {
+                    // Every access the memory system has to perform stalls separately, so
+                    // emulated misaligned accesses pay for each of their aligned parts
+                    let mut stall = 0;
+                    let mut missed = None;
+                    let chunks = self.mem_chunks(addr, len);
+                    for (i, &(chunk_addr, _)) in chunks.iter().enumerate() {
+                        self.populate_page(chunk_addr)?;
+                        stall += self.tlb_access(chunk_addr);
+                        let paddr = self.mmu.translate_addr(chunk_addr, Perms::READ)?;
+                        let line = PAddr(paddr.0 & !(CACHE_LINE_SIZE as u32 - 1));
+                        if self.mmu.access_level(paddr) != MemLevel::L1
+                                || self.pipeline.mshrs.iter().any(|mshr| mshr.line == line) {
+                            missed.get_or_insert(line);
+                        }
+                        let chunk_stall = if non_temporal {
+                            self.ram_access_stall(stall)
+                        } else {
+                            self.mem_access_stall(paddr, stall)
+                        };
+                        if i != 0 {
+                            self.stats.misaligned_clock += chunk_stall as u64;
+                        }
+                        stall += chunk_stall;
+                    }
+                    if chunks.len() > 1 {
+                        self.stats.misaligned_accesses += 1;
+                    }
 
-                    self.stats.mem_clock += 1.0;
+                    // Continue past load misses with a non-blocking cache
+                    if let Some(line) = missed {
+                        if self.nonblocking_cache && self.track_load_miss(line, stall) {
+                            self.pipeline.slots[3].mem_stall = Some(0);
+                            return Ok(false);
+                        }
+                    }
+                    self.pipeline.slots[3].mem_stall = Some(stall - 1);
+
+                    self.stats.load_store_stall_clock += 1;
                     if MEM_DBG_PRINTS {
                         gui_log_print("Waiting for memory fetch in Stage-3", err_log);
                     }
@@ -317,7 +1227,7 @@ impl Simulator {
             } else if let Some(stall_time) = self.pipeline.slots[3].mem_stall {
                 if stall_time != 0 {
                     self.pipeline.slots[3].mem_stall = Some(stall_time - 1);
-                    self.stats.mem_clock += 1.0;
+                    self.stats.load_store_stall_clock += 1;
                     if MEM_DBG_PRINTS {
                         gui_log_print("Waiting for memory fetch in Stage-3", err_log);
                     }
@@ -340,7 +1250,7 @@ impl Simulator {
 
         let instr: u32 = as_u32_le(&reader);
 
-        cpu::decode_instr(instr)
+        self.decode_cache.decode(pc.0, instr)
     }
 
     /// Decode instruction at `pc`
@@ -352,7 +1262,17 @@ impl Simulator {
 
         let instr: u32 = as_u32_le(&reader);
 
-        cpu::decode_instr(instr)
+        self.decode_cache.decode(pc.0, instr)
+    }
+
+    /// Snapshot the current contents of all mapped pages, replacing any previous snapshot
+    pub fn take_snapshot(&mut self) {
+        self.snapshot = Some(self.mmu.snapshot());
+    }
+
+    /// List memory changes since the last snapshot. Returns `None` if no snapshot was taken yet
+    pub fn snapshot_diff(&self) -> Option<Vec<MemChange>> {
+        self.snapshot.as_ref().map(|snapshot| self.mmu.diff(snapshot))
     }
 
     /// Map a page into physical memory using the given virtual address: `addr`
@@ -361,12 +1281,137 @@ impl Simulator {
         self.mmu.map_page(addr, perms)
     }
 
+    /// Map a large page at the large-page aligned address `addr`
+    pub fn map_large_page(&mut self, addr: VAddr, perms: u8) -> Result<(), SimErr> {
+        self.mmu.map_large_page(addr, perms)
+    }
+
+    /// Map the pages overlapping `[addr, addr+len)` with permissions `perms`. Lazily mapped pages
+    /// are only backed by physical memory once they are first accessed
+    pub fn map_region(&mut self, addr: VAddr, len: usize, perms: u8, lazy: bool) 
+            -> Result<(), SimErr> {
+        self.mmu.map_region(addr, len, perms, lazy)
+    }
+
+    /// Map the address space layout programs expect: The interrupt-vector, vga-buffer and mmio
+    /// pages, and a stack at `stack_base` whose top is written to the stack pointer `r15`. Stack
+    /// pages are only backed by physical memory once they are used
+    pub fn map_default_layout(&mut self, stack_base: u32) -> Result<(), SimErr> {
+        self.stack_base = stack_base;
+        self.map_page(VAddr(0x0), Perms::READ | Perms::WRITE)?;
+        self.map_page(VAddr(VGA_BASE), Perms::READ | Perms::WRITE)?;
+        self.map_page(VAddr(CMD_PORT_ADDR), Perms::READ | Perms::WRITE)?;
+
+        let stack_size = STACK_PAGES * PAGE_SIZE;
+        self.map_region(VAddr(stack_base), stack_size, Perms::READ | Perms::WRITE, true)?;
+        self.write_reg(Register::R15, stack_base + stack_size as u32 - 4);
+        Ok(())
+    }
+
+    /// Add a custom peripheral for the address range `base..base+size`. Its interrupt line, if
+    /// given, raises interrupt-vector[`irq`]. Pages of the range that aren't mapped yet are mapped
+    /// readable and writable so programs can access the device
+    pub fn register_device(&mut self, base: VAddr, size: u32, irq: Option<u32>,
+                           device: Box<dyn MmioDevice>) -> Result<(), SimErr> {
+        self.devices.register(base, size, device)?;
+        if let Some(region) = self.devices.regions.last_mut() {
+            region.irq = irq;
+        }
+
+        let start = base.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..base.0 as usize + size as usize).step_by(PAGE_SIZE) {
+            match self.map_page(VAddr(page as u32), Perms::READ | Perms::WRITE) {
+                Ok(()) | Err(SimErr::MemOverlap) => {},
+                Err(err) => {
+                    self.devices.unregister(base);
+                    return Err(err);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmap the page containing `addr` and free the physical memory backing it
+    pub fn unmap_page(&mut self, addr: VAddr) -> Result<(), SimErr> {
+        self.mmu.unmap_page(addr)
+    }
+
+    /// Move the page mapped at `from` to the virtual address `to`
+    pub fn remap_page(&mut self, from: VAddr, to: VAddr) -> Result<(), SimErr> {
+        self.mmu.remap_page(from, to)
+    }
+
+    /// Change the permissions of the pages overlapping `[addr, addr+len)` to `perms`
+    pub fn protect(&mut self, addr: VAddr, len: usize, perms: u8) -> Result<(), SimErr> {
+        self.mmu.protect(addr, len, perms)
+    }
+
+    /// Allocate a physical frame for `addr` if it lives in a lazily mapped page that hasn't been
+    /// touched yet
+    pub(crate) fn populate_page(&mut self, addr: VAddr) -> Result<(), SimErr> {
+        if self.mmu.populate_page(addr)? {
+            self.stats.demand_pages += 1;
+        }
+        Ok(())
+    }
+
+    /// Number of cycles an access to `paddr` issued `delay` cycles from now stalls for. Ram
+    /// accesses additionally have to wait for a free slot on the memory bus
+    pub(crate) fn mem_access_stall(&mut self, paddr: PAddr, delay: usize) -> usize {
+        if self.mmu.access_level(paddr) != MemLevel::Ram {
+            return self.mmu.access_stall(paddr);
+        }
+        self.ram_access_stall(delay)
+    }
+
+    /// Number of cycles a ram access issued `delay` cycles from now stalls for, including the time
+    /// spent waiting for the memory bus
+    fn ram_access_stall(&mut self, delay: usize) -> usize {
+        let (wait, busy) = self.mmu.bus.request(self.clock as u64 + delay as u64, RAM_STALL);
+        self.stats.bus_wait_clock += wait as u64;
+        self.stats.bus_busy_clock += busy as u64;
+        RAM_STALL + wait
+    }
+
+    /// Run `addr` through the tlb and track hit/miss statistics
+    /// Returns the number of cycles spent walking the page table on a tlb-miss
+    pub(crate) fn tlb_access(&mut self, addr: VAddr) -> usize {
+        if self.mmu.tlb_access(addr) {
+            self.stats.tlb_hits += 1;
+            0
+        } else {
+            let walk = self.mmu.walk_levels(addr) * PAGE_WALK_STALL;
+            self.stats.tlb_misses      += 1;
+            self.stats.page_walk_clock += walk as u64;
+            walk
+        }
+    }
+
     /// Completely flush cache
     pub fn clear_caches(&mut self) {
         self.cur_cache_set = (0, 0);
         self.mmu.clear_caches();
     }
 
+    /// Address the memory view is centered on. Followed addresses are rounded down to a word, so
+    /// eg. byte pointers can be followed
+    pub fn mem_view_addr(&self) -> VAddr {
+        match self.mem_follow {
+            MemFollow::Fixed    => self.cur_mem,
+            MemFollow::Pc       => VAddr(self.pc.0 & !0x3),
+            MemFollow::Reg(reg) => VAddr(self.gen_regs[reg as usize] & !0x3),
+        }
+    }
+
+    /// Cache-level currently selected for display on the gui
+    pub fn displayed_cache(&self) -> &Cache {
+        if self.cur_cache_level == 2 {
+            &self.mmu.l2_cache
+        } else {
+            &self.mmu.cache
+        }
+    }
+
     /// Wrapper around `mmu.mem_read` to expose an api that can read more than 4 bytes at once
     /// Returns number of clock cycles this operation took
     pub fn mem_read(&mut self, addr: VAddr, reader: &mut Vec<u8>) -> Result<(), SimErr> {
@@ -375,14 +1420,15 @@ impl Simulator {
         while offset < reader.len() {
             let len = std::cmp::min(reader.len() - offset, 4);
 
-            let cache_hit = 
-                self.mmu.mem_read(VAddr(addr.0 + offset as u32), &mut reader[offset..len])?;
-
-            // Update stats
-            if cache_hit {
-                self.stats.cache_hits += 1.0;
-            } else {
-                self.stats.cache_misses += 1.0;
+            for (chunk_addr, chunk_len) in self.mem_chunks(VAddr(addr.0 + offset as u32), len) {
+                let chunk_offset = (chunk_addr.0 - addr.0) as usize;
+                self.populate_page(chunk_addr)?;
+                let level = self.mmu.mem_read(chunk_addr, 
+                        &mut reader[chunk_offset..chunk_offset + chunk_len])?;
+                self.record_mem_access(level);
+                self.devices.read(chunk_addr, 
+                                  &mut reader[chunk_offset..chunk_offset + chunk_len]);
+                self.count_device_accesses(chunk_addr, chunk_len, "reads");
             }
 
             offset += len;
@@ -390,16 +1436,63 @@ impl Simulator {
         Ok(())
     }
 
-    /// Wrapper around `mmu.mem_read` to expose an api that can read more than 4 bytes at once
-    /// Returns number of clock cycles this operation took
-    /// Tuned for gui usage, other implementation tracks some stats that gui shouldn't
-    pub fn gui_mem_read(&mut self, addr: VAddr, reader: &mut Vec<u8>) -> Result<(), SimErr> {
-        let mut offset: usize = 0;
+    /// Accesses performed by the memory system for a `len` byte access at `addr`. With unaligned
+    /// emulation enabled, misaligned accesses are split into multiple aligned ones
+    fn mem_chunks(&self, addr: VAddr, len: usize) -> Vec<(VAddr, usize)> {
+        if self.unaligned_emulation {
+            split_aligned(addr, len)
+        } else {
+            vec![(addr, len)]
+        }
+    }
 
-        while offset < reader.len() {
-            let len = std::cmp::min(reader.len() - offset, 4);
-            self.mmu.gui_mem_read(VAddr(addr.0 + offset as u32), &mut reader[offset..len])?;
-            offset += len;
+    /// Update cache statistics for a memory access that was serviced by `level`
+    fn record_mem_access(&mut self, level: MemLevel) {
+        match level {
+            MemLevel::L1 => {
+                self.stats.cache_hits += 1;
+            },
+            MemLevel::L2 => {
+                self.stats.cache_misses += 1;
+                self.stats.l2_cache_hits += 1;
+            },
+            MemLevel::Ram => {
+                self.stats.cache_misses += 1;
+                if self.mmu.cache_enabled && self.mmu.l2_enabled {
+                    self.stats.l2_cache_misses += 1;
+                }
+            },
+        }
+    }
+
+    /// Track cache statistics for a store to `addr`. Only the cache-level the line resides in
+    /// before the write is relevant, so this has to be called before the write is performed
+    pub(crate) fn record_store_access(&mut self, addr: VAddr) {
+        if let Ok(paddr) = self.mmu.translate_addr(addr, Perms::WRITE) {
+            let level = self.mmu.access_level(paddr);
+            self.record_mem_access(level);
+        }
+    }
+
+    /// Read an arbitrary amount of bytes at once through `mmu.mem_read_bulk`
+    /// Tuned for gui usage, this bypasses caches and doesn't track any stats
+    pub fn gui_mem_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<(), SimErr> {
+        self.mmu.mem_read_bulk(addr, reader)
+    }
+
+    /// Patch memory at `addr` with `data` from the debugger, eg. to fix data or instructions
+    /// during a debug session. Unlike stores this ignores write-permissions, doesn't reach devices
+    /// and takes no cycles. In-flight instructions may have fetched the old bytes, so they are
+    /// discarded and refetched
+    pub fn patch_mem(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        self.flush_pipeline();
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + data.len()).step_by(PAGE_SIZE) {
+            self.mmu.populate_page(VAddr(page as u32))?;
+        }
+        self.mmu.patch_bulk(addr, data)?;
+        if let Some(golden) = self.golden.as_mut() {
+            golden.patch(addr, data)?;
         }
         Ok(())
     }
@@ -410,37 +1503,219 @@ impl Simulator {
         let mut addr_to_write = addr;
         let writer_cpy = writer.clone();
 
+        // Larger writes are only performed by the loader and don't model individual stores, so
+        // they can take the bulk path
+        if writer.len() > 4 {
+            for page in (addr.0 & !(PAGE_SIZE as u32 - 1)..addr.0 + writer.len() as u32)
+                    .step_by(PAGE_SIZE) {
+                self.populate_page(VAddr(page))?;
+            }
+            self.mmu.mem_write_bulk(addr, writer)?;
+            writer.clear();
+        }
+
         while !writer.is_empty() {
             let len = std::cmp::min(writer.len(), 4);
-            self.mmu.mem_write(addr_to_write, &writer[0..len])?;
+            for (chunk_addr, chunk_len) in self.mem_chunks(addr_to_write, len) {
+                let chunk_offset = (chunk_addr.0 - addr_to_write.0) as usize;
+                self.populate_page(chunk_addr)?;
+                self.mmu.mem_write(chunk_addr, &writer[chunk_offset..chunk_offset + chunk_len])?;
+            }
             writer.drain(..len);
             addr_to_write.0 += len as u32;
         }
 
-        if addr.0 == 0x2000 && writer_cpy[0] == 0x41 {
-            // MMIO-Region field was written to exit guest
-            self.online = false;
-            return Err(SimErr::Shutdown);
-        } else if addr.0 == 0x2000 && writer_cpy[0] == 0x42 {
-            // MMIO-Region field was written to get current clock-counter
-            self.write_reg(Register::R1, self.clock);
-        } else if addr.0 == 0x2000 && writer_cpy[0] == 0x43 {
-            // MMIO-Region field was written to get random number
-            let mut rng = rand::thread_rng();
-            self.write_reg(Register::R1, rng.gen());
+        if writer_cpy.len() <= 4 {
+            self.fire_hooks(&HookEvent::MemWrite { addr, data: &writer_cpy });
         }
+        self.mmio_write(addr, &writer_cpy)
+    }
 
-        // Write to vga-buf
-        if addr.0 >= 0x1000 && addr.0 <= 0x10f0 {
-            self.vga.write(addr, &writer_cpy);
-        }
+    /// Non-temporal store of `writer` to `addr` that bypasses the caches
+    pub fn mem_write_nt(&mut self, addr: VAddr, writer: &[u8]) -> Result<u32, SimErr> {
+        self.populate_page(addr)?;
+        self.mmu.mem_write_nt(addr, writer)?;
+        self.fire_hooks(&HookEvent::MemWrite { addr, data: writer });
+        self.mmio_write(addr, writer)
+    }
+
+    /// Forward a write of `writer_cpy` to `addr` to the memory-mapped devices and perform the
+    /// actions they request
+    fn mmio_write(&mut self, addr: VAddr, writer_cpy: &[u8]) -> Result<u32, SimErr> {
+        self.count_device_accesses(addr, writer_cpy.len(), "writes");
+        for action in self.devices.write(addr, writer_cpy, self.clock) {
+            match action {
+                MmioAction::Shutdown => {
+                    self.online = false;
+                    return Err(SimErr::Shutdown);
+                },
+                MmioAction::WriteReg(reg, val) => self.write_reg(reg, val),
+
+                // The store is still in flight, so these take effect when the next cycle starts
+                action @ (MmioAction::Interrupt(_) | MmioAction::Dma { .. }
+                          | MmioAction::DeviceDmaRead { .. } | MmioAction::Sleep(_)) => {
+                    self.devices.schedule(self.clock + 1, action);
+                },
 
+                // Interrupt lines and dma reads are resolved by the mmio map
+                MmioAction::RaiseIrq | MmioAction::DmaRead { .. } => {},
+            }
+        }
         Ok(1)
     }
 
+    /// Count a `len` byte access at `addr` in the `device.<name>.<kind>` metric of every device it
+    /// overlaps with
+    fn count_device_accesses(&mut self, addr: VAddr, len: usize, kind: &str) {
+        for name in self.devices.names_at(addr, len) {
+            self.stats.metrics.add(&format!("device.{}.{}", name, kind), 1);
+        }
+    }
+
+    /// Start a clock-cycle for the memory-mapped devices, and perform the actions they posted for
+    /// it. Interrupts are only taken between instructions, until then they stay pending
+    fn tick_devices(&mut self, err_log: &dyn Logger) {
+        let actions = self.devices.tick(self.clock);
+        if actions.is_empty() {
+            return;
+        }
+        for action in actions {
+            match action {
+                MmioAction::Shutdown => {
+                    self.online = false;
+                    gui_log_print("Device requested shutdown - Simulator stopped", err_log);
+                },
+                MmioAction::WriteReg(reg, val) => {
+                    self.flush_pipeline();
+                    self.write_reg(reg, val);
+                },
+                MmioAction::Interrupt(vector) => {
+                    if self.mid_instr() {
+                        self.devices.schedule(self.clock + 1, action);
+                    } else {
+                        self.sleep_until = None;
+                        if let Err(err) = self.raise_interrupt(vector) {
+                            self.guest_fault(err, self.pc, err_log);
+                        }
+                    }
+                },
+                MmioAction::Sleep(cycles) => {
+                    if self.mid_instr() {
+                        self.devices.schedule(self.clock + 1, action);
+                    } else {
+                        // Instructions behind the store are fetched again once the core wakes up
+                        self.flush_pipeline();
+                        self.sleep_until = Some(match cycles {
+                            Some(cycles) => self.clock.saturating_add(cycles),
+                            None         => u32::MAX,
+                        });
+                    }
+                },
+                MmioAction::Dma { addr, data } => {
+                    if let Err(err) = self.dma_write(addr, &data) {
+                        self.online = false;
+                        gui_err_print(&format!("Error: Dma write to {:#x} failed: {:?}", addr.0,
+                                               err), err_log);
+                    }
+                },
+                MmioAction::DeviceDmaRead { device, addr, len } => {
+                    let mut data = vec![0u8; len as usize];
+                    match self.dma_read(addr, &mut data) {
+                        Ok(()) => self.devices.dma_read(device, addr, &data),
+                        Err(err) => {
+                            self.online = false;
+                            gui_err_print(&format!("Error: Dma read from {:#x} failed: {:?}",
+                                                   addr.0, err), err_log);
+                        },
+                    }
+                },
+                MmioAction::RaiseIrq | MmioAction::DmaRead { .. } => {},
+            }
+            if !self.online {
+                return;
+            }
+        }
+    }
+
+    /// Whether an instruction is partway through the stages without pipelining, device actions
+    /// that interrupt the program wait until it completes
+    fn mid_instr(&self) -> bool {
+        !self.pipelining_enabled && !self.ooo_enabled && self.pipeline.cur_stage != 0
+    }
+
+    /// Interrupt the running program with the handler at interrupt-vector[`vector`]. In-flight
+    /// instructions are discarded, then the handler is entered like a `call` from the interrupted
+    /// instruction, so it can resume the program with `ret`. Interrupts without a registered
+    /// handler are ignored
+    pub fn raise_interrupt(&mut self, vector: u32) -> Result<(), SimErr> {
+        let mut reader = vec![0x0; 4];
+        self.mem_read(VAddr(vector.wrapping_mul(4)), &mut reader)?;
+        let handler = VAddr(as_u32_le(&reader));
+        if handler.0 == 0 {
+            return Ok(());
+        }
+
+        self.flush_pipeline();
+        let resume = self.pc;
+        self.write_reg(Register::R15, self.read_reg(Register::R15).wrapping_sub(4));
+        let mut prev_ra = self.read_reg(Register::R14).to_le_bytes().to_vec();
+        self.mem_write(VAddr(self.read_reg(Register::R15)), &mut prev_ra)?;
+        self.write_reg(Register::R14, resume.0);
+
+        self.pc          = handler;
+        self.pipeline.pc = handler;
+        self.enter_call(resume, handler);
+        if self.golden.is_some() {
+            self.golden = Some(GoldenModel::new(self));
+        }
+        Ok(())
+    }
+
+    /// Write `data` to memory at `addr` on behalf of a device. In-flight instructions may already
+    /// have read the old data, so they are discarded first. Lazily mapped pages are populated and
+    /// cached copies of the written lines are updated
+    fn dma_write(&mut self, addr: VAddr, data: &[u8]) -> Result<(), SimErr> {
+        self.flush_pipeline();
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + data.len()).step_by(PAGE_SIZE) {
+            self.mmu.populate_page(VAddr(page as u32))?;
+        }
+        self.mmu.mem_write_bulk(addr, data)?;
+        if let Some(golden) = self.golden.as_mut() {
+            golden.dma_write(addr, data)?;
+        }
+        self.dma_bus_requests(addr, data.len());
+        Ok(())
+    }
+
+    /// Read memory at `addr` into `reader` on behalf of a device. Stores have already been
+    /// written through to ram, so it is read directly
+    fn dma_read(&mut self, addr: VAddr, reader: &mut [u8]) -> Result<(), SimErr> {
+        let start = addr.0 as usize & !(PAGE_SIZE - 1);
+        for page in (start..addr.0 as usize + reader.len()).step_by(PAGE_SIZE) {
+            self.mmu.populate_page(VAddr(page as u32))?;
+        }
+        self.mmu.mem_read_bulk(addr, reader)?;
+        self.dma_bus_requests(addr, reader.len());
+        Ok(())
+    }
+
+    /// Occupy the memory bus with a ram request for every cache-line a `len` byte device transfer
+    /// at `addr` touches, so transfers compete with the cpu for it
+    fn dma_bus_requests(&mut self, addr: VAddr, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = addr.0 as usize / CACHE_LINE_SIZE;
+        let last  = (addr.0 as usize + len - 1) / CACHE_LINE_SIZE;
+        for _ in first..=last {
+            self.ram_access_stall(0);
+        }
+    }
+
     /// Assemble instruction from string-representation to its 32-bit assembled version
     fn assemble_instr(&mut self, instr_str: &str, labels: &FxHashMap<String, i32>, pc: u32,
-                      err_log: &Rc<RefCell<Frame>>) -> Result<u32, SimErr> {
+                      err_log: &dyn Logger) -> Result<u32, SimErr> {
         let mut instr = instr_str.split(' ').collect::<Vec<&str>>();
         let mut operation = instr[0];
 
@@ -471,9 +1746,9 @@ impl Simulator {
                 }
 
                 // Parse out registers from instruction
-                let rs3_idx = instr[1][1..].parse::<u32>().unwrap();
-                let rs1_idx = instr[2][1..].parse::<u32>().unwrap();
-                let rs2_idx = instr[3][1..].parse::<u32>().unwrap();
+                let rs3_idx = parse_reg(instr[1], err_log)?;
+                let rs1_idx = parse_reg(instr[2], err_log)?;
+                let rs2_idx = parse_reg(instr[3], err_log)?;
                 Ok(encode_rs1(rs1_idx) | encode_rs2(rs2_idx) | encode_rs3(rs3_idx) |
                    encode_opcode(operation))
             },
@@ -483,6 +1758,7 @@ impl Simulator {
             "stb"     |
             "sth"     |
             "st"      |
+            "stnt"    |
             "movi"    |
             "lui"     |
             "addi"    |
@@ -506,11 +1782,11 @@ impl Simulator {
                 }
 
                 // Parse out registers from instruction
-                let rs3_idx = instr[1][1..].parse::<u32>().unwrap();
-                let rs1_idx = instr[2][1..].parse::<u32>().unwrap();
+                let rs3_idx = parse_reg(instr[1], err_log)?;
+                let rs1_idx = parse_reg(instr[2], err_log)?;
 
                 let without_prefix = instr[3].trim_start_matches("0x");
-                let imm_idx = u32::from_str_radix(without_prefix, 16).unwrap();
+                let imm_idx = parse_hex(without_prefix, err_log)?;
 
                 Ok(encode_rs1(rs1_idx) | encode_rs3(rs3_idx) | encode_imm(imm_idx) |
                     encode_opcode(operation))
@@ -526,11 +1802,11 @@ impl Simulator {
                 }
 
                 // Parse out registers from instruction
-                let rs3_idx = instr[1][1..].parse::<u32>().unwrap();
-                let rs1_idx = instr[2][1..].parse::<u32>().unwrap();
+                let rs3_idx = parse_reg(instr[1], err_log)?;
+                let rs1_idx = parse_reg(instr[2], err_log)?;
 
                 let label = instr[3];
-                let addr = labels.get(label).unwrap();
+                let addr = parse_label(labels, label, err_log)?;
 
                 // Calculate relative offset corresponding to pc
                 let offset = addr.wrapping_sub(pc as i32) as u32;
@@ -547,7 +1823,7 @@ impl Simulator {
                 }
 
                 let label = instr[1];
-                let addr = labels.get(label).unwrap();
+                let addr = parse_label(labels, label, err_log)?;
 
                 // Zero-register as argument
                 let rs1_idx = 0;
@@ -574,7 +1850,7 @@ impl Simulator {
                 }
 
                 let without_prefix = instr[1].trim_start_matches("0x");
-                let addr = u32::from_str_radix(without_prefix, 16).unwrap();
+                let addr = parse_hex(without_prefix, err_log)?;
 
                 Ok(encode_opcode(operation) | encode_offset(addr))
             },
@@ -596,43 +1872,104 @@ impl Simulator {
         }
     }
 
-    /// Parse input from code-box, decode it into machine-code and write it into the specified
-    /// load-address
-    pub fn load_input(&mut self, input: &str, err_log: &Rc<RefCell<Frame>>)
-            -> Result<(), SimErr> {
-        // Split up lines and filter out comments/remove whitespace
-        let mut lines: Vec<&str> = input.split('\n').collect();
-        lines = lines.iter().map(|e| e.trim()).collect();
-        lines.retain(|e| !e.is_empty() && e.as_bytes()[0] != 0x23);
+    /// Parse input from code-box, decode it into machine-code and write it into the specified
+    /// load-address
+    pub fn load_input(&mut self, input: &str, err_log: &dyn Logger)
+            -> Result<(), SimErr> {
+        if let Some(log) = self.undo_log.as_mut() {
+            log.clear();
+        }
+        if let Some(trace) = self.exec_trace.as_mut() {
+            trace.sync_regs(self.gen_regs);
+        }
+        if let Some(history) = self.reg_history.as_mut() {
+            history.reset(self.gen_regs);
+        }
+        if let Some(log) = self.retire_log.as_mut() {
+            log.clear();
+        }
+        self.call_depth = 0;
+        self.call_stack.clear();
+        self.source = Some(input.to_string());
+        self.load_error_line = None;
+        self.symbols.clear();
+        self.functions.clear();
+        if let Some(profile) = self.profile.as_mut() {
+            profile.clear();
+        }
+
+        // Split up lines and filter out comments/remove whitespace
+        let mut lines: Vec<&str> = input.split('\n').collect();
+        lines = lines.iter().map(|e| e.trim()).collect();
+        lines.retain(|e| !e.is_empty() && e.as_bytes()[0] != 0x23);
+
+        // Pull out the entry-point directive (`.entry label` or `.global label`). It can be placed
+        // anywhere in the input and is resolved once all sections have been assembled
+        let mut entry: Option<String> = None;
+        for line in lines.iter() {
+            let parts = line.split_whitespace().collect::<Vec<&str>>();
+            if parts[0] != ".entry" && parts[0] != ".global" {
+                continue;
+            }
+
+            if parts.len() != 2 {
+                gui_err_print("Error: Entry directive expects a single label", err_log);
+                return Err(SimErr::LoadErr);
+            }
+
+            if entry.is_some() {
+                gui_err_print("Error: Multiple entry points defined", err_log);
+                return Err(SimErr::LoadErr);
+            }
+
+            // Labels are referenced with their leading `.`, but allow it to be omitted here
+            entry = if parts[1].starts_with('.') {
+                Some(parts[1].to_string())
+            } else {
+                Some(format!(".{}", parts[1]))
+            };
+        }
+        lines.retain(|e| !matches!(e.split_whitespace().next(), Some(".entry" | ".global")));
 
         #[derive(Debug)]
-        struct Function {
+        struct Function<'a> {
             name: String,
             load_addr: u32,
-            lines: Vec<String>,
+            lines: Vec<&'a str>,
         }
 
         // Iterate through lines and separate them into code-sections with different load-addresses
         let mut functions: Vec<Function> = Vec::new();
         let mut counter = 0;
         let mut first = true;
-        let mut tmp_lines: Vec<String> = Vec::new();
+        let mut tmp_lines: Vec<&str> = Vec::new();
         let mut name = "";
         let mut load_addr = 0x0;
+        let mut constants: FxHashMap<String, u32> = FxHashMap::default();
         while counter < lines.len() {
+            // Constant definition: `.equ NAME expr`
+            if lines[counter].split_whitespace().next() == Some(".equ") {
+                let parts = lines[counter].split_whitespace().collect::<Vec<&str>>();
+                if parts.len() < 3 {
+                    gui_err_print("Error: Constant definitions use `.equ NAME value`", err_log);
+                    return Err(SimErr::LoadErr);
+                }
+
+                let val = eval_const_expr(&parts[2..].join(" "), &constants, err_log)?;
+                constants.insert(parts[1].to_string(), val);
+                counter += 1;
+                continue;
+            }
+
             if first && !lines[counter].contains(".load") {
                 gui_err_print("Error: Code needs to start with load instructions", err_log);
                 return Err(SimErr::LoadErr);
             } else if first {
-                // Parse out load address for this code section
-                let raw_addr = lines[counter].split(' ').collect::<Vec<&str>>()[1];
-                let without_prefix = raw_addr.trim_start_matches("0x");
-                if let Ok(addr) = u32::from_str_radix(without_prefix, 16) {
-                    load_addr = addr
-                } else {
-                    gui_err_print("Error: Invalid load address", err_log);
-                    return Err(SimErr::LoadErr);
-                }
+                // Parse out load address for this code section. This can be an expression using
+                // previously defined constants, eg. `.load BASE + 0x1000`
+                let raw_addr = lines[counter].trim_start_matches(".load");
+                load_addr = eval_const_expr(raw_addr, &constants, err_log)?
+                    .wrapping_add(self.code_slide);
 
                 name = lines[counter + 1];
 
@@ -654,10 +1991,13 @@ impl Simulator {
                 continue;
             }
 
-            tmp_lines.push(lines[counter].to_string());
+            tmp_lines.push(lines[counter]);
             counter += 1;
         }
 
+        // Addresses of all section-names and labels, used to resolve the entry-point
+        let mut symbols: FxHashMap<String, u32> = FxHashMap::default();
+
         for function in functions {
             let mut size = 0;
 
@@ -681,7 +2021,11 @@ impl Simulator {
             let mut cur_addr = function.load_addr;
             for line in &function.lines {
                 if line.chars().nth(0).unwrap() != '.' {
-                    raw.push(self.assemble_instr(line, &labels, cur_addr, err_log)?);
+                    let instr = self.assemble_instr(line, &labels, cur_addr, err_log);
+                    if instr.is_err() {
+                        self.load_error_line = Some(line_number(input, line));
+                    }
+                    raw.push(instr?);
                     cur_addr += 4;
                 }
             }
@@ -692,19 +2036,48 @@ impl Simulator {
 
             self.mem_write(VAddr(function.load_addr), &mut u8_arr)?;
 
-            // Entry-point
-            if function.name == "._start" {
-                *CODE_LOAD_ADDR.lock().unwrap() = VAddr(function.load_addr);
-                self.pc = VAddr(function.load_addr);
-                self.pipeline.pc = self.pc;
+            symbols.insert(function.name.clone(), function.load_addr);
+            let name = function.name.trim_start_matches('.').to_string();
+            self.symbols.insert(function.load_addr, name.clone());
+            self.functions.insert(function.load_addr, name);
+            for (label, addr) in labels {
+                // Sections are named after their function, so they take precedence over labels
+                let name = label.trim_start_matches('.').to_string();
+                self.symbols.entry(addr as u32).or_insert(name);
+                symbols.insert(label, addr as u32);
             }
 
             if size > (PAGE_SIZE / 4) {
-                panic!("Section too big");
+                gui_err_print(&format!("Error: Section `{}` does not fit into a page",
+                                       function.name), err_log);
+                return Err(SimErr::LoadErr);
+            }
+        }
+
+        // Entry-point
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                gui_err_print("Error: No entry point defined, use `.entry <label>`", err_log);
+                return Err(SimErr::LoadErr);
             }
+        };
+        if let Some(addr) = symbols.get(&entry) {
+            *CODE_LOAD_ADDR.lock().unwrap() = VAddr(*addr);
+            self.pc = VAddr(*addr);
+            self.pipeline.pc = self.pc;
+        } else {
+            gui_err_print(&format!("Error: Entry point `{}` is not a defined label", entry), 
+                          err_log);
+            return Err(SimErr::LoadErr);
         }
 
         self.clear_caches();
+
+        // Restart the golden model from the newly loaded program
+        if self.golden.is_some() {
+            self.golden = Some(GoldenModel::new(self));
+        }
         Ok(())
     }
 
@@ -725,56 +2098,196 @@ impl Simulator {
     /// Reads next instruction from memory @ `pipeline.pc`
     /// Increments `pipeline.pc`
     pub fn pl_fetch_stage(&mut self) -> Result<(), SimErr> {
-        // Fetch instruction from memory
-        let mut reader = vec![0x0u8; 4];
-        self.mem_read(self.pipeline.pc, &mut reader)?;
-        let raw: u32 = as_u32_le(&reader);
+        // Instructions can only be fetched from mapped executable pages. Whether this faults is
+        // decided once the instruction reaches the execute stage
+        let raw: u32 = match self.mmu.translate_addr(self.pipeline.pc, Perms::READ | Perms::EXEC) {
+            Err(err) => {
+                self.pipeline.slots[0].fetch_fault = Some(match err {
+                    SimErr::Permission => SimErr::ExecFault,
+                    _ => err,
+                });
+                0
+            },
+            _ => {
+                // Fetch instruction from memory
+                let mut reader = vec![0x0u8; 4];
+                self.mem_read(self.pipeline.pc, &mut reader)?;
+                as_u32_le(&reader)
+            }
+        };
 
         // Load it into our pipeline instruction backing so we can use the bytes in future pipeline
         // stages
         self.pipeline.slots[0].instr_backing = raw;
         self.pipeline.slots[0].valid         = true;
         self.pipeline.slots[0].pc            = self.pipeline.pc;
+        self.pipeline.slots[0].seq           = self.pipeline.next_seq;
+        self.pipeline.slots[0].squashed      = None;
+        self.pipeline.slots[0].bubble        = None;
+        self.pipeline.next_seq += 1;
 
         // Advance internal pc. This does not yet advance the actual pc, but the pc that future
         // pipeline stages operate on
-        self.pipeline.pc.0 += 4;
+        self.pipeline.pc.0 = self.pipeline.pc.0.wrapping_add(4);
         Ok(())
     }
 
-    /// Checks if there are any data hazards in the pipeline for one of the registers in `reg_uses`
-    fn caused_data_hazards(&mut self, cur_stage: usize, reg_uses: &Vec<Register>) -> bool {
-        // Loop through all instructions in the pipeline that were scheduled before this instruction
-        for i in (cur_stage + 1)..=4 {
-            // Skip invalid pipeline slots
-            if !self.pipeline.slots[i].valid {
+    /// Returns the closest pipeline slot scheduled before `cur_stage` that will write to `reg`
+    fn pending_writer(&self, cur_stage: usize, reg: Register) -> Option<usize> {
+        ((cur_stage + 1)..=4).find(|&i| {
+            self.pipeline.slots[i].valid && self.pipeline.slots[i].instr.writes_to_rs3()
+                                                                         .contains(&reg)
+        }).or_else(|| {
+            // Loads with an outstanding miss are reported in the memory stage
+            self.pipeline.mshrs.iter().any(|mshr| mshr.reg == reg).then_some(3)
+        })
+    }
+
+    /// Scoreboard of pending register writes. For every register this returns the pipeline slot
+    /// whose instruction will write it, out of the slots an instruction in decode has to wait for
+    pub fn scoreboard(&self) -> [Option<usize>; NUM_REGS] {
+        let mut board = [None; NUM_REGS];
+        for (i, entry) in board.iter_mut().enumerate() {
+            *entry = self.pending_writer(1, Register::from(i as u32));
+        }
+        board
+    }
+
+    /// Registers the instruction stalled in the decode stage is waiting on
+    pub fn hazard_regs(&self) -> Vec<Register> {
+        if self.pipeline.hazard_thrower != Some(1) {
+            return Vec::new();
+        }
+        let instr = self.pipeline.slots[1].instr;
+        instr.uses_regs().into_iter()
+            .filter(|reg| self.data_hazard(1, instr, *reg).is_some())
+            .collect()
+    }
+
+    /// Data dependences of the instruction in decode on the older instructions in the pipeline,
+    /// closest first. Loads with an outstanding miss are reported in the memory stage
+    pub fn decode_hazards(&self) -> Vec<Hazard> {
+        let decode = &self.pipeline.slots[1];
+        if !decode.valid {
+            return Vec::new();
+        }
+
+        let mut hazards = Vec::new();
+        for stage in 2..=4 {
+            let older = &self.pipeline.slots[stage];
+            if !older.valid {
                 continue;
             }
-            // Check if this instruction writes to rs3
-            let regs_written = self.pipeline.slots[i].instr.writes_to_rs3();
-            for reg_written in regs_written {
-                for reg in reg_uses.iter() {
-                    if reg_written == *reg {
-                        // Data Hazard
-                        // This instruction tries reading a register that is still in the pipeline
-                        // to be written to
-
-                        // Disablethe pipeline so we no longer attempt to execute new instructions
-                        self.pipeline.disable = true;
-
-                        // Disable all instructions placed lower in the pipeline since these should
-                        // not be executing while this instruction is stalled
-                        let mut counter = cur_stage+1;
-                        while counter > 0 {
-                            self.pipeline.slots[counter-1].disable = true;
-                            counter-=1;
-                        }
-                        return true;
-                    }
+            for (kind, reg) in HazardKind::classify(&decode.instr, &older.instr) {
+                hazards.push(Hazard { kind, reg, stage });
+            }
+        }
+        for mshr in self.pipeline.mshrs.iter() {
+            let load = Instr::Ld { rs3: mshr.reg, rs1: Register::R0, imm: 0 };
+            for (kind, reg) in HazardKind::classify(&decode.instr, &load) {
+                if kind != HazardKind::War {
+                    hazards.push(Hazard { kind, reg, stage: 3 });
                 }
             }
         }
-        return false;
+        hazards
+    }
+
+    /// Checks if `instr` in `cur_stage` has to wait before it can read `reg`, and returns the kind
+    /// of bubble the stall inserts. With forwarding, results reach the execute stage as soon as
+    /// they are computed. This leaves loads that did not access memory yet (`LoadUse`), and
+    /// instructions whose results are not forwarded, eg. because they are only written in the
+    /// writeback stage (`DataHazard`)
+    fn data_hazard(&self, cur_stage: usize, instr: Instr, reg: Register) -> Option<Bubble> {
+        let stage = self.pending_writer(cur_stage, reg)?;
+
+        // Call and ret read their operands before they reach the execute stage
+        if !self.forwarding_enabled || matches!(instr, Instr::Call { .. } | Instr::Ret { .. }) {
+            return Some(Bubble::DataHazard);
+        }
+
+        // Loads with an outstanding miss don't have their data yet
+        if self.pipeline.mshrs.iter().any(|mshr| mshr.reg == reg) {
+            return Some(Bubble::DataHazard);
+        }
+
+        match self.pipeline.slots[stage].instr {
+            Instr::Ldb { .. } | Instr::Ldh { .. } | Instr::Ld { .. } => {
+                (stage == cur_stage + 1).then_some(Bubble::LoadUse)
+            },
+            Instr::Add  { .. } | Instr::Sub  { .. } | Instr::Xor  { .. } | Instr::Or   { .. } |
+            Instr::And  { .. } | Instr::Shr  { .. } | Instr::Shl  { .. } | Instr::Mul  { .. } |
+            Instr::Div  { .. } | Instr::Addi { .. } | Instr::Subi { .. } | Instr::Xori { .. } |
+            Instr::Ori  { .. } | Instr::Andi { .. } | Instr::Lui  { .. } => None,
+            _ => Some(Bubble::DataHazard),
+        }
+    }
+
+    /// Checks if there are any data hazards in the pipeline for one of the registers `instr` reads.
+    /// Returns the kind of bubble the stall inserts, hazards that forwarding can't resolve take
+    /// precedence over load-use hazards
+    fn caused_data_hazards(&mut self, cur_stage: usize, instr: Instr) -> Option<Bubble> {
+        // This instruction tries reading a register that is still in the pipeline to be written to
+        let bubble = instr.uses_regs().into_iter()
+            .filter_map(|reg| self.data_hazard(cur_stage, instr, reg))
+            .min_by_key(|bubble| *bubble != Bubble::DataHazard)?;
+
+        // Disable all instructions placed lower in the pipeline since these should not be
+        // executing while this instruction is stalled. Fetching continues into the fetch
+        // queue until it is full
+        for slot in self.pipeline.slots[1..=cur_stage].iter_mut() {
+            slot.disable = true;
+        }
+        Some(bubble)
+    }
+
+    /// Read the operands of the instruction in the execute stage again, forwarding the results of
+    /// older instructions from the memory and writeback stages that are not written back yet
+    fn forward_operands(&mut self) {
+        let value = |reg: Register| {
+            if reg == Register::R0 {
+                return 0;
+            }
+            self.pipeline.slots[3..=4].iter()
+                .find(|slot| slot.valid && slot.instr.writes_to_rs3().contains(&reg))
+                .map_or(self.read_reg(reg), |slot| slot.rs3)
+        };
+
+        let slot = &self.pipeline.slots[2];
+        let (rs1, rs2, rs3) = match slot.instr {
+            Instr::Add { rs1, rs2, .. } |
+            Instr::Sub { rs1, rs2, .. } |
+            Instr::Xor { rs1, rs2, .. } |
+            Instr::Or  { rs1, rs2, .. } |
+            Instr::And { rs1, rs2, .. } |
+            Instr::Div { rs1, rs2, .. } |
+            Instr::Mul { rs1, rs2, .. } |
+            Instr::Shr { rs1, rs2, .. } |
+            Instr::Shl { rs1, rs2, .. } => (value(rs1), value(rs2), slot.rs3),
+            Instr::Ldb  { rs1, .. } |
+            Instr::Ldh  { rs1, .. } |
+            Instr::Ld   { rs1, .. } |
+            Instr::Addi { rs1, .. } |
+            Instr::Subi { rs1, .. } |
+            Instr::Xori { rs1, .. } |
+            Instr::Ori  { rs1, .. } |
+            Instr::Andi { rs1, .. } => (value(rs1), slot.rs2, slot.rs3),
+            Instr::Stb  { rs3, rs1, .. } |
+            Instr::Sth  { rs3, rs1, .. } |
+            Instr::St   { rs3, rs1, .. } |
+            Instr::Stnt { rs3, rs1, .. } |
+            Instr::Beq  { rs3, rs1, .. } |
+            Instr::Bne  { rs3, rs1, .. } |
+            Instr::Blt  { rs3, rs1, .. } |
+            Instr::Bgt  { rs3, rs1, .. } => (value(rs1), slot.rs2, value(rs3)),
+            Instr::Jmpr { rs3, .. } => (slot.rs1, slot.rs2, value(rs3)),
+            _ => return,
+        };
+
+        let slot = &mut self.pipeline.slots[2];
+        slot.rs1 = rs1;
+        slot.rs2 = rs2;
+        slot.rs3 = rs3;
     }
 
     /// Perform decode stage of pipeline
@@ -783,15 +2296,28 @@ impl Simulator {
             return Ok(())
         }
 
+        // Nothing to decode if the fetch already faulted
+        if self.pipeline.slots[1].fetch_fault.is_some() {
+            self.pipeline.slots[1].instr = Instr::Invalid;
+            return Ok(())
+        }
+
         // Decode the instruction and load it into the pipeline
-        let instr = cpu::decode_instr(self.pipeline.slots[1].instr_backing)?;
+        let slot  = &self.pipeline.slots[1];
+        let instr = self.decode_cache.decode(slot.pc.0, slot.instr_backing)?;
         self.pipeline.slots[1].instr = instr;
 
-        let use_regs = instr.uses_regs();
-        if self.caused_data_hazards(1, &use_regs) {
+        if let Some(bubble) = self.caused_data_hazards(1, instr) {
+            if bubble == Bubble::LoadUse {
+                self.stats.load_use_stall_clock += 1;
+            } else {
+                self.stats.raw_stall_clock += 1;
+            }
+
             // Caused hazard - can't continue executing pipeline-stage
             // Indicate that this instruction threw the hazard
             self.pipeline.hazard_thrower = Some(1);
+            self.pipeline.hazard_bubble  = Some(bubble);
             return Ok(())
         } else {
             // Didn't cause hazard
@@ -799,7 +2325,6 @@ impl Simulator {
                 // If we are the one that caused the hazard, and its no longer an issue, reenable
                 // the entire pipeline
                 if c == 1 {
-                    self.pipeline.disable = false;
                     for i in 0..5 {
                         self.pipeline.slots[i].disable = false;
                     }
@@ -828,6 +2353,7 @@ impl Simulator {
             Instr::Stb  { rs3, rs1, imm} |
             Instr::Sth  { rs3, rs1, imm} |
             Instr::St   { rs3, rs1, imm} |
+            Instr::Stnt { rs3, rs1, imm} |
             Instr::Addi { rs3, rs1, imm} |
             Instr::Subi { rs3, rs1, imm} |
             Instr::Xori { rs3, rs1, imm} |
@@ -850,44 +2376,61 @@ impl Simulator {
                 self.pipeline.slots[1].imm    = imm;
                 self.pipeline.slots[1].rs3    = self.read_reg(rs3);
 
-                // Reset incorrect pipeline slot
-                // We properly handle the flush in the exec state
-                self.pipeline.slots[0] = Slot::default();
+                let pc        = self.pipeline.slots[1].pc;
+                let target    = VAddr(((pc.0 as i64) + imm as i64) as u32);
+                let predicted = if self.pipelining_enabled {
+                    self.pipeline.predictor.predict(pc, target)
+                } else {
+                    None
+                };
 
-                // We won't know what the next pc will be until exec-stage so stop unnecessarily 
-                // fetching new instructions until we know the correct address
-                self.pipeline.disable = true;
+                match predicted {
+                    Some(next_pc) => {
+                        // Speculatively continue fetching down the predicted path. The execute
+                        // stage squashes these instructions if the prediction was wrong
+                        self.pipeline.slots[1].predicted_pc = Some(next_pc);
+                        if next_pc.0 != pc.0.wrapping_add(4) {
+                            self.flush_front_end();
+                            self.pipeline.pc = next_pc;
+                        }
+                    },
+                    None => {
+                        // Reset incorrect pipeline slots
+                        // We properly handle the flush in the exec state
+                        self.flush_front_end();
+
+                        // We won't know what the next pc will be until exec-stage so stop
+                        // unnecessarily fetching new instructions until we know the correct address
+                        self.pipeline.disable = true;
+                    },
+                }
             },
             Instr::Jmpr { rs3, offset } => {
                 self.pipeline.slots[1].offset = offset;
                 self.pipeline.slots[1].rs3    = self.read_reg(rs3);
 
-                // Reset incorrect pipeline slot and redirect `pipeline.pc` to decode at 
+                // Reset incorrect pipeline slots and redirect `pipeline.pc` to decode at 
                 // branch-target
-                self.pipeline.slots[0] = Slot::default();
-                if self.pipelining_enabled {
-                    self.pipeline.pc.0 = (((self.pipeline.pc.0 - 8) as i32) + offset) as u32;
-                } else {
-                    self.pipeline.pc.0 = (((self.pipeline.pc.0 - 4) as i32) + offset) as u32;
-                }
+                self.flush_front_end();
+                self.pipeline.pc.0 = ((self.pipeline.slots[1].pc.0 as i32) + offset) as u32;
             },
             Instr::Call { offset, .. } => {
                 self.pipeline.slots[1].addr = VAddr(offset as u32);
 
-                // Reset incorrect pipeline slot and redirect `pipeline.pc` to decode at 
+                // Reset incorrect pipeline slots and redirect `pipeline.pc` to decode at 
                 // branch-target
-                self.pipeline.slots[0] = Slot::default();
+                self.flush_front_end();
                 self.pipeline.pc = self.pipeline.slots[1].addr;
             },
             Instr::Ret { } => {
                 self.pipeline.slots[1].addr = VAddr(self.read_reg(Register::R14));
-                self.pipeline.slots[0] = Slot::default();
+                self.flush_front_end();
                 self.pipeline.pc = self.pipeline.slots[1].addr;
             }
             Instr::Int0 {} => {
-                // This means the instruction we just loaded into the pipeline is no longer valid
+                // This means the instructions we just loaded into the pipeline are no longer valid
                 // We properly handle the flush in the exec state
-                self.pipeline.slots[0] = Slot::default();
+                self.flush_front_end();
 
                 // We won't know what the next pc will be until mem-stage so stop unnecessarily 
                 // fetching new instructions until we know the correct address
@@ -901,13 +2444,40 @@ impl Simulator {
         Ok(())
     }
 
+    /// Squash the wrong-path instruction in pipeline slot `idx`. The slot is left as a bubble that
+    /// remembers the squashed instruction so the gui can still display it
+    fn squash_slot(&mut self, idx: usize) {
+        let slot = &self.pipeline.slots[idx];
+        let squashed = if slot.valid {
+            // The instruction in the fetch stage was not decoded yet
+            let instr = match (idx, slot.fetch_fault.is_some()) {
+                (_, true) => Instr::Invalid,
+                (0, _)    => cpu::decode_instr(slot.instr_backing).unwrap_or(Instr::Invalid),
+                _         => slot.instr,
+            };
+            self.stats.wrong_path_instrs += 1;
+            Some((slot.pc, instr))
+        } else {
+            None
+        };
+        self.pipeline.slots[idx] = Slot { squashed, ..Slot::bubble(Bubble::Flush) };
+    }
+
     /// Perform execute stage of pipeline
     pub fn pl_execute_stage(&mut self) -> Result<(), SimErr> {
         if self.pipeline.slots[2].valid == false {
             return Ok(())
         }
 
-        self.stats.total_instrs += 1.0;
+        if let Some(fault) = self.pipeline.slots[2].fetch_fault {
+            return Err(fault);
+        }
+
+        self.stats.total_instrs += 1;
+
+        if self.forwarding_enabled && self.pipelining_enabled {
+            self.forward_operands();
+        }
 
         let instr = self.pipeline.slots[2].instr;
 
@@ -915,27 +2485,31 @@ impl Simulator {
             Instr::Ldb { .. } |
             Instr::Ldh { .. } |
             Instr::Ld  { .. } => { // (rs1 + offset) address calculation
-                self.stats.load_instrs += 1.0;
+                self.stats.load_instrs += 1;
                 self.pipeline.slots[2].addr = VAddr((self.pipeline.slots[2].rs1 as i64
                             + self.pipeline.slots[2].offset as i64) as u32);
             }
-            Instr::Stb { .. } |
-            Instr::Sth { .. } |
-            Instr::St  { .. } => { // (rs1 + offset) address calculation
-                self.stats.store_instrs += 1.0;
+            Instr::Stb  { .. } |
+            Instr::Sth  { .. } |
+            Instr::St   { .. } |
+            Instr::Stnt { .. } => { // (rs1 + offset) address calculation
+                self.stats.store_instrs += 1;
+                if let Instr::Stnt { .. } = instr {
+                    self.stats.nt_store_instrs += 1;
+                }
                 self.pipeline.slots[2].addr = VAddr((self.pipeline.slots[2].rs1 as i64
                             + self.pipeline.slots[2].offset as i64) as u32);
             },
             Instr::Jmpr { .. } => { // (pc + offset) address calculation
-                self.stats.control_instrs += 1.0;
-                self.pipeline.slots[2].addr = VAddr((self.pipeline.pc.0 as i64
+                self.stats.control_instrs += 1;
+                self.pipeline.slots[2].addr = VAddr((self.pipeline.slots[2].pc.0 as i64
                             + self.pipeline.slots[2].offset as i64) as u32);
             },
             Instr::Bne { .. } |
             Instr::Beq { .. } |
             Instr::Blt { .. } |
             Instr::Bgt { .. } => { // (comparison & (pc + offset)) address calculation
-                self.stats.control_instrs += 1.0;
+                self.stats.control_instrs += 1;
 
                 let is_true = match instr {
                     Instr::Bne { .. } => self.pipeline.slots[2].rs3 != self.pipeline.slots[2].rs1,
@@ -945,109 +2519,131 @@ impl Simulator {
                     _ => unreachable!(),
                 };
 
-                // Flush invalid pipeline stages and redirect pipeline-fetches to interrupt handler
-                self.pipeline.slots[0] = Slot::default();
-                self.pipeline.slots[1] = Slot::default();
-
                 // Assign the target-address to one either true-target or false-target
+                let pc     = self.pipeline.slots[2].pc;
+                let target = VAddr(((pc.0 as i64) + self.pipeline.slots[2].imm as i64) as u32);
+                let next   = VAddr(pc.0.wrapping_add(4));
+                self.pipeline.slots[2].addr = if is_true { target } else { next };
                 if is_true {
-                    self.pipeline.slots[2].addr = VAddr(((self.pipeline.slots[2].pc.0) as i64 +
-                                                    self.pipeline.slots[2].imm as i64) as u32);
-                } else {
-                    self.pipeline.slots[2].addr.0 = self.pipeline.slots[2].pc.0 + 4;
+                    self.stats.metrics.add("branch.taken", 1);
+                }
+
+                let predicted_pc = self.pipeline.slots[2].predicted_pc;
+                if predicted_pc.is_some() {
+                    self.pipeline.predictor.update(pc, target, is_true);
+                    self.stats.branch_predictions += 1;
+                }
+
+                // Nothing to do if the front-end already fetched down the correct path
+                if predicted_pc == Some(self.pipeline.slots[2].addr) {
+                    return Ok(());
+                }
+
+                if predicted_pc.is_some() {
+                    self.stats.branch_mispredicts += 1;
+                    self.stats.mispredict_clock   += MISPREDICT_PENALTY as u64;
                 }
 
+                // Flush wrong-path pipeline stages and redirect pipeline-fetches to the target
+                self.squash_slot(0);
+                self.squash_slot(1);
+                self.stats.wrong_path_instrs += self.pipeline.fetch_queue.len() as u64;
+                self.pipeline.fetch_queue.clear();
+                self.pipeline.hazard_thrower = None;
+
                 self.pipeline.pc = self.pipeline.slots[2].addr;
 
                 // We now know the correct pipeline-pc so start fetching again
                 self.pipeline.disable = false;
             },
             Instr::Lui { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 = (self.pipeline.slots[2].imm << 12) as u32;
             },
             Instr::Add { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    self.pipeline.slots[2].rs1 + self.pipeline.slots[2].rs2;
+                    self.pipeline.slots[2].rs1.wrapping_add(self.pipeline.slots[2].rs2);
             },
             Instr::Sub { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    self.pipeline.slots[2].rs1 - self.pipeline.slots[2].rs2;
+                    self.pipeline.slots[2].rs1.wrapping_sub(self.pipeline.slots[2].rs2);
             },
             Instr::Xor { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     self.pipeline.slots[2].rs1 ^ self.pipeline.slots[2].rs2;
             },
             Instr::Or  { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     self.pipeline.slots[2].rs1 | self.pipeline.slots[2].rs2;
             },
             Instr::And { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     self.pipeline.slots[2].rs1 & self.pipeline.slots[2].rs2;
             },
             Instr::Shr { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    self.pipeline.slots[2].rs1 >> self.pipeline.slots[2].rs2;
+                    self.pipeline.slots[2].rs1.wrapping_shr(self.pipeline.slots[2].rs2);
             },
             Instr::Shl { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    self.pipeline.slots[2].rs1 << self.pipeline.slots[2].rs2;
+                    self.pipeline.slots[2].rs1.wrapping_shl(self.pipeline.slots[2].rs2);
             },
             Instr::Mul { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    self.pipeline.slots[2].rs1 * self.pipeline.slots[2].rs2;
+                    self.pipeline.slots[2].rs1.wrapping_mul(self.pipeline.slots[2].rs2);
             },
             Instr::Div { .. } => {
                 if self.pipeline.slots[2].rs2 == 0 {
                     return Err(SimErr::DivByZero);
                 }
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     self.pipeline.slots[2].rs1 / self.pipeline.slots[2].rs2;
             },
             Instr::Addi { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    ((self.pipeline.slots[2].rs1 as i32) + self.pipeline.slots[2].imm ) as u32;
+                    (self.pipeline.slots[2].rs1 as i32).wrapping_add(self.pipeline.slots[2].imm)
+                        as u32;
             },
             Instr::Subi { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
-                    ((self.pipeline.slots[2].rs1 as i32) - self.pipeline.slots[2].imm ) as u32;
+                    (self.pipeline.slots[2].rs1 as i32).wrapping_sub(self.pipeline.slots[2].imm)
+                        as u32;
             },
             Instr::Xori { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     ((self.pipeline.slots[2].rs1 as i32) ^ self.pipeline.slots[2].imm ) as u32;
             },
             Instr::Ori  { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     ((self.pipeline.slots[2].rs1 as i32) | self.pipeline.slots[2].imm ) as u32;
             },
             Instr::Andi { .. } => {
-                self.stats.arithmetic_instrs += 1.0;
+                self.stats.arithmetic_instrs += 1;
                 self.pipeline.slots[2].rs3 =
                     ((self.pipeline.slots[2].rs1 as i32) & self.pipeline.slots[2].imm ) as u32;
             },
             Instr::Invalid { .. } => {},
             Instr::Call    { .. } => {
-                self.stats.control_instrs += 1.0;
+                self.stats.control_instrs += 1;
             },
             Instr::Ret     { .. } => {
-                self.stats.control_instrs += 1.0;
+                self.stats.control_instrs += 1;
             },
             Instr::Int0 { .. } => {
-                self.stats.control_instrs += 1.0;
+                self.stats.control_instrs += 1;
             },
             Instr::Nop            => {},
             Instr::None    { .. } => unreachable!(),
@@ -1070,11 +2666,12 @@ impl Simulator {
                 // Read link register from stack and store in r14
                 let mut reader = vec![0x0; 4];
                 let addr_to_read = self.read_reg(Register::R15);
-                self.mem_read(VAddr(addr_to_read), &mut reader).unwrap();
+                self.mem_read(VAddr(addr_to_read), &mut reader)?;
                 let new_link = as_u32_le(&reader);
                 self.pipeline.slots[3].rs3 = new_link;
 
                 self.pc = self.pipeline.slots[3].addr;
+                self.leave_call();
             },
             Instr::Bne  { .. } |
             Instr::Beq  { .. } |
@@ -1084,23 +2681,24 @@ impl Simulator {
             },
             Instr::Jmpr { .. } => {
                 let pc = self.pc;
-                self.pc = VAddr(((pc.0 as i32) + self.pipeline.slots[3].offset) as u32);
+                self.pc = VAddr((pc.0 as i32).wrapping_add(self.pipeline.slots[3].offset) as u32);
             },
             Instr::Call { .. } => {
                 // Make room on stack
-                self.write_reg(Register::R15, self.read_reg(Register::R15) - 4);
+                self.write_reg(Register::R15, self.read_reg(Register::R15).wrapping_sub(4));
 
                 // Push link register
                 let mut prev_ra = self.read_reg(Register::R14).to_le().to_ne_bytes().to_vec();
-                self.mem_write(VAddr(self.read_reg(Register::R15)), &mut prev_ra).unwrap();
+                self.mem_write(VAddr(self.read_reg(Register::R15)), &mut prev_ra)?;
 
                 // Update link-register to return address
-                self.write_reg(Register::R14, self.pc.0 + 4);
+                self.write_reg(Register::R14, self.pc.0.wrapping_add(4));
                                
                 self.pc = self.pipeline.slots[3].addr;
+                self.enter_call(self.pipeline.slots[3].pc, self.pc);
             },
             _ => { // Everything else, just increment pc
-                self.pc.0 = self.pipeline.slots[3].pc.0 + 4;
+                self.pc.0 = self.pipeline.slots[3].pc.0.wrapping_add(4);
             },
         }
 
@@ -1109,11 +2707,13 @@ impl Simulator {
             Instr::Ldb { .. } => {
                 let mut reader = vec![0x0; 1];
                 self.mem_read(self.pipeline.slots[3].addr, &mut reader)?;
+                reader.resize(4, 0);
                 self.pipeline.slots[3].rs3 = as_u32_le(&reader);
             },
             Instr::Ldh { .. } => {
                 let mut reader = vec![0x0; 2];
                 self.mem_read(self.pipeline.slots[3].addr, &mut reader)?;
+                reader.resize(4, 0);
                 self.pipeline.slots[3].rs3 = as_u32_le(&reader);
             },
             Instr::Ld { .. } => {
@@ -1124,18 +2724,25 @@ impl Simulator {
             Instr::Stb { .. } => {
                 let mut writer = vec![self.pipeline.slots[3].rs3 as u8];
                 assert_eq!(writer.len(), 1);
+                self.record_store_access(self.pipeline.slots[3].addr);
                 self.mem_write(self.pipeline.slots[3].addr, &mut writer)?;
             },
             Instr::Sth { .. } => {
                 let mut writer = (self.pipeline.slots[3].rs3 as u16).to_le().to_ne_bytes().to_vec();
                 assert_eq!(writer.len(), 2);
+                self.record_store_access(self.pipeline.slots[3].addr);
                 self.mem_write(self.pipeline.slots[3].addr, &mut writer)?;
             },
             Instr::St { .. } => {
                 let mut writer = self.pipeline.slots[3].rs3.to_le().to_ne_bytes().to_vec();
                 assert_eq!(writer.len(), 4);
+                self.record_store_access(self.pipeline.slots[3].addr);
                 self.mem_write(self.pipeline.slots[3].addr, &mut writer)?;
             },
+            Instr::Stnt { .. } => {
+                let writer = self.pipeline.slots[3].rs3.to_le().to_ne_bytes();
+                self.mem_write_nt(self.pipeline.slots[3].addr, &writer)?;
+            },
             Instr::Int0 { .. } => {
                 // Read Interrupt-table+0x0 to find address that is responsible for handling Int0
                 let mut reader = vec![0x0; 4];
@@ -1145,9 +2752,9 @@ impl Simulator {
                 self.pipeline.slots[3].addr = VAddr(addr);
 
                 // Flush invalid pipeline stages and redirect pipeline-fetches to interrupt handler
-                self.pipeline.slots[0] = Slot::default();
-                self.pipeline.slots[1] = Slot::default();
-                self.pipeline.slots[2] = Slot::default();
+                self.flush_front_end();
+                self.pipeline.slots[1] = Slot::bubble(Bubble::Flush);
+                self.pipeline.slots[2] = Slot::bubble(Bubble::Flush);
 
                 self.pipeline.pc = VAddr(addr);
                 self.pc = self.pipeline.slots[3].addr;
@@ -1166,8 +2773,9 @@ impl Simulator {
             return Ok(())
         }
 
+        // Instructions that failed to decode fault once they are known to be on the correct path
         if self.pipeline.slots[4].instr == Instr::Invalid {
-            panic!("Invalid instr made it through the pipeline");
+            return Err(SimErr::InstrDecode);
         }
 
         let instr = self.pipeline.slots[4].instr;
@@ -1179,6 +2787,7 @@ impl Simulator {
             Instr::Stb     { .. } |
             Instr::Sth     { .. } |
             Instr::St      { .. } |
+            Instr::Stnt    { .. } |
             Instr::Bne     { .. } |
             Instr::Beq     { .. } |
             Instr::Blt     { .. } |
@@ -1214,10 +2823,11 @@ impl Simulator {
 
                 // Increase stack pointer
                 let addr_to_read = self.read_reg(Register::R15);
-                self.write_reg(Register::R15, addr_to_read + 4);
+                self.write_reg(Register::R15, addr_to_read.wrapping_add(4));
             },
             Instr::Nop => {},
         }
+        self.instr_retired(self.pipeline.slots[4].pc, instr);
         Ok(())
     }
 
@@ -1233,6 +2843,93 @@ impl Simulator {
     }
 }
 
+/// Evaluate a constant expression such as `BASE + 0x1000 - OFF` consisting of hex-values and
+/// previously defined `.equ` constants. Hex-values can leave out the `0x` prefix unless they are
+/// the name of a constant. Malformed expressions, undefined constants and results that don't fit
+/// into 32 bits fail to assemble
+fn eval_const_expr(expr: &str, constants: &FxHashMap<String, u32>, err_log: &dyn Logger)
+        -> Result<u32, SimErr> {
+    let fail = |msg: String| {
+        gui_err_print(&format!("Error: {}", msg), err_log);
+        SimErr::LoadErr
+    };
+    let expr   = expr.trim();
+    let spaced = expr.replace('+', " + ").replace('-', " - ");
+    let tokens = spaced.split_whitespace().collect::<Vec<&str>>();
+
+    // Expressions alternate between terms and operators, so they always have an odd length
+    if tokens.len() % 2 == 0 {
+        return Err(fail(format!("Malformed expression `{}`", expr)));
+    }
+
+    let mut result: u32 = 0;
+    let mut op = "+";
+    for (i, token) in tokens.iter().enumerate() {
+        // Operators missing an operand on either side show up in the wrong position
+        let is_op = *token == "+" || *token == "-";
+        if is_op != (i % 2 == 1) {
+            return Err(fail(format!("Malformed expression `{}`", expr)));
+        }
+        if is_op {
+            op = token;
+            continue;
+        }
+
+        let val = if let Some(val) = constants.get(*token) {
+            *val
+        } else if let Some(hex) = token.strip_prefix("0x") {
+            parse_hex(hex, err_log).map_err(|_| SimErr::LoadErr)?
+        } else if token.chars().all(|c| c.is_ascii_hexdigit()) {
+            parse_hex(token, err_log).map_err(|_| SimErr::LoadErr)?
+        } else {
+            return Err(fail(format!("Undefined constant `{}`", token)));
+        };
+
+        let next = if op == "+" {
+            result.checked_add(val)
+        } else {
+            result.checked_sub(val)
+        };
+        result = next.ok_or_else(|| fail(format!("Expression `{}` does not fit into 32 bits",
+                                                 expr)))?;
+    }
+    Ok(result)
+}
+
+/// Parse a register operand such as `r3` into its index
+fn parse_reg(arg: &str, err_log: &dyn Logger) -> Result<u32, SimErr> {
+    match arg.get(1..).and_then(|idx| idx.parse::<u32>().ok()) {
+        Some(idx) if (idx as usize) < NUM_REGS => Ok(idx),
+        _ => {
+            gui_err_print(&format!("Error: Invalid register `{}`", arg), err_log);
+            Err(SimErr::InstrDecode)
+        },
+    }
+}
+
+/// Line of `input`, counted from 1, that `line` starts on. `line` has to be a slice of `input`
+fn line_number(input: &str, line: &str) -> usize {
+    let offset = line.as_ptr() as usize - input.as_ptr() as usize;
+    input[..offset].matches('\n').count() + 1
+}
+
+/// Parse a hex-value with its `0x` prefix already removed
+fn parse_hex(arg: &str, err_log: &dyn Logger) -> Result<u32, SimErr> {
+    u32::from_str_radix(arg, 16).map_err(|_| {
+        gui_err_print(&format!("Error: Invalid hex-value `{}`", arg), err_log);
+        SimErr::InstrDecode
+    })
+}
+
+/// Address of `label`, which has to be defined in the same section
+fn parse_label(labels: &FxHashMap<String, i32>, label: &str, err_log: &dyn Logger)
+        -> Result<i32, SimErr> {
+    labels.get(label).copied().ok_or_else(|| {
+        gui_err_print(&format!("Error: Undefined label `{}`", label), err_log);
+        SimErr::InstrDecode
+    })
+}
+
 /// Encode `val` into the position `rs1` is expected in an instruction
 fn encode_rs1(val: u32) -> u32 {
     val << 16
@@ -1283,6 +2980,7 @@ fn encode_opcode(val_str: &str) -> u32 {
         "stb"  => InstrCode::Stb.into(),
         "sth"  => InstrCode::Sth.into(),
         "st"   => InstrCode::St.into(),
+        "stnt" => InstrCode::Stnt.into(),
         "bne"  => InstrCode::Bne.into(),
         "beq"  => InstrCode::Beq.into(),
         "blt"  => InstrCode::Blt.into(),
@@ -1298,4 +2996,1048 @@ fn encode_opcode(val_str: &str) -> u32 {
     op << 26
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftest::{MsgLog, run_program};
+    use crate::VgaBuffer;
+
+    #[test]
+    fn mispredict_squashes_wrong_path_instructions() {
+        let mut sim = Simulator::new();
+        let add = Instr::Add { rs3: Register::R1, rs1: Register::R2, rs2: Register::R3 };
+
+        // Taken branch that was predicted not taken, with the fall-through path behind it
+        let branch = &mut sim.pipeline.slots[2];
+        branch.valid        = true;
+        branch.instr        = Instr::Beq { rs3: Register::R0, rs1: Register::R0, imm: 0x40 };
+        branch.pc           = VAddr(0x100);
+        branch.imm          = 0x40;
+        branch.predicted_pc = Some(VAddr(0x104));
+        sim.pipeline.slots[1] = Slot { valid: true, pc: VAddr(0x104), instr: add,
+                                       ..Slot::default() };
+        sim.pipeline.slots[0] = Slot { valid: true, pc: VAddr(0x108), ..Slot::default() };
+
+        sim.pl_execute_stage().unwrap();
+        assert_eq!(sim.pipeline.pc, VAddr(0x140));
+        assert_eq!(sim.stats.wrong_path_instrs, 2);
+        assert!(!sim.pipeline.slots[1].valid);
+        assert_eq!(sim.pipeline.slots[1].squashed, Some((VAddr(0x104), add)));
+        assert_eq!(sim.pipeline.slots[0].squashed.map(|(pc, _)| pc), Some(VAddr(0x108)));
+    }
+
+    #[test]
+    fn seed_reproduces_frames_and_random_numbers() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x43
+                movi r2 0x2000
+                st r1 r2 0x0
+                movi r3 0x41
+                st r3 r2 0x0
+            .end_section
+        ";
+        let run = |seed| {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.set_seed(seed);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            sim.load_input(program, &log).unwrap();
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+            (sim.read_reg(Register::R1), sim.mmu.translate_addr(VAddr(0x10000), Perms::READ))
+        };
+
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1).0, run(2).0);
+        assert_ne!(run(1).1, run(2).1);
+    }
+
+    #[test]
+    fn saved_state_resumes_the_run() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x0
+                movi r2 0x20
+            .loop
+                addi r1 r1 0x1
+                st r1 r15 0x0
+                blt r1 r2 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(program, &log).unwrap();
+        for _ in 0..1_000 {
+            sim.step(&log);
+        }
+
+        let path = std::env::temp_dir().join(format!("seal_state_{}.bin", std::process::id()));
+        sim.save_state(&path).unwrap();
+        let mut resumed = Simulator::new();
+        resumed.load_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.clock, sim.clock);
+        assert_eq!(resumed.seed, sim.seed);
+
+        for sim in [&mut sim, &mut resumed] {
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+        }
+        assert!(!sim.online && !resumed.online);
+        assert_eq!(resumed.clock, sim.clock);
+        assert_eq!(resumed.gen_regs, sim.gen_regs);
+        assert_eq!(resumed.stats.cache_hits, sim.stats.cache_hits);
+        assert!(resumed.mmu.diff(&sim.mmu.snapshot()).is_empty());
+    }
+
+    #[test]
+    fn reset_restores_the_power_on_state() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r3 0x48
+                movi r4 0x1000
+                stb r3 r4 0x0
+                st r3 r15 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let setup = |vga: VgaBuffer| {
+            let mut sim = Simulator::new();
+            sim.mmu.frame_allocator = FrameAllocator::sequential();
+            sim.pipelining_enabled  = false;
+            sim.set_seed(3);
+            sim.map_default_layout(0x90000).unwrap();
+            sim.devices.register(VAddr(VGA_BASE), sim.vga_size.device_size(), Box::new(vga))
+                .unwrap();
+            sim
+        };
+        let log = MsgLog::default();
+        let mut fresh = setup(VgaBuffer::new());
+        fresh.load_input(program, &log).unwrap();
+
+        let vga = VgaBuffer::new();
+        let mut sim = setup(vga.clone());
+        sim.load_input(program, &log).unwrap();
+        sim.breakpoints.insert(0x10008, 0);
+        while sim.online {
+            sim.step(&log);
+        }
+        let clock  = sim.clock;
+        assert!(vga.text().starts_with('H'));
+
+        sim.reset(true, &log).unwrap();
+        assert!(sim.online && sim.clock == 0 && sim.stats.total_instrs == 0);
+        assert_eq!((sim.pc, sim.gen_regs), (fresh.pc, fresh.gen_regs));
+        assert_eq!(sim.read_reg(Register::R15), 0x90000 + (STACK_PAGES * PAGE_SIZE) as u32 - 4);
+        assert!(sim.breakpoints.contains_key(&0x10008) && !sim.pipelining_enabled);
+        assert!(sim.mmu.diff(&fresh.mmu.snapshot()).is_empty());
+        assert!(!vga.text().contains('H'));
+
+        // The reloaded program runs exactly like the first time
+        sim.breakpoints.clear();
+        while sim.online {
+            sim.step(&log);
+        }
+        assert_eq!(sim.clock, clock);
+
+        sim.reset(false, &log).unwrap();
+        assert!(sim.source.is_none() && sim.breakpoints.is_empty());
+        assert_eq!(sim.pc, VAddr(0));
+        assert!(sim.mmu.translate_addr(VAddr(0x10000), Perms::READ).is_err());
+        assert!(log.errors.borrow().is_empty(), "{:?}", log);
+    }
+
+    #[test]
+    fn exec_trace_matches_across_execution_modes() {
+        let program = "
+            .entry _start
+            .load 0x20000
+            .double
+                add r1 r1 r1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                subi r15 r15 0x10
+                movi r1 0x5
+                st r1 r15 0x4
+                call 0x20000
+                ld r3 r15 0x4
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let path = |mode: &str| {
+            std::env::temp_dir().join(format!("seal_exec_{}_{}.log", mode, std::process::id()))
+        };
+        let trace = |mode: &str, setup: &dyn Fn(&mut Simulator)| {
+            let mut run = run_program(program, mode != "seq", 10_000, &|sim| {
+                setup(sim);
+                sim.exec_trace_path = path(mode);
+                sim.start_exec_trace().unwrap();
+            });
+            assert!(!run.sim.online, "{} did not finish: {:?}", mode, run.log);
+            run.sim.stop_exec_trace().unwrap();
+            let log = std::fs::read_to_string(path(mode)).unwrap();
+            std::fs::remove_file(path(mode)).unwrap();
+
+            // Clocks differ between the modes
+            log.lines().map(|line| line.split_once('\t').unwrap().1.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let pipelined = trace("pipe", &|_| {});
+        assert_eq!(pipelined[0], "0x00010000\tsubi r15 r15 0x10\tr15=0x93ffc->0x93fec\t-");
+        assert_eq!(pipelined[3], "0x0001000c\tCall 0x20000\t\
+                                  r14=0x0->0x10010,r15=0x93fec->0x93fe8\t0x93fe8");
+        assert_eq!(pipelined[6], "0x00010010\tld r3 r15 0x4\tr3=0x0->0x5\t0x93ff0");
+        assert_eq!(trace("seq", &|_| {}), pipelined);
+        assert_eq!(trace("ooo", &|sim| sim.set_out_of_order(true)), pipelined);
+    }
+
+    #[test]
+    fn step_back_rewinds_registers_memory_and_pipeline() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x0
+                movi r2 0x10
+            .loop
+                addi r1 r1 0x1
+                st r1 r15 0x0
+                blt r1 r2 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.mmu.frame_allocator = FrameAllocator::sequential();
+        sim.undo_log = Some(UndoLog::new(1_000));
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(program, &log).unwrap();
+        for _ in 0..300 {
+            sim.step(&log);
+        }
+
+        let before = sim.clone();
+        let snapshot = sim.mmu.snapshot();
+        for _ in 0..200 {
+            sim.step(&log);
+        }
+        assert_eq!(sim.mmu.diff(&snapshot).len(), 1);
+        for _ in 0..200 {
+            assert!(sim.step_back());
+        }
+        assert_eq!(sim.clock, before.clock);
+        assert_eq!(sim.gen_regs, before.gen_regs);
+        assert_eq!(sim.pipeline.pc, before.pipeline.pc);
+        assert!(sim.mmu.diff(&snapshot).is_empty());
+
+        // Stepping forward again ends the same way as a run that was never rewound
+        let mut reference = before;
+        for sim in [&mut sim, &mut reference] {
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+        }
+        assert_eq!(sim.gen_regs, reference.gen_regs);
+        assert!(sim.mmu.diff(&reference.mmu.snapshot()).is_empty());
+
+        sim.undo_log = Some(UndoLog::new(2));
+        assert!(!sim.step_back());
+    }
+
+    #[test]
+    fn window_cpi_only_covers_recent_cycles() {
+        let mut sim = Simulator::new();
+        assert_eq!(sim.cpi(), None);
+        assert_eq!(sim.window_cpi(), None);
+
+        // One instruction per cycle, followed by one instruction every 4 cycles
+        for cycle in 1..=(3 * CPI_WINDOW) {
+            sim.clock = cycle;
+            sim.stats.total_instrs += (cycle <= CPI_WINDOW || cycle.is_multiple_of(4)) as u64;
+            if cycle.is_multiple_of(CPI_SAMPLE_INTERVAL) {
+                sim.sample_cpi();
+            }
+        }
+
+        assert_eq!(sim.window_cpi(), Some(4.0));
+        assert_eq!(sim.cpi(), Some(2.0));
+    }
+
+    #[test]
+    fn scoreboard_tracks_closest_pending_writer() {
+        let mut sim = Simulator::new();
+        let add = |rs3| Instr::Add { rs3, rs1: Register::R1, rs2: Register::R2 };
+        for (slot, instr) in [(2, add(Register::R3)), (3, add(Register::R4)),
+                              (4, add(Register::R3))] {
+            sim.pipeline.slots[slot].valid = true;
+            sim.pipeline.slots[slot].instr = instr;
+        }
+
+        let board = sim.scoreboard();
+        assert_eq!(board[3], Some(2));
+        assert_eq!(board[4], Some(3));
+        assert_eq!(board[5], None);
+
+        // The instruction in decode reads r4 and r5, but only r4 has a pending write
+        sim.pipeline.slots[1].valid = true;
+        sim.pipeline.slots[1].instr = Instr::Add { rs3: Register::R6, rs1: Register::R4,
+                                                   rs2: Register::R5 };
+        assert!(sim.hazard_regs().is_empty());
+        assert!(sim.caused_data_hazards(1, sim.pipeline.slots[1].instr).is_some());
+        sim.pipeline.hazard_thrower = Some(1);
+        assert_eq!(sim.hazard_regs(), vec![Register::R4]);
+    }
+
+    #[test]
+    fn fetch_queue_fills_while_decode_stalls() {
+        let mut sim = Simulator::new();
+        let add = |rs3, rs1| Instr::Add { rs3, rs1, rs2: Register::R0 };
+
+        // Decode reads r3 while the execute stage still has to write it
+        sim.pipeline.slots[2] = Slot { valid: true, instr: add(Register::R3, Register::R1),
+                                       ..Default::default() };
+        sim.pipeline.slots[1] = Slot { valid: true, instr: add(Register::R4, Register::R3),
+                                       ..Default::default() };
+        assert!(sim.caused_data_hazards(1, sim.pipeline.slots[1].instr).is_some());
+        assert!(!sim.pipeline.disable);
+
+        // Fetched instructions queue up behind the stalled decode stage
+        for i in 0..FETCH_QUEUE_SIZE + 1 {
+            sim.pipeline.slots[0] = Slot { valid: true, seq: i as u64, ..Default::default() };
+            sim.advance_pipeline(false).unwrap();
+        }
+        assert_eq!(sim.pipeline.fetch_queue.len(), FETCH_QUEUE_SIZE);
+        assert_eq!(sim.pipeline.slots[0].seq, FETCH_QUEUE_SIZE as u64);
+        assert!(sim.pipeline.slots[0].valid);
+
+        // Once the hazard resolves decode takes the oldest queued instruction
+        sim.pipeline.slots[1].disable = false;
+        sim.advance_pipeline(false).unwrap();
+        assert_eq!(sim.pipeline.slots[1].seq, 0);
+        assert_eq!(sim.pipeline.fetch_queue.len(), FETCH_QUEUE_SIZE);
+        assert!(!sim.pipeline.slots[0].valid);
+    }
+
+    #[test]
+    fn bubbles_record_their_cause() {
+        let mut sim = Simulator::new();
+        let add = |rs3, rs1| Instr::Add { rs3, rs1, rs2: Register::R0 };
+
+        // The instruction stalled in decode leaves a bubble in the execute stage
+        sim.pipeline.slots[2] = Slot { valid: true, instr: add(Register::R3, Register::R1),
+                                       ..Default::default() };
+        sim.pipeline.slots[1] = Slot { valid: true, instr: add(Register::R4, Register::R3),
+                                       ..Default::default() };
+        assert!(sim.caused_data_hazards(1, sim.pipeline.slots[1].instr).is_some());
+        sim.advance_pipeline(false).unwrap();
+        assert_eq!(sim.pipeline.slots[2].bubble, Some(Bubble::DataHazard));
+        assert!(sim.pipeline.slots[3].valid);
+
+        // Bubbles move down the pipeline like instructions
+        sim.pipeline.slots[1].disable = false;
+        sim.advance_pipeline(false).unwrap();
+        assert_eq!(sim.pipeline.slots[3].bubble, Some(Bubble::DataHazard));
+
+        // A redirected front-end hands a flush bubble to decode
+        sim.flush_front_end();
+        sim.advance_pipeline(false).unwrap();
+        assert_eq!(sim.pipeline.slots[1].bubble, Some(Bubble::Flush));
+
+        // While the memory stage waits, writeback gets a bubble
+        sim.advance_pipeline(true).unwrap();
+        assert_eq!(sim.pipeline.slots[4].bubble, Some(Bubble::MemStall));
+    }
+
+    #[test]
+    fn flush_pipeline_resyncs_with_pc() {
+        let mut sim = Simulator::new();
+        let addi = Instr::Addi { rs3: Register::R1, rs1: Register::R0, imm: 5 };
+        sim.pc = VAddr(0x200);
+        sim.pipeline.pc = VAddr(0x210);
+        sim.pipeline.slots[4] = Slot { valid: true, instr: addi, rs3: 5, ..Default::default() };
+        sim.pipeline.slots[2] = Slot { valid: true, instr: addi, rs3: 7, ..Default::default() };
+        sim.pipeline.fetch_queue.push_back(Slot { valid: true, ..Default::default() });
+        sim.pipeline.disable = true;
+
+        sim.flush_pipeline();
+
+        // Only the instruction in writeback completes
+        assert_eq!(sim.read_reg(Register::R1), 5);
+        assert!(sim.pipeline.slots.iter().all(|slot| !slot.valid));
+        assert_eq!(sim.pipeline.slots[2].bubble, Some(Bubble::Flush));
+        assert!(sim.pipeline.fetch_queue.is_empty());
+        assert_eq!(sim.pipeline.pc, VAddr(0x200));
+        assert!(!sim.pipeline.disable);
+    }
+
+    /// Replace the branch of an endless loop with the no-op behind it while the loop is running
+    #[test]
+    fn patched_instructions_are_refetched() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+            .loop
+                addi r3 r3 0x1
+                beq r0 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+                addi r0 r0 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+            while sim.clock < 1000 {
+                sim.step(&log);
+            }
+
+            let mut nop = [0u8; 4];
+            sim.gui_mem_read(VAddr(0x10014), &mut nop).unwrap();
+            sim.patch_mem(VAddr(0x10004), &nop).unwrap();
+            assert_eq!(sim.patch_mem(VAddr(0x30000000), &nop), Err(SimErr::AddrTranslation));
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+
+            // The loop ran more than once, and the golden model saw the patch as well
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert!(sim.read_reg(Register::R3) > 1, "{}", mode);
+            assert!(sim.clock < 2000, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn forwarding_only_stalls_on_load_use() {
+        let program = |scheduled: bool| format!("
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r1 0x40
+                addi r2 r1 0x4
+                add r3 r2 r1
+                st r3 r15 0x0
+                ld r4 r15 0x0
+                {}
+                add r5 r4 r3
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ", if scheduled { "addi r6 r0 0x1" } else { "" });
+        let forwarding = |sim: &mut Simulator| sim.forwarding_enabled = true;
+
+        let run = crate::difftest::run_program(&program(false), true, 10_000, &forwarding);
+        assert!(!run.sim.online, "program did not finish: {:?}", run.log);
+        assert_eq!(run.sim.read_reg(Register::R5), 0x108);
+        assert_eq!(run.sim.stats.load_use_stall_clock, 1);
+
+        // Only the store, which writes back the value it stored, is not forwarded
+        let stalls = run.sim.stats.raw_stall_clock;
+        let stalled = crate::difftest::run_program(&program(false), true, 10_000, &|_| {});
+        assert!(stalls < stalled.sim.stats.raw_stall_clock);
+        assert_eq!(stalled.sim.stats.load_use_stall_clock, 0);
+
+        // An independent instruction after the load hides the load-use bubble
+        let run = crate::difftest::run_program(&program(true), true, 10_000, &forwarding);
+        assert_eq!(run.sim.read_reg(Register::R5), 0x108);
+        assert_eq!(run.sim.stats.load_use_stall_clock, 0);
+    }
+
+    #[test]
+    fn load_misses_only_block_their_register() {
+        let mut sim = Simulator::new();
+        sim.pipelining_enabled = true;
+        let ld = |rs3| Instr::Ld { rs3, rs1: Register::R1, imm: 0 };
+
+        sim.pipeline.slots[3] = Slot { valid: true, instr: ld(Register::R5), ..Default::default() };
+        assert!(sim.track_load_miss(PAddr(0x1000), 100));
+
+        // A second load to the same line also waits for the outstanding miss
+        sim.pipeline.slots[3].instr = ld(Register::R6);
+        assert!(sim.track_load_miss(PAddr(0x1000), 10));
+        let board = sim.scoreboard();
+        assert_eq!((board[5], board[6], board[7]), (Some(3), Some(3), None));
+
+        for _ in 0..99 {
+            sim.tick_mshrs();
+        }
+        assert_eq!(sim.scoreboard()[6], Some(3));
+        sim.tick_mshrs();
+        assert!(sim.pipeline.mshrs.is_empty());
+
+        // Only loads can continue past a miss
+        sim.pipeline.slots[3].instr = Instr::St { rs3: Register::R5, rs1: Register::R1, imm: 0 };
+        assert!(!sim.track_load_miss(PAddr(0x1000), 100));
+        assert_eq!(sim.stats.hit_under_miss, 2);
+    }
+
+    #[test]
+    fn fetch_and_memory_stage_stall_independently() {
+        // Walks a stack buffer, so loads miss while the loop body is still being fetched
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                subi r12 r15 0x400
+                movi r4 0x0
+                movi r5 0x400
+            .loop
+                add r6 r12 r4
+                ld r2 r6 0x0
+                addi r4 r4 0x40
+                blt r4 r5 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let run = crate::difftest::run_program(program, true, 100_000, &|_| {});
+        assert!(!run.sim.online);
+
+        let stats = &run.sim.stats;
+        assert!(stats.fetch_stall_clock > 0 && stats.load_store_stall_clock > 0);
+        assert!(stats.mem_clock < stats.fetch_stall_clock + stats.load_store_stall_clock);
+    }
+
+    /// Load `body` at 0x10000, configure the simulator with `setup`, and place the first
+    /// `prefetched` instructions in the fetch queue so they don't wait on instruction fetches
+    fn prefetched_sim(body: &str, prefetched: usize, setup: &dyn Fn(&mut Simulator))
+            -> (Simulator, MsgLog) {
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.mmu.frame_allocator = FrameAllocator::sequential();
+        setup(&mut sim);
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        let program = format!(".entry _start\n.load 0x10000\n._start\n{}\n.end_section", body);
+        sim.load_input(&program, &log).unwrap();
+
+        for _ in 0..prefetched {
+            let mut raw = vec![0u8; 4];
+            let pc = sim.pipeline.pc;
+            sim.gui_mem_read(pc, &mut raw).unwrap();
+            sim.pipeline.fetch_queue.push_back(Slot { valid: true, instr_backing: as_u32_le(&raw),
+                                                      pc, seq: sim.pipeline.next_seq,
+                                                      ..Default::default() });
+            sim.pipeline.next_seq += 1;
+            sim.pipeline.pc.0 += 4;
+        }
+        (sim, log)
+    }
+
+    /// Step until `count` instructions completed writeback, and return the cycles this took
+    fn retire(sim: &mut Simulator, log: &MsgLog, count: usize) -> u32 {
+        let start = sim.clock;
+        let mut retired = 0;
+        while retired < count {
+            assert!(sim.online && sim.clock - start < 10_000, "stuck: {:?}", log);
+            let wb = &sim.pipeline.slots[4];
+            let pending = wb.valid.then_some(wb.seq);
+            sim.step(log);
+            let wb = &sim.pipeline.slots[4];
+            if pending.is_some_and(|seq| !wb.valid || wb.seq != seq) {
+                retired += 1;
+            }
+        }
+        sim.clock - start
+    }
+
+    #[test]
+    fn independent_instructions_retire_every_cycle() {
+        let body = "addi r1 r0 0x1\naddi r2 r0 0x2\naddi r3 r0 0x3\naddi r4 r0 0x4";
+        let (mut sim, log) = prefetched_sim(body, 4, &|_| {});
+
+        // 4 cycles to fill the pipeline, then one instruction completes per cycle
+        assert_eq!(retire(&mut sim, &log, 4), 8);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 2, 3, 4]);
+        assert_eq!(sim.stats.raw_stall_clock, 0);
+    }
+
+    #[test]
+    fn raw_hazards_stall_decode_until_writeback() {
+        let body = "addi r1 r0 0x1\naddi r2 r1 0x2\naddi r3 r2 0x3\naddi r4 r3 0x4";
+        let (mut sim, log) = prefetched_sim(body, 4, &|_| {});
+
+        // Every dependent instruction waits 3 cycles for its producer to leave writeback
+        assert_eq!(retire(&mut sim, &log, 4), 8 + 9);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 3, 6, 10]);
+        assert_eq!(sim.stats.raw_stall_clock, 9);
+
+        // Forwarding removes the stalls without changing the results
+        let (mut sim, log) = prefetched_sim(body, 4, &|sim| sim.forwarding_enabled = true);
+        assert_eq!(retire(&mut sim, &log, 4), 8);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 3, 6, 10]);
+        assert_eq!(sim.stats.raw_stall_clock, 0);
+    }
+
+    #[test]
+    fn hazard_stalls_hold_back_younger_instructions() {
+        let body = "addi r1 r0 0x1\naddi r2 r1 0x2\naddi r3 r0 0x3\naddi r4 r0 0x4";
+        let (mut sim, log) = prefetched_sim(body, 4, &|_| {});
+
+        // Only the dependent instruction stalls, the independent ones behind it wait in order
+        assert_eq!(retire(&mut sim, &log, 4), 8 + 3);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 3, 3, 4]);
+        assert_eq!(sim.stats.raw_stall_clock, 3);
+    }
+
+    #[test]
+    fn branches_flush_the_front_end() {
+        use crate::predictor::{BranchPredictor, PredictorKind};
+
+        let program = |branch: &str| format!("addi r1 r0 0x1\n{}\naddi r2 r0 0x2\n\
+                                              addi r3 r0 0x3\n.skip\naddi r4 r0 0x4", branch);
+        let run = |branch: &str, kind: PredictorKind, count: usize| {
+            let (mut sim, log) = prefetched_sim(&program(branch), 4, &|sim| {
+                sim.pipeline.predictor = BranchPredictor::new(kind);
+            });
+            let cycles = retire(&mut sim, &log, count);
+            (sim, cycles)
+        };
+        let (_, nop) = run("add r0 r0 r0", PredictorKind::Stall, 5);
+
+        // A correctly predicted branch costs no more than any other instruction, while waiting
+        // for the branch to resolve discards the instructions that were already fetched
+        let (sim, predicted) = run("bne r1 r1 .skip", PredictorKind::NotTaken, 5);
+        assert_eq!(predicted, nop);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 2, 3, 4]);
+        let (sim, stalled) = run("bne r1 r1 .skip", PredictorKind::Stall, 5);
+        assert!(stalled > nop);
+        assert_eq!(sim.stats.control_stall_clock, 1);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 2, 3, 4]);
+
+        // The wrong-path instructions of a mispredicted branch never complete
+        let (sim, _) = run("beq r1 r1 .skip", PredictorKind::NotTaken, 3);
+        assert_eq!(&sim.gen_regs[1..5], &[1, 0, 0, 4]);
+        assert_eq!(sim.stats.branch_mispredicts, 1);
+        assert_eq!(sim.stats.wrong_path_instrs, 2);
+        assert_eq!(sim.stats.mispredict_clock, MISPREDICT_PENALTY as u64);
+    }
+
+    #[test]
+    fn call_and_ret_sequence_through_the_stack() {
+        let program = "
+            .entry _start
+            .load 0x20000
+            .func
+                addi r1 r1 0x10
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                mov r9 r15
+                movi r1 0x1
+                call 0x20000
+                addi r2 r1 0x1
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let run = crate::difftest::run_program(program, true, 10_000, &|_| {});
+        assert!(!run.sim.online, "program did not finish: {:?}", run.log);
+
+        let pcs = run.retired.iter().map(|r| r.pc.0).collect::<Vec<_>>();
+        assert_eq!(&pcs[..7], &[0x10000, 0x10004, 0x10008, 0x20000, 0x20004, 0x1000c, 0x10010]);
+        assert_eq!(run.retired[5].writes, vec![(Register::R2, 0x12)]);
+
+        // The caller's link register was pushed to the stack and restored again
+        assert_eq!(run.sim.read_reg(Register::R15), run.sim.read_reg(Register::R9));
+        assert_eq!(run.sim.read_reg(Register::R14), 0);
+    }
+
+    #[test]
+    fn int0_redirects_to_the_handler() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r5 0x5
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x0
+                int0
+                movi r6 0x6
+            .end_section
+        ";
+        let run = crate::difftest::run_program(program, true, 10_000, &|_| {});
+        assert!(!run.sim.online, "program did not finish: {:?}", run.log);
+
+        // Nothing behind the interrupt completes, execution continues at the handler
+        let pcs = run.retired.iter().map(|r| r.pc.0).collect::<Vec<_>>();
+        assert_eq!(&pcs[..4], &[0x10000, 0x10004, 0x10008, 0x40000]);
+        assert_eq!(run.sim.read_reg(Register::R5), 5);
+        assert_eq!(run.sim.read_reg(Register::R6), 0);
+    }
+
+    /// Device that interrupts the program on cycle 300 and copies data into memory on cycle 320
+    #[derive(Debug, Clone)]
+    struct Ticker;
+
+    impl crate::mmio::MmioDevice for Ticker {
+        fn name(&self) -> &str {
+            "ticker"
+        }
+
+        fn write(&mut self, _offset: u32, _data: &[u8], _clock: u32) -> Option<MmioAction> {
+            None
+        }
+
+        fn tick(&mut self, clock: u32) -> Vec<MmioAction> {
+            match clock {
+                300 => vec![MmioAction::Interrupt(2)],
+                320 => vec![MmioAction::Dma { addr: VAddr(0x3000), data: vec![1, 2, 3, 4] }],
+                _ => Vec::new(),
+            }
+        }
+
+        fn clone_box(&self) -> Box<dyn crate::mmio::MmioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn device_interrupts_and_dma_happen_on_their_cycle() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .handler
+                movi r6 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0x8
+            .loop
+                addi r5 r5 0x1
+                beq r6 r0 .loop
+                movi r7 0x3000
+                ld r8 r7 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.ooo_enabled = ooo;
+                sim.map_page(VAddr(0x3000), Perms::READ | Perms::WRITE).unwrap();
+                sim.devices.register(VAddr(0x2200), 4, Box::new(Ticker)).unwrap();
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            // The handler returned into the loop, which saw its flag and read the copied data
+            assert_eq!(run.sim.read_reg(Register::R6), 1, "{}", mode);
+            assert_eq!(run.sim.read_reg(Register::R8), 0x04030201, "{}", mode);
+            assert_eq!(run.sim.read_reg(Register::R14), 0, "{}", mode);
+            assert_eq!(run.sim.read_reg(Register::R15), DEFAULT_STACK_BASE
+                       + (STACK_PAGES * PAGE_SIZE) as u32 - 4, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn tlb_misses_charge_a_stall_per_page_walk_level() {
+        let mut sim = Simulator::new();
+        sim.map_page(VAddr(0x10000), Perms::READ).unwrap();
+        sim.map_large_page(VAddr(0x400000), Perms::READ).unwrap();
+
+        assert_eq!(sim.tlb_access(VAddr(0x10000)), 2 * PAGE_WALK_STALL);
+        assert_eq!(sim.tlb_access(VAddr(0x10004)), 0);
+
+        // Large pages are mapped by the first level, so their walk is one level shorter
+        assert_eq!(sim.tlb_access(VAddr(0x400000)), PAGE_WALK_STALL);
+        assert_eq!(sim.stats.page_walk_clock, 3 * PAGE_WALK_STALL as u64);
+        assert_eq!((sim.stats.tlb_hits, sim.stats.tlb_misses), (1, 2));
+    }
+
+    #[test]
+    fn lazy_pages_are_counted_when_first_touched() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                lui r2 0x30
+                movi r1 0x7
+                st r1 r2 0x0
+                st r1 r2 0x4
+                ld r3 r2 0x8
+                lui r2 0x31
+                ld r4 r2 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for pipelined in [true, false] {
+            let run = run_program(program, pipelined, 10_000, &|sim| {
+                sim.map_region(VAddr(0x30000), 3 * PAGE_SIZE, Perms::READ | Perms::WRITE, true)
+                    .unwrap();
+            });
+            assert!(run.log.errors.borrow().is_empty(), "{:?}", run.log);
+            assert_eq!(run.sim.stats.demand_pages, 2, "pipelined: {}", pipelined);
+            assert_eq!(run.sim.read_reg(Register::R3), 0);
+            assert!(run.sim.mmu.translate_addr(VAddr(0x32000), Perms::READ).is_err());
+        }
+    }
+
+    #[test]
+    fn subsystems_count_into_the_metrics_registry() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r6 0x5
+            .loop
+                subi r6 r6 0x1
+                bne r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 100_000, &|sim| sim.ooo_enabled = ooo);
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            let metrics = &run.sim.stats.metrics;
+            assert_eq!(metrics.get("branch.taken"), 4, "{}", mode);
+            assert_eq!(metrics.get("device.command-port.writes"), 1, "{}", mode);
+
+            // Built-in counters are listed next to the registered ones
+            let counters = run.sim.stats.counters();
+            assert_eq!(counters.get("instrs.total"), run.sim.stats.total_instrs, "{}", mode);
+            assert_eq!(counters.get("branch.taken"), 4, "{}", mode);
+            assert_eq!(counters.with_prefix("stall.").count(), 7, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn decode_hazards_report_waw_and_war() {
+        let mut sim = Simulator::new();
+        let slot = |instr| Slot { valid: true, instr, ..Default::default() };
+        sim.pipeline.slots[1] = slot(Instr::Add { rs3: Register::R3, rs1: Register::R1,
+                                                  rs2: Register::R0 });
+        sim.pipeline.slots[2] = slot(Instr::Add { rs3: Register::R1, rs1: Register::R3,
+                                                  rs2: Register::R0 });
+        sim.pipeline.slots[4] = slot(Instr::Lui { rs3: Register::R3, imm: 1 });
+
+        assert_eq!(sim.decode_hazards(), vec![
+            Hazard { kind: HazardKind::Raw, reg: Register::R1, stage: 2 },
+            Hazard { kind: HazardKind::War, reg: Register::R3, stage: 2 },
+            Hazard { kind: HazardKind::Waw, reg: Register::R3, stage: 4 },
+        ]);
+    }
+
+    #[test]
+    fn guest_faults_stop_the_simulation_without_panicking() {
+        let faults = [
+            // Return with a misaligned stack pointer
+            ("movi r15 0x3\n ret", "Error: Misaligned memory access"),
+            // Jump to a word that does not decode
+            ("lui r1 0xffff\n st r1 r0 0x40\n call 0x10040",
+             "Error: Invalid instruction at 0x10040"),
+        ];
+        for (code, err) in faults {
+            let program = format!(".entry _start\n.load 0x10000\n._start\n{}\n.end_section", code);
+            for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+                let run = run_program(&program, pipelined, 1_000, &|sim| {
+                    sim.set_out_of_order(ooo);
+                });
+                assert!(!run.sim.online);
+                assert_eq!(run.log.errors.borrow().last().map(String::as_str), Some(err),
+                           "pipelined: {}, ooo: {}", pipelined, ooo);
+            }
+        }
+
+        // Overflowing arithmetic wraps around like it does in hardware
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                lui r1 0xffff
+                mul r2 r1 r1
+                add r3 r1 r1
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let run = run_program(program, true, 1_000, &|_| {});
+        assert!(run.log.errors.borrow().is_empty(), "{:?}", run.log);
+        assert_eq!(run.sim.read_reg(Register::R3), 0xffffe000);
+    }
+
+    #[test]
+    fn load_addresses_evaluate_constant_expressions() {
+        let program = "
+            .equ BASE 0x10000
+            .equ OFF BASE - 0xff00
+            .entry _start
+            .load BASE + 0x1000 - OFF
+            ._start
+                movi r1 0x1
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.load_input(program, &log).unwrap();
+        assert_eq!(sim.pc, VAddr(0x10f00));
+        assert!(sim.mmu.translate_addr(VAddr(0x10f00), Perms::READ).is_ok());
+
+        // Hex-values without a prefix still assemble, constants take precedence over them
+        let program = "
+            .equ ADD 0x100
+            .entry _start
+            .load 20000 + ADD
+            ._start
+                movi r1 0x1
+            .end_section
+        ";
+        let mut sim = Simulator::new();
+        sim.load_input(program, &log).unwrap();
+        assert_eq!(sim.pc, VAddr(0x20100));
+        assert!(log.errors.borrow().is_empty(), "{:?}", log);
+    }
+
+    #[test]
+    fn malformed_constant_expressions_fail_to_assemble() {
+        let exprs = [
+            ("BASE + LIMIT", "Error: Undefined constant `LIMIT`"),
+            ("BASE + 0xffff0000",
+             "Error: Expression `BASE + 0xffff0000` does not fit into 32 bits"),
+            ("0x1000 - BASE", "Error: Expression `0x1000 - BASE` does not fit into 32 bits"),
+            ("0x100000000", "Error: Invalid hex-value `100000000`"),
+            ("100000000", "Error: Invalid hex-value `100000000`"),
+            ("BASE +", "Error: Malformed expression `BASE +`"),
+            ("BASE + - 0x10", "Error: Malformed expression `BASE + - 0x10`"),
+            ("BASE 0x10", "Error: Malformed expression `BASE 0x10`"),
+            ("", "Error: Malformed expression ``"),
+        ];
+        for (expr, err) in exprs {
+            let program = format!(".equ BASE 0x10000\n.entry _start\n.load {}\n._start\n\
+                                   movi r1 0x1\n.end_section", expr);
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            assert_eq!(sim.load_input(&program, &log), Err(SimErr::LoadErr), "{}", expr);
+            assert_eq!(log.errors.borrow().last().map(String::as_str), Some(err));
+        }
+
+        // Constants are checked the same way
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        assert_eq!(sim.load_input(".equ BASE 0x10 -\n", &log), Err(SimErr::LoadErr));
+        assert_eq!(log.errors.borrow().last().map(String::as_str),
+                   Some("Error: Malformed expression `0x10 -`"));
+    }
+
+    #[test]
+    fn only_mapped_pages_raise_exec_faults() {
+        let faults = [
+            // The vga-buffer is mapped, but not executable
+            ("call 0x1000", "Error: Fetch from non-executable page at 0x1000"),
+            ("call 0x50000", "Error: Access to unmapped memory at 0x50000"),
+            // Unmapped fetches don't go through the exec-fault handler
+            ("lui r1 0x10\n st r1 r0 0x4\n call 0x50000",
+             "Error: Access to unmapped memory at 0x50000"),
+        ];
+        for (code, err) in faults {
+            let program = format!(".entry _start\n.load 0x10000\n._start\n{}\n.end_section", code);
+            for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+                let run = run_program(&program, pipelined, 1_000, &|sim| {
+                    sim.set_out_of_order(ooo);
+                });
+                assert!(!run.sim.online);
+                assert_eq!(run.log.errors.borrow().last().map(String::as_str), Some(err),
+                           "pipelined: {}, ooo: {}", pipelined, ooo);
+            }
+        }
+    }
+
+    #[test]
+    fn memory_view_follows_the_pc_and_registers() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r3 0x2003
+                addi r3 r3 0x10
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = Simulator::new();
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(program, &log).unwrap();
+        sim.cur_mem = VAddr(0x3000);
+        assert_eq!(sim.mem_view_addr(), VAddr(0x3000));
+
+        assert_eq!(MemFollow::parse("PC"), Some(MemFollow::Pc));
+        assert_eq!(MemFollow::parse("r16"), None);
+        sim.mem_follow = MemFollow::parse(" r3").unwrap();
+        assert_eq!(sim.mem_follow.name(), "r3");
+        while sim.online && sim.clock < 1000 {
+            sim.step(&log);
+        }
+        assert_eq!(sim.mem_view_addr(), VAddr(0x2010));
+
+        sim.mem_follow = MemFollow::Pc;
+        assert_eq!(sim.mem_view_addr(), VAddr(sim.pc.0 & !0x3));
+        assert_eq!(sim.mem_follow.next().next(), MemFollow::Fixed);
+    }
 
+    #[test]
+    fn malformed_programs_fail_to_load() {
+        for (line, err) in [("add r1 rx r2", "Error: Invalid register `rx`"),
+                            ("addi r1 r1 0xzz", "Error: Invalid hex-value `zz`"),
+                            ("beq r1 r2 .missing", "Error: Undefined label `.missing`")] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            let program = format!(".entry _start\n.load 0x10000\n._start\n\n# Fails\n    {}\n\
+                                   .end_section", line);
+            assert_eq!(sim.load_input(&program, &log), Err(SimErr::InstrDecode));
+            assert_eq!(log.errors.borrow().last().map(String::as_str), Some(err));
+            assert_eq!(sim.load_error_line, Some(6), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/// Color as red, green and blue
+pub type Rgb = (u8, u8, u8);
+
+/// Color scheme of the gui
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// Dark text on a white window
+    #[default]
+    Light,
+
+    /// Light text on a dark-grey window, easier on the eyes during long sessions
+    Dark,
+}
+
+/// Colors of the gui's widgets and of the state they highlight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Background of the main window
+    pub window: Rgb,
+
+    /// Background of buttons and of the other windows
+    pub background: Rgb,
+
+    /// Background of inputs, text displays and lists
+    pub background2: Rgb,
+
+    /// Text and labels
+    pub foreground: Rgb,
+
+    /// Greyed out text, eg. squashed instructions
+    pub inactive: Rgb,
+
+    /// Selected text
+    pub selection: Rgb,
+
+    /// Every other row of the register and memory views
+    pub alt_text: Rgb,
+
+    /// Row of the pc in the disassembly
+    pub current: Rgb,
+
+    /// Chart lines and the stages of the pipeline diagram
+    pub accent: Rgb,
+
+    /// Registers with only write-after-write/write-after-read dependences
+    pub dependence: Rgb,
+
+    /// Registers written during the last cycle
+    pub written: Rgb,
+
+    /// Empty pipeline stages
+    pub bubble: Rgb,
+
+    /// Valid and invalid entries of the cache grid
+    pub cache_valid:   Rgb,
+    pub cache_invalid: Rgb,
+
+    /// Highlighted mnemonics, registers, labels, immediates and comments in the code editor
+    pub mnemonic: Rgb,
+    pub register: Rgb,
+    pub label:    Rgb,
+    pub number:   Rgb,
+    pub comment:  Rgb,
+}
+
+/// Colors of `Theme::Light`
+const LIGHT: Palette = Palette {
+    window:        (0xff, 0xff, 0xff),
+    background:    (0xc0, 0xc0, 0xc0),
+    background2:   (0xff, 0xff, 0xff),
+    foreground:    (0x00, 0x00, 0x00),
+    inactive:      (0x90, 0x90, 0x90),
+    selection:     (0x00, 0x00, 0x80),
+    alt_text:      (0x00, 0x00, 0xff),
+    current:       (0xff, 0xff, 0x00),
+    accent:        (0x00, 0x00, 0x88),
+    dependence:    (0x88, 0x88, 0x00),
+    written:       (0x00, 0x88, 0x00),
+    bubble:        (0x00, 0x88, 0x88),
+    cache_valid:   (0x40, 0xb0, 0x40),
+    cache_invalid: (0xdd, 0xdd, 0xdd),
+    mnemonic:      (0x00, 0x00, 0x88),
+    register:      (0x88, 0x00, 0x88),
+    label:         (0x00, 0x88, 0x88),
+    number:        (0x88, 0x00, 0x00),
+    comment:       (0x70, 0x70, 0x70),
+};
+
+/// Colors of `Theme::Dark`
+const DARK: Palette = Palette {
+    window:        (0x20, 0x22, 0x25),
+    background:    (0x38, 0x3b, 0x40),
+    background2:   (0x18, 0x19, 0x1c),
+    foreground:    (0xdd, 0xdd, 0xdd),
+    inactive:      (0x70, 0x70, 0x70),
+    selection:     (0x3d, 0x5a, 0x80),
+    alt_text:      (0x7a, 0xb8, 0xff),
+    current:       (0x80, 0x70, 0x10),
+    accent:        (0x7a, 0xb8, 0xff),
+    dependence:    (0xe0, 0xc0, 0x40),
+    written:       (0x60, 0xd0, 0x60),
+    bubble:        (0x40, 0xc8, 0xc8),
+    cache_valid:   (0x40, 0xa0, 0x40),
+    cache_invalid: (0x50, 0x50, 0x50),
+    mnemonic:      (0x7a, 0xb8, 0xff),
+    register:      (0xd0, 0x8c, 0xf0),
+    label:         (0x50, 0xc8, 0xc8),
+    number:        (0xf0, 0x90, 0x70),
+    comment:       (0x90, 0x90, 0x90),
+};
+
+impl Theme {
+    /// Parse a theme from its name, `light` or `dark`
+    pub fn parse(config: &str) -> Option<Self> {
+        match config.trim() {
+            "light" => Some(Theme::Light),
+            "dark"  => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+
+    /// Name displayed on the gui
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Light => "Light",
+            Theme::Dark  => "Dark",
+        }
+    }
+
+    /// Theme that follows this one when cycling through them on the gui
+    pub fn next(&self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark  => Theme::Light,
+        }
+    }
+
+    /// Colors of the theme
+    pub fn palette(&self) -> &'static Palette {
+        match self {
+            Theme::Light => &LIGHT,
+            Theme::Dark  => &DARK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Perceived brightness of `color`, from 0 to 255
+    fn luma((r, g, b): Rgb) -> f64 {
+        0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+    }
+
+    #[test]
+    fn themes_cycle_through_their_names() {
+        let mut theme = Theme::default();
+        for _ in 0..2 {
+            assert_eq!(Theme::parse(&theme.name().to_lowercase()), Some(theme));
+            theme = theme.next();
+        }
+        assert_eq!(theme, Theme::default());
+        assert_eq!(Theme::parse("solarized"), None);
+    }
+
+    #[test]
+    fn text_stands_out_from_the_background() {
+        for theme in [Theme::Light, Theme::Dark] {
+            let palette = theme.palette();
+            let text = [palette.foreground, palette.alt_text, palette.accent, palette.mnemonic,
+                        palette.register, palette.label, palette.number, palette.comment];
+            for background in [palette.window, palette.background2] {
+                for color in text {
+                    assert!((luma(color) - luma(background)).abs() > 60.0,
+                            "{:?}: {:?} on {:?}", theme, color, background);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::Stats;
+
+use serde::{Serialize, Deserialize};
+
+/// Cycles between the first samples of a run, the interval doubles whenever the timeline is full
+pub const TIMELINE_INTERVAL: u32 = 1_000;
+
+/// Number of samples kept before the interval doubles
+pub const TIMELINE_CAPACITY: usize = 512;
+
+/// Counters of the stats at a sampled clock-cycle
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsSample {
+    pub clock: u32,
+
+    pub instrs: u64,
+
+    pub cache_hits: u64,
+
+    pub cache_misses: u64,
+
+    pub mem_clock: u64,
+}
+
+/// Value charted over time, computed over the cycles between two samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartMetric {
+    /// L1 cache hits in percent of all L1 accesses
+    HitRate,
+
+    /// Cycles per retired instruction
+    Cpi,
+
+    /// Cycles spent waiting on memory in percent of all cycles
+    MemStall,
+}
+
+impl ChartMetric {
+    /// Title displayed on the gui
+    pub fn title(&self) -> &'static str {
+        match self {
+            ChartMetric::HitRate  => "L1 hit rate (%)",
+            ChartMetric::Cpi      => "CPI",
+            ChartMetric::MemStall => "Memory stalls (%)",
+        }
+    }
+}
+
+/// Samples of the stats taken at a regular interval over the whole run, so the phases of longer
+/// programs can be charted. Once `capacity` samples were taken, every other sample is dropped and
+/// the interval doubles, so runs of any length fit into a bounded number of samples
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsTimeline {
+    /// Cycles between samples
+    interval: u32,
+
+    /// Maximum number of samples that are kept
+    capacity: usize,
+
+    /// Samples, oldest first
+    samples: Vec<StatsSample>,
+}
+
+impl Default for StatsTimeline {
+    fn default() -> Self {
+        Self::new(TIMELINE_INTERVAL, TIMELINE_CAPACITY)
+    }
+}
+
+impl StatsTimeline {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            interval,
+            capacity: capacity.max(2),
+            samples:  Vec::new(),
+        }
+    }
+
+    /// Sample `stats` if a sample is due at `clock`
+    pub fn record(&mut self, clock: u32, stats: &Stats) {
+        if !clock.is_multiple_of(self.interval) {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.interval *= 2;
+            let interval = self.interval;
+            self.samples.retain(|sample| sample.clock.is_multiple_of(interval));
+            if !clock.is_multiple_of(interval) {
+                return;
+            }
+        }
+        self.samples.push(StatsSample {
+            clock,
+            instrs:       stats.total_instrs,
+            cache_hits:   stats.cache_hits,
+            cache_misses: stats.cache_misses,
+            mem_clock:    stats.mem_clock,
+        });
+    }
+
+    /// Drop the samples taken at or after `clock`, after the simulator was rewound to it
+    pub fn rewind(&mut self, clock: u32) {
+        self.samples.retain(|sample| sample.clock < clock);
+    }
+
+    /// Samples, oldest first
+    pub fn samples(&self) -> &[StatsSample] {
+        &self.samples
+    }
+
+    /// `(clock, value)` of `metric` over the cycles between each pair of consecutive samples, at
+    /// the clock of the later sample. The counters start out at zero on clock 0, so the first
+    /// sample is compared against that. Intervals without accesses or retired instructions are
+    /// skipped for the hit rate and cpi
+    pub fn series(&self, metric: ChartMetric) -> Vec<(u32, f64)> {
+        let mut samples = self.samples.clone();
+        if samples.first().is_some_and(|sample| sample.clock != 0) {
+            samples.insert(0, StatsSample::default());
+        }
+        samples.windows(2).filter_map(|pair| {
+            let (prev, cur) = (pair[0], pair[1]);
+            let value = match metric {
+                ChartMetric::HitRate => {
+                    let hits     = cur.cache_hits - prev.cache_hits;
+                    let accesses = hits + cur.cache_misses - prev.cache_misses;
+                    (accesses != 0).then(|| hits as f64 * 100.0 / accesses as f64)
+                },
+                ChartMetric::Cpi => {
+                    let instrs = cur.instrs - prev.instrs;
+                    (instrs != 0).then(|| (cur.clock - prev.clock) as f64 / instrs as f64)
+                },
+                ChartMetric::MemStall => Some((cur.mem_clock - prev.mem_clock) as f64 * 100.0 /
+                                              (cur.clock - prev.clock) as f64),
+            };
+            value.map(|value| (cur.clock, value))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_timelines_double_the_interval() {
+        let mut timeline = StatsTimeline::new(10, 4);
+        let mut stats = Stats::default();
+        for clock in 0..=100 {
+            stats.total_instrs += 1;
+            timeline.record(clock, &stats);
+        }
+
+        // Filled up at 40 and 80, so samples are 40 cycles apart
+        assert_eq!(timeline.samples().iter().map(|sample| sample.clock).collect::<Vec<_>>(),
+                   [0, 40, 80]);
+        assert_eq!(timeline.samples()[1].instrs, 41);
+
+        timeline.rewind(80);
+        assert_eq!(timeline.samples().len(), 2);
+    }
+
+    #[test]
+    fn series_cover_the_cycles_between_samples() {
+        let mut timeline = StatsTimeline::new(100, 8);
+        let mut stats = Stats::default();
+        (stats.total_instrs, stats.cache_hits, stats.cache_misses) = (50, 3, 1);
+        stats.mem_clock = 20;
+        timeline.record(100, &stats);
+        stats.mem_clock = 70;
+        timeline.record(200, &stats);
+
+        assert_eq!(timeline.series(ChartMetric::HitRate), [(100, 75.0)]);
+        assert_eq!(timeline.series(ChartMetric::Cpi), [(100, 2.0)]);
+        assert_eq!(timeline.series(ChartMetric::MemStall), [(100, 20.0), (200, 50.0)]);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::mmio::{MmioDevice, MmioAction, read_regs, write_regs};
+
+/// Address of the interval timer's registers, next to the command-port
+pub const TIMER_BASE: u32 = 0x2010;
+
+/// Size of the timer's register block
+pub const TIMER_SIZE: u32 = 0x10;
+
+/// Interrupt-vector raised when the timer fires, its handler is stored at address 0x8
+pub const TIMER_IRQ: u32 = 2;
+
+/// Control bit that lets the counter run
+pub const TIMER_ENABLE: u32 = 1 << 0;
+
+/// Control bit that restarts the counter from 0 when it fires. One-shot timers disable themselves
+/// instead
+pub const TIMER_PERIODIC: u32 = 1 << 1;
+
+/// Control bit that raises `TIMER_IRQ` when the timer fires
+pub const TIMER_IRQ_ENABLE: u32 = 1 << 2;
+
+/// Programmable interval timer at `TIMER_BASE`. Its 32-bit registers are:
+///  - 0x0 Count: Incremented every clock-cycle while the timer is enabled
+///  - 0x4 Compare: The timer fires when the count reaches this value
+///  - 0x8 Control: `TIMER_ENABLE`, `TIMER_PERIODIC` and `TIMER_IRQ_ENABLE`
+///  - 0xc Status: Bit 0 is set when the timer fires, writing a 1 to it clears it
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    count:   u32,
+    compare: u32,
+    control: u32,
+    status:  u32,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        [self.count, self.compare, self.control, self.status]
+    }
+}
+
+impl MmioDevice for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    /// Registers can be written a byte at a time, bytes that aren't written keep their value
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let regs   = write_regs(self.regs(), offset, data);
+        let offset = offset as usize;
+
+        self.count   = regs[0];
+        self.compare = regs[1];
+        self.control = regs[2];
+
+        // Status bits are cleared by writing a 1 to them
+        let mut cleared = [0u8; 4];
+        for (idx, &byte) in data.iter().enumerate() {
+            if let Some(status_idx) = (offset + idx).checked_sub(12) {
+                cleared[status_idx] = byte;
+            }
+        }
+        self.status &= !u32::from_le_bytes(cleared);
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        read_regs(&self.regs(), offset, reader);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        if self.control & TIMER_ENABLE == 0 {
+            return Vec::new();
+        }
+        self.count = self.count.wrapping_add(1);
+        if self.count != self.compare {
+            return Vec::new();
+        }
+
+        self.status |= 1;
+        if self.control & TIMER_PERIODIC != 0 {
+            self.count = 0;
+        } else {
+            self.control &= !TIMER_ENABLE;
+        }
+        if self.control & TIMER_IRQ_ENABLE != 0 {
+            vec![MmioAction::RaiseIrq]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, mmio::read_reg, mmu::VAddr, VGA_BASE};
+
+    #[test]
+    fn fires_periodically_or_once() {
+        let mut timer = Timer::new();
+        timer.write(0x4, &3u32.to_le_bytes(), 0);
+        timer.write(0x8, &[(TIMER_ENABLE | TIMER_PERIODIC | TIMER_IRQ_ENABLE) as u8], 0);
+        let fired = (1..=9).filter(|&clock| !timer.tick(clock).is_empty()).collect::<Vec<_>>();
+        assert_eq!(fired, [3, 6, 9]);
+
+        assert_eq!(read_reg(&mut timer, 0xc), 1);
+
+        // Writing other registers leaves the status alone, writing a 1 to it clears it
+        timer.write(0x0, &[0x0], 9);
+        assert_eq!(timer.status, 1);
+        timer.write(0xc, &[0x1], 9);
+        assert_eq!(timer.status, 0);
+
+        // One-shot timers stop, and without interrupts only the status is set
+        timer.write(0x8, &[TIMER_ENABLE as u8], 9);
+        assert!((10..20).all(|clock| timer.tick(clock).is_empty()));
+        assert_eq!((timer.count, timer.status, timer.control), (3, 1, 0));
+    }
+
+    #[test]
+    fn interrupts_switch_between_tasks() {
+        let program = include_str!("../code/timer_demo");
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let run = run_program(program, pipelined, 1_000_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            let mut sim = run.sim;
+            let mut screen = [0u8; 0x2e];
+            sim.gui_mem_read(VAddr(VGA_BASE), &mut screen).unwrap();
+            assert_eq!(&screen[..0x10], b"AAAAAAAAAAAAAAAA", "{}", mode);
+            assert_eq!(&screen[0x1e..], b"BBBBBBBBBBBBBBBB", "{}", mode);
+
+            // Besides the two writes that start it, every interrupt acknowledges the timer
+            assert!(sim.stats.metrics.get("device.timer.writes") > 6, "{}", mode);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    pipeline::{Pipeline, Slot},
+    cpu::{Instr, Register},
+    mmu::VAddr,
+};
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// File the pipeline trace is written to unless configured otherwise
+pub const DEFAULT_TRACE_PATH: &str = "pipeline_trace.log";
+
+/// File the execution trace is written to unless configured otherwise
+pub const DEFAULT_EXEC_TRACE_PATH: &str = "exec_trace.log";
+
+/// Stage names as they appear in the trace, `Q` is the fetch queue between fetch and decode
+const STAGE_NAMES: [&str; 6] = ["F", "Q", "D", "X", "M", "W"];
+
+/// Index of the writeback stage in `STAGE_NAMES`
+const WRITEBACK: usize = 5;
+
+/// Buffered trace data is written to the file once it grows beyond this many bytes
+const FLUSH_THRESHOLD: usize = 1 << 20;
+
+/// Instruction that was in the pipeline during the last recorded cycle
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    /// Fetch sequence number of the instruction, see `Slot::seq`
+    seq: u64,
+
+    /// Id of the instruction in the trace file
+    id: u64,
+
+    /// Stage the instruction occupied, indexes `STAGE_NAMES`
+    stage: usize,
+
+    /// Instruction did not advance during the last recorded cycle
+    stalled: bool,
+
+    /// Disassembly was already added to the instruction's label
+    labeled: bool,
+}
+
+/// Records the pipeline occupancy of every instruction in the log format of the Konata pipeline
+/// visualizer. Cycles in which an instruction could not advance are shown as `Stl` in a second
+/// lane, and instructions that left the pipeline before writeback are marked as flushed
+#[derive(Debug, Clone)]
+pub struct PipelineTrace {
+    /// File the trace is written to
+    path: PathBuf,
+
+    /// Trace data that was not yet written to the file
+    buf: String,
+
+    /// Instructions that were in the pipeline during the last recorded cycle
+    in_flight: Vec<Tracked>,
+
+    /// Id assigned to the next instruction, Konata requires these to start at 0
+    next_id: u64,
+
+    /// Retire id assigned to the next instruction that completes writeback
+    next_retire_id: u64,
+
+    /// Last recorded clock-cycle
+    last_cycle: Option<u32>,
+}
+
+impl PipelineTrace {
+    /// Start a new trace at `path`, replacing the file if it already exists
+    pub fn create(path: &Path) -> io::Result<Self> {
+        File::create(path)?;
+        Ok(Self {
+            path:           path.to_path_buf(),
+            buf:            String::from("Kanata\t0004\n"),
+            in_flight:      Vec::new(),
+            next_id:        0,
+            next_retire_id: 0,
+            last_cycle:     None,
+        })
+    }
+
+    /// Record the state of `pipeline` during clock-cycle `cycle`. This has to be called after all
+    /// stages executed, but before the pipeline advances to the next cycle
+    pub fn record(&mut self, cycle: u32, pipeline: &Pipeline) -> io::Result<()> {
+        // Pair every occupied slot with its stage, queued instructions sit between fetch and decode
+        let slots = std::iter::once((0, &pipeline.slots[0]))
+            .chain(pipeline.fetch_queue.iter().map(|slot| (1, slot)))
+            .chain(pipeline.slots[1..].iter().enumerate().map(|(i, slot)| (i + 2, slot)))
+            .filter(|(_, slot)| slot.valid)
+            .collect::<Vec<(usize, &Slot)>>();
+
+        match self.last_cycle {
+            None => { let _ = writeln!(self.buf, "C=\t{}", cycle); },
+            Some(last) if cycle > last => { let _ = writeln!(self.buf, "C\t{}", cycle - last); },
+            Some(_) => {},
+        }
+        self.last_cycle = Some(cycle);
+
+        // Instructions that left the pipeline either retired from writeback, or were squashed
+        let gone = self.in_flight.iter()
+            .filter(|t| !slots.iter().any(|(_, slot)| slot.seq == t.seq))
+            .copied()
+            .collect::<Vec<_>>();
+        for t in gone {
+            if t.stalled {
+                let _ = writeln!(self.buf, "E\t{}\t1\tStl", t.id);
+            }
+            if t.stage == WRITEBACK {
+                let _ = writeln!(self.buf, "R\t{}\t{}\t0", t.id, self.next_retire_id);
+                self.next_retire_id += 1;
+            } else {
+                let _ = writeln!(self.buf, "R\t{}\t0\t1", t.id);
+            }
+        }
+
+        let mut in_flight = Vec::new();
+        for &(stage, slot) in slots.iter() {
+            let prev = self.in_flight.iter().find(|t| t.seq == slot.seq).copied();
+            let mut t = match prev {
+                Some(t) => t,
+                None => {
+                    let t = Tracked { seq: slot.seq, id: self.next_id, stage, stalled: false,
+                                      labeled: false };
+                    self.next_id += 1;
+                    let _ = writeln!(self.buf, "I\t{}\t{}\t0", t.id, t.seq);
+                    let _ = writeln!(self.buf, "L\t{}\t0\t{:#010x}: ", t.id, slot.pc.0);
+                    let _ = writeln!(self.buf, "S\t{}\t0\t{}", t.id, STAGE_NAMES[stage]);
+                    t
+                },
+            };
+
+            if prev.is_some() && t.stage == stage {
+                if !t.stalled {
+                    let _ = writeln!(self.buf, "S\t{}\t1\tStl", t.id);
+                    t.stalled = true;
+                }
+            } else if prev.is_some() {
+                if t.stalled {
+                    let _ = writeln!(self.buf, "E\t{}\t1\tStl", t.id);
+                    t.stalled = false;
+                }
+                let _ = writeln!(self.buf, "S\t{}\t0\t{}", t.id, STAGE_NAMES[stage]);
+                t.stage = stage;
+            }
+
+            // The instruction is only known once it was decoded
+            if !t.labeled && stage >= 2 {
+                let _ = writeln!(self.buf, "L\t{}\t0\t{}", t.id, slot.instr);
+                t.labeled = true;
+            }
+            in_flight.push(t);
+        }
+        self.in_flight = in_flight;
+
+        if self.buf.len() >= FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Append all buffered trace data to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(self.buf.as_bytes())?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// Records one tab-separated line per retired instruction: clock, pc, disassembly, the registers
+/// it wrote as `reg=old->new`, and the virtual address of the memory it accessed. Fields without a
+/// value are `-`. Since the format does not depend on the pipeline, traces of different execution
+/// modes or other simulators can be diffed directly
+#[derive(Debug, Clone)]
+pub struct ExecTrace {
+    /// File the trace is written to
+    path: PathBuf,
+
+    /// Trace data that was not yet written to the file
+    buf: String,
+
+    /// Register values after the most recently retired instruction, these are the old values of
+    /// the next instruction's destination registers
+    regs: [u32; 16],
+}
+
+impl ExecTrace {
+    /// Start a new trace at `path`, replacing the file if it already exists. `regs` are the
+    /// current register values
+    pub fn create(path: &Path, regs: [u32; 16]) -> io::Result<Self> {
+        File::create(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            buf:  String::new(),
+            regs,
+        })
+    }
+
+    /// Take `regs` as the current register values, after they were changed from outside the
+    /// running program
+    pub fn sync_regs(&mut self, regs: [u32; 16]) {
+        self.regs = regs;
+    }
+
+    /// Record that `instr` at `pc` retired during clock-cycle `cycle`, leaving the registers at
+    /// `regs`. Nothing is written to the file, see `flush_if_full`
+    pub fn record(&mut self, cycle: u32, pc: VAddr, instr: Instr, regs: &[u32; 16]) {
+        let old = std::mem::replace(&mut self.regs, *regs);
+        let is_store = matches!(instr, Instr::Stb { .. } | Instr::Sth { .. } | Instr::St { .. } |
+                                       Instr::Stnt { .. });
+
+        // Stores only write `rs3` if an mmio command returned a value
+        let writes = instr.writes_to_rs3().into_iter()
+            .filter(|&reg| !is_store || old[reg as usize] != regs[reg as usize])
+            .map(|reg| format!("{}={:#x}->{:#x}", reg, old[reg as usize], regs[reg as usize]))
+            .collect::<Vec<_>>();
+        let writes = if writes.is_empty() { String::from("-") } else { writes.join(",") };
+
+        let addr = match instr {
+            Instr::Ldb  { rs1, imm, .. } | Instr::Ldh { rs1, imm, .. } |
+            Instr::Ld   { rs1, imm, .. } | Instr::Stb { rs1, imm, .. } |
+            Instr::Sth  { rs1, imm, .. } | Instr::St  { rs1, imm, .. } |
+            Instr::Stnt { rs1, imm, .. } => {
+                Some((old[rs1 as usize] as i32).wrapping_add(imm) as u32)
+            },
+            // The return address is pushed to, and popped from the stack
+            Instr::Call { .. } => Some(regs[Register::R15 as usize]),
+            Instr::Ret  { }    => Some(old[Register::R15 as usize]),
+            // The interrupt handler is read from address 0
+            Instr::Int0 { }    => Some(0),
+            _ => None,
+        };
+        let addr = addr.map_or(String::from("-"), |addr| format!("{:#x}", addr));
+
+        let _ = writeln!(self.buf, "{}\t{:#010x}\t{}\t{}\t{}", cycle, pc.0, instr, writes, addr);
+    }
+
+    /// Append the buffered trace data to the file once it grew large enough
+    pub fn flush_if_full(&mut self) -> io::Result<()> {
+        if self.buf.len() >= FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Append all buffered trace data to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(self.buf.as_bytes())?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(seq: u64, pc: u32, instr: Instr) -> Slot {
+        Slot { valid: true, seq, pc: VAddr(pc), instr, ..Default::default() }
+    }
+
+    #[test]
+    fn records_stalls_retires_and_squashes() {
+        let path = std::env::temp_dir().join(format!("seal_trace_{}.log", std::process::id()));
+        let mut trace = PipelineTrace::create(&path).unwrap();
+        let add = Instr::Add { rs3: Register::R1, rs1: Register::R2, rs2: Register::R3 };
+
+        let mut pipeline = Pipeline::default();
+        pipeline.slots[0] = slot(1, 0x100, Instr::None);
+        trace.record(10, &pipeline).unwrap();
+
+        // Wait in the fetch queue, then advance into decode and stall there for a cycle
+        pipeline.fetch_queue.push_back(pipeline.slots[0].clone());
+        pipeline.slots[0] = slot(2, 0x104, Instr::None);
+        trace.record(11, &pipeline).unwrap();
+        pipeline.fetch_queue.clear();
+        pipeline.slots[1] = slot(1, 0x100, add);
+        trace.record(12, &pipeline).unwrap();
+        trace.record(13, &pipeline).unwrap();
+
+        // The first instruction retires, the second one is squashed
+        pipeline = Pipeline::default();
+        pipeline.slots[4] = slot(1, 0x100, add);
+        trace.record(14, &pipeline).unwrap();
+        trace.record(15, &Pipeline::default()).unwrap();
+        trace.flush().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log, [
+            "Kanata\t0004", "C=\t10", "I\t0\t1\t0", "L\t0\t0\t0x00000100: ", "S\t0\t0\tF",
+            "C\t1", "I\t1\t2\t0", "L\t1\t0\t0x00000104: ", "S\t1\t0\tF", "S\t0\t0\tQ",
+            "C\t1", "S\t1\t1\tStl", "S\t0\t0\tD", &format!("L\t0\t0\t{}", add),
+            "C\t1", "S\t0\t1\tStl",
+            "C\t1", "E\t1\t1\tStl", "R\t1\t0\t1", "E\t0\t1\tStl", "S\t0\t0\tW",
+            "C\t1", "R\t0\t0\t0", "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    simulator::{Simulator, SimErr},
+    golden::GoldenModel,
+    cpu::Instr,
+    logger::Logger,
+};
+
+/// Reason a fast-forward stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurboStop {
+    /// Execution reached a breakpoint
+    Breakpoint,
+
+    /// The requested number of instructions were executed
+    Limit,
+
+    /// A device requested an interrupt or dma, the detailed model performs it
+    Device,
+
+    /// The program exited or stopped with an error
+    Offline,
+}
+
+impl Simulator {
+    /// Execute up to `max_instrs` instructions functionally, without modeling the pipeline or
+    /// memory stalls, then continue with the detailed model. Every instruction takes a single
+    /// cycle. Retired instructions are not reported to hooks, traces or the profiler
+    pub fn fast_forward(&mut self, max_instrs: u64, err_log: &dyn Logger) -> TurboStop {
+        if !self.online {
+            return TurboStop::Offline;
+        }
+        self.flush_pipeline();
+
+        let mut model = GoldenModel::take_over(self);
+        let mut fault = None;
+        let mut executed = 0;
+        let stop = loop {
+            if executed > 0 && self.breakpoints.contains_key(&model.pc.0) {
+                break TurboStop::Breakpoint;
+            }
+            if executed >= max_instrs {
+                break TurboStop::Limit;
+            }
+
+            // Device side effects the model can't perform are left to the detailed model
+            let clock = model.clock;
+            let actions = model.devices_mut().tick(clock);
+            if !actions.is_empty() {
+                for action in actions {
+                    model.devices_mut().schedule(clock + 1, action);
+                }
+                model.clock += 1;
+                break TurboStop::Device;
+            }
+
+            let pc = model.pc;
+            let result = model.fetch().and_then(|instr| {
+                let pc = model.pc;
+                model.execute(instr).map(|_| (pc, instr))
+            });
+            let (pc, instr) = match result {
+                Ok(retired) => retired,
+                Err(err) => {
+                    fault = Some((err, pc));
+                    break TurboStop::Offline;
+                },
+            };
+            model.clock += 1;
+            executed += 1;
+            self.stats.total_instrs += 1;
+            match instr {
+                Instr::Call { .. } => self.enter_call(pc, model.pc),
+                Instr::Ret  { .. } => self.leave_call(),
+                _ => {},
+            }
+
+            // Interrupts and dma requested by a store take effect in the next cycle
+            if !model.pending.is_empty() {
+                let next = model.clock;
+                for action in std::mem::take(&mut model.pending) {
+                    model.devices_mut().schedule(next, action);
+                }
+                break TurboStop::Device;
+            }
+        };
+        model.hand_back(self);
+
+        if let Some((err, pc)) = fault {
+            if err == SimErr::Shutdown {
+                self.online = false;
+            }
+            self.guest_fault(err, pc, err_log);
+        }
+        if self.golden.is_some() {
+            self.golden = Some(GoldenModel::new(self));
+        }
+        if stop == TurboStop::Breakpoint {
+            self.checkpoint_stop();
+        }
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        difftest::{MsgLog, run_program},
+        simulator::DEFAULT_STACK_BASE,
+        mmu::{VAddr, FrameAllocator},
+        cpu::Register,
+    };
+
+    /// Load `program` into a fresh simulator with the default memory layout and seed 1
+    fn load(program: &str, log: &MsgLog) -> Simulator {
+        let mut sim = Simulator::new();
+        sim.mmu.frame_allocator = FrameAllocator::sequential();
+        sim.set_seed(1);
+        sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+        sim.load_input(program, log).unwrap();
+        sim
+    }
+
+    #[test]
+    fn continues_in_the_detailed_model() {
+        // Numbers loaded from the rng depend on the clock, which runs differently in turbo mode.
+        // The command-port's numbers only depend on the seed
+        let program = include_str!("../code/matrix_mul").replace(
+            "movi r2 0x20a0\n    ld r1 r2 0x0",
+            "movi r1 0x43\n    movi r2 0x2000\n    st r1 r2 0x0");
+        let program = program.as_str();
+        let detailed = run_program(program, true, 2_000_000, &|sim| sim.set_seed(1));
+        assert!(!detailed.sim.online);
+
+        let log = MsgLog::default();
+        let mut sim = load(program, &log);
+        sim.golden = Some(GoldenModel::new(&sim));
+        assert_eq!(sim.fast_forward(20_000, &log), TurboStop::Limit);
+        assert_eq!(sim.clock, 20_000);
+        assert_eq!(sim.stats.total_instrs, 20_000);
+        while sim.online && sim.clock < 2_000_000 {
+            sim.step(&log);
+        }
+        assert!(log.errors.borrow().is_empty(), "{:?}", log);
+        assert!(!sim.online);
+        assert!(sim.clock < detailed.sim.clock);
+        assert_eq!(sim.gen_regs, detailed.sim.gen_regs);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_exits() {
+        let program = "
+            .entry _start
+            .load 0x20000
+            .count
+                addi r3 r3 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                movi r6 0x5
+            .loop
+                call 0x20000
+                subi r6 r6 0x1
+                bne r6 r0 .loop
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        let log = MsgLog::default();
+        let mut sim = load(program, &log);
+        sim.breakpoints.insert(0x20000, 0);
+        assert_eq!(sim.fast_forward(1_000, &log), TurboStop::Breakpoint);
+        assert_eq!(sim.pc, VAddr(0x20000));
+        assert_eq!(sim.call_depth, 1);
+
+        // Resuming from a breakpoint executes the instruction at it
+        assert_eq!(sim.fast_forward(1_000, &log), TurboStop::Breakpoint);
+        assert_eq!(sim.read_reg(Register::R3), 1);
+
+        sim.breakpoints.clear();
+        assert_eq!(sim.fast_forward(1_000, &log), TurboStop::Offline);
+        assert!(!sim.online);
+        assert_eq!(sim.read_reg(Register::R3), 5);
+        assert_eq!(sim.call_depth, 0);
+        assert_eq!(*log.logs.borrow(), ["Guest invoked shutdown request - Simulator stopped"]);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::mmio::{MmioDevice, MmioAction};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Address of the uart's registers, next to the interval timer
+pub const UART_BASE: u32 = 0x2020;
+
+/// Size of the uart's register block
+pub const UART_SIZE: u32 = 0x10;
+
+/// Interrupt-vector raised when input arrives, its handler is stored at address 0xc
+pub const UART_IRQ: u32 = 3;
+
+/// Status bit that is set while received bytes are waiting to be read
+pub const UART_RX_READY: u32 = 1 << 0;
+
+/// Control bit that raises `UART_IRQ` whenever input arrives, or when it is set while input is
+/// waiting
+pub const UART_RX_IRQ_ENABLE: u32 = 1 << 0;
+
+/// State shared between all clones of a `Uart`
+#[derive(Debug, Default)]
+struct UartState {
+    /// Bytes the guest sent, oldest first
+    output: Vec<u8>,
+
+    /// Received bytes the guest didn't take yet, oldest first
+    input: VecDeque<u8>,
+
+    control: u32,
+
+    /// Set when input arrived since the last tick, to raise the interrupt
+    input_arrived: bool,
+
+    /// Print sent bytes to stdout as well
+    echo: bool,
+}
+
+/// Serial port at `UART_BASE`. Its 32-bit registers are:
+///  - 0x0 Tx: Writing a byte sends it
+///  - 0x4 Rx: Oldest received byte, 0 if there is none. Reading it does not take the byte, since
+///    loads may execute speculatively
+///  - 0x8 Status: `UART_RX_READY`. Writing it with `UART_RX_READY` set takes the oldest byte
+///  - 0xc Control: `UART_RX_IRQ_ENABLE`
+///
+/// Clones share the same state, so the host can send input and read the output after registering
+/// the device
+#[derive(Clone, Debug, Default)]
+pub struct Uart {
+    state: Rc<RefCell<UartState>>,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uart that also prints the bytes it sends to stdout, for runs without the gui
+    pub fn stdout() -> Self {
+        let uart = Self::new();
+        uart.state.borrow_mut().echo = true;
+        uart
+    }
+
+    /// Everything the guest sent so far
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.state.borrow().output).into_owned()
+    }
+
+    /// Queue `bytes` for the guest to receive
+    pub fn send(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        state.input.extend(bytes);
+        state.input_arrived = true;
+    }
+
+    /// Current register values, in the order they are mapped
+    fn regs(&self) -> [u32; 4] {
+        let state = self.state.borrow();
+        let status = if state.input.is_empty() { 0 } else { UART_RX_READY };
+        [0, state.input.front().copied().unwrap_or(0) as u32, status, state.control]
+    }
+}
+
+impl MmioDevice for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    /// Only the lowest byte of the tx, status and control registers is used
+    fn write(&mut self, offset: u32, data: &[u8], _clock: u32) -> Option<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        match offset {
+            0x0 => {
+                state.output.push(data[0]);
+                if state.echo {
+                    let mut stdout = std::io::stdout();
+                    let _ = stdout.write_all(&data[..1]).and_then(|_| stdout.flush());
+                }
+            },
+            0x8 if data[0] as u32 & UART_RX_READY != 0 => {
+                state.input.pop_front();
+            },
+            0xc => {
+                // Input that is already waiting raises the interrupt once it is enabled
+                state.control = data[0] as u32;
+                state.input_arrived |= !state.input.is_empty();
+            },
+            _ => {},
+        }
+        None
+    }
+
+    fn read(&mut self, offset: u32, reader: &mut [u8]) {
+        let bytes = self.regs().map(u32::to_le_bytes).concat();
+        let offset = offset as usize;
+        reader.copy_from_slice(&bytes[offset..offset + reader.len()]);
+    }
+
+    fn tick(&mut self, _clock: u32) -> Vec<MmioAction> {
+        let mut state = self.state.borrow_mut();
+        if !std::mem::take(&mut state.input_arrived) {
+            return Vec::new();
+        }
+        if state.control & UART_RX_IRQ_ENABLE != 0 {
+            vec![MmioAction::RaiseIrq]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let echo = state.echo;
+        *state = UartState { echo, ..UartState::default() };
+    }
+
+    fn clone_box(&self) -> Box<dyn MmioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simulator::{Simulator, DEFAULT_STACK_BASE},
+        difftest::MsgLog,
+        mmu::VAddr,
+        cpu::Register,
+    };
+
+    #[test]
+    fn buffers_input_until_it_is_taken() {
+        let mut uart = Uart::new();
+        let mut reg = [0u8; 4];
+        uart.read(0x8, &mut reg);
+        assert_eq!(reg, [0; 4]);
+
+        uart.send(b"hi");
+        uart.read(0x4, &mut reg);
+        assert_eq!(reg, [b'h', 0, 0, 0]);
+        uart.read(0x4, &mut reg);
+        assert_eq!(reg, [b'h', 0, 0, 0]);
+        uart.write(0x8, &[UART_RX_READY as u8], 0);
+        uart.read(0x4, &mut reg[..1]);
+        assert_eq!(reg[0], b'i');
+
+        // Input only raises an interrupt once it is enabled
+        assert!(uart.tick(0).is_empty());
+        uart.write(0xc, &[UART_RX_IRQ_ENABLE as u8], 0);
+        uart.send(b"!");
+        assert_eq!(uart.tick(1), [MmioAction::RaiseIrq]);
+        assert!(uart.tick(2).is_empty());
+
+        uart.reset();
+        uart.read(0x8, &mut reg);
+        assert_eq!(reg, [0; 4]);
+    }
+
+    /// Echo every received byte back, upper-cased, until a newline arrives. Input is read from an
+    /// interrupt handler, the main loop waits for it to set `r6`
+    #[test]
+    fn echoes_input_from_an_interrupt_handler() {
+        let program = "
+            .entry _start
+            .load 0x40000
+            .rx_handler
+                movi r2 0x2020
+            .next
+                ld r3 r2 0x4
+                movi r4 0x1
+                st r4 r2 0x8
+                movi r4 0xa
+                beq r3 r4 .done
+                subi r3 r3 0x20
+                st r3 r2 0x0
+                ld r4 r2 0x8
+                bne r4 r0 .next
+                ret
+            .done
+                movi r6 0x1
+                ret
+            .end_section
+            .load 0x10000
+            ._start
+                lui r1 0x40
+                st r1 r0 0xc
+                movi r2 0x2020
+                movi r1 0x1
+                st r1 r2 0xc
+            .wait
+                beq r6 r0 .wait
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let log = MsgLog::default();
+            let mut sim = Simulator::new();
+            sim.pipelining_enabled = pipelined;
+            sim.set_out_of_order(ooo);
+            sim.map_default_layout(DEFAULT_STACK_BASE).unwrap();
+            let uart = Uart::new();
+            sim.register_device(VAddr(UART_BASE), UART_SIZE, Some(UART_IRQ),
+                                Box::new(uart.clone())).unwrap();
+            sim.load_input(program, &log).unwrap();
+            sim.start_golden();
+
+            uart.send(b"seal\n");
+            while sim.online && sim.clock < 100_000 {
+                sim.step(&log);
+            }
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!sim.online, "{}: {:?}", mode, log);
+            assert!(log.errors.borrow().is_empty(), "{}: {:?}", mode, log);
+            assert_eq!(uart.output(), "SEAL", "{}", mode);
+            assert_eq!(sim.read_reg(Register::R6), 1, "{}", mode);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    mmu::{VAddr, PAddr},
+    pipeline::Pipeline,
+    ooo::OooEngine,
+    debugger::CallFrame,
+    Stats,
+};
+
+use std::collections::VecDeque;
+
+/// Number of cycles that can be stepped back through unless configured otherwise
+pub const DEFAULT_UNDO_DEPTH: usize = 10_000;
+
+/// State needed to rewind the simulator by a single clock-cycle. Registers, the pipeline and the
+/// stats are kept as they were before the cycle, memory only through the bytes it overwrote
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pub clock: u32,
+
+    pub pc: VAddr,
+
+    pub gen_regs: [u32; 16],
+
+    pub online: bool,
+
+    pub sleep_until: Option<u32>,
+
+    pub pipeline: Pipeline,
+
+    pub ooo: OooEngine,
+
+    pub stats: Stats,
+
+    pub cpi_samples: VecDeque<(u32, u64)>,
+
+    pub call_depth: i32,
+
+    pub call_stack: Vec<CallFrame>,
+
+    /// Physical address and previous contents of every memory write during the cycle, in the
+    /// order the writes happened
+    pub mem_writes: Vec<(PAddr, Vec<u8>)>,
+}
+
+/// Ring buffer of undo records for the most recent clock-cycles
+#[derive(Debug, Clone)]
+pub struct UndoLog {
+    /// Maximum number of cycles that are kept, older cycles can't be rewound anymore
+    capacity: usize,
+
+    /// Recorded cycles, oldest first
+    records: VecDeque<UndoRecord>,
+}
+
+impl UndoLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Add the record of the most recent cycle, dropping the oldest one if the log is full
+    pub fn push(&mut self, record: UndoRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Take the record of the most recent cycle
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+
+    /// Number of cycles that can currently be rewound
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Forget all recorded cycles
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
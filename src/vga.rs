@@ -0,0 +1,343 @@
+use crate::{
+    mmio::{MmioAction, CMD_PORT_ADDR},
+    mmu::VAddr,
+    VGA_BASE, vga_char,
+};
+
+/// Smallest offset of the attribute bytes from `VGA_BASE`, larger screens move them past their
+/// characters
+pub const VGA_ATTR_OFFSET: u32 = 0x100;
+
+/// Smallest offset of the control registers from `VGA_BASE`, larger screens move them past their
+/// attributes
+pub const VGA_CTRL_OFFSET: u32 = 0x200;
+
+/// Size of the control registers
+const VGA_CTRL_SIZE: u32 = 0x10;
+
+/// Largest address range a screen can be registered for, it has to end before the exit port
+pub const VGA_MAX_DEVICE_SIZE: u32 = CMD_PORT_ADDR - VGA_BASE;
+
+/// Dimensions of the text screen. Every line is followed by a newline in the vga-buffer, so a
+/// line takes up one more byte than it has columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VgaSize {
+    /// Number of lines on the screen
+    pub lines: usize,
+
+    /// Characters per line
+    pub columns: usize,
+}
+
+impl Default for VgaSize {
+    /// 8 lines of 29 characters
+    fn default() -> Self {
+        Self { lines: 8, columns: 29 }
+    }
+}
+
+impl VgaSize {
+    /// Screen of `lines` lines of `columns` characters, if its address range fits in front of the
+    /// exit port
+    pub fn new(lines: usize, columns: usize) -> Option<Self> {
+        let size = Self { lines, columns };
+        let fits = lines > 0 && columns > 0 &&
+            lines.saturating_mul(columns.saturating_add(1)) < VGA_MAX_DEVICE_SIZE as usize &&
+            size.device_size() <= VGA_MAX_DEVICE_SIZE;
+        fits.then_some(size)
+    }
+
+    /// Parse a size given as lines times columns, eg. `25x80`
+    pub fn parse(config: &str) -> Option<Self> {
+        let (lines, columns) = config.trim().split_once('x')?;
+        Self::new(lines.parse().ok()?, columns.parse().ok()?)
+    }
+
+    /// Bytes per line of the vga-buffer, the last one holds the newline
+    pub fn line_len(&self) -> usize {
+        self.columns + 1
+    }
+
+    /// Size of the vga-buffer's characters in bytes
+    pub fn chars(&self) -> u32 {
+        (self.lines * self.line_len()) as u32
+    }
+
+    /// Offset of the attribute bytes from `VGA_BASE`, one per character of the vga-buffer
+    pub fn attr_offset(&self) -> u32 {
+        std::cmp::max(VGA_ATTR_OFFSET, self.chars().next_multiple_of(0x10))
+    }
+
+    /// Offset of the control registers from `VGA_BASE`
+    pub fn ctrl_offset(&self) -> u32 {
+        std::cmp::max(VGA_CTRL_OFFSET, (self.attr_offset() + self.chars()).next_multiple_of(0x10))
+    }
+
+    /// Size of the address range the screen is registered for: characters, attributes and
+    /// control registers
+    pub fn device_size(&self) -> u32 {
+        self.ctrl_offset() + VGA_CTRL_SIZE
+    }
+}
+
+/// Command that blanks the screen and moves the cursor to the top left
+pub const VGA_CMD_CLEAR: u32 = 1;
+
+/// Rgb values of the 16 colors an attribute can select, in the order of the cga palette
+pub const VGA_PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xaa), (0x00, 0xaa, 0x00), (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00), (0xaa, 0x00, 0xaa), (0xaa, 0x55, 0x00), (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xff), (0x55, 0xff, 0x55), (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55), (0xff, 0x55, 0xff), (0xff, 0xff, 0x55), (0xff, 0xff, 0xff),
+];
+
+/// Foreground and background color of `attr` as indices into `VGA_PALETTE`. The low nibble
+/// selects the foreground and the high nibble the background. Attribute 0, which cleared memory
+/// holds, is shown as white on black
+pub fn attr_colors(attr: u8) -> (usize, usize) {
+    match attr {
+        0 => (15, 0),
+        attr => ((attr & 0xf) as usize, (attr >> 4) as usize),
+    }
+}
+
+/// Text-mode screen behind the vga-buffer, shared by the gui and headless screens. Its address
+/// range starts at `VGA_BASE` and holds, at the offsets of the default screen size:
+///  - 0x000: The characters, `lines` lines of `line_len` bytes
+///  - 0x100 (`attr_offset`): An attribute byte for every character, see `attr_colors`
+///  - 0x200 (`ctrl_offset`) Cursor: Index of the character the next byte written to `Putc`
+///    replaces
+///  - 0x204 Putc: Writing a byte prints it at the cursor with the current attribute and advances
+///    the cursor. Newlines move it to the next line, the screen scrolls up past the last one
+///  - 0x208 Attribute: Attribute of the characters printed through `Putc`
+///  - 0x20c Command: Writing `VGA_CMD_CLEAR` clears the screen
+///
+/// Characters and attributes the screen changes itself are copied back into memory by dma, so
+/// loads see what is displayed
+#[derive(Debug, Clone)]
+pub struct TextMode {
+    /// Bytes of the vga-buffer, as written
+    pub chars: Vec<u8>,
+
+    /// Attribute of every character
+    pub attrs: Vec<u8>,
+
+    size:   VgaSize,
+    cursor: u32,
+    attr:   u32,
+}
+
+impl Default for TextMode {
+    fn default() -> Self {
+        Self::new(VgaSize::default())
+    }
+}
+
+impl TextMode {
+    /// Blank screen of `size`, every line is filled with spaces and ends in a newline
+    pub fn new(size: VgaSize) -> Self {
+        let mut line = vec![b' '; size.line_len()];
+        line[size.columns] = b'\n';
+        Self {
+            chars:  line.repeat(size.lines),
+            attrs:  vec![0; size.chars() as usize],
+            size,
+            cursor: 0,
+            attr:   0,
+        }
+    }
+
+    /// Dimensions of the screen
+    pub fn size(&self) -> VgaSize {
+        self.size
+    }
+
+    /// Current screen contents
+    pub fn text(&self) -> String {
+        self.chars.iter().map(|&byte| vga_char(byte)).collect()
+    }
+
+    /// Handle a write of `data` to `offset` into the screen's address range. Returns the dma that
+    /// copies the screen back into memory if the write printed, scrolled or cleared it
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Option<MmioAction> {
+        let chars = self.size.chars();
+        let attrs = self.size.attr_offset();
+        let ctrl  = self.size.ctrl_offset();
+        let mut changed = false;
+        for (offset, &byte) in (offset..).zip(data) {
+            let reg = offset as usize % 4;
+            match offset.wrapping_sub(ctrl) {
+                _ if offset < chars => self.chars[offset as usize] = byte,
+                _ if (attrs..attrs + chars).contains(&offset) => {
+                    self.attrs[(offset - attrs) as usize] = byte;
+                },
+                0x0..=0x3 => self.cursor = set_byte(self.cursor, reg, byte),
+                0x4 => {
+                    self.putc(byte);
+                    changed = true;
+                },
+                0x8..=0xb => self.attr = set_byte(self.attr, reg, byte),
+                0xc if byte as u32 == VGA_CMD_CLEAR => {
+                    *self = Self { attr: self.attr, ..Self::new(self.size) };
+                    changed = true;
+                },
+                _ => {},
+            }
+        }
+        changed.then(|| {
+            let mut data = self.chars.clone();
+            data.resize(attrs as usize, 0);
+            data.extend(&self.attrs);
+            data.resize(ctrl as usize, 0);
+            data.extend(self.cursor.to_le_bytes());
+            MmioAction::Dma { addr: VAddr(VGA_BASE), data }
+        })
+    }
+
+    /// Print `byte` at the cursor and advance it, scrolling once it moves past the last line
+    fn putc(&mut self, byte: u8) {
+        let (size, line_len) = (self.size.chars() as usize, self.size.line_len());
+        let mut cursor = self.cursor as usize % size;
+        if byte == b'\n' {
+            cursor += line_len - cursor % line_len;
+        } else {
+            self.chars[cursor] = byte;
+            self.attrs[cursor] = self.attr as u8;
+            cursor += 1;
+            if cursor % line_len == line_len - 1 {
+                cursor += 1;
+            }
+        }
+        if cursor >= size {
+            self.scroll();
+            cursor -= line_len;
+        }
+        self.cursor = cursor as u32;
+    }
+
+    /// Move every line up by one and blank the last line
+    fn scroll(&mut self) {
+        let line_len = self.size.line_len();
+        let last = self.chars.len() - line_len;
+        self.chars.copy_within(line_len.., 0);
+        self.chars[last..].fill(b' ');
+        self.chars[last + self.size.columns] = b'\n';
+        self.attrs.copy_within(line_len.., 0);
+        self.attrs[last..].fill(0);
+    }
+}
+
+/// `value` with its byte `idx` replaced by `byte`
+fn set_byte(value: u32, idx: usize, byte: u8) -> u32 {
+    let mut bytes = value.to_le_bytes();
+    bytes[idx] = byte;
+    u32::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{difftest::run_program, cpu::Register, VgaBuffer};
+
+    #[test]
+    fn sizes_lay_out_the_address_range() {
+        let size = VgaSize::default();
+        assert_eq!((size.chars(), size.attr_offset(), size.ctrl_offset(), size.device_size()),
+                   (240, VGA_ATTR_OFFSET, VGA_CTRL_OFFSET, 0x210));
+
+        // Larger screens move the attributes and control registers past their characters
+        let size = VgaSize::parse("25x80").unwrap();
+        assert_eq!((size.chars(), size.attr_offset(), size.ctrl_offset()), (2025, 0x7f0, 0xfe0));
+        assert!(size.device_size() <= VGA_MAX_DEVICE_SIZE);
+        assert_eq!(VgaSize::parse("26x80"), None);
+        assert_eq!(VgaSize::parse("0x80"), None);
+        assert_eq!(VgaSize::parse("25"), None);
+    }
+
+    #[test]
+    fn putc_wraps_and_scrolls() {
+        let size = VgaSize::new(4, 40).unwrap();
+        let (line_len, chars, ctrl) = (size.line_len(), size.chars() as usize, size.ctrl_offset());
+        let mut screen = TextMode::new(size);
+        screen.write(ctrl + 8, &[0x1e]);
+        for &byte in b"hi\nthere" {
+            assert!(screen.write(ctrl + 4, &[byte]).is_some());
+        }
+        assert!(screen.text().starts_with("hi "));
+        assert_eq!(&screen.text()[line_len..line_len + 6], "there ");
+        assert_eq!(screen.attrs[line_len..line_len + 6], [0x1e, 0x1e, 0x1e, 0x1e, 0x1e, 0]);
+        assert_eq!(screen.cursor as usize, line_len + 5);
+
+        // Long lines wrap past the newline, printing past the last line scrolls
+        screen.write(ctrl, &((chars - 2) as u32).to_le_bytes());
+        let dma = screen.write(ctrl + 4, b"x");
+        assert!(screen.text().starts_with("there"));
+        assert_eq!(screen.chars[chars - line_len - 2], b'x');
+        assert_eq!(screen.cursor as usize, chars - line_len);
+        assert!(screen.text().ends_with(&format!("{:40}\n", "")));
+        let Some(MmioAction::Dma { addr, data }) = dma else { panic!("no dma: {:?}", dma) };
+        assert_eq!((addr, data.len()), (VAddr(VGA_BASE), ctrl as usize + 4));
+        assert_eq!(&data[..chars], &screen.chars);
+
+        // Plain writes to the characters don't need a dma, clearing keeps the attribute
+        assert!(screen.write(0x0, b"ab").is_none());
+        assert!(screen.write(ctrl + 0xc, &[VGA_CMD_CLEAR as u8]).is_some());
+        assert_eq!(screen.text(), TextMode::new(size).text());
+        assert_eq!((screen.cursor, screen.attr), (0, 0x1e));
+        assert_eq!(attr_colors(0), (15, 0));
+        assert_eq!(attr_colors(0x1e), (14, 1));
+    }
+
+    /// Print nine lines through the control registers and read the screen back from memory. The
+    /// last newline scrolls again, so two lines scrolled off
+    #[test]
+    fn loads_see_the_printed_screen() {
+        let program = "
+            .entry _start
+            .load 0x10000
+            ._start
+                movi r2 0x1200
+                movi r1 0x4f
+                st r1 r2 0x8
+                movi r6 0x9
+            .line
+                movi r1 0x41
+                add r1 r1 r6
+                st r1 r2 0x4
+                movi r1 0xa
+                st r1 r2 0x4
+                subi r6 r6 0x1
+                bne r6 r0 .line
+                movi r3 0x1000
+                ld r4 r3 0x0
+                ld r5 r3 0x100
+                ld r7 r2 0x0
+                movi r1 0x41
+                movi r2 0x2000
+                st r1 r2 0x0
+            .end_section
+        ";
+        for (pipelined, ooo) in [(true, false), (false, false), (true, true)] {
+            let vga = VgaBuffer::new();
+            let run = run_program(program, pipelined, 100_000, &|sim| {
+                sim.set_out_of_order(ooo);
+                sim.devices.register(VAddr(VGA_BASE), vga.size().device_size(),
+                                     Box::new(vga.clone())).unwrap();
+                sim.start_golden();
+            });
+            let mode = format!("pipelined: {}, ooo: {}", pipelined, ooo);
+            assert!(!run.sim.online, "{}: {:?}", mode, run.log);
+            assert!(run.log.errors.borrow().is_empty(), "{}: {:?}", mode, run.log);
+
+            // Nine lines were printed on eight, so the first one scrolled off
+            let text = vga.text();
+            assert!(text.starts_with("H "), "{}: {}", mode, text);
+            assert!(text.ends_with(&format!("{:29}\n", "")), "{}: {}", mode, text);
+            assert_eq!(run.sim.read_reg(Register::R4), 0x20202048, "{}", mode);
+            assert_eq!(run.sim.read_reg(Register::R5), 0x4f, "{}", mode);
+            let size = vga.size();
+            assert_eq!(run.sim.read_reg(Register::R7), size.chars() - size.line_len() as u32,
+                       "{}", mode);
+        }
+    }
+}